@@ -1,14 +1,104 @@
 use std::collections::BTreeMap;
 
-use super::error::Result;
+use super::code_pages::CodePageTracker;
+use super::error::{EmulatorError, MemoryAccessKind, Result};
+use super::exclusive::GlobalMonitor;
+use super::irq::IrqLine;
 use super::memory::Memory;
+use super::memory_hooks::{MemoryAccess, MemoryHooks};
 
+const MMIO_PAGE_SHIFT: u32 = 12;
+
+/// The width of a bus access, passed to devices so a byte store stays a byte
+/// store instead of becoming a read-modify-write of the whole register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessWidth {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+impl AccessWidth {
+    pub fn bytes(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn mask(self) -> u64 {
+        u64::MAX >> (64 - 8 * self.bytes())
+    }
+}
+
+/// Work a device requests while handling an access or one of its events,
+/// collected by the bus and drained by the emulator.
+#[derive(Debug, Default)]
+pub struct DeviceContext {
+    /// Base of the mapping whose device is running.
+    base: u32,
+    /// The core whose step made the access, for banked registers.
+    core: usize,
+    irqs: Vec<IrqLine>,
+    private_irqs: Vec<PrivateIrq>,
+    events: Vec<DeviceEvent>,
+}
+
+impl DeviceContext {
+    pub fn core(&self) -> usize {
+        self.core
+    }
+
+    pub fn raise_irq(&mut self, line: IrqLine) {
+        self.irqs.push(line);
+    }
+
+    /// Drives one core's level-sensitive private interrupt `line`; it stays
+    /// where it was left until the device drives it again.
+    pub fn drive_private_irq(&mut self, core: usize, line: IrqLine, asserted: bool) {
+        self.private_irqs.push(PrivateIrq {
+            core,
+            line,
+            asserted,
+        });
+    }
+
+    /// Calls the device's [`BusDevice::handle_event`] with `token` once
+    /// `delay_cycles` have passed.
+    pub fn schedule_event(&mut self, delay_cycles: u64, token: u32) {
+        self.events.push(DeviceEvent {
+            base: self.base,
+            delay_cycles,
+            token,
+        });
+    }
+}
+
+/// A change to a core's private interrupt line, applied in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrivateIrq {
+    pub core: usize,
+    pub line: IrqLine,
+    pub asserted: bool,
+}
+
+/// A device callback waiting to be put on the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub base: u32,
+    pub delay_cycles: u64,
+    pub token: u32,
+}
+
+/// A memory-mapped device. Offsets are relative to the base it was mapped
+/// at, values are little-endian and only the low `width` bytes are used.
 pub trait BusDevice {
-    fn read_u32(&mut self, _addr: u32) -> u32 {
+    fn read(&mut self, _offset: u32, _width: AccessWidth, _ctx: &mut DeviceContext) -> u64 {
         0
     }
 
-    fn write_u32(&mut self, _addr: u32, _value: u32) {}
+    fn write(&mut self, _offset: u32, _width: AccessWidth, _value: u64, _ctx: &mut DeviceContext) {}
+
+    /// An event scheduled through [`DeviceContext::schedule_event`] is due.
+    fn handle_event(&mut self, _token: u32, _ctx: &mut DeviceContext) {}
 }
 
 pub trait Bus {
@@ -23,6 +113,18 @@ pub trait Bus {
         let _ = self.write_u8_checked(addr, value);
     }
 
+    fn read_u16_checked(&mut self, addr: u32) -> Result<u16> {
+        let b0 = self.read_u8_checked(addr)?;
+        let b1 = self.read_u8_checked(addr.wrapping_add(1))?;
+        Ok(u16::from_le_bytes([b0, b1]))
+    }
+
+    fn write_u16_checked(&mut self, addr: u32, value: u16) -> Result<()> {
+        let bytes = value.to_le_bytes();
+        self.write_u8_checked(addr, bytes[0])?;
+        self.write_u8_checked(addr.wrapping_add(1), bytes[1])
+    }
+
     fn read_u32_checked(&mut self, addr: u32) -> Result<u32> {
         let b0 = self.read_u8_checked(addr)?;
         let b1 = self.read_u8_checked(addr.wrapping_add(1))?;
@@ -40,6 +142,17 @@ pub trait Bus {
         Ok(())
     }
 
+    fn read_u64_checked(&mut self, addr: u32) -> Result<u64> {
+        let lo = self.read_u32_checked(addr)?;
+        let hi = self.read_u32_checked(addr.wrapping_add(4))?;
+        Ok(u64::from(lo) | (u64::from(hi) << 32))
+    }
+
+    fn write_u64_checked(&mut self, addr: u32, value: u64) -> Result<()> {
+        self.write_u32_checked(addr, value as u32)?;
+        self.write_u32_checked(addr.wrapping_add(4), (value >> 32) as u32)
+    }
+
     fn read_u32(&mut self, addr: u32) -> u32 {
         self.read_u32_checked(addr).unwrap_or(0)
     }
@@ -47,6 +160,39 @@ pub trait Bus {
     fn write_u32(&mut self, addr: u32, value: u32) {
         let _ = self.write_u32_checked(addr, value);
     }
+
+    /// A read made by a core for itself rather than for a load: instruction
+    /// fetches, translation lookahead and table walks. These always fail on
+    /// a bad address, whatever the [`AccessViolationPolicy`], so a fetch from
+    /// unmapped memory takes a prefetch abort.
+    fn fetch_u16(&mut self, addr: u32) -> Result<u16> {
+        self.read_u16_checked(addr)
+    }
+
+    /// See [`Bus::fetch_u16`].
+    fn fetch_u32(&mut self, addr: u32) -> Result<u32> {
+        self.read_u32_checked(addr)
+    }
+
+    /// Global exclusive monitor shared by the cores on this bus, if any.
+    fn exclusive_monitor(&mut self) -> Option<&mut GlobalMonitor> {
+        None
+    }
+
+    /// Write tracking for pages holding translated code. Without it the CPU
+    /// cannot notice self-modifying code and always interprets.
+    fn code_pages(&mut self) -> Option<&mut CodePageTracker> {
+        None
+    }
+
+    /// Whether anything observes data accesses; cores skip building a
+    /// [`MemoryAccess`] for [`Bus::report_access`] otherwise.
+    fn memory_hooks_active(&self) -> bool {
+        false
+    }
+
+    /// A core completed a data access. The bus stamps `cycle`.
+    fn report_access(&mut self, _access: MemoryAccess) {}
 }
 
 impl Bus for Memory {
@@ -57,17 +203,76 @@ impl Bus for Memory {
     fn write_u8_checked(&mut self, addr: u32, value: u8) -> Result<()> {
         Memory::write_u8_checked(self, addr, value)
     }
+
+    fn read_u16_checked(&mut self, addr: u32) -> Result<u16> {
+        Memory::read_u16_checked(self, addr)
+    }
+
+    fn write_u16_checked(&mut self, addr: u32, value: u16) -> Result<()> {
+        Memory::write_u16_checked(self, addr, value)
+    }
+
+    fn read_u32_checked(&mut self, addr: u32) -> Result<u32> {
+        Memory::read_u32_checked(self, addr)
+    }
+
+    fn write_u32_checked(&mut self, addr: u32, value: u32) -> Result<()> {
+        Memory::write_u32_checked(self, addr, value)
+    }
+}
+
+/// What the bus does with an access that writes read-only memory or that
+/// no memory or device answers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessViolationPolicy {
+    /// Drop the write or read zero.
+    #[default]
+    Ignore,
+    /// Like `Ignore`, but report the violation.
+    Log,
+    /// Report the violation and fail the access, which a core takes as a
+    /// data abort.
+    Fault,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessViolation {
+    ReadOnlyWrite {
+        address: u32,
+    },
+    Unmapped {
+        address: u32,
+        access: MemoryAccessKind,
+    },
+}
+
+struct MmioMapping {
+    size: u32,
+    device: Box<dyn BusDevice>,
 }
 
 #[derive(Default)]
 pub struct SystemBus {
     memory: Memory,
-    mmio: BTreeMap<u32, Box<dyn BusDevice>>,
+    /// Device mappings keyed by base address; they must not overlap.
+    mmio: BTreeMap<u32, MmioMapping>,
+    /// One bit per 4 KiB page a device covers, so plain memory accesses
+    /// skip the device map.
+    mmio_pages: Vec<u64>,
+    device_context: DeviceContext,
+    monitor: GlobalMonitor,
+    code_pages: CodePageTracker,
+    hooks: MemoryHooks,
+    violation_policy: AccessViolationPolicy,
+    violations: Vec<AccessViolation>,
 }
 
 impl SystemBus {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn with_memory(memory: Memory) -> Self {
+        Self {
+            memory,
+            ..Self::default()
+        }
     }
 
     pub fn memory(&self) -> &Memory {
@@ -78,34 +283,382 @@ impl SystemBus {
         &mut self.memory
     }
 
-    pub fn map_mmio_device(&mut self, base: u32, device: Box<dyn BusDevice>) {
-        self.mmio.insert(base, device);
+    pub fn set_violation_policy(&mut self, policy: AccessViolationPolicy) {
+        self.violation_policy = policy;
+    }
+
+    /// Drains the violations reported since the last call.
+    pub fn take_violations(&mut self) -> Vec<AccessViolation> {
+        std::mem::take(&mut self.violations)
+    }
+
+    /// Applies the [`AccessViolationPolicy`] to the result of a memory
+    /// access.
+    fn police<T: Default>(&mut self, result: Result<T>, access: MemoryAccessKind) -> Result<T> {
+        let violation = match result {
+            Err(EmulatorError::ReadOnlyMemory { address }) => {
+                AccessViolation::ReadOnlyWrite { address }
+            }
+            Err(EmulatorError::MemoryOutOfBounds { address }) => {
+                AccessViolation::Unmapped { address, access }
+            }
+            _ => return result,
+        };
+        match self.violation_policy {
+            AccessViolationPolicy::Ignore => Ok(T::default()),
+            AccessViolationPolicy::Log => {
+                self.violations.push(violation);
+                Ok(T::default())
+            }
+            AccessViolationPolicy::Fault => {
+                self.violations.push(violation);
+                result
+            }
+        }
+    }
+
+    /// A memory read of `width` bytes under the [`AccessViolationPolicy`].
+    /// When the policy lets a partly unmapped read through, the mapped bytes
+    /// keep their values and only the rest read as zero.
+    fn read_memory(&mut self, addr: u32, width: AccessWidth) -> Result<u64> {
+        let result = match width {
+            AccessWidth::Byte => self.memory.read_u8_checked(addr).map(u64::from),
+            AccessWidth::Half => self.memory.read_u16_checked(addr).map(u64::from),
+            AccessWidth::Word => self.memory.read_u32_checked(addr).map(u64::from),
+            AccessWidth::Double => self.memory.read_u32_checked(addr).and_then(|lo| {
+                let hi = self.memory.read_u32_checked(addr.wrapping_add(4))?;
+                Ok(u64::from(lo) | (u64::from(hi) << 32))
+            }),
+        };
+        let failed = result.is_err();
+        let value = self.police(result, MemoryAccessKind::Read)?;
+        if !failed {
+            return Ok(value);
+        }
+        Ok((0..width.bytes()).fold(0, |value, i| {
+            let byte = self
+                .memory
+                .read_u8_checked(addr.wrapping_add(i))
+                .unwrap_or(0);
+            value | (u64::from(byte) << (8 * i))
+        }))
+    }
+
+    pub fn memory_hooks_mut(&mut self) -> &mut MemoryHooks {
+        &mut self.hooks
+    }
+
+    /// Routes `size` bytes from `base` to `device`, taking precedence over
+    /// any memory segment underneath.
+    pub fn map_mmio_device(&mut self, base: u32, size: u32, device: Box<dyn BusDevice>) {
+        if size > 0 {
+            let last = base.saturating_add(size - 1);
+            for page in (base >> MMIO_PAGE_SHIFT)..=(last >> MMIO_PAGE_SHIFT) {
+                let word = (page / u64::BITS) as usize;
+                if self.mmio_pages.len() <= word {
+                    self.mmio_pages.resize(word + 1, 0);
+                }
+                self.mmio_pages[word] |= 1 << (page % u64::BITS);
+            }
+        }
+        self.mmio.insert(base, MmioMapping { size, device });
     }
 
-    fn mmio_base(addr: u32) -> u32 {
-        addr & !0xFFF
+    /// Devices see accesses from here on as made by `core`.
+    pub fn set_current_core(&mut self, core: usize) {
+        self.device_context.core = core;
+    }
+
+    /// Drains the interrupts devices have raised since the last call.
+    pub fn take_device_irqs(&mut self) -> Vec<IrqLine> {
+        std::mem::take(&mut self.device_context.irqs)
+    }
+
+    /// Drains the private interrupt lines devices have driven since the
+    /// last call.
+    pub fn take_private_irqs(&mut self) -> Vec<PrivateIrq> {
+        std::mem::take(&mut self.device_context.private_irqs)
+    }
+
+    /// Drains the events devices have scheduled since the last call.
+    pub fn take_device_events(&mut self) -> Vec<DeviceEvent> {
+        std::mem::take(&mut self.device_context.events)
+    }
+
+    /// Delivers a due [`DeviceEvent`] to the device mapped at `base`.
+    pub fn fire_device_event(&mut self, base: u32, token: u32) {
+        if let Some(mapping) = self.mmio.get_mut(&base) {
+            self.device_context.base = base;
+            mapping.device.handle_event(token, &mut self.device_context);
+        }
+    }
+
+    fn is_mmio_page(&self, addr: u32) -> bool {
+        let page = addr >> MMIO_PAGE_SHIFT;
+        self.mmio_pages
+            .get((page / u64::BITS) as usize)
+            .is_some_and(|word| word & (1 << (page % u64::BITS)) != 0)
+    }
+
+    fn device_read(&mut self, addr: u32, width: AccessWidth) -> Option<u64> {
+        if !self.is_mmio_page(addr) {
+            return None;
+        }
+        let (&base, mapping) = self.mmio.range_mut(..=addr).next_back()?;
+        let offset = addr - base;
+        if offset >= mapping.size {
+            return None;
+        }
+        self.device_context.base = base;
+        let value = mapping.device.read(offset, width, &mut self.device_context);
+        Some(value & width.mask())
+    }
+
+    /// Returns false when no device covers `addr`.
+    fn device_write(&mut self, addr: u32, width: AccessWidth, value: u64) -> bool {
+        let last = addr.wrapping_add(width.bytes() - 1);
+        self.monitor.observe_write(addr);
+        self.code_pages.observe_write(addr);
+        self.monitor.observe_write(last);
+        self.code_pages.observe_write(last);
+        if !self.is_mmio_page(addr) {
+            return false;
+        }
+        let Some((&base, mapping)) = self.mmio.range_mut(..=addr).next_back() else {
+            return false;
+        };
+        let offset = addr - base;
+        if offset >= mapping.size {
+            return false;
+        }
+        self.device_context.base = base;
+        mapping.device.write(
+            offset,
+            width,
+            value & width.mask(),
+            &mut self.device_context,
+        );
+        true
     }
 }
 
 impl Bus for SystemBus {
     fn read_u8_checked(&mut self, addr: u32) -> Result<u8> {
-        if let Some(device) = self.mmio.get_mut(&Self::mmio_base(addr)) {
-            let lane = (addr & 3) * 8;
-            return Ok(((device.read_u32(addr & !3) >> lane) & 0xFF) as u8);
-        }
-        self.memory.read_u8_checked(addr)
+        let value = match self.device_read(addr, AccessWidth::Byte) {
+            Some(value) => value,
+            None => self.read_memory(addr, AccessWidth::Byte)?,
+        };
+        Ok(value as u8)
     }
 
     fn write_u8_checked(&mut self, addr: u32, value: u8) -> Result<()> {
-        if let Some(device) = self.mmio.get_mut(&Self::mmio_base(addr)) {
-            let aligned = addr & !3;
-            let shift = (addr & 3) * 8;
-            let mut word = device.read_u32(aligned);
-            word &= !(0xFF << shift);
-            word |= u32::from(value) << shift;
-            device.write_u32(aligned, word);
+        if self.device_write(addr, AccessWidth::Byte, value.into()) {
+            return Ok(());
+        }
+        let result = self.memory.write_u8_checked(addr, value);
+        self.police(result, MemoryAccessKind::Write)
+    }
+
+    fn read_u16_checked(&mut self, addr: u32) -> Result<u16> {
+        let value = match self.device_read(addr, AccessWidth::Half) {
+            Some(value) => value,
+            None => self.read_memory(addr, AccessWidth::Half)?,
+        };
+        Ok(value as u16)
+    }
+
+    fn write_u16_checked(&mut self, addr: u32, value: u16) -> Result<()> {
+        if self.device_write(addr, AccessWidth::Half, value.into()) {
+            return Ok(());
+        }
+        let result = self.memory.write_u16_checked(addr, value);
+        self.police(result, MemoryAccessKind::Write)
+    }
+
+    fn read_u32_checked(&mut self, addr: u32) -> Result<u32> {
+        let value = match self.device_read(addr, AccessWidth::Word) {
+            Some(value) => value,
+            None => self.read_memory(addr, AccessWidth::Word)?,
+        };
+        Ok(value as u32)
+    }
+
+    fn write_u32_checked(&mut self, addr: u32, value: u32) -> Result<()> {
+        if self.device_write(addr, AccessWidth::Word, value.into()) {
+            return Ok(());
+        }
+        let result = self.memory.write_u32_checked(addr, value);
+        self.police(result, MemoryAccessKind::Write)
+    }
+
+    fn read_u64_checked(&mut self, addr: u32) -> Result<u64> {
+        match self.device_read(addr, AccessWidth::Double) {
+            Some(value) => Ok(value),
+            None => self.read_memory(addr, AccessWidth::Double),
+        }
+    }
+
+    fn write_u64_checked(&mut self, addr: u32, value: u64) -> Result<()> {
+        if self.device_write(addr, AccessWidth::Double, value) {
             return Ok(());
         }
-        self.memory.write_u8_checked(addr, value)
+        let result = self
+            .memory
+            .write_u32_checked(addr, value as u32)
+            .and_then(|()| {
+                self.memory
+                    .write_u32_checked(addr.wrapping_add(4), (value >> 32) as u32)
+            });
+        self.police(result, MemoryAccessKind::Write)
+    }
+
+    // Fetches skip devices too, so a lookahead can't trigger read side
+    // effects.
+    fn fetch_u16(&mut self, addr: u32) -> Result<u16> {
+        self.memory.read_u16_checked(addr)
+    }
+
+    fn fetch_u32(&mut self, addr: u32) -> Result<u32> {
+        self.memory.read_u32_checked(addr)
+    }
+
+    fn exclusive_monitor(&mut self) -> Option<&mut GlobalMonitor> {
+        Some(&mut self.monitor)
+    }
+
+    fn code_pages(&mut self) -> Option<&mut CodePageTracker> {
+        Some(&mut self.code_pages)
+    }
+
+    fn memory_hooks_active(&self) -> bool {
+        self.hooks.is_active()
+    }
+
+    fn report_access(&mut self, access: MemoryAccess) {
+        self.hooks.dispatch(access);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::core::memory::{BIOS_START, FCRAM_START, IO_SIZE, IO_START};
+
+    type AccessLog = Rc<RefCell<Vec<(&'static str, u32, AccessWidth, u64)>>>;
+
+    struct Recorder(AccessLog);
+
+    impl BusDevice for Recorder {
+        fn read(&mut self, offset: u32, width: AccessWidth, _ctx: &mut DeviceContext) -> u64 {
+            self.0.borrow_mut().push(("read", offset, width, 0));
+            u64::MAX
+        }
+
+        fn write(&mut self, offset: u32, width: AccessWidth, value: u64, ctx: &mut DeviceContext) {
+            self.0.borrow_mut().push(("write", offset, width, value));
+            ctx.raise_irq(IrqLine::Gpu);
+            ctx.schedule_event(10, offset);
+        }
+    }
+
+    #[test]
+    fn device_accesses_keep_their_width() {
+        let log = AccessLog::default();
+        let mut bus = SystemBus::default();
+        bus.map_mmio_device(0x1EC0_0000, 0x3000, Box::new(Recorder(log.clone())));
+
+        assert_eq!(bus.write_u8_checked(0x1EC0_2001, 0xAB), Ok(()));
+        assert_eq!(bus.read_u16_checked(0x1EC0_0002), Ok(0xFFFF));
+        assert_eq!(bus.read_u64_checked(0x1EC0_1008), Ok(u64::MAX));
+        assert_eq!(
+            *log.borrow(),
+            [
+                ("write", 0x2001, AccessWidth::Byte, 0xAB),
+                ("read", 0x0002, AccessWidth::Half, 0),
+                ("read", 0x1008, AccessWidth::Double, 0),
+            ]
+        );
+        // Past the end of the mapping is plain memory again.
+        assert_eq!(bus.read_u32_checked(0x1EC0_3000), Ok(0));
+
+        assert_eq!(bus.take_device_irqs(), [IrqLine::Gpu]);
+        assert_eq!(
+            bus.take_device_events(),
+            [DeviceEvent {
+                base: 0x1EC0_0000,
+                delay_cycles: 10,
+                token: 0x2001,
+            }]
+        );
+    }
+
+    #[test]
+    fn memory_accesses_compose_little_endian() {
+        let mut bus = SystemBus::default();
+        let base = FCRAM_START + 0x1000;
+        assert_eq!(bus.write_u64_checked(base, 0x8877_6655_4433_2211), Ok(()));
+        assert_eq!(bus.read_u16_checked(base + 2), Ok(0x4433));
+        assert_eq!(bus.read_u32_checked(base + 4), Ok(0x8877_6655));
+        assert_eq!(bus.write_u16_checked(base + 6, 0xBEEF), Ok(()));
+        assert_eq!(bus.read_u64_checked(base), Ok(0xBEEF_6655_4433_2211));
+    }
+
+    #[test]
+    fn violation_policy_decides_what_bad_accesses_do() {
+        let unmapped = IO_START - 1;
+        for policy in [
+            AccessViolationPolicy::Ignore,
+            AccessViolationPolicy::Log,
+            AccessViolationPolicy::Fault,
+        ] {
+            let mut bus = SystemBus::default();
+            bus.set_violation_policy(policy);
+            let write = bus.write_u32_checked(BIOS_START, 1);
+            let read = bus.read_u16_checked(unmapped);
+            if policy == AccessViolationPolicy::Fault {
+                assert!(write.is_err() && read.is_err());
+            } else {
+                assert_eq!((write, read), (Ok(()), Ok(0)));
+            }
+            let expected = if policy == AccessViolationPolicy::Ignore {
+                vec![]
+            } else {
+                vec![
+                    AccessViolation::ReadOnlyWrite {
+                        address: BIOS_START,
+                    },
+                    AccessViolation::Unmapped {
+                        address: unmapped,
+                        access: MemoryAccessKind::Read,
+                    },
+                ]
+            };
+            assert_eq!(bus.take_violations(), expected, "{policy:?}");
+            assert_eq!(bus.memory().read_u32_checked(BIOS_START), Ok(0));
+        }
+    }
+
+    #[test]
+    fn fetches_bypass_the_violation_policy() {
+        let mut bus = SystemBus::default();
+        bus.set_violation_policy(AccessViolationPolicy::Log);
+        let unmapped = IO_START - 4;
+        assert_eq!(bus.read_u32_checked(unmapped), Ok(0));
+        assert!(bus.fetch_u32(unmapped).is_err());
+        assert!(bus.fetch_u16(unmapped).is_err());
+        assert_eq!(bus.take_violations().len(), 1, "only the data read");
+    }
+
+    #[test]
+    fn partly_unmapped_reads_zero_only_the_unmapped_bytes() {
+        let mut bus = SystemBus::default();
+        bus.set_violation_policy(AccessViolationPolicy::Ignore);
+        let edge = IO_START + IO_SIZE as u32 - 4;
+        assert_eq!(bus.write_u32_checked(edge, 0x4433_2211), Ok(()));
+        assert_eq!(bus.read_u32_checked(edge + 2), Ok(0x4433));
+        assert_eq!(bus.read_u64_checked(edge), Ok(0x4433_2211));
     }
 }
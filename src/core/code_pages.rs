@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+/// Granularity at which writes invalidate translated code.
+pub const CODE_PAGE_SIZE: u32 = 0x1000;
+
+pub fn code_page(pa: u32) -> u32 {
+    pa & !(CODE_PAGE_SIZE - 1)
+}
+
+/// Tracks the physical pages each core has translated code from.
+///
+/// A write into a watched page, from a core or from DMA, queues the page for
+/// every watching core and drops the watch; the core re-arms it when it next
+/// translates code there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodePageTracker {
+    watchers: HashMap<u32, u32>,
+    dirty: Vec<Vec<u32>>,
+}
+
+impl CodePageTracker {
+    pub fn watch(&mut self, core: usize, pa: u32) {
+        *self.watchers.entry(code_page(pa)).or_default() |= 1 << core;
+    }
+
+    pub fn observe_write(&mut self, pa: u32) {
+        let page = code_page(pa);
+        let Some(cores) = self.watchers.remove(&page) else {
+            return;
+        };
+        for core in (0..u32::BITS as usize).filter(|core| cores & (1 << core) != 0) {
+            if self.dirty.len() <= core {
+                self.dirty.resize_with(core + 1, Vec::new);
+            }
+            self.dirty[core].push(page);
+        }
+    }
+
+    pub fn take_dirty(&mut self, core: usize) -> Vec<u32> {
+        self.dirty
+            .get_mut(core)
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_report_watched_pages_once_per_core() {
+        let mut tracker = CodePageTracker::default();
+        tracker.watch(0, 0x1_0040);
+        tracker.watch(1, 0x1_0800);
+        tracker.watch(1, 0x2_0000);
+
+        tracker.observe_write(0x1_0FFC);
+        tracker.observe_write(0x1_0004);
+        tracker.observe_write(0x3_0000);
+        assert_eq!(tracker.take_dirty(0), vec![0x1_0000]);
+        assert_eq!(tracker.take_dirty(1), vec![0x1_0000]);
+        assert!(tracker.take_dirty(0).is_empty());
+
+        tracker.observe_write(0x2_0010);
+        assert_eq!(tracker.take_dirty(1), vec![0x2_0000]);
+        assert!(tracker.take_dirty(2).is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+//! Golden trace conformance runner.
+//!
+//! A fixture is a text file describing a program, the state it starts from
+//! and what every step is expected to do:
+//!
+//! ```text
+//! # Anything after '#' is a comment.
+//! code 0x1000 E3A00001 E2800002   # ARM words, or 4-digit Thumb halfwords
+//! mem 0x2000 11223344             # initial data words
+//! reg r1=0x2000 sp=0x3000 cpsr=0x10
+//! step 0x1000 E3A00001 arm r0=0x1
+//! step 0x1004 E2800002 arm r0=0x3 nzcv=0000
+//! ```
+//!
+//! Execution starts at the first `code` address unless `reg pc=` says
+//! otherwise. Each `step` names the fetched PC, opcode and instruction set,
+//! then the state that step changes: `r0`-`r15` (or `sp`, `lr`, `pc`),
+//! `cpsr`, `nzcv` as four binary digits, and `mem[addr]` words. Registers and
+//! CPSR bits not listed must keep their value, and the PC must fall through
+//! to the next instruction unless `pc=` is given.
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use super::cpu::{Arm11Cpu, FLAG_T, FLAGS_MASK, PC_INDEX};
+use super::disasm::disassemble;
+use super::error::EmulatorError;
+use super::memory::Memory;
+
+/// Steps of disassembly shown before a divergence.
+const CONTEXT_STEPS: usize = 4;
+const FIXTURE_EXTENSION: &str = "trace";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureError {
+    pub fixture: String,
+    /// 1-based; 0 when the file couldn't be read.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for FixtureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.fixture, self.line, self.message)
+    }
+}
+
+impl std::error::Error for FixtureError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Value {
+        field: String,
+        expected: u32,
+        actual: u32,
+    },
+    Error(EmulatorError),
+}
+
+/// The first step whose outcome differs from the fixture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub fixture: String,
+    /// 1-based index of the diverging step.
+    pub step: usize,
+    pub pc: u32,
+    pub mismatch: Mismatch,
+    /// Disassembly of the steps leading up to and including this one.
+    pub context: Vec<String>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} diverged at step {} (PC=0x{:08X}): ",
+            self.fixture, self.step, self.pc
+        )?;
+        match &self.mismatch {
+            Mismatch::Value {
+                field,
+                expected,
+                actual,
+            } => write!(f, "{field} expected 0x{expected:08X}, got 0x{actual:08X}")?,
+            Mismatch::Error(err) => write!(f, "{err}")?,
+        }
+        for line in &self.context {
+            write!(f, "\n  {line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Register(usize),
+    Cpsr,
+    Nzcv,
+    Memory(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExpectedStep {
+    line: usize,
+    pc: u32,
+    opcode: u32,
+    thumb: bool,
+    changes: Vec<(Target, u32)>,
+}
+
+impl ExpectedStep {
+    fn change(&self, target: Target) -> Option<u32> {
+        self.changes
+            .iter()
+            .find_map(|&(t, value)| (t == target).then_some(value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFixture {
+    pub name: String,
+    /// Little-endian bytes to place at each address before running.
+    image: Vec<(u32, Vec<u8>)>,
+    initial: Vec<(Target, u32)>,
+    steps: Vec<ExpectedStep>,
+}
+
+impl TraceFixture {
+    pub fn parse(name: &str, text: &str) -> Result<Self, FixtureError> {
+        let mut fixture = Self {
+            name: name.to_string(),
+            image: Vec::new(),
+            initial: Vec::new(),
+            steps: Vec::new(),
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| FixtureError {
+                fixture: name.to_string(),
+                line: line_number,
+                message,
+            };
+            let content = line.split('#').next().unwrap_or_default();
+            let mut fields = content.split_whitespace();
+            let Some(keyword) = fields.next() else {
+                continue;
+            };
+            let fields: Vec<&str> = fields.collect();
+            match keyword {
+                "code" | "mem" => {
+                    let (address, values) = fields
+                        .split_first()
+                        .ok_or_else(|| error(format!("`{keyword}` needs an address")))?;
+                    let address = parse_hex(address).map_err(error)?;
+                    let mut bytes = Vec::new();
+                    for value in values {
+                        let word = parse_hex(value).map_err(error)?;
+                        let halfword = keyword == "code" && hex_digits(value).len() <= 4;
+                        let width = if halfword { 2 } else { 4 };
+                        bytes.extend_from_slice(&word.to_le_bytes()[..width]);
+                    }
+                    fixture.image.push((address, bytes));
+                }
+                "reg" => {
+                    for field in fields {
+                        fixture.initial.push(parse_change(field).map_err(error)?);
+                    }
+                }
+                "step" => {
+                    let [pc, opcode, set, changes @ ..] = &fields[..] else {
+                        return Err(error("`step` needs a PC, opcode and arm/thumb".into()));
+                    };
+                    let thumb = match *set {
+                        "arm" => false,
+                        "thumb" => true,
+                        other => return Err(error(format!("unknown instruction set `{other}`"))),
+                    };
+                    fixture.steps.push(ExpectedStep {
+                        line: line_number,
+                        pc: parse_hex(pc).map_err(error)?,
+                        opcode: parse_hex(opcode).map_err(error)?,
+                        thumb,
+                        changes: changes
+                            .iter()
+                            .map(|field| parse_change(field))
+                            .collect::<Result<_, _>>()
+                            .map_err(error)?,
+                    });
+                }
+                other => return Err(error(format!("unknown directive `{other}`"))),
+            }
+        }
+        Ok(fixture)
+    }
+
+    /// Runs every step on a fresh core, returning how many ran.
+    pub fn run(&self) -> Result<usize, Divergence> {
+        let mut cpu = Arm11Cpu::new();
+        let mut memory = Memory::flat();
+        cpu.enable_instruction_trace(1);
+        for (address, bytes) in &self.image {
+            for (offset, &byte) in bytes.iter().enumerate() {
+                memory.write_u8(address.wrapping_add(offset as u32), byte);
+            }
+        }
+        if let Some(&(address, _)) = self.image.first() {
+            cpu.set_register(PC_INDEX, address);
+        }
+        for &(target, value) in &self.initial {
+            match target {
+                Target::Register(index) => cpu.set_register(index, value),
+                Target::Cpsr => cpu.set_cpsr(value),
+                Target::Nzcv => cpu.set_cpsr((cpu.cpsr() & !FLAGS_MASK) | value),
+                Target::Memory(address) => memory.write_u32(address, value),
+            }
+        }
+
+        let mut context = Vec::new();
+        for (index, expected) in self.steps.iter().enumerate() {
+            let before_regs = *cpu.regs();
+            let before_cpsr = cpu.cpsr();
+            let pc = cpu.pc();
+            let diverge = |mismatch: Mismatch, context: &Vec<String>| Divergence {
+                fixture: format!("{}:{}", self.name, expected.line),
+                step: index + 1,
+                pc,
+                mismatch,
+                context: context.clone(),
+            };
+
+            if let Err(err) = cpu.step(&mut memory) {
+                return Err(diverge(Mismatch::Error(err), &context));
+            }
+            let (opcode, thumb) = cpu
+                .take_last_instruction_trace()
+                .map_or((0, before_cpsr & FLAG_T != 0), |entry| {
+                    (entry.opcode, entry.thumb)
+                });
+            context.push(format!("0x{pc:08X}: {}", disassemble(pc, opcode, thumb)));
+            if context.len() > CONTEXT_STEPS + 1 {
+                context.remove(0);
+            }
+
+            let width = if thumb { 2 } else { 4 };
+            let mut checks = vec![
+                ("fetched pc".to_string(), expected.pc, pc),
+                ("opcode".to_string(), expected.opcode, opcode),
+                (
+                    "thumb".to_string(),
+                    u32::from(expected.thumb),
+                    u32::from(thumb),
+                ),
+            ];
+            for (reg, &before) in before_regs.iter().enumerate() {
+                let unchanged = if reg == PC_INDEX {
+                    pc.wrapping_add(width)
+                } else {
+                    before
+                };
+                let want = expected.change(Target::Register(reg)).unwrap_or(unchanged);
+                checks.push((register_name(reg), want, cpu.regs()[reg]));
+            }
+            let cpsr = cpu.cpsr();
+            let want_cpsr = match (expected.change(Target::Cpsr), expected.change(Target::Nzcv)) {
+                (Some(value), _) => value,
+                (None, Some(flags)) => (before_cpsr & !FLAGS_MASK) | flags,
+                (None, None) => before_cpsr,
+            };
+            checks.push(("cpsr".to_string(), want_cpsr, cpsr));
+            for &(target, value) in &expected.changes {
+                if let Target::Memory(address) = target {
+                    checks.push((
+                        format!("mem[0x{address:08X}]"),
+                        value,
+                        memory.read_u32(address),
+                    ));
+                }
+            }
+
+            if let Some((field, expected, actual)) =
+                checks.into_iter().find(|(_, want, got)| want != got)
+            {
+                let mismatch = Mismatch::Value {
+                    field,
+                    expected,
+                    actual,
+                };
+                return Err(diverge(mismatch, &context));
+            }
+        }
+        Ok(self.steps.len())
+    }
+}
+
+/// Parses every `.trace` file in `dir`, in file name order.
+pub fn load_fixture_dir(dir: impl AsRef<Path>) -> Result<Vec<TraceFixture>, FixtureError> {
+    let dir = dir.as_ref();
+    let io_error = |path: &Path, err: std::io::Error| FixtureError {
+        fixture: path.display().to_string(),
+        line: 0,
+        message: err.to_string(),
+    };
+    let mut paths = fs::read_dir(dir)
+        .map_err(|err| io_error(dir, err))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| io_error(dir, err))?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == FIXTURE_EXTENSION));
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let text = fs::read_to_string(path).map_err(|err| io_error(path, err))?;
+            let name = path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+            TraceFixture::parse(&name, &text)
+        })
+        .collect()
+}
+
+/// `text` without its optional `0x` prefix.
+fn hex_digits(text: &str) -> &str {
+    text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text)
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    u32::from_str_radix(hex_digits(text), 16).map_err(|_| format!("bad hex value `{text}`"))
+}
+
+fn parse_change(field: &str) -> Result<(Target, u32), String> {
+    let (name, value) = field
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, got `{field}`"))?;
+    if name == "nzcv" {
+        let flags = u32::from_str_radix(value, 2)
+            .ok()
+            .filter(|_| value.len() == 4)
+            .ok_or_else(|| format!("nzcv needs four binary digits, got `{value}`"))?;
+        return Ok((Target::Nzcv, flags << 28));
+    }
+    let value = parse_hex(value)?;
+    let target = match name {
+        "cpsr" => Target::Cpsr,
+        "sp" => Target::Register(13),
+        "lr" => Target::Register(14),
+        "pc" => Target::Register(PC_INDEX),
+        _ => {
+            if let Some(address) = name
+                .strip_prefix("mem[")
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                Target::Memory(parse_hex(address)?)
+            } else {
+                let index = name
+                    .strip_prefix('r')
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|&n| n <= PC_INDEX)
+                    .ok_or_else(|| format!("unknown register `{name}`"))?;
+                Target::Register(index)
+            }
+        }
+    };
+    Ok((target, value))
+}
+
+fn register_name(index: usize) -> String {
+    match index {
+        13 => "sp".to_string(),
+        14 => "lr".to_string(),
+        PC_INDEX => "pc".to_string(),
+        _ => format!("r{index}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests_cpu_traces");
+
+    #[test]
+    fn fixture_directory_runs_without_divergence() {
+        let fixtures = load_fixture_dir(FIXTURE_DIR).unwrap_or_else(|e| panic!("{e}"));
+        assert!(fixtures.len() >= 4);
+        for fixture in &fixtures {
+            let steps = fixture.run().unwrap_or_else(|d| panic!("{d}"));
+            assert!(steps > 0, "{} has no steps", fixture.name);
+        }
+    }
+
+    #[test]
+    fn first_divergence_is_reported_with_context() {
+        let text = "\
+code 0x1000 E3A00001 E2800002 E2800003
+step 0x1000 E3A00001 arm r0=1
+step 0x1004 E2800002 arm r0=3
+step 0x1008 E2800003 arm r0=7 # should be 6
+step 0x100C E2800003 arm r0=9
+";
+        let divergence = TraceFixture::parse("sum", text)
+            .map(|fixture| fixture.run())
+            .unwrap_or_else(|e| panic!("{e}"))
+            .expect_err("wrong expectation");
+        assert_eq!(divergence.fixture, "sum:4");
+        assert_eq!(divergence.step, 3);
+        assert_eq!(divergence.pc, 0x1008);
+        assert_eq!(
+            divergence.mismatch,
+            Mismatch::Value {
+                field: "r0".into(),
+                expected: 7,
+                actual: 6,
+            }
+        );
+        assert_eq!(divergence.context.len(), 3);
+        assert!(divergence.context[2].contains("add r0, r0, #3"));
+    }
+
+    #[test]
+    fn prefixed_four_digit_code_values_are_thumb_halfwords() {
+        let text = "\
+code 0x1000 0x2005 0x3001
+reg cpsr=0x30
+step 0x1000 2005 thumb r0=5
+step 0x1002 3001 thumb r0=6
+";
+        let fixture = TraceFixture::parse("prefixed", text).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(fixture.image, [(0x1000, vec![0x05, 0x20, 0x01, 0x30])]);
+        assert_eq!(fixture.run().unwrap_or_else(|d| panic!("{d}")), 2);
+    }
+
+    #[test]
+    fn malformed_lines_name_the_fixture_and_line() {
+        let err = TraceFixture::parse("bad", "code 0x0 E3A00001\nstep 0x0 E3A00001 mips\n")
+            .expect_err("bad instruction set");
+        assert_eq!((err.fixture.as_str(), err.line), ("bad", 2));
+        let err = TraceFixture::parse("bad", "reg r16=1\n").expect_err("bad register");
+        assert!(err.message.contains("r16"));
+    }
+}
@@ -1,12 +1,35 @@
-use super::bus::Bus;
+use super::bus::{AccessWidth, Bus};
 use super::error::EmulatorError;
 use super::error::MemoryAccessKind;
 use super::error::Result;
 use super::irq::IrqLine;
-use super::mmu::Mmu;
+use super::memory_hooks::MemoryAccess;
+use super::mmu::{Mmu, TlbStats};
+
+mod cp15;
+mod cycles;
+mod debug;
+mod exclusive;
+mod media;
+mod multiply;
+mod pmu;
+mod thumb;
+mod translate;
+mod unconditional;
+mod vfp;
+
+use cp15::{Cp15, Cp15Reg, SCTLR_EE};
+use cycles::CycleCounter;
+pub use cycles::CycleModel;
+use debug::DebugUnit;
+pub use debug::{AddressSpace, BreakCondition, DebugEvent, WatchKind, Watchpoint};
+use media::sign_extend;
+use pmu::Pmu;
+use translate::TranslationCache;
+use vfp::{VfpOutcome, VfpState};
 
 const REG_COUNT: usize = 16;
-const PC_INDEX: usize = 15;
+pub(crate) const PC_INDEX: usize = 15;
 const LR_INDEX: usize = 14;
 const SP_INDEX: usize = 13;
 
@@ -14,15 +37,31 @@ const FLAG_N: u32 = 1 << 31;
 const FLAG_Z: u32 = 1 << 30;
 const FLAG_C: u32 = 1 << 29;
 const FLAG_V: u32 = 1 << 28;
+const FLAG_Q: u32 = 1 << 27;
+const FLAG_E: u32 = 1 << 9;
+const FLAG_A: u32 = 1 << 8;
 const FLAG_I: u32 = 1 << 7;
-const FLAG_T: u32 = 1 << 5;
+const FLAG_F: u32 = 1 << 6;
+pub(crate) const FLAG_T: u32 = 1 << 5;
+/// The NZCV condition flags.
+pub(crate) const FLAGS_MASK: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+
+const GE_SHIFT: u32 = 16;
+const GE_MASK: u32 = 0xF << GE_SHIFT;
 
 const MODE_MASK: u32 = 0x1F;
 const MODE_USR: u32 = 0b1_0000;
+const MODE_FIQ: u32 = 0b1_0001;
 const MODE_IRQ: u32 = 0b1_0010;
 const MODE_SVC: u32 = 0b1_0011;
 const MODE_ABT: u32 = 0b1_0111;
 const MODE_UND: u32 = 0b1_1011;
+const MODE_SYS: u32 = 0b1_1111;
+
+/// CPSR bits an unprivileged MSR may write: NZCVQ, GE[3:0] and E.
+const PSR_USER_MASK: u32 = 0xF80F_0200;
+/// Execution state bits (J, T) that MSR never writes.
+const PSR_STATE_MASK: u32 = 0x0100_0020;
 
 const VECTOR_BASE: u32 = 0x0010_0000;
 const VECTOR_UND: u32 = VECTOR_BASE + 0x0000_0004;
@@ -30,11 +69,11 @@ const VECTOR_SWI: u32 = VECTOR_BASE + 0x0000_0008;
 const VECTOR_PABT: u32 = VECTOR_BASE + 0x0000_000C;
 const VECTOR_DABT: u32 = VECTOR_BASE + 0x0000_0010;
 const VECTOR_IRQ: u32 = VECTOR_BASE + 0x0000_0018;
+const VECTOR_FIQ: u32 = VECTOR_BASE + 0x0000_001C;
 
-const CP15_DFSR: usize = 5;
-const CP15_IFSR: usize = 6;
-const CP15_DFAR: usize = 7;
-const CP15_IFAR: usize = 8;
+const FAULT_STATUS_DEBUG: u32 = 0b00010;
+const FSR_DOMAIN_SHIFT: u32 = 4;
+const FSR_WNR_SHIFT: u32 = 11;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuRunState {
@@ -48,15 +87,20 @@ pub enum FaultKind {
     Domain,
     Permission,
     Alignment,
+    /// The bus rejected an access the MMU let through.
+    External,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExceptionKind {
     UndefinedInstruction,
     SoftwareInterrupt,
+    Breakpoint,
+    VfpDisabled,
     PrefetchAbort(FaultKind),
     DataAbort(FaultKind),
     Interrupt(IrqLine),
+    FastInterrupt,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,6 +109,18 @@ pub struct CpuException {
     pub vector: u32,
     pub return_address: u32,
     pub fault_opcode: u32,
+    pub thumb: bool,
+}
+
+impl CpuException {
+    /// The SWI comment field: 8 bits in Thumb state, 24 bits in ARM state.
+    pub fn swi_immediate(&self) -> u32 {
+        if self.thumb {
+            self.fault_opcode & 0xFF
+        } else {
+            self.fault_opcode & 0x00FF_FFFF
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -80,6 +136,187 @@ pub struct MmuFaultDetail {
     pub pa: Option<u32>,
     pub access: MemoryAccessKind,
     pub kind: FaultKind,
+    /// The fault hit a second-level page mapping rather than a section.
+    pub page: bool,
+    pub domain: u8,
+}
+
+/// A decoded data-processing instruction, shared by the interpreter and the
+/// translation cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AluInstr {
+    op: u32,
+    set_flags: bool,
+    rn: usize,
+    rd: usize,
+    operand: Operand2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand2 {
+    /// A rotated immediate; `carry` is `None` when the rotation is zero and
+    /// the shifter carry-out is the current C flag.
+    Immediate { value: u32, carry: Option<bool> },
+    ShiftedRegister {
+        rm: usize,
+        shift_type: u32,
+        shift_imm: u32,
+    },
+}
+
+impl AluInstr {
+    /// Decodes the immediate and immediate-shift forms. `RSC` is not
+    /// implemented and falls through to the remaining decoders.
+    fn decode_arm(opcode: u32) -> Option<Self> {
+        let op = (opcode >> 21) & 0xF;
+        if op == 0x7 {
+            return None;
+        }
+        let operand = if ((opcode >> 25) & 1) == 1 {
+            let imm8 = opcode & 0xFF;
+            let rotate = ((opcode >> 8) & 0xF) * 2;
+            let value = imm8.rotate_right(rotate);
+            Operand2::Immediate {
+                value,
+                carry: (rotate != 0).then_some(value & FLAG_N != 0),
+            }
+        } else {
+            Operand2::ShiftedRegister {
+                rm: (opcode & 0xF) as usize,
+                shift_type: (opcode >> 5) & 0x3,
+                shift_imm: (opcode >> 7) & 0x1F,
+            }
+        };
+        Some(Self {
+            op,
+            set_flags: ((opcode >> 20) & 1) == 1,
+            rn: ((opcode >> 16) & 0xF) as usize,
+            rd: ((opcode >> 12) & 0xF) as usize,
+            operand,
+        })
+    }
+
+    fn is_logical(&self) -> bool {
+        is_logical_op(self.op)
+    }
+
+    /// NZCV bits this instruction overwrites.
+    fn flags_written(&self) -> u32 {
+        if self.set_flags {
+            flags_written_by(self.op)
+        } else {
+            0
+        }
+    }
+
+    /// NZCV bits this instruction reads, through carry-in or the shifter.
+    fn flags_read(&self) -> u32 {
+        let shifter_reads_carry = match self.operand {
+            Operand2::Immediate { carry, .. } => carry.is_none(),
+            Operand2::ShiftedRegister {
+                shift_type,
+                shift_imm,
+                ..
+            } => shift_type == 0 && shift_imm == 0,
+        };
+        if matches!(self.op, 0x5 | 0x6)
+            || (self.set_flags && self.is_logical() && shifter_reads_carry)
+        {
+            FLAG_C
+        } else {
+            0
+        }
+    }
+}
+
+fn is_valid_mode(mode: u32) -> bool {
+    matches!(
+        mode,
+        MODE_USR | MODE_FIQ | MODE_IRQ | MODE_SVC | MODE_ABT | MODE_UND | MODE_SYS
+    )
+}
+
+/// The bus width of a 1, 2 or 4 byte data access.
+fn access_width(size: u32) -> AccessWidth {
+    match size {
+        1 => AccessWidth::Byte,
+        2 => AccessWidth::Half,
+        _ => AccessWidth::Word,
+    }
+}
+
+fn is_logical_op(op: u32) -> bool {
+    matches!(op, 0x0 | 0x1 | 0x8 | 0x9 | 0xC..=0xF)
+}
+
+/// Logical ops leave V alone; arithmetic ops write all of NZCV.
+fn flags_written_by(op: u32) -> u32 {
+    if is_logical_op(op) {
+        FLAG_N | FLAG_Z | FLAG_C
+    } else {
+        FLAG_N | FLAG_Z | FLAG_C | FLAG_V
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlagUpdate {
+    Write,
+    /// The flags are dead; keep the inputs so they can still be recovered if
+    /// an exception captures the CPSR before they are overwritten.
+    Defer,
+}
+
+/// The inputs an ALU op's NZCV depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LazyFlags {
+    op: u32,
+    lhs: u32,
+    rhs: u32,
+    result: u32,
+    shifter_carry: bool,
+    carry_in: bool,
+}
+
+impl LazyFlags {
+    fn written(&self) -> u32 {
+        flags_written_by(self.op)
+    }
+
+    fn resolve(&self, cpsr: u32) -> u32 {
+        let (lhs, rhs, res) = (self.lhs, self.rhs, self.result);
+        let sub_overflow = ((lhs ^ rhs) & (lhs ^ res) & FLAG_N) != 0;
+        let add_overflow = ((!(lhs ^ rhs)) & (lhs ^ res) & FLAG_N) != 0;
+        let c = u32::from(self.carry_in);
+        let (carry, overflow) = match self.op {
+            0x2 | 0xA => (lhs >= rhs, sub_overflow),
+            0x3 => (rhs >= lhs, ((rhs ^ lhs) & (rhs ^ res) & FLAG_N) != 0),
+            0x4 | 0xB => (lhs.overflowing_add(rhs).1, add_overflow),
+            0x5 => {
+                let (tmp, c1) = lhs.overflowing_add(rhs);
+                (c1 || tmp.overflowing_add(c).1, add_overflow)
+            }
+            0x6 => {
+                let (tmp, b1) = lhs.overflowing_sub(rhs);
+                (!(b1 || tmp.overflowing_sub(1 - c).1), sub_overflow)
+            }
+            _ => (self.shifter_carry, cpsr & FLAG_V != 0),
+        };
+
+        let mut flags = 0;
+        if res & FLAG_N != 0 {
+            flags |= FLAG_N;
+        }
+        if res == 0 {
+            flags |= FLAG_Z;
+        }
+        if carry {
+            flags |= FLAG_C;
+        }
+        if overflow {
+            flags |= FLAG_V;
+        }
+        (cpsr & !self.written()) | (flags & self.written())
+    }
 }
 
 #[derive(Clone)]
@@ -90,6 +327,10 @@ pub struct Arm11Cpu {
     spsr_svc: u32,
     spsr_irq: u32,
     spsr_abt: u32,
+    spsr_fiq: u32,
+    /// R8-R12 of every mode but FIQ, while FIQ mode has them swapped out.
+    bank_usr_r8_r12: [u32; 5],
+    bank_fiq_r8_r12: [u32; 5],
     bank_usr_sp: u32,
     bank_usr_lr: u32,
     bank_svc_sp: u32,
@@ -100,15 +341,32 @@ pub struct Arm11Cpu {
     bank_und_lr: u32,
     bank_abt_sp: u32,
     bank_abt_lr: u32,
+    bank_fiq_sp: u32,
+    bank_fiq_lr: u32,
     state: CpuRunState,
     last_exception: Option<CpuException>,
-    cp15_regs: [u32; 16],
+    exception_entered: bool,
+    cp15: Cp15,
     mmu: Mmu,
+    vfp: VfpState,
+    core_id: usize,
+    exclusive_tag: Option<u32>,
+    pending_flags: Option<LazyFlags>,
+    translation: TranslationCache,
     trace_enabled: bool,
     trace_limit: usize,
     trace_log: Vec<InstructionTraceEntry>,
     last_trace_entry: Option<InstructionTraceEntry>,
     last_mmu_fault: Option<MmuFaultDetail>,
+    /// The VA and direction of a data access the bus rejected this step.
+    bus_fault: Option<(u32, MemoryAccessKind)>,
+    debug: DebugUnit,
+    cycles: CycleCounter,
+    pmu: Pmu,
+    /// The instruction being stepped and the address of its latest data
+    /// access, for [`Bus::report_access`].
+    step_pc: u32,
+    data_va: u32,
 }
 
 impl Default for Arm11Cpu {
@@ -126,6 +384,9 @@ impl Arm11Cpu {
             spsr_svc: MODE_USR,
             spsr_irq: MODE_USR,
             spsr_abt: MODE_USR,
+            spsr_fiq: MODE_USR,
+            bank_usr_r8_r12: [0; 5],
+            bank_fiq_r8_r12: [0; 5],
             bank_usr_sp: 0,
             bank_usr_lr: 0,
             bank_svc_sp: 0,
@@ -136,18 +397,45 @@ impl Arm11Cpu {
             bank_und_lr: 0,
             bank_abt_sp: 0,
             bank_abt_lr: 0,
+            bank_fiq_sp: 0,
+            bank_fiq_lr: 0,
             state: CpuRunState::Running,
             last_exception: None,
-            cp15_regs: [0; 16],
+            exception_entered: false,
+            cp15: Cp15::new(),
             mmu: Mmu::new(),
+            vfp: VfpState::new(),
+            core_id: 0,
+            exclusive_tag: None,
+            pending_flags: None,
+            translation: TranslationCache::new(),
             trace_enabled: false,
             trace_limit: 0,
             trace_log: Vec::new(),
             last_trace_entry: None,
             last_mmu_fault: None,
+            bus_fault: None,
+            debug: DebugUnit::default(),
+            cycles: CycleCounter::default(),
+            pmu: Pmu::default(),
+            step_pc: 0,
+            data_va: 0,
         }
     }
 
+    /// A core of an MPCore cluster; `core_id` is what its CP15 CPU ID
+    /// register reports.
+    pub fn with_core_id(core_id: usize) -> Self {
+        Self {
+            core_id,
+            ..Self::new()
+        }
+    }
+
+    pub fn core_id(&self) -> usize {
+        self.core_id
+    }
+
     pub fn reset(&mut self, pc: u32) {
         self.regs = [0; REG_COUNT];
         self.regs[PC_INDEX] = pc;
@@ -156,6 +444,9 @@ impl Arm11Cpu {
         self.spsr_svc = MODE_USR;
         self.spsr_irq = MODE_USR;
         self.spsr_abt = MODE_USR;
+        self.spsr_fiq = MODE_USR;
+        self.bank_usr_r8_r12 = [0; 5];
+        self.bank_fiq_r8_r12 = [0; 5];
         self.bank_usr_sp = 0;
         self.bank_usr_lr = 0;
         self.bank_svc_sp = 0;
@@ -166,13 +457,24 @@ impl Arm11Cpu {
         self.bank_und_lr = 0;
         self.bank_abt_sp = 0;
         self.bank_abt_lr = 0;
+        self.bank_fiq_sp = 0;
+        self.bank_fiq_lr = 0;
         self.state = CpuRunState::Running;
         self.last_exception = None;
-        self.cp15_regs = [0; 16];
+        self.exception_entered = false;
+        self.cp15 = Cp15::new();
         self.mmu.reset();
+        self.vfp = VfpState::new();
+        self.exclusive_tag = None;
+        self.pending_flags = None;
+        self.translation.flush();
         self.trace_log.clear();
         self.last_trace_entry = None;
         self.last_mmu_fault = None;
+        self.bus_fault = None;
+        self.debug.clear_pending();
+        self.set_cycle_model(self.cycles.model);
+        self.pmu = Pmu::default();
     }
 
     pub fn enable_instruction_trace(&mut self, limit: usize) {
@@ -193,6 +495,10 @@ impl Arm11Cpu {
         self.last_mmu_fault.take()
     }
 
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.mmu.tlb_stats()
+    }
+
     pub fn run_state(&self) -> CpuRunState {
         self.state
     }
@@ -206,17 +512,55 @@ impl Arm11Cpu {
     }
 
     pub fn cpsr(&self) -> u32 {
-        self.cpsr
+        match self.pending_flags {
+            Some(pending) => pending.resolve(self.cpsr),
+            None => self.cpsr,
+        }
     }
 
     pub fn last_exception(&self) -> Option<CpuException> {
         self.last_exception
     }
 
+    /// Whether the most recent `step` entered an exception vector.
+    pub fn exception_entered(&self) -> bool {
+        self.exception_entered
+    }
+
     pub fn interrupts_enabled(&self) -> bool {
         self.cpsr & FLAG_I == 0
     }
 
+    pub fn fast_interrupts_enabled(&self) -> bool {
+        self.cpsr & FLAG_F == 0
+    }
+
+    /// Parks the core as if it had executed `WFI`; an interrupt or
+    /// [`Self::start_thread`] resumes it.
+    pub fn park(&mut self) {
+        self.state = CpuRunState::Halted;
+    }
+
+    /// Resumes a parked core at a thread entry point, with the argument in
+    /// R0 and SP at the top of the thread's stack.
+    pub fn start_thread(&mut self, entry: u32, argument: u32, stack_top: u32) {
+        self.settle_flags();
+        self.translation.leave_block();
+        self.state = CpuRunState::Running;
+        self.regs[0] = argument;
+        self.regs[SP_INDEX] = stack_top;
+        self.set_thumb_state(entry & 1 != 0);
+        self.regs[PC_INDEX] = entry & !1;
+    }
+
+    pub fn enter_fiq(&mut self) {
+        if self.state == CpuRunState::Halted {
+            self.state = CpuRunState::Running;
+        }
+        let pc = self.pc();
+        self.take_exception(ExceptionKind::FastInterrupt, pc, 0, true);
+    }
+
     pub fn enter_irq(&mut self, line: IrqLine) {
         if self.state == CpuRunState::Halted {
             self.state = CpuRunState::Running;
@@ -228,10 +572,37 @@ impl Arm11Cpu {
     pub fn step(&mut self, memory: &mut dyn Bus) -> Result<u32> {
         self.last_trace_entry = None;
         self.last_mmu_fault = None;
+        self.bus_fault = None;
+        self.exception_entered = false;
         if self.state == CpuRunState::Halted {
             return Ok(1);
         }
+        if self.breakpoint_hit(self.pc()) {
+            return Ok(0);
+        }
 
+        let pc = self.pc();
+        self.step_pc = pc;
+        self.cycles.begin_step();
+        let cycles = match self.cycles.model {
+            CycleModel::Simple => self.execute_next(memory)?,
+            CycleModel::Arm11 => {
+                let regs = self.regs;
+                let simple = self.execute_next(memory)?;
+                self.arm11_cycles(&regs, pc, simple)
+            }
+        };
+        if self.pmu.enabled() {
+            self.count_pmu_events(pc, cycles);
+        }
+        Ok(cycles)
+    }
+
+    fn execute_next(&mut self, memory: &mut dyn Bus) -> Result<u32> {
+        if let Some(cycles) = self.step_translated(memory) {
+            return cycles;
+        }
+        self.settle_flags();
         if self.is_thumb() {
             self.step_thumb(memory)
         } else {
@@ -241,18 +612,31 @@ impl Arm11Cpu {
 
     fn step_arm(&mut self, memory: &mut dyn Bus) -> Result<u32> {
         let pc = self.pc();
-        let opcode = match self.fetch_instruction(memory, pc) {
-            Ok(op) => op,
+        match self.fetch_instruction(memory, pc) {
+            Ok(opcode) => self.execute_arm(memory, pc, opcode),
             Err(kind) => {
                 self.take_prefetch_abort(kind, pc, 0);
-                return Ok(3);
+                Ok(3)
             }
-        };
+        }
+    }
 
+    fn execute_arm(&mut self, memory: &mut dyn Bus, pc: u32, opcode: u32) -> Result<u32> {
         self.record_trace(pc, opcode, false);
         self.regs[PC_INDEX] = pc.wrapping_add(4);
 
+        if opcode >> 28 == 0xF {
+            return match self.exec_unconditional(opcode, pc, memory) {
+                Ok(cycles) => Ok(cycles),
+                Err(kind) => {
+                    self.take_data_abort(kind, pc, opcode);
+                    Ok(3)
+                }
+            };
+        }
+
         if !self.condition_passed(opcode >> 28) {
+            self.cycles.skipped = true;
             return Ok(1);
         }
 
@@ -270,6 +654,24 @@ impl Arm11Cpu {
             return Ok(1);
         }
 
+        if let Some(outcome) = self.exec_vfp(opcode, memory) {
+            return Ok(match outcome {
+                VfpOutcome::Executed(cycles) => cycles,
+                VfpOutcome::Disabled => {
+                    self.take_exception(ExceptionKind::VfpDisabled, pc, opcode, true);
+                    3
+                }
+                VfpOutcome::Undefined => {
+                    self.take_exception(ExceptionKind::UndefinedInstruction, pc, opcode, true);
+                    3
+                }
+                VfpOutcome::Abort(kind) => {
+                    self.take_data_abort(kind, pc, opcode);
+                    3
+                }
+            });
+        }
+
         if self.exec_coprocessor(opcode, memory) {
             return Ok(2);
         }
@@ -290,6 +692,17 @@ impl Arm11Cpu {
             };
         }
 
+        if (opcode >> 25) & 0x7 == 0b011 && (opcode & 0x10) != 0 {
+            if let Some(cycles) = self.exec_media_multiply(opcode) {
+                return Ok(cycles);
+            }
+            if self.exec_media(opcode) {
+                return Ok(1);
+            }
+            self.take_exception(ExceptionKind::UndefinedInstruction, pc, opcode, true);
+            return Ok(3);
+        }
+
         if (opcode >> 26) & 0x3 == 0b01 {
             return match self.exec_single_data_transfer(opcode, memory) {
                 Ok(_) => Ok(3),
@@ -300,10 +713,7 @@ impl Arm11Cpu {
             };
         }
 
-        if (opcode >> 25) & 0x7 == 0b000
-            && (opcode & 0x90) == 0x90
-            && (opcode & 0x0FB0_0FF0) != 0x0100_0090
-        {
+        if (opcode >> 25) & 0x7 == 0b000 && (opcode & 0x90) == 0x90 && (opcode & 0x60) != 0 {
             return match self.exec_halfword_data_transfer(opcode, memory) {
                 Ok(true) => Ok(3),
                 Ok(false) => Ok(1),
@@ -315,14 +725,22 @@ impl Arm11Cpu {
         }
 
         if (opcode >> 26) & 0x3 == 0b00 {
-            if self.exec_data_processing(opcode) {
-                return Ok(1);
+            match self.exec_exclusive(opcode, memory) {
+                Ok(true) => return Ok(3),
+                Ok(false) => {}
+                Err(kind) => {
+                    self.take_data_abort(kind, pc, opcode);
+                    return Ok(3);
+                }
+            }
+            if let Some(cycles) = self.exec_multiply(opcode) {
+                return Ok(cycles);
             }
             if self.exec_bx(opcode) {
                 return Ok(2);
             }
-            if self.exec_multiply(opcode) {
-                return Ok(2);
+            if self.exec_data_processing(opcode) {
+                return Ok(1);
             }
             match self.exec_swap(opcode, memory) {
                 Ok(true) => return Ok(3),
@@ -338,45 +756,8 @@ impl Arm11Cpu {
         Ok(3)
     }
 
-    fn step_thumb(&mut self, memory: &mut dyn Bus) -> Result<u32> {
-        let pc = self.pc();
-        let opcode = match self.fetch_thumb_instruction(memory, pc) {
-            Ok(op) => op,
-            Err(kind) => {
-                self.take_prefetch_abort(kind, pc, 0);
-                return Ok(3);
-            }
-        };
-
-        self.record_trace(pc, u32::from(opcode), true);
-        self.regs[PC_INDEX] = pc.wrapping_add(2);
-
-        if self.exec_thumb_shift_imm(opcode)
-            || self.exec_thumb_add_sub(opcode)
-            || self.exec_thumb_mov_cmp_add_sub_imm(opcode)
-            || self.exec_thumb_hi_reg_bx(opcode)
-            || self.exec_thumb_cond_branch(opcode)
-            || self.exec_thumb_uncond_branch(opcode)
-        {
-            return Ok(1);
-        }
-
-        if self.exec_thumb_ldr_literal(opcode, memory, pc)
-            || self.exec_thumb_load_store_imm(opcode, memory, pc)
-        {
-            return Ok(1);
-        }
-
-        self.take_exception(
-            ExceptionKind::UndefinedInstruction,
-            pc,
-            u32::from(opcode),
-            true,
-        );
-        Ok(3)
-    }
-
     fn record_trace(&mut self, pc: u32, opcode: u32, thumb: bool) {
+        self.cycles.retired = Some((opcode, thumb));
         if !self.trace_enabled {
             return;
         }
@@ -398,9 +779,7 @@ impl Arm11Cpu {
             return Err(FaultKind::Alignment);
         }
         let pa = self.translate_instruction_va(memory, va)?;
-        memory
-            .read_u32_checked(pa)
-            .map_err(|_| FaultKind::Translation)
+        memory.fetch_u32(pa).map_err(|_| FaultKind::Translation)
     }
 
     fn fetch_thumb_instruction(
@@ -412,13 +791,7 @@ impl Arm11Cpu {
             return Err(FaultKind::Alignment);
         }
         let pa = self.translate_instruction_va(memory, va)?;
-        let lo = memory
-            .read_u8_checked(pa)
-            .map_err(|_| FaultKind::Translation)?;
-        let hi = memory
-            .read_u8_checked(pa.wrapping_add(1))
-            .map_err(|_| FaultKind::Translation)?;
-        Ok(u16::from_le_bytes([lo, hi]))
+        memory.fetch_u16(pa).map_err(|_| FaultKind::Translation)
     }
 
     fn translate_instruction_va(
@@ -428,22 +801,15 @@ impl Arm11Cpu {
     ) -> std::result::Result<u32, FaultKind> {
         self.mmu
             .translate_instruction(memory, va, self.is_privileged())
-            .map_err(|err| {
-                let kind = Self::fault_kind_from_error(err);
-                self.last_mmu_fault = Some(MmuFaultDetail {
-                    va,
-                    pa: None,
-                    access: MemoryAccessKind::Execute,
-                    kind,
-                });
-                kind
-            })
+            .map_err(|err| self.record_mmu_fault(err, va, MemoryAccessKind::Execute))
     }
 
+    /// Translates the first byte of a `size`-byte data access.
     fn translate_va(
         &mut self,
         memory: &mut dyn Bus,
         va: u32,
+        size: u32,
         access: MemoryAccessKind,
     ) -> std::result::Result<u32, FaultKind> {
         let translated = match access {
@@ -454,16 +820,30 @@ impl Arm11Cpu {
                     .translate_instruction(memory, va, self.is_privileged())
             }
         };
-        translated.map_err(|err| {
-            let kind = Self::fault_kind_from_error(err);
-            self.last_mmu_fault = Some(MmuFaultDetail {
-                va,
-                pa: None,
-                access,
-                kind,
-            });
-            kind
-        })
+        if let Ok(pa) = translated {
+            self.check_watchpoints(va, pa, size, access);
+            self.data_va = va;
+        }
+        translated.map_err(|err| self.record_mmu_fault(err, va, access))
+    }
+
+    fn record_mmu_fault(
+        &mut self,
+        err: EmulatorError,
+        va: u32,
+        access: MemoryAccessKind,
+    ) -> FaultKind {
+        let kind = Self::fault_kind_from_error(err);
+        let location = self.mmu.last_fault();
+        self.last_mmu_fault = Some(MmuFaultDetail {
+            va,
+            pa: None,
+            access,
+            kind,
+            page: location.is_some_and(|l| l.page),
+            domain: location.map_or(0, |l| l.domain),
+        });
+        kind
     }
 
     fn fault_kind_from_error(err: EmulatorError) -> FaultKind {
@@ -476,6 +856,13 @@ impl Arm11Cpu {
         }
     }
 
+    /// A bus error behind a successful translation is a precise external
+    /// abort on the VA that was just translated.
+    fn record_bus_fault(&mut self, access: MemoryAccessKind) -> FaultKind {
+        self.bus_fault = Some((self.data_va, access));
+        FaultKind::External
+    }
+
     fn is_privileged(&self) -> bool {
         self.mode() != MODE_USR
     }
@@ -485,10 +872,6 @@ impl Arm11Cpu {
     }
 
     fn exec_system(&mut self, opcode: u32) -> bool {
-        if opcode == 0xF57F_F05F || opcode == 0xF57F_F04F {
-            return true;
-        }
-
         if opcode & 0x0FF0_00F0 == 0x0160_0010 {
             let rd = ((opcode >> 12) & 0xF) as usize;
             let rm = (opcode & 0xF) as usize;
@@ -507,65 +890,61 @@ impl Arm11Cpu {
             return true;
         }
 
-        if opcode & 0x0FB0_FFF0 == 0x0120_F000 {
-            let rm = (opcode & 0xF) as usize;
-            let value = self.regs[rm];
-            self.cpsr =
-                (self.cpsr & !0xF000_0000) | (value & 0xF000_0000) | (self.cpsr & MODE_MASK);
-            if self.is_privileged() {
-                let new_mode = value & MODE_MASK;
-                if matches!(
-                    new_mode,
-                    MODE_USR | MODE_SVC | MODE_IRQ | MODE_UND | MODE_ABT
-                ) {
-                    self.switch_mode(new_mode);
-                }
-                self.set_thumb_state((value & FLAG_T) != 0);
-                self.set_flag(FLAG_I, (value & FLAG_I) != 0);
-            }
+        // MSR, register and immediate forms. An immediate MSR with no fields
+        // selected is a hint (NOP, YIELD, WFE, SEV).
+        let fields = (opcode >> 16) & 0xF;
+        let msr_immediate = opcode & 0x0FB0_F000 == 0x0320_F000 && fields != 0;
+        if opcode & 0x0FB0_FFF0 == 0x0120_F000 || msr_immediate {
+            let value = if msr_immediate {
+                (opcode & 0xFF).rotate_right(((opcode >> 8) & 0xF) * 2)
+            } else {
+                self.regs[(opcode & 0xF) as usize]
+            };
+            let field_mask = (0..4)
+                .filter(|field| (fields >> field) & 1 == 1)
+                .fold(0, |mask, field| mask | (0xFF << (field * 8)));
+            self.write_psr(((opcode >> 22) & 1) == 1, value, field_mask);
             return true;
         }
 
         false
     }
 
+    /// Writes the `_c`, `_x`, `_s` and `_f` byte fields selected by
+    /// `field_mask` into the CPSR or the current mode's SPSR.
+    fn write_psr(&mut self, spsr: bool, value: u32, field_mask: u32) {
+        if spsr {
+            if let Some(current) = self.current_spsr() {
+                self.set_current_spsr((current & !field_mask) | (value & field_mask));
+            }
+            return;
+        }
+
+        let writable = if self.is_privileged() {
+            field_mask & !PSR_STATE_MASK
+        } else {
+            field_mask & PSR_USER_MASK
+        };
+        let updated = (self.cpsr & !writable) | (value & writable);
+        let new_mode = updated & MODE_MASK;
+        if writable & MODE_MASK != 0 && is_valid_mode(new_mode) {
+            self.switch_mode(new_mode);
+        }
+        self.cpsr = (updated & !MODE_MASK) | self.mode();
+    }
+
     fn exec_coprocessor(&mut self, opcode: u32, _memory: &mut dyn Bus) -> bool {
         if (opcode & 0x0F00_0010) == 0x0E00_0010 {
             let cp_num = (opcode >> 8) & 0xF;
             let rd = ((opcode >> 12) & 0xF) as usize;
-            let crn = (opcode >> 16) & 0xF;
-            let crm = opcode & 0xF;
-            let opc2 = (opcode >> 5) & 0x7;
             let is_mrc = ((opcode >> 20) & 1) == 1;
 
             if cp_num == 15 {
-                let idx = usize::try_from(crm & 0xF).unwrap_or(0);
+                let reg = Cp15Reg::from_opcode(opcode);
                 if is_mrc {
-                    self.regs[rd] = match (crn, crm, opc2) {
-                        (1, 0, 0) => self.cp15_regs[1],
-                        (2, 0, 0) => self.cp15_regs[2],
-                        (3, 0, 0) => self.cp15_regs[3],
-                        _ => self.cp15_regs[idx],
-                    };
+                    self.exec_mrc_cp15(reg, rd);
                 } else {
-                    let value = self.regs[rd];
-                    self.cp15_regs[idx] = value;
-                    match (crn, crm, opc2) {
-                        (1, 0, 0) => {
-                            self.cp15_regs[1] = value;
-                            self.mmu.write_control(value);
-                        }
-                        (2, 0, 0) => {
-                            self.cp15_regs[2] = value;
-                            self.mmu.write_ttbr0(value);
-                        }
-                        (3, 0, 0) => {
-                            self.cp15_regs[3] = value;
-                            self.mmu.write_dacr(value);
-                        }
-                        (8, 7, 0) | (8, 5, 0) | (8, 6, 0) => self.mmu.invalidate_tlb(),
-                        _ => {}
-                    }
+                    self.exec_mcr_cp15(reg, self.regs[rd]);
                 }
                 return true;
             }
@@ -573,28 +952,6 @@ impl Arm11Cpu {
         false
     }
 
-    fn exec_multiply(&mut self, opcode: u32) -> bool {
-        if opcode & 0x0FC0_00F0 == 0x0000_0090 {
-            let accumulate = ((opcode >> 21) & 1) == 1;
-            let set_flags = ((opcode >> 20) & 1) == 1;
-            let rd = ((opcode >> 16) & 0xF) as usize;
-            let rn = ((opcode >> 12) & 0xF) as usize;
-            let rs = ((opcode >> 8) & 0xF) as usize;
-            let rm = (opcode & 0xF) as usize;
-
-            let mut result = self.regs[rm].wrapping_mul(self.regs[rs]);
-            if accumulate {
-                result = result.wrapping_add(self.regs[rn]);
-            }
-            self.regs[rd] = result;
-            if set_flags {
-                self.update_nzcv_logical(result, self.cpsr & FLAG_C != 0);
-            }
-            return true;
-        }
-        false
-    }
-
     fn exec_branch(&mut self, opcode: u32, pc: u32) {
         let link = ((opcode >> 24) & 1) == 1;
         let mut offset = ((opcode & 0x00FF_FFFF) << 2) as i32;
@@ -638,35 +995,17 @@ impl Arm11Cpu {
         };
 
         let address = if pre_index { effective } else { base };
-        let is_byte = ((opcode >> 22) & 1) == 1;
-        if !is_byte && address & 3 != 0 {
+        let size = if ((opcode >> 22) & 1) == 1 { 1 } else { 4 };
+        if address & (size - 1) != 0 {
             return Err(FaultKind::Alignment);
         }
 
         if load {
-            let pa = self.translate_va(memory, address, MemoryAccessKind::Read)?;
-            self.regs[rd] = if is_byte {
-                u32::from(
-                    memory
-                        .read_u8_checked(pa)
-                        .map_err(|_| FaultKind::Translation)?,
-                )
-            } else {
-                memory
-                    .read_u32_checked(pa)
-                    .map_err(|_| FaultKind::Translation)?
-            };
+            let pa = self.translate_va(memory, address, size, MemoryAccessKind::Read)?;
+            self.regs[rd] = self.read_physical(memory, pa, size)?;
         } else {
-            let pa = self.translate_va(memory, address, MemoryAccessKind::Write)?;
-            if is_byte {
-                memory
-                    .write_u8_checked(pa, (self.regs[rd] & 0xFF) as u8)
-                    .map_err(|_| FaultKind::Translation)?;
-            } else {
-                memory
-                    .write_u32_checked(pa, self.regs[rd])
-                    .map_err(|_| FaultKind::Translation)?;
-            }
+            let pa = self.translate_va(memory, address, size, MemoryAccessKind::Write)?;
+            self.write_physical(memory, pa, size, self.regs[rd])?;
         }
 
         if write_back || !pre_index {
@@ -711,42 +1050,20 @@ impl Arm11Cpu {
         }
 
         if load {
-            let pa = self.translate_va(memory, address, MemoryAccessKind::Read)?;
-            self.regs[rd] = if halfword {
-                let lo = memory
-                    .read_u8_checked(pa)
-                    .map_err(|_| FaultKind::Translation)?;
-                let hi = memory
-                    .read_u8_checked(pa.wrapping_add(1))
-                    .map_err(|_| FaultKind::Translation)?;
-                let v = u16::from_le_bytes([lo, hi]);
-                if signed {
-                    i16::from_le_bytes(v.to_le_bytes()) as i32 as u32
-                } else {
-                    u32::from(v)
-                }
+            let size = if halfword { 2 } else { 1 };
+            let pa = self.translate_va(memory, address, size, MemoryAccessKind::Read)?;
+            let value = self.read_physical(memory, pa, size)?;
+            self.regs[rd] = if signed {
+                sign_extend(value, size * 8) as u32
             } else {
-                let v = memory
-                    .read_u8_checked(pa)
-                    .map_err(|_| FaultKind::Translation)?;
-                if signed {
-                    i8::from_le_bytes([v]) as i32 as u32
-                } else {
-                    u32::from(v)
-                }
+                value
             };
         } else {
             if signed || !halfword {
                 return Ok(false);
             }
-            let pa = self.translate_va(memory, address, MemoryAccessKind::Write)?;
-            let bytes = (self.regs[rd] as u16).to_le_bytes();
-            memory
-                .write_u8_checked(pa, bytes[0])
-                .map_err(|_| FaultKind::Translation)?;
-            memory
-                .write_u8_checked(pa.wrapping_add(1), bytes[1])
-                .map_err(|_| FaultKind::Translation)?;
+            let pa = self.translate_va(memory, address, 2, MemoryAccessKind::Write)?;
+            self.write_physical(memory, pa, 2, self.regs[rd])?;
         }
 
         if write_back || !pre_index {
@@ -763,37 +1080,21 @@ impl Arm11Cpu {
         if opcode & 0x0FB0_0FF0 != 0x0100_0090 {
             return Ok(false);
         }
-        let is_byte = ((opcode >> 22) & 1) != 0;
+        let size = if ((opcode >> 22) & 1) != 0 { 1 } else { 4 };
         let rn = ((opcode >> 16) & 0xF) as usize;
         let rd = ((opcode >> 12) & 0xF) as usize;
         let rm = (opcode & 0xF) as usize;
         let addr = self.regs[rn];
 
-        if !is_byte && (addr & 3) != 0 {
+        if (addr & (size - 1)) != 0 {
             return Err(FaultKind::Alignment);
         }
 
-        if is_byte {
-            let pa = self.translate_va(memory, addr, MemoryAccessKind::Read)?;
-            let old = memory
-                .read_u8_checked(pa)
-                .map_err(|_| FaultKind::Translation)?;
-            let pa = self.translate_va(memory, addr, MemoryAccessKind::Write)?;
-            memory
-                .write_u8_checked(pa, (self.regs[rm] & 0xFF) as u8)
-                .map_err(|_| FaultKind::Translation)?;
-            self.regs[rd] = u32::from(old);
-        } else {
-            let pa = self.translate_va(memory, addr, MemoryAccessKind::Read)?;
-            let old = memory
-                .read_u32_checked(pa)
-                .map_err(|_| FaultKind::Translation)?;
-            let pa = self.translate_va(memory, addr, MemoryAccessKind::Write)?;
-            memory
-                .write_u32_checked(pa, self.regs[rm])
-                .map_err(|_| FaultKind::Translation)?;
-            self.regs[rd] = old;
-        }
+        let pa = self.translate_va(memory, addr, size, MemoryAccessKind::Read)?;
+        let old = self.read_physical(memory, pa, size)?;
+        let pa = self.translate_va(memory, addr, size, MemoryAccessKind::Write)?;
+        self.write_physical(memory, pa, size, self.regs[rm])?;
+        self.regs[rd] = old;
 
         Ok(true)
     }
@@ -820,7 +1121,7 @@ impl Arm11Cpu {
         let mut addr = match (add, pre_index) {
             (true, true) => base.wrapping_add(4),
             (true, false) => base,
-            (false, true) => base.wrapping_sub(4),
+            (false, true) => base.wrapping_sub(count.wrapping_mul(4)),
             (false, false) => base.wrapping_sub((count.wrapping_sub(1)).wrapping_mul(4)),
         };
 
@@ -833,21 +1134,16 @@ impl Arm11Cpu {
             }
 
             if load {
-                let pa = self.translate_va(memory, addr, MemoryAccessKind::Read)?;
-                let value = memory
-                    .read_u32_checked(pa)
-                    .map_err(|_| FaultKind::Translation)?;
-                self.regs[reg as usize] = value;
+                let pa = self.translate_va(memory, addr, 4, MemoryAccessKind::Read)?;
+                self.regs[reg as usize] = self.read_physical(memory, pa, 4)?;
             } else {
-                let pa = self.translate_va(memory, addr, MemoryAccessKind::Write)?;
+                let pa = self.translate_va(memory, addr, 4, MemoryAccessKind::Write)?;
                 let value = if reg as usize == PC_INDEX {
                     self.regs[PC_INDEX].wrapping_add(4)
                 } else {
                     self.regs[reg as usize]
                 };
-                memory
-                    .write_u32_checked(pa, value)
-                    .map_err(|_| FaultKind::Translation)?;
+                self.write_physical(memory, pa, 4, value)?;
             }
             addr = addr.wrapping_add(4);
         }
@@ -870,121 +1166,77 @@ impl Arm11Cpu {
     }
 
     fn exec_data_processing(&mut self, opcode: u32) -> bool {
-        let immediate = ((opcode >> 25) & 1) == 1;
-        let op = (opcode >> 21) & 0xF;
-        let set_flags = ((opcode >> 20) & 1) == 1;
-        let rn = ((opcode >> 16) & 0xF) as usize;
-        let rd = ((opcode >> 12) & 0xF) as usize;
-
-        let (operand2, shifter_carry) = if immediate {
-            let imm8 = opcode & 0xFF;
-            let rotate = ((opcode >> 8) & 0xF) * 2;
-            let value = imm8.rotate_right(rotate);
-            let carry = if rotate == 0 {
-                self.cpsr & FLAG_C != 0
-            } else {
-                value & FLAG_N != 0
-            };
-            (value, carry)
-        } else {
-            let rm = (opcode & 0xF) as usize;
-            let shift_imm = (opcode >> 7) & 0x1F;
-            let shift_type = (opcode >> 5) & 0x3;
-            self.shift_value(self.regs[rm], shift_imm, shift_type)
+        let Some(instr) = AluInstr::decode_arm(opcode) else {
+            return false;
         };
+        self.exec_alu(instr, FlagUpdate::Write);
+        true
+    }
 
-        let lhs = self.regs[rn];
-        let mut write_result = None;
-
-        match op {
-            0x0 => write_result = Some(lhs & operand2),
-            0x1 => write_result = Some(lhs ^ operand2),
-            0x2 => {
-                let (res, borrow) = lhs.overflowing_sub(operand2);
-                write_result = Some(res);
-                if set_flags {
-                    let overflow = ((lhs ^ operand2) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, !borrow, overflow);
-                }
+    fn exec_alu(&mut self, instr: AluInstr, update: FlagUpdate) {
+        let (operand2, shifter_carry) = match instr.operand {
+            Operand2::Immediate { value, carry } => {
+                (value, carry.unwrap_or(self.cpsr & FLAG_C != 0))
             }
-            0x3 => {
-                let (res, borrow) = operand2.overflowing_sub(lhs);
-                write_result = Some(res);
-                if set_flags {
-                    let overflow = ((operand2 ^ lhs) & (operand2 ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, !borrow, overflow);
-                }
-            }
-            0x4 => {
-                let (res, carry) = lhs.overflowing_add(operand2);
-                write_result = Some(res);
-                if set_flags {
-                    let overflow = ((!(lhs ^ operand2)) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, carry, overflow);
-                }
-            }
-            0x5 => {
-                let c = if self.cpsr & FLAG_C != 0 { 1 } else { 0 };
-                let (tmp, c1) = lhs.overflowing_add(operand2);
-                let (res, c2) = tmp.overflowing_add(c);
-                write_result = Some(res);
-                if set_flags {
-                    let overflow = ((!(lhs ^ operand2)) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, c1 || c2, overflow);
-                }
-            }
-            0x6 => {
-                let c = if self.cpsr & FLAG_C != 0 { 1 } else { 0 };
-                let (tmp, b1) = lhs.overflowing_sub(operand2);
-                let (res, b2) = tmp.overflowing_sub(1 - c);
-                write_result = Some(res);
-                if set_flags {
-                    let overflow = ((lhs ^ operand2) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, !(b1 || b2), overflow);
-                }
-            }
-            0x8 => {
-                if set_flags {
-                    self.update_nzcv_logical(lhs & operand2, shifter_carry);
-                }
-            }
-            0x9 => {
-                if set_flags {
-                    self.update_nzcv_logical(lhs ^ operand2, shifter_carry);
-                }
-            }
-            0xA => {
-                let (res, borrow) = lhs.overflowing_sub(operand2);
-                if set_flags {
-                    let overflow = ((lhs ^ operand2) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, !borrow, overflow);
-                }
-            }
-            0xB => {
-                let (res, carry) = lhs.overflowing_add(operand2);
-                if set_flags {
-                    let overflow = ((!(lhs ^ operand2)) & (lhs ^ res) & FLAG_N) != 0;
-                    self.update_nzcv_arithmetic(res, carry, overflow);
-                }
-            }
-            0xC => write_result = Some(lhs | operand2),
-            0xD => write_result = Some(operand2),
-            0xE => write_result = Some(lhs & !operand2),
-            0xF => write_result = Some(!operand2),
-            _ => return false,
+            Operand2::ShiftedRegister {
+                rm,
+                shift_type,
+                shift_imm,
+            } => self.shift_value(self.regs[rm], shift_imm, shift_type),
+        };
+
+        let lhs = self.regs[instr.rn];
+        let carry_in = self.cpsr & FLAG_C != 0;
+        let c = u32::from(carry_in);
+        let result = match instr.op {
+            0x0 | 0x8 => lhs & operand2,
+            0x1 | 0x9 => lhs ^ operand2,
+            0x2 | 0xA => lhs.wrapping_sub(operand2),
+            0x3 => operand2.wrapping_sub(lhs),
+            0x4 | 0xB => lhs.wrapping_add(operand2),
+            0x5 => lhs.wrapping_add(operand2).wrapping_add(c),
+            0x6 => lhs.wrapping_sub(operand2).wrapping_sub(1 - c),
+            0xC => lhs | operand2,
+            0xD => operand2,
+            0xE => lhs & !operand2,
+            _ => !operand2,
+        };
+        let writes_result = !matches!(instr.op, 0x8..=0xB);
+        if writes_result {
+            self.regs[instr.rd] = result;
+        }
+        if !instr.set_flags {
+            return;
         }
 
-        if let Some(result) = write_result {
-            self.regs[rd] = result;
-            if matches!(op, 0x0 | 0x1 | 0xC | 0xD | 0xE | 0xF) && set_flags {
-                self.update_nzcv_logical(result, shifter_carry);
-            }
-            if set_flags && rd == PC_INDEX {
-                self.restore_cpsr_from_spsr();
-            }
+        let flags = LazyFlags {
+            op: instr.op,
+            lhs,
+            rhs: operand2,
+            result,
+            shifter_carry,
+            carry_in,
+        };
+        if let Some(pending) = self.pending_flags.take()
+            && flags.written() & pending.written() != pending.written()
+        {
+            self.cpsr = pending.resolve(self.cpsr);
         }
+        match update {
+            FlagUpdate::Write => self.cpsr = flags.resolve(self.cpsr),
+            FlagUpdate::Defer => self.pending_flags = Some(flags),
+        }
+        if writes_result && instr.rd == PC_INDEX {
+            self.restore_cpsr_from_spsr();
+        }
+    }
 
-        true
+    /// Applies flags a translated block deferred, before anything can
+    /// observe the CPSR.
+    fn settle_flags(&mut self) {
+        if let Some(pending) = self.pending_flags.take() {
+            self.cpsr = pending.resolve(self.cpsr);
+        }
     }
 
     fn exec_bx(&mut self, opcode: u32) -> bool {
@@ -999,241 +1251,162 @@ impl Arm11Cpu {
         }
     }
 
-    fn exec_thumb_shift_imm(&mut self, opcode: u16) -> bool {
-        if (opcode >> 13) != 0 {
-            return false;
-        }
-        let op = (opcode >> 11) & 0x3;
-        let offset = u32::from((opcode >> 6) & 0x1F);
-        let rs = usize::from((opcode >> 3) & 0x7);
-        let rd = usize::from(opcode & 0x7);
-        let value = self.regs[rs];
-        let (result, carry) = match op {
-            0b00 => {
-                if offset == 0 {
-                    (value, self.cpsr & FLAG_C != 0)
-                } else {
-                    (value << offset, ((value >> (32 - offset)) & 1) != 0)
-                }
-            }
-            0b01 => {
-                let s = if offset == 0 { 32 } else { offset };
-                if s == 32 {
-                    (0, (value >> 31) != 0)
-                } else {
-                    (value >> s, ((value >> (s - 1)) & 1) != 0)
-                }
-            }
-            0b10 => {
-                let s = if offset == 0 { 32 } else { offset };
-                let result = if s == 32 {
-                    if (value >> 31) != 0 { u32::MAX } else { 0 }
-                } else {
-                    ((value as i32) >> s) as u32
-                };
-                (result, ((value >> (s.saturating_sub(1).min(31))) & 1) != 0)
-            }
-            _ => return false,
-        };
-
-        self.regs[rd] = result;
-        self.update_nzcv_logical(result, carry);
-        true
-    }
-
-    fn exec_thumb_add_sub(&mut self, opcode: u16) -> bool {
-        if (opcode & 0xF800) != 0x1800 {
-            return false;
-        }
-        let immediate = ((opcode >> 10) & 1) != 0;
-        let sub = ((opcode >> 9) & 1) != 0;
-        let rn_or_imm = u32::from((opcode >> 6) & 0x7);
-        let rs = usize::from((opcode >> 3) & 0x7);
-        let rd = usize::from(opcode & 0x7);
-        let lhs = self.regs[rs];
-        let rhs = if immediate {
-            rn_or_imm
-        } else {
-            self.regs[rn_or_imm as usize]
-        };
-
-        if sub {
-            let (res, borrow) = lhs.overflowing_sub(rhs);
-            let overflow = ((lhs ^ rhs) & (lhs ^ res) & FLAG_N) != 0;
-            self.regs[rd] = res;
-            self.update_nzcv_arithmetic(res, !borrow, overflow);
-        } else {
-            let (res, carry) = lhs.overflowing_add(rhs);
-            let overflow = ((!(lhs ^ rhs)) & (lhs ^ res) & FLAG_N) != 0;
-            self.regs[rd] = res;
-            self.update_nzcv_arithmetic(res, carry, overflow);
+    /// Loads `size` bytes (1, 2 or 4) from a data address, zero-extended.
+    fn read_data(
+        &mut self,
+        memory: &mut dyn Bus,
+        va: u32,
+        size: u32,
+    ) -> std::result::Result<u32, FaultKind> {
+        if va & (size - 1) != 0 {
+            return Err(FaultKind::Alignment);
         }
-        true
+        let pa = self.translate_va(memory, va, size, MemoryAccessKind::Read)?;
+        self.read_physical(memory, pa, size)
     }
 
-    fn exec_thumb_mov_cmp_add_sub_imm(&mut self, opcode: u16) -> bool {
-        if (opcode & 0xE000) != 0x2000 {
-            return false;
-        }
-        let op = (opcode >> 11) & 0x3;
-        let rd = usize::from((opcode >> 8) & 0x7);
-        let imm = u32::from(opcode & 0xFF);
-
-        match op {
-            0b00 => {
-                self.regs[rd] = imm;
-                self.update_nzcv_logical(imm, self.cpsr & FLAG_C != 0);
-            }
-            0b01 => {
-                let lhs = self.regs[rd];
-                let (res, borrow) = lhs.overflowing_sub(imm);
-                let overflow = ((lhs ^ imm) & (lhs ^ res) & FLAG_N) != 0;
-                self.update_nzcv_arithmetic(res, !borrow, overflow);
-            }
-            0b10 => {
-                let lhs = self.regs[rd];
-                let (res, carry) = lhs.overflowing_add(imm);
-                let overflow = ((!(lhs ^ imm)) & (lhs ^ res) & FLAG_N) != 0;
-                self.regs[rd] = res;
-                self.update_nzcv_arithmetic(res, carry, overflow);
-            }
-            0b11 => {
-                let lhs = self.regs[rd];
-                let (res, borrow) = lhs.overflowing_sub(imm);
-                let overflow = ((lhs ^ imm) & (lhs ^ res) & FLAG_N) != 0;
-                self.regs[rd] = res;
-                self.update_nzcv_arithmetic(res, !borrow, overflow);
-            }
-            _ => return false,
+    /// Stores the low `size` bytes (1, 2 or 4) of `value` to a data address.
+    fn write_data(
+        &mut self,
+        memory: &mut dyn Bus,
+        va: u32,
+        size: u32,
+        value: u32,
+    ) -> std::result::Result<(), FaultKind> {
+        if va & (size - 1) != 0 {
+            return Err(FaultKind::Alignment);
         }
-        true
+        let pa = self.translate_va(memory, va, size, MemoryAccessKind::Write)?;
+        self.write_physical(memory, pa, size, value)
     }
 
-    fn exec_thumb_hi_reg_bx(&mut self, opcode: u16) -> bool {
-        if (opcode & 0xFC00) != 0x4400 {
-            return false;
+    /// Every data access funnels through here, the store twin or the
+    /// doubleword pair below, so the CPSR E bit (BE-8) byte-reverses
+    /// halfwords and words in one place.
+    /// Instruction fetches stay little-endian.
+    fn read_physical(
+        &mut self,
+        memory: &mut dyn Bus,
+        pa: u32,
+        size: u32,
+    ) -> std::result::Result<u32, FaultKind> {
+        self.cycles.add_access(pa);
+        let value = match size {
+            1 => memory.read_u8_checked(pa).map(u32::from),
+            2 => memory.read_u16_checked(pa).map(u32::from),
+            _ => memory.read_u32_checked(pa),
         }
-        let op = (opcode >> 8) & 0x3;
-        let h1 = ((opcode >> 7) & 1) as usize;
-        let h2 = ((opcode >> 6) & 1) as usize;
-        let rs = usize::from((opcode >> 3) & 0x7) | (h2 << 3);
-        let rd = usize::from(opcode & 0x7) | (h1 << 3);
-
-        match op {
-            0b00 => self.regs[rd] = self.regs[rd].wrapping_add(self.regs[rs]),
-            0b01 => {
-                let lhs = self.regs[rd];
-                let rhs = self.regs[rs];
-                let (res, borrow) = lhs.overflowing_sub(rhs);
-                let overflow = ((lhs ^ rhs) & (lhs ^ res) & FLAG_N) != 0;
-                self.update_nzcv_arithmetic(res, !borrow, overflow);
-            }
-            0b10 => self.regs[rd] = self.regs[rs],
-            0b11 => {
-                let target = self.regs[rs];
-                self.set_thumb_state((target & 1) != 0);
-                self.regs[PC_INDEX] = target & !1;
-            }
-            _ => return false,
+        .map_err(|_| self.record_bus_fault(MemoryAccessKind::Read))?;
+        if memory.memory_hooks_active() {
+            let width = access_width(size);
+            self.report_access(memory, pa, width, value.into(), MemoryAccessKind::Read);
         }
-        true
+        Ok(self.data_endian(value, size))
     }
 
-    fn exec_thumb_ldr_literal(&mut self, opcode: u16, memory: &mut dyn Bus, pc: u32) -> bool {
-        if (opcode & 0xF800) != 0x4800 {
-            return false;
-        }
-        let rd = usize::from((opcode >> 8) & 0x7);
-        let imm = u32::from(opcode & 0xFF) << 2;
-        let address = (self.regs[PC_INDEX] & !3).wrapping_add(imm);
-        if address & 3 != 0 {
-            self.take_data_abort(FaultKind::Alignment, pc, u32::from(opcode));
-            return true;
-        }
-        let pa = match self.translate_va(memory, address, MemoryAccessKind::Read) {
-            Ok(pa) => pa,
-            Err(kind) => {
-                self.take_data_abort(kind, pc, u32::from(opcode));
-                return true;
-            }
-        };
-        match memory.read_u32_checked(pa) {
-            Ok(v) => self.regs[rd] = v,
-            Err(_) => self.take_data_abort(FaultKind::Translation, pc, u32::from(opcode)),
+    fn write_physical(
+        &mut self,
+        memory: &mut dyn Bus,
+        pa: u32,
+        size: u32,
+        value: u32,
+    ) -> std::result::Result<(), FaultKind> {
+        self.cycles.add_access(pa);
+        let value = self.data_endian(value, size);
+        match size {
+            1 => memory.write_u8_checked(pa, value as u8),
+            2 => memory.write_u16_checked(pa, value as u16),
+            _ => memory.write_u32_checked(pa, value),
+        }
+        .map_err(|_| self.record_bus_fault(MemoryAccessKind::Write))?;
+        if memory.memory_hooks_active() {
+            let width = access_width(size);
+            self.report_access(memory, pa, width, value.into(), MemoryAccessKind::Write);
         }
-        true
+        Ok(())
     }
 
-    fn exec_thumb_load_store_imm(&mut self, opcode: u16, memory: &mut dyn Bus, pc: u32) -> bool {
-        if (opcode & 0xE000) != 0x6000 {
-            return false;
-        }
-        let load = ((opcode >> 11) & 1) != 0;
-        let imm5 = u32::from((opcode >> 6) & 0x1F) << 2;
-        let rb = usize::from((opcode >> 3) & 0x7);
-        let rd = usize::from(opcode & 0x7);
-        let address = self.regs[rb].wrapping_add(imm5);
-
-        if address & 3 != 0 {
-            self.take_data_abort(FaultKind::Alignment, pc, u32::from(opcode));
-            return true;
-        }
-
-        if load {
-            let pa = match self.translate_va(memory, address, MemoryAccessKind::Read) {
-                Ok(pa) => pa,
-                Err(kind) => {
-                    self.take_data_abort(kind, pc, u32::from(opcode));
-                    return true;
-                }
-            };
-            self.regs[rd] = match memory.read_u32_checked(pa) {
-                Ok(v) => v,
-                Err(_) => {
-                    self.take_data_abort(FaultKind::Translation, pc, u32::from(opcode));
-                    return true;
-                }
-            };
-        } else {
-            let pa = match self.translate_va(memory, address, MemoryAccessKind::Write) {
-                Ok(pa) => pa,
-                Err(kind) => {
-                    self.take_data_abort(kind, pc, u32::from(opcode));
-                    return true;
-                }
-            };
-            if memory.write_u32_checked(pa, self.regs[rd]).is_err() {
-                self.take_data_abort(FaultKind::Translation, pc, u32::from(opcode));
-                return true;
-            }
+    /// A doubleword access, which reaches a device as one 64-bit transfer.
+    /// Each word is byte-reversed separately under BE-8.
+    fn read_physical_pair(
+        &mut self,
+        memory: &mut dyn Bus,
+        pa: u32,
+    ) -> std::result::Result<(u32, u32), FaultKind> {
+        self.cycles.add_access(pa);
+        self.cycles.add_access(pa.wrapping_add(4));
+        let value = memory
+            .read_u64_checked(pa)
+            .map_err(|_| self.record_bus_fault(MemoryAccessKind::Read))?;
+        if memory.memory_hooks_active() {
+            self.report_access(
+                memory,
+                pa,
+                AccessWidth::Double,
+                value,
+                MemoryAccessKind::Read,
+            );
+        }
+        Ok((
+            self.data_endian(value as u32, 4),
+            self.data_endian((value >> 32) as u32, 4),
+        ))
+    }
+
+    fn write_physical_pair(
+        &mut self,
+        memory: &mut dyn Bus,
+        pa: u32,
+        (first, second): (u32, u32),
+    ) -> std::result::Result<(), FaultKind> {
+        self.cycles.add_access(pa);
+        self.cycles.add_access(pa.wrapping_add(4));
+        let value =
+            u64::from(self.data_endian(first, 4)) | (u64::from(self.data_endian(second, 4)) << 32);
+        memory
+            .write_u64_checked(pa, value)
+            .map_err(|_| self.record_bus_fault(MemoryAccessKind::Write))?;
+        if memory.memory_hooks_active() {
+            self.report_access(
+                memory,
+                pa,
+                AccessWidth::Double,
+                value,
+                MemoryAccessKind::Write,
+            );
         }
-
-        true
+        Ok(())
     }
 
-    fn exec_thumb_cond_branch(&mut self, opcode: u16) -> bool {
-        if (opcode & 0xF000) != 0xD000 || (opcode & 0x0F00) == 0x0F00 {
-            return false;
-        }
-        let cond = u32::from((opcode >> 8) & 0xF);
-        if !self.condition_passed(cond) {
-            return true;
-        }
-        let offset = i32::from((opcode & 0xFF) as i8) << 1;
-        self.regs[PC_INDEX] = self.regs[PC_INDEX].wrapping_add(offset as u32);
-        true
+    fn report_access(
+        &self,
+        memory: &mut dyn Bus,
+        pa: u32,
+        width: AccessWidth,
+        value: u64,
+        kind: MemoryAccessKind,
+    ) {
+        memory.report_access(MemoryAccess {
+            core: self.core_id,
+            pc: self.step_pc,
+            va: self.data_va,
+            pa,
+            width,
+            value: value & width.mask(),
+            kind,
+            cycle: 0,
+        });
     }
 
-    fn exec_thumb_uncond_branch(&mut self, opcode: u16) -> bool {
-        if (opcode & 0xF800) != 0xE000 {
-            return false;
+    fn data_endian(&self, value: u32, size: u32) -> u32 {
+        if self.cpsr & FLAG_E == 0 {
+            return value;
         }
-        let mut offset = i32::from((opcode & 0x07FF) as i16) << 1;
-        if (offset & 0x0800) != 0 {
-            offset |= !0x0FFF;
+        match size {
+            4 => value.swap_bytes(),
+            2 => u32::from((value as u16).swap_bytes()),
+            _ => value,
         }
-        self.regs[PC_INDEX] = self.regs[PC_INDEX].wrapping_add(offset as u32);
-        true
     }
 
     fn set_thumb_state(&mut self, thumb: bool) {
@@ -1276,6 +1449,42 @@ impl Arm11Cpu {
         }
     }
 
+    /// Shifts by a register amount (bottom byte), where zero leaves the
+    /// value and carry untouched and amounts of 32 or more saturate.
+    fn shift_by_register(&self, value: u32, amount: u32, shift_type: u32) -> (u32, bool) {
+        let carry_in = self.cpsr & FLAG_C != 0;
+        if amount == 0 {
+            return (value, carry_in);
+        }
+        match shift_type {
+            0b00 => match amount {
+                1..=31 => (value << amount, ((value >> (32 - amount)) & 1) == 1),
+                32 => (0, (value & 1) == 1),
+                _ => (0, false),
+            },
+            0b01 => match amount {
+                1..=31 => (value >> amount, ((value >> (amount - 1)) & 1) == 1),
+                32 => (0, (value & FLAG_N) != 0),
+                _ => (0, false),
+            },
+            0b10 => {
+                if amount >= 32 {
+                    let sign = (value & FLAG_N) != 0;
+                    (if sign { u32::MAX } else { 0 }, sign)
+                } else {
+                    (
+                        ((value as i32) >> amount) as u32,
+                        ((value >> (amount - 1)) & 1) == 1,
+                    )
+                }
+            }
+            _ => {
+                let res = value.rotate_right(amount & 31);
+                (res, (res & FLAG_N) != 0)
+            }
+        }
+    }
+
     fn update_nzcv_logical(&mut self, result: u32, carry: bool) {
         self.set_flag(FLAG_N, result & FLAG_N != 0);
         self.set_flag(FLAG_Z, result == 0);
@@ -1307,6 +1516,7 @@ impl Arm11Cpu {
             MODE_SVC => Some(self.spsr_svc),
             MODE_IRQ => Some(self.spsr_irq),
             MODE_ABT => Some(self.spsr_abt),
+            MODE_FIQ => Some(self.spsr_fiq),
             _ => None,
         }
     }
@@ -1317,6 +1527,7 @@ impl Arm11Cpu {
             MODE_SVC => self.spsr_svc = value,
             MODE_IRQ => self.spsr_irq = value,
             MODE_ABT => self.spsr_abt = value,
+            MODE_FIQ => self.spsr_fiq = value,
             _ => {}
         }
     }
@@ -1328,6 +1539,8 @@ impl Arm11Cpu {
             MODE_IRQ => (&mut self.bank_irq_sp, &mut self.bank_irq_lr),
             MODE_UND => (&mut self.bank_und_sp, &mut self.bank_und_lr),
             MODE_ABT => (&mut self.bank_abt_sp, &mut self.bank_abt_lr),
+            MODE_FIQ => (&mut self.bank_fiq_sp, &mut self.bank_fiq_lr),
+            // System mode shares the User registers.
             _ => (&mut self.bank_usr_sp, &mut self.bank_usr_lr),
         }
     }
@@ -1352,6 +1565,16 @@ impl Arm11Cpu {
         };
         self.regs[SP_INDEX] = new_sp;
         self.regs[LR_INDEX] = new_lr;
+
+        if (old_mode == MODE_FIQ) != (new_mode == MODE_FIQ) {
+            let (outgoing, incoming) = if new_mode == MODE_FIQ {
+                (&mut self.bank_usr_r8_r12, &self.bank_fiq_r8_r12)
+            } else {
+                (&mut self.bank_fiq_r8_r12, &self.bank_usr_r8_r12)
+            };
+            outgoing.copy_from_slice(&self.regs[8..13]);
+            self.regs[8..13].copy_from_slice(incoming);
+        }
         self.cpsr = (self.cpsr & !MODE_MASK) | new_mode;
     }
 
@@ -1363,57 +1586,102 @@ impl Arm11Cpu {
     }
 
     fn take_prefetch_abort(&mut self, fault: FaultKind, pc: u32, opcode: u32) {
-        self.cp15_regs[CP15_IFAR] = pc;
-        self.cp15_regs[CP15_IFSR] = self.encode_fault_status(fault);
+        self.cp15.ifar = pc;
+        self.cp15.ifsr = self.encode_fault_status(fault);
         self.take_exception(ExceptionKind::PrefetchAbort(fault), pc, opcode, true);
     }
 
+    fn take_breakpoint(&mut self, pc: u32, opcode: u32) {
+        self.cp15.ifar = pc;
+        self.cp15.ifsr = FAULT_STATUS_DEBUG;
+        self.take_exception(ExceptionKind::Breakpoint, pc, opcode, true);
+    }
+
     fn take_data_abort(&mut self, fault: FaultKind, pc: u32, opcode: u32) {
-        self.cp15_regs[CP15_DFAR] = self.last_mmu_fault.map(|f| f.va).unwrap_or(pc);
-        self.cp15_regs[CP15_DFSR] = self.encode_fault_status(fault);
+        let access = self
+            .bus_fault
+            .or(self.last_mmu_fault.map(|f| (f.va, f.access)));
+        self.cp15.dfar = access.map_or(pc, |(va, _)| va);
+        let write = access.is_some_and(|(_, kind)| kind == MemoryAccessKind::Write);
+        self.cp15.dfsr = self.encode_fault_status(fault) | (u32::from(write) << FSR_WNR_SHIFT);
         self.take_exception(ExceptionKind::DataAbort(fault), pc, opcode, false);
     }
 
+    /// FSR status and domain; page-level faults use the odd codes one above
+    /// their section-level counterparts.
     fn encode_fault_status(&self, fault: FaultKind) -> u32 {
-        match fault {
+        let (page, domain) = self
+            .last_mmu_fault
+            .map_or((false, 0), |f| (f.page, u32::from(f.domain)));
+        let status = match fault {
             FaultKind::Translation => 0b00101,
             FaultKind::Domain => 0b01001,
             FaultKind::Permission => 0b01101,
-            FaultKind::Alignment => 0b00001,
-        }
+            FaultKind::Alignment => return 0b00001,
+            // Precise, with no domain to report.
+            FaultKind::External => return 0b01000,
+        };
+        (status | (u32::from(page) << 1)) | (domain << FSR_DOMAIN_SHIFT)
     }
 
     fn take_exception(&mut self, kind: ExceptionKind, pc: u32, fault_opcode: u32, lr_plus_4: bool) {
         let (vector, mode) = match kind {
-            ExceptionKind::UndefinedInstruction => (VECTOR_UND, MODE_UND),
+            ExceptionKind::UndefinedInstruction | ExceptionKind::VfpDisabled => {
+                (VECTOR_UND, MODE_UND)
+            }
             ExceptionKind::SoftwareInterrupt => (VECTOR_SWI, MODE_SVC),
-            ExceptionKind::PrefetchAbort(_) => (VECTOR_PABT, MODE_ABT),
+            ExceptionKind::Breakpoint | ExceptionKind::PrefetchAbort(_) => (VECTOR_PABT, MODE_ABT),
             ExceptionKind::DataAbort(_) => (VECTOR_DABT, MODE_ABT),
             ExceptionKind::Interrupt(_) => (VECTOR_IRQ, MODE_IRQ),
+            ExceptionKind::FastInterrupt => (VECTOR_FIQ, MODE_FIQ),
         };
 
-        let return_addr = if matches!(kind, ExceptionKind::PrefetchAbort(_)) {
+        let thumb = self.is_thumb();
+        let return_addr = if matches!(
+            kind,
+            ExceptionKind::PrefetchAbort(_) | ExceptionKind::Breakpoint
+        ) {
             pc.wrapping_add(4)
         } else if matches!(kind, ExceptionKind::DataAbort(_)) {
             pc.wrapping_add(8)
+        } else if thumb
+            && matches!(
+                kind,
+                ExceptionKind::UndefinedInstruction | ExceptionKind::SoftwareInterrupt
+            )
+        {
+            // Thumb UND/SWI return to the next halfword.
+            pc.wrapping_add(2)
         } else if lr_plus_4 {
             pc.wrapping_add(4)
         } else {
             pc
         };
 
-        self.set_current_spsr(self.cpsr);
+        self.settle_flags();
+        self.translation.leave_block();
+        let saved_cpsr = self.cpsr;
+        self.clear_local_monitor();
         self.switch_mode(mode);
+        self.set_current_spsr(saved_cpsr);
         self.regs[LR_INDEX] = return_addr;
         self.regs[PC_INDEX] = vector;
         self.cpsr |= FLAG_I;
+        if kind == ExceptionKind::FastInterrupt {
+            self.cpsr |= FLAG_F;
+        }
         self.cpsr &= !FLAG_T;
-        self.last_exception = Some(CpuException {
+        self.set_flag(FLAG_E, self.cp15.sctlr & SCTLR_EE != 0);
+        self.exception_entered = true;
+        let exception = CpuException {
             kind,
             vector,
             return_address: return_addr,
             fault_opcode,
-        });
+            thumb,
+        };
+        self.last_exception = Some(exception);
+        self.catch_exception(exception);
     }
 
     fn condition_passed(&self, cond: u32) -> bool {
@@ -1443,6 +1711,16 @@ impl Arm11Cpu {
     }
 }
 
+/// Writes `opcode` at the PC and steps it; the single-instruction tests in
+/// the submodules share this.
+#[cfg(test)]
+fn run_opcode(cpu: &mut Arm11Cpu, memory: &mut dyn Bus, opcode: u32) {
+    let pc = cpu.pc();
+    memory.write_u32(pc, opcode);
+    cpu.step(memory)
+        .unwrap_or_else(|e| panic!("0x{opcode:08x}: {e}"));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1455,7 +1733,7 @@ mod tests {
     #[test]
     fn instruction_fetch_fault_routes_to_prefetch_abort() {
         let mut cpu = Arm11Cpu::new();
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
 
         cpu.regs[0] = 0x0000_4000;
         assert!(cpu.exec_coprocessor(mcr_cp15(2, 0, 0, 0), &mut memory));
@@ -1475,9 +1753,9 @@ mod tests {
     }
 
     #[test]
-    fn mcr_ttbr_write_invalidates_cached_translation() {
+    fn ttbr_write_keeps_cached_translation_until_tlb_invalidate() {
         let mut cpu = Arm11Cpu::new();
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, 0x0800_0000 | (0b11 << 10) | 0b10)
             .unwrap_or_else(|e| panic!("descriptor write: {e}"));
@@ -1496,13 +1774,15 @@ mod tests {
 
         cpu.regs[0] = 0x0000_8000;
         cpu.exec_coprocessor(mcr_cp15(2, 0, 0, 0), &mut memory);
+        assert_eq!(cpu.mmu.tlb_len(), 1);
+        cpu.exec_coprocessor(mcr_cp15(8, 0, 7, 0), &mut memory);
         assert_eq!(cpu.mmu.tlb_len(), 0);
     }
 
     #[test]
     fn write_to_apx_read_only_section_routes_to_data_abort() {
         let mut cpu = Arm11Cpu::new();
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, (1 << 15) | (0b11 << 10) | 0b10)
             .unwrap_or_else(|e| panic!("descriptor write: {e}"));
@@ -1528,10 +1808,40 @@ mod tests {
         assert_eq!(ex.vector, VECTOR_DABT);
     }
 
+    #[test]
+    fn unmapped_page_write_reports_page_translation_fault_status() {
+        let mut cpu = Arm11Cpu::new();
+        let mut memory = Memory::flat();
+        // VA 0-1 MiB goes through a coarse table in domain 2 with no pages.
+        memory
+            .write_u32_checked(0x0000_4000, 0x0000_8000 | (2 << 5) | 0b01)
+            .unwrap_or_else(|e| panic!("descriptor write: {e}"));
+        memory
+            .write_u32_checked(0x0000_4004, 0x0010_0000 | (0b11 << 10) | 0b10)
+            .unwrap_or_else(|e| panic!("descriptor write: {e}"));
+        memory.write_u32(0x0010_0000, 0xE5801000); // str r1, [r0]
+
+        cpu.regs[0] = 0x0000_4000;
+        cpu.exec_coprocessor(mcr_cp15(2, 0, 0, 0), &mut memory);
+        cpu.regs[0] = 0b01;
+        cpu.exec_coprocessor(mcr_cp15(3, 0, 0, 0), &mut memory);
+        cpu.regs[0] = 1;
+        cpu.exec_coprocessor(mcr_cp15(1, 0, 0, 0), &mut memory);
+
+        cpu.regs[PC_INDEX] = 0x0010_0000;
+        cpu.regs[0] = 0x0000_2000;
+        cpu.step(&mut memory).expect("step must handle abort");
+
+        let ex = cpu.last_exception().expect("data abort raised");
+        assert_eq!(ex.kind, ExceptionKind::DataAbort(FaultKind::Translation));
+        assert_eq!(cpu.cp15.dfar, 0x0000_2000);
+        assert_eq!(cpu.cp15.dfsr, (1 << 11) | (2 << 4) | 0b00111);
+    }
+
     #[test]
     fn execute_never_section_routes_to_prefetch_abort_permission() {
         let mut cpu = Arm11Cpu::new();
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, 0x0800_0000 | (1 << 4) | (0b11 << 10) | 0b10)
             .unwrap_or_else(|e| panic!("descriptor write: {e}"));
@@ -1555,7 +1865,7 @@ mod tests {
     #[test]
     fn arm_byte_transfer_and_register_offset_work() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.regs[PC_INDEX] = 0;
         cpu.regs[0] = 0x100;
         cpu.regs[1] = 0xAB;
@@ -1574,7 +1884,7 @@ mod tests {
     #[test]
     fn arm_signed_halfword_load_sign_extends() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.regs[PC_INDEX] = 0;
         cpu.regs[0] = 0x200;
         mem.write_u8(0x200, 0x80);
@@ -1588,7 +1898,7 @@ mod tests {
     #[test]
     fn thumb_data_abort_sets_abort_lr_offset() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.cpsr |= FLAG_T;
         cpu.regs[PC_INDEX] = 0;
 
@@ -1606,7 +1916,7 @@ mod tests {
     #[test]
     fn movs_pc_lr_restores_mode_and_banked_registers() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
 
         cpu.regs[SP_INDEX] = 0x1000;
         cpu.regs[LR_INDEX] = 0x2000;
@@ -1625,6 +1935,96 @@ mod tests {
         assert_eq!(cpu.regs[LR_INDEX], 0x2000);
         assert_eq!(cpu.pc(), 4);
     }
+
+    #[test]
+    fn srs_cps_rfe_return_through_the_user_stack() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[SP_INDEX] = 0x1000;
+        cpu.regs[PC_INDEX] = 0;
+        let user_cpsr = cpu.cpsr;
+
+        mem.write_u32(0, 0xEF00_0000); // swi
+        mem.write_u32(VECTOR_SWI, 0xF96D_051F); // srsdb sp!, #0x1f
+        mem.write_u32(VECTOR_SWI + 4, 0xF10C_0080); // cpsid i
+        mem.write_u32(VECTOR_SWI + 8, 0xF102_001F); // cps #0x1f
+        mem.write_u32(VECTOR_SWI + 12, 0xF8BD_0A00); // rfeia sp!
+        cpu.step(&mut mem).expect("swi");
+        cpu.regs[SP_INDEX] = 0x3000;
+        cpu.step(&mut mem).expect("srs");
+        assert_eq!(cpu.regs[SP_INDEX], 0x3000, "srs wrote the user stack");
+        assert_eq!(cpu.bank_usr_sp, 0xFF8);
+        assert_eq!((mem.read_u32(0xFF8), mem.read_u32(0xFFC)), (4, user_cpsr));
+
+        cpu.step(&mut mem).expect("cpsid");
+        cpu.step(&mut mem).expect("cps");
+        assert_eq!(cpu.mode(), MODE_SYS);
+        assert_eq!(cpu.regs[SP_INDEX], 0xFF8);
+        assert_eq!(cpu.bank_svc_sp, 0x3000);
+
+        cpu.step(&mut mem).expect("rfe");
+        assert_eq!(cpu.mode(), MODE_USR);
+        assert_eq!(cpu.cpsr, user_cpsr);
+        assert_eq!(cpu.regs[SP_INDEX], 0x1000);
+        assert_eq!(cpu.pc(), 4);
+
+        mem.write_u32(4, 0xF102_0013); // cps #0x13
+        cpu.step(&mut mem).expect("cps in user mode");
+        assert_eq!(cpu.mode(), MODE_USR, "cps is ignored unprivileged");
+    }
+
+    #[test]
+    fn unconditional_space_branches_hints_and_traps() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0x100;
+        mem.write_u32(0x100, 0xFB00_003F); // blx +0xFE
+        cpu.step(&mut mem).expect("blx");
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.regs[LR_INDEX], 0x104);
+        assert_ne!(cpu.cpsr & FLAG_T, 0, "blx switches to Thumb");
+
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[PC_INDEX] = 0;
+        mem.write_u32(0, 0xF5D0_F000); // pld [r0]
+        mem.write_u32(4, 0xF57F_F04F); // dsb sy
+        mem.write_u32(8, 0xF57F_F01F); // clrex
+        mem.write_u32(12, 0xF000_0000); // undefined
+        for _ in 0..4 {
+            cpu.step(&mut mem).expect("step");
+        }
+        let ex = cpu.last_exception().expect("undefined instruction raised");
+        assert_eq!(ex.kind, ExceptionKind::UndefinedInstruction);
+        assert_eq!(ex.vector, VECTOR_UND);
+        assert_eq!(cpu.regs[LR_INDEX], 16, "only the last opcode trapped");
+    }
+
+    #[test]
+    fn setend_byte_reverses_data_until_exception_entry() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0;
+        cpu.regs[0] = 0x400;
+        cpu.regs[1] = 0x1122_3344;
+
+        mem.write_u32(0, 0xF101_0200); // setend be
+        mem.write_u32(4, 0xE580_1000); // str r1, [r0]
+        mem.write_u32(8, 0xE1D0_20B0); // ldrh r2, [r0]
+        mem.write_u32(12, 0xEF00_0000); // swi
+        mem.write_u32(VECTOR_SWI, 0xE590_3000); // ldr r3, [r0]
+        mem.write_u32(VECTOR_SWI + 4, 0xE1B0_F00E); // movs pc, lr
+        mem.write_u32(16, 0xE590_4000); // ldr r4, [r0]
+        for _ in 0..7 {
+            cpu.step(&mut mem).expect("step");
+        }
+
+        assert_eq!(mem.read_u8(0x400), 0x11, "big-endian store");
+        assert_eq!(cpu.regs[2], 0x1122);
+        assert_eq!(cpu.regs[3], 0x4433_2211, "handler runs little-endian");
+        assert_ne!(cpu.cpsr & FLAG_E, 0, "return restores E from the SPSR");
+        assert_eq!(cpu.regs[4], 0x1122_3344);
+    }
+
     #[test]
     fn banked_sp_lr_switch_between_usr_and_irq() {
         let mut cpu = Arm11Cpu::new();
@@ -1638,10 +2038,71 @@ mod tests {
         assert_eq!(cpu.regs[LR_INDEX], 0x2000);
     }
 
+    #[test]
+    fn fiq_entry_banks_r8_to_r14_and_spsr() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        for reg in 8..15 {
+            cpu.regs[reg] = reg as u32;
+        }
+        cpu.regs[PC_INDEX] = 0x200;
+        let user_cpsr = cpu.cpsr;
+
+        cpu.enter_fiq();
+        assert_eq!(cpu.mode(), MODE_FIQ);
+        assert_eq!(cpu.cpsr & (FLAG_F | FLAG_I), FLAG_F | FLAG_I);
+        assert_eq!(cpu.spsr_fiq, user_cpsr);
+        assert_eq!(cpu.regs[8..14], [0; 6]);
+        assert_eq!(cpu.regs[LR_INDEX], 0x204);
+        cpu.regs[8] = 0xF8;
+
+        mem.write_u32(VECTOR_FIQ, 0xE25E_F004); // subs pc, lr, #4
+        cpu.step(&mut mem).expect("fiq return");
+        assert_eq!(cpu.mode(), MODE_USR);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.regs[8..15], [8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(cpu.bank_fiq_r8_r12[0], 0xF8);
+    }
+
+    #[test]
+    fn msr_field_masks_and_system_mode() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        let mut run = |cpu: &mut Arm11Cpu, opcode: u32| {
+            let pc = cpu.pc();
+            mem.write_u32(pc, opcode);
+            cpu.step(&mut mem).expect("msr step");
+        };
+        cpu.regs[SP_INDEX] = 0x1000;
+        cpu.switch_mode(MODE_SVC);
+        cpu.regs[SP_INDEX] = 0x2000;
+
+        cpu.regs[3] = 0x6000_01D3;
+        run(&mut cpu, 0xE16F_F003); // msr spsr_fsxc, r3
+        assert_eq!(cpu.spsr_svc, 0x6000_01D3);
+
+        run(&mut cpu, 0xE321_F01F); // msr cpsr_c, #0x1f
+        assert_eq!(cpu.mode(), MODE_SYS);
+        assert!(cpu.is_privileged());
+        assert_eq!(cpu.regs[SP_INDEX], 0x1000, "SYS shares the USR stack");
+
+        cpu.regs[0] = 0xF000_0010;
+        run(&mut cpu, 0xE128_F000); // msr cpsr_f, r0
+        assert_eq!(cpu.cpsr & 0xF000_0000, 0xF000_0000);
+        assert_eq!(cpu.mode(), MODE_SYS, "_f leaves the mode alone");
+
+        run(&mut cpu, 0xE321_F010); // msr cpsr_c, #0x10
+        assert_eq!(cpu.mode(), MODE_USR);
+        cpu.regs[2] = 0x8000_0093;
+        run(&mut cpu, 0xE129_F002); // msr cpsr_fc, r2
+        assert_eq!(cpu.mode(), MODE_USR, "user mode cannot leave itself");
+        assert_eq!(cpu.cpsr & (0xF000_0000 | FLAG_I), 0x8000_0000);
+    }
+
     #[test]
     fn thumb_fixture_conformance_sequence() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.cpsr |= FLAG_T;
         cpu.regs[PC_INDEX] = 0x0000_0000;
 
@@ -1665,7 +2126,7 @@ mod tests {
     #[test]
     fn boot_trace_replay_records_first_failure_context() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.enable_instruction_trace(8);
         cpu.regs[PC_INDEX] = 0;
 
@@ -1685,7 +2146,7 @@ mod tests {
     #[test]
     fn arm_stm_and_ldm_round_trip() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.regs[PC_INDEX] = 0;
         cpu.regs[0] = 0x200;
         cpu.regs[1] = 0x1111_1111;
@@ -1706,10 +2167,34 @@ mod tests {
         assert_eq!(cpu.regs[6], 0x3333_3333);
     }
 
+    #[test]
+    fn arm_stmdb_and_ldmdb_end_just_below_the_base() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0;
+        cpu.regs[0] = 0x20C;
+        cpu.regs[1] = 0x1111_1111;
+        cpu.regs[2] = 0x2222_2222;
+        cpu.regs[3] = 0x3333_3333;
+        cpu.regs[7] = 0x20C;
+
+        mem.write_u32(0, 0xE920_000E); // stmdb r0!, {r1-r3}
+        mem.write_u32(4, 0xE917_0070); // ldmdb r7, {r4-r6}
+
+        cpu.step(&mut mem).expect("stmdb executes");
+        cpu.step(&mut mem).expect("ldmdb executes");
+
+        assert_eq!(mem.read_u32(0x200), 0x1111_1111);
+        assert_eq!(mem.read_u32(0x208), 0x3333_3333);
+        assert_eq!(mem.read_u32(0x20C), 0, "the base itself is not written");
+        assert_eq!(cpu.regs[0], 0x200);
+        assert_eq!(cpu.regs[4..7], [0x1111_1111, 0x2222_2222, 0x3333_3333]);
+    }
+
     #[test]
     fn arm_swp_exchanges_register_and_memory() {
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.regs[PC_INDEX] = 0;
         cpu.regs[0] = 0x300;
         cpu.regs[1] = 0xAABB_CCDD;
@@ -1725,7 +2210,7 @@ mod tests {
     fn trace_fixture_matches_expected_sequence() {
         let fixture = include_str!("../../tests_cpu_trace_fixture.txt");
         let mut cpu = Arm11Cpu::new();
-        let mut mem = Memory::new();
+        let mut mem = Memory::flat();
         cpu.enable_instruction_trace(8);
         cpu.regs[PC_INDEX] = 0;
 
@@ -0,0 +1,335 @@
+//! CP15 system control coprocessor.
+//!
+//! Registers are addressed by the full `(CRn, opc1, CRm, opc2)` tuple. The
+//! identification registers describe the 3DS's ARM11 MPCore; the translation
+//! registers are mirrored into the MMU as they are written. Encodings the
+//! model doesn't implement read as zero and ignore writes. Privilege isn't
+//! checked: titles run in User mode with the kernel emulated around them, so
+//! the accesses the kernel would have made come from User code here.
+
+use super::{Arm11Cpu, CpuRunState, FLAG_C, FLAG_N, FLAG_V, FLAG_Z, PC_INDEX};
+
+/// ARM11 MPCore r0p5.
+const MIDR: u32 = 0x410F_B025;
+/// 16 KiB 4-way instruction and data caches with 32-byte lines.
+const CTR: u32 = 0x1D19_2992;
+/// The MPCore has no tightly coupled memory.
+const TCMTR: u32 = 0;
+
+/// SCTLR MMU enable.
+const SCTLR_M: u32 = 1;
+/// SCTLR ARMv6 (rather than subpage AP) page table format.
+const SCTLR_XP: u32 = 1 << 23;
+/// Client access checking in every domain.
+const DACR_ALL_CLIENT: u32 = 0x5555_5555;
+/// SCTLR reads these bits as one.
+const SCTLR_SBO: u32 = 0x0005_0078;
+/// M, A, C, B, S, R, Z, I, V, RR, L4, FI, U, XP, VE, EE, TRE and AFE.
+const SCTLR_WRITABLE: u32 = 0x33E0_FB87;
+/// SCTLR bit giving the CPSR E value on exception entry.
+pub(super) const SCTLR_EE: u32 = 1 << 25;
+/// RS, DB, SB, F, EXCL and SMP.
+const ACTLR_WRITABLE: u32 = 0x3F;
+/// Only the CP10 and CP11 access fields exist.
+const CPACR_WRITABLE: u32 = 0x00F0_0000;
+/// N, PD0 and PD1.
+const TTBCR_WRITABLE: u32 = 0x37;
+/// Status, domain, FS[4] and WnR.
+const FSR_WRITABLE: u32 = 0x0CFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Cp15 {
+    pub(super) sctlr: u32,
+    actlr: u32,
+    cpacr: u32,
+    ttbr0: u32,
+    ttbr1: u32,
+    ttbcr: u32,
+    dacr: u32,
+    pub(super) dfsr: u32,
+    pub(super) ifsr: u32,
+    pub(super) dfar: u32,
+    pub(super) ifar: u32,
+    wfar: u32,
+    context_id: u32,
+    tpidrurw: u32,
+    tpidruro: u32,
+    tpidrprw: u32,
+}
+
+impl Cp15 {
+    /// The state the 3DS kernel leaves behind for a title: MMU off and
+    /// CP10/CP11 open to User mode.
+    pub(super) fn new() -> Self {
+        Self {
+            sctlr: SCTLR_SBO,
+            actlr: 0,
+            cpacr: CPACR_WRITABLE,
+            ttbr0: 0,
+            ttbr1: 0,
+            ttbcr: 0,
+            dacr: 0,
+            dfsr: 0,
+            ifsr: 0,
+            dfar: 0,
+            ifar: 0,
+            wfar: 0,
+            context_id: 0,
+            tpidrurw: 0,
+            tpidruro: 0,
+            tpidrprw: 0,
+        }
+    }
+
+    /// Whether CPACR lets the current mode use coprocessor `cp`.
+    pub(super) fn coprocessor_allowed(&self, cp: u32, privileged: bool) -> bool {
+        match (self.cpacr >> (cp * 2)) & 0b11 {
+            0b01 => privileged,
+            0b11 => true,
+            _ => false,
+        }
+    }
+}
+
+/// An `MRC`/`MCR` register operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Cp15Reg {
+    crn: u32,
+    opc1: u32,
+    crm: u32,
+    opc2: u32,
+}
+
+impl Cp15Reg {
+    pub(super) fn from_opcode(opcode: u32) -> Self {
+        Self {
+            crn: (opcode >> 16) & 0xF,
+            opc1: (opcode >> 21) & 0x7,
+            crm: opcode & 0xF,
+            opc2: (opcode >> 5) & 0x7,
+        }
+    }
+
+    fn key(self) -> (u32, u32, u32, u32) {
+        (self.crn, self.opc1, self.crm, self.opc2)
+    }
+}
+
+impl Arm11Cpu {
+    /// Points TTBR0 at a process's translation table and turns the MMU on
+    /// with every domain a client, as the kernel does before running a title.
+    pub fn enter_address_space(&mut self, ttbr0: u32) {
+        let sctlr = self.cp15.sctlr | SCTLR_M | SCTLR_XP;
+        for (crn, value) in [(2, ttbr0), (3, DACR_ALL_CLIENT), (1, sctlr)] {
+            let reg = Cp15Reg {
+                crn,
+                opc1: 0,
+                crm: 0,
+                opc2: 0,
+            };
+            self.exec_mcr_cp15(reg, value);
+        }
+        self.mmu.invalidate_tlb();
+    }
+
+    /// `MRC p15`; a read into R15 sets NZCV from bits 31:28 instead.
+    pub(super) fn exec_mrc_cp15(&mut self, reg: Cp15Reg, rd: usize) {
+        let value = self.read_cp15(reg);
+        if rd == PC_INDEX {
+            self.settle_flags();
+            let flags = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+            self.cpsr = (self.cpsr & !flags) | (value & flags);
+        } else {
+            self.regs[rd] = value;
+        }
+    }
+
+    pub(super) fn exec_mcr_cp15(&mut self, reg: Cp15Reg, value: u32) {
+        let cp15 = &mut self.cp15;
+        match reg.key() {
+            (1, 0, 0, 0) => {
+                cp15.sctlr = SCTLR_SBO | (value & SCTLR_WRITABLE);
+                self.mmu.write_control(cp15.sctlr);
+                self.translation.flush();
+            }
+            (1, 0, 0, 1) => cp15.actlr = value & ACTLR_WRITABLE,
+            (1, 0, 0, 2) => cp15.cpacr = value & CPACR_WRITABLE,
+            // Blocks are keyed by PA as well as VA, so they survive a table
+            // switch; only the rest of the current one has to be re-fetched.
+            (2, 0, 0, 0) => {
+                cp15.ttbr0 = value;
+                self.mmu.write_ttbr0(value);
+                self.translation.leave_block();
+            }
+            (2, 0, 0, 1) => {
+                cp15.ttbr1 = value;
+                self.mmu.write_ttbr1(value);
+                self.translation.leave_block();
+            }
+            (2, 0, 0, 2) => {
+                cp15.ttbcr = value & TTBCR_WRITABLE;
+                self.mmu.write_ttbcr(cp15.ttbcr);
+                self.translation.leave_block();
+            }
+            (3, 0, 0, 0) => {
+                cp15.dacr = value;
+                self.mmu.write_dacr(value);
+            }
+            (5, 0, 0, 0) => cp15.dfsr = value & FSR_WRITABLE,
+            (5, 0, 0, 1) => cp15.ifsr = value & FSR_WRITABLE,
+            (6, 0, 0, 0) => cp15.dfar = value,
+            (6, 0, 0, 1) => cp15.wfar = value,
+            (6, 0, 0, 2) => cp15.ifar = value,
+            (7, 0, 0, 4) => self.state = CpuRunState::Halted,
+            // Invalidate the instruction or unified cache, whole or by line.
+            (7, 0, 5 | 7, 0..=2) => self.translation.flush(),
+            // Invalidate the TLB whole, by MVA and ASID, by ASID, or by MVA
+            // for every ASID (the ARMv7 encoding).
+            (8, 0, 5..=7, 0) => {
+                self.mmu.invalidate_tlb();
+                self.translation.flush();
+            }
+            (8, 0, 5..=7, 1) => {
+                self.mmu.invalidate_tlb_va_asid(value, value as u8);
+                self.translation.flush();
+            }
+            (8, 0, 5..=7, 2) => {
+                self.mmu.invalidate_tlb_asid(value as u8);
+                self.translation.flush();
+            }
+            (8, 0, 5..=7, 3) => {
+                self.mmu.invalidate_tlb_va(value);
+                self.translation.flush();
+            }
+            (13, 0, 0, 0) => {}
+            (13, 0, 0, 1) => {
+                cp15.context_id = value;
+                self.mmu.write_context_id(value);
+            }
+            (13, 0, 0, 2) => cp15.tpidrurw = value,
+            (13, 0, 0, 3) => cp15.tpidruro = value,
+            (13, 0, 0, 4) => cp15.tpidrprw = value,
+            (15, 0, 12, opc2) => self.write_pmu(opc2, value),
+            // D-cache maintenance, barriers, the prefetch buffer and branch
+            // predictor have no state to change.
+            _ => {}
+        }
+    }
+
+    fn read_cp15(&self, reg: Cp15Reg) -> u32 {
+        let cp15 = &self.cp15;
+        match reg.key() {
+            (0, 0, 0, 0) => MIDR,
+            (0, 0, 0, 1) => CTR,
+            (0, 0, 0, 2) => TCMTR,
+            (0, 0, 0, 5) => self.core_id as u32,
+            (1, 0, 0, 0) => cp15.sctlr,
+            (1, 0, 0, 1) => cp15.actlr,
+            (1, 0, 0, 2) => cp15.cpacr,
+            (2, 0, 0, 0) => cp15.ttbr0,
+            (2, 0, 0, 1) => cp15.ttbr1,
+            (2, 0, 0, 2) => cp15.ttbcr,
+            (3, 0, 0, 0) => cp15.dacr,
+            (5, 0, 0, 0) => cp15.dfsr,
+            (5, 0, 0, 1) => cp15.ifsr,
+            (6, 0, 0, 0) => cp15.dfar,
+            (6, 0, 0, 1) => cp15.wfar,
+            (6, 0, 0, 2) => cp15.ifar,
+            (13, 0, 0, 1) => cp15.context_id,
+            (13, 0, 0, 2) => cp15.tpidrurw,
+            (13, 0, 0, 3) => cp15.tpidruro,
+            (13, 0, 0, 4) => cp15.tpidrprw,
+            (15, 0, 12, opc2) => self.pmu.read(opc2),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, CpuRunState, ExceptionKind, FLAG_C, FLAG_N, FLAG_Z, run_opcode};
+    use super::{CTR, MIDR, SCTLR_SBO, SCTLR_WRITABLE};
+    use crate::core::error::MemoryAccessKind;
+    use crate::core::memory::Memory;
+
+    fn mcr(crn: u32, rd: u32, crm: u32, opc2: u32) -> u32 {
+        0xEE00_0F10 | (crn << 16) | (rd << 12) | (opc2 << 5) | crm
+    }
+
+    fn mrc(crn: u32, rd: u32, crm: u32, opc2: u32) -> u32 {
+        mcr(crn, rd, crm, opc2) | (1 << 20)
+    }
+
+    #[test]
+    fn registers_are_addressed_by_the_full_tuple() {
+        let mut cpu = Arm11Cpu::with_core_id(2);
+        let mut mem = Memory::flat();
+        for (rd, opc2) in [(1, 0), (2, 1), (3, 5)] {
+            run_opcode(&mut cpu, &mut mem, mrc(0, rd, 0, opc2));
+        }
+        assert_eq!(cpu.regs[1..4], [MIDR, CTR, 2]);
+
+        for (rd, opc2) in [(4, 1), (5, 2), (6, 3), (7, 4)] {
+            cpu.regs[rd as usize] = 0x1000 * rd;
+            run_opcode(&mut cpu, &mut mem, mcr(13, rd, 0, opc2));
+        }
+        // Everything but the MMU enable, which would fault the next fetch.
+        cpu.regs[8] = !1;
+        run_opcode(&mut cpu, &mut mem, mcr(1, 8, 0, 0));
+        run_opcode(&mut cpu, &mut mem, mcr(0, 8, 0, 0));
+        for (rd, opc2) in [(4, 1), (5, 2), (6, 3), (7, 4)] {
+            run_opcode(&mut cpu, &mut mem, mrc(13, rd + 5, 0, opc2));
+        }
+        run_opcode(&mut cpu, &mut mem, mrc(1, 1, 0, 0));
+        run_opcode(&mut cpu, &mut mem, mrc(0, 2, 0, 0));
+        assert_eq!(cpu.regs[9..13], [0x4000, 0x5000, 0x6000, 0x7000]);
+        assert_eq!(cpu.regs[1], SCTLR_SBO | (SCTLR_WRITABLE & !1));
+        assert_eq!(cpu.regs[2], MIDR);
+    }
+
+    #[test]
+    fn mrc_to_pc_sets_flags_and_cpacr_gates_the_vfp() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[0] = FLAG_Z | FLAG_C;
+        run_opcode(&mut cpu, &mut mem, mcr(13, 0, 0, 2));
+        run_opcode(&mut cpu, &mut mem, mrc(13, 15, 0, 2));
+        assert_eq!(cpu.cpsr() & (FLAG_N | FLAG_Z | FLAG_C), FLAG_Z | FLAG_C);
+        assert_eq!(cpu.pc(), 8);
+
+        // CP10/CP11 privileged-only, so User mode can't reach the VFP.
+        cpu.regs[0] = 0x0050_0000;
+        run_opcode(&mut cpu, &mut mem, mcr(1, 0, 0, 2));
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0A81); // fadds s0, s1, s2
+        assert_eq!(
+            cpu.last_exception().map(|ex| ex.kind),
+            Some(ExceptionKind::UndefinedInstruction)
+        );
+    }
+
+    #[test]
+    fn maintenance_ops_invalidate_by_mva_and_wait_for_interrupt() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        mem.write_u32(0x4000, 0x0000_0C02);
+        mem.write_u32(0x4004, 0x0010_0C02);
+        cpu.regs[0] = 0x4000;
+        run_opcode(&mut cpu, &mut mem, mcr(2, 0, 0, 0));
+        cpu.regs[0] = 1;
+        run_opcode(&mut cpu, &mut mem, mcr(3, 0, 0, 0));
+        run_opcode(&mut cpu, &mut mem, mcr(1, 0, 0, 0));
+        for va in [0x0000_1000, 0x0010_1000] {
+            cpu.mmu
+                .translate(&mut mem, va, MemoryAccessKind::Read, true)
+                .unwrap_or_else(|e| panic!("translation: {e}"));
+        }
+        assert_eq!(cpu.mmu.tlb_len(), 2);
+
+        cpu.regs[0] = 0x0010_1234;
+        run_opcode(&mut cpu, &mut mem, mcr(8, 0, 7, 1));
+        assert_eq!(cpu.mmu.tlb_len(), 1);
+
+        run_opcode(&mut cpu, &mut mem, mcr(7, 0, 0, 4));
+        assert_eq!(cpu.run_state(), CpuRunState::Halted);
+    }
+}
@@ -0,0 +1,395 @@
+//! Instruction cycle costs.
+//!
+//! `CycleModel::Simple` charges the flat per-class costs the interpreter
+//! returns. `CycleModel::Arm11` follows the cycle timing chapters of the
+//! ARM1176JZF-S and ARM11 MPCore TRMs: register-specified shifts, multiplies
+//! terminating early on small multipliers, LDM/STM moving two registers per
+//! cycle, penalties for taken branches and other PC writes, load-use
+//! interlocks and data-access wait states per memory region. Instruction
+//! fetches are assumed to hit the I-cache. Classes the model doesn't cover
+//! (VFP, coprocessor, media and exception entry) keep their simple cost.
+
+use super::super::memory::{
+    FCRAM_START, IO_SIZE, IO_START, N3DS_FCRAM_SIZE, VRAM_SIZE, VRAM_START,
+};
+use super::{Arm11Cpu, LR_INDEX, PC_INDEX, REG_COUNT, SP_INDEX};
+
+/// Taken `B`/`BL`/`BLX <imm>`: the static predictor refetches from the target.
+const BRANCH_TAKEN_PENALTY: u32 = 2;
+/// Any other PC write flushes the pipeline at the execute stage.
+const PC_WRITE_PENALTY: u32 = 5;
+/// Loaded data reaches the register file two cycles after the issue slot of
+/// the next instruction.
+const LOAD_USE_STALL: u32 = 2;
+
+const FCRAM_WAIT_STATES: u32 = 2;
+const VRAM_WAIT_STATES: u32 = 1;
+const IO_WAIT_STATES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CycleModel {
+    #[default]
+    Simple,
+    Arm11,
+}
+
+/// Per-step bookkeeping for `CycleModel::Arm11`.
+#[derive(Debug, Clone, Default)]
+pub(super) struct CycleCounter {
+    pub(super) model: CycleModel,
+    /// The instruction `step` ran, and whether it was Thumb.
+    pub(super) retired: Option<(u32, bool)>,
+    /// The instruction failed its condition check.
+    pub(super) skipped: bool,
+    pub(super) wait_states: u32,
+    /// Registers the previous instruction loaded from memory.
+    loaded: u16,
+}
+
+impl CycleCounter {
+    pub(super) fn begin_step(&mut self) {
+        self.retired = None;
+        self.skipped = false;
+        self.wait_states = 0;
+    }
+
+    pub(super) fn add_access(&mut self, pa: u32) {
+        self.wait_states += wait_states(pa);
+    }
+}
+
+impl Arm11Cpu {
+    pub fn set_cycle_model(&mut self, model: CycleModel) {
+        self.cycles = CycleCounter {
+            model,
+            ..CycleCounter::default()
+        };
+    }
+
+    /// The `CycleModel::Arm11` cost of the instruction `step` just ran, given
+    /// the registers and PC from before it ran and its simple cost.
+    pub(super) fn arm11_cycles(&mut self, regs: &[u32; REG_COUNT], pc: u32, simple: u32) -> u32 {
+        let loaded = std::mem::take(&mut self.cycles.loaded);
+        let Some((opcode, thumb)) = self.cycles.retired else {
+            return simple;
+        };
+        if self.exception_entered {
+            return simple;
+        }
+        if self.cycles.skipped {
+            return 1;
+        }
+
+        let width = if thumb { 2 } else { 4 };
+        let pc_written = self.pc() != pc.wrapping_add(width);
+        let (base, sources, loads) = if thumb {
+            thumb_costs(opcode as u16, regs, pc_written)
+        } else {
+            arm_costs(opcode, regs, pc_written)
+        };
+        self.cycles.loaded = loads;
+        let interlock = if loaded & sources != 0 {
+            LOAD_USE_STALL
+        } else {
+            0
+        };
+        base.unwrap_or(simple) + interlock + self.cycles.wait_states
+    }
+}
+
+fn wait_states(pa: u32) -> u32 {
+    let in_region = |start: u32, size: usize| (pa.wrapping_sub(start) as usize) < size;
+    // The New 3DS extension continues the same FCRAM.
+    if in_region(FCRAM_START, N3DS_FCRAM_SIZE) {
+        FCRAM_WAIT_STATES
+    } else if in_region(VRAM_START, VRAM_SIZE) {
+        VRAM_WAIT_STATES
+    } else if in_region(IO_START, IO_SIZE) {
+        IO_WAIT_STATES
+    } else {
+        0
+    }
+}
+
+/// Extra cycles for a multiplier whose significant bits end in the lowest
+/// byte, halfword or three bytes; signed multipliers also terminate early on
+/// runs of ones.
+fn multiplier_cycles(rs: u32, signed: bool) -> u32 {
+    let magnitude = if signed && (rs as i32) < 0 { !rs } else { rs };
+    match magnitude {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x1_0000..=0xFF_FFFF => 3,
+        _ => 4,
+    }
+}
+
+fn block_transfer_cycles(list: u16) -> u32 {
+    1 + list.count_ones().div_ceil(2).max(1)
+}
+
+fn reg_bit(opcode: u32, shift: u32) -> u16 {
+    1 << ((opcode >> shift) & 0xF)
+}
+
+/// The base cost (`None` for the simple cost), the registers read and the
+/// registers loaded from memory by an ARM instruction.
+fn arm_costs(opcode: u32, regs: &[u32; REG_COUNT], pc_written: bool) -> (Option<u32>, u16, u16) {
+    let pc_penalty = if pc_written { PC_WRITE_PENALTY } else { 0 };
+    let rn = reg_bit(opcode, 16);
+    let rd = reg_bit(opcode, 12);
+    let rs = reg_bit(opcode, 8);
+    let rm = reg_bit(opcode, 0);
+    let load = opcode & (1 << 20) != 0;
+
+    if opcode >> 28 == 0xF {
+        // BLX <imm> is the only unconditional-space branch.
+        let blx = (opcode >> 25) & 0x7 == 0b101;
+        let cost = if blx {
+            1 + BRANCH_TAKEN_PENALTY
+        } else {
+            1 + pc_penalty
+        };
+        return (Some(cost), 0, 0);
+    }
+    match (opcode >> 25) & 0x7 {
+        0b101 => {
+            let cost = if pc_written {
+                1 + BRANCH_TAKEN_PENALTY
+            } else {
+                1
+            };
+            (Some(cost), 0, 0)
+        }
+        0b100 => {
+            let list = opcode as u16;
+            let sources = if load { rn } else { rn | list };
+            let loads = if load { list } else { 0 };
+            (
+                Some(block_transfer_cycles(list) + pc_penalty),
+                sources,
+                loads,
+            )
+        }
+        0b010 | 0b011 if (opcode >> 25) & 1 == 0 || opcode & 0x10 == 0 => {
+            let offset = if opcode & (1 << 25) != 0 { rm } else { 0 };
+            let sources = rn | offset | if load { 0 } else { rd };
+            (Some(1 + pc_penalty), sources, if load { rd } else { 0 })
+        }
+        0b000 if opcode & 0x0FC0_00F0 == 0x0000_0090 => {
+            // MUL/MLA: Rd is bits 19:16 and the accumulator bits 15:12.
+            let accumulate = opcode & (1 << 21) != 0;
+            let cost = 1
+                + u32::from(accumulate)
+                + multiplier_cycles(regs[(opcode as usize >> 8) & 0xF], true);
+            let sources = rm | rs | if accumulate { rd } else { 0 };
+            (Some(cost), sources, 0)
+        }
+        0b000 if opcode & 0x0F80_00F0 == 0x0080_0090 => {
+            // UMULL/UMLAL/SMULL/SMLAL: RdHi bits 19:16, RdLo bits 15:12.
+            let signed = opcode & (1 << 22) != 0;
+            let accumulate = opcode & (1 << 21) != 0;
+            let multiplier = regs[(opcode as usize >> 8) & 0xF];
+            let cost = 2 + u32::from(accumulate) + multiplier_cycles(multiplier, signed);
+            let sources = rm | rs | if accumulate { rn | rd } else { 0 };
+            (Some(cost), sources, 0)
+        }
+        0b000 if opcode & 0x0FFF_FFF0 == 0x012F_FF10 => (Some(1 + PC_WRITE_PENALTY), rm, 0),
+        0b000 if opcode & 0x90 == 0x90 && opcode & 0x60 != 0 => {
+            // Halfword and signed transfers; bit 22 selects an immediate offset.
+            let offset = if opcode & (1 << 22) == 0 { rm } else { 0 };
+            let sources = rn | offset | if load { 0 } else { rd };
+            (Some(1 + pc_penalty), sources, if load { rd } else { 0 })
+        }
+        0b000 | 0b001 if is_data_processing(opcode) => {
+            let immediate = opcode & (1 << 25) != 0;
+            let register_shift = !immediate && opcode & 0x10 != 0;
+            let op = (opcode >> 21) & 0xF;
+            let first = if op == 0b1101 || op == 0b1111 { 0 } else { rn };
+            let second = if immediate {
+                0
+            } else {
+                rm | if register_shift { rs } else { 0 }
+            };
+            let cost = 1 + u32::from(register_shift) + pc_penalty;
+            (Some(cost), first | second, 0)
+        }
+        _ => (None, rn | rm, 0),
+    }
+}
+
+/// Data processing, excluding the miscellaneous and multiply encodings that
+/// share its space.
+fn is_data_processing(opcode: u32) -> bool {
+    let immediate = opcode & (1 << 25) != 0;
+    let op = (opcode >> 21) & 0xF;
+    let test_without_s = (0b1000..=0b1011).contains(&op) && opcode & (1 << 20) == 0;
+    !test_without_s && (immediate || opcode & 0x90 != 0x90)
+}
+
+fn low_reg(opcode: u16, shift: u16) -> u16 {
+    1 << ((opcode >> shift) & 0x7)
+}
+
+/// The Thumb counterpart of `arm_costs`.
+fn thumb_costs(opcode: u16, regs: &[u32; REG_COUNT], pc_written: bool) -> (Option<u32>, u16, u16) {
+    let pc_penalty = if pc_written { PC_WRITE_PENALTY } else { 0 };
+    let r0 = low_reg(opcode, 0);
+    let r3 = low_reg(opcode, 3);
+    let r6 = low_reg(opcode, 6);
+    let r8 = low_reg(opcode, 8);
+    let load = opcode & (1 << 11) != 0;
+    let list = opcode & 0xFF;
+    let sp = 1 << SP_INDEX;
+
+    match opcode >> 11 {
+        // Shift by immediate, add/subtract.
+        0b00000..=0b00010 => (Some(1), r3, 0),
+        0b00011 => {
+            let register = opcode & (1 << 10) == 0;
+            (Some(1), r3 | if register { r6 } else { 0 }, 0)
+        }
+        // MOV/CMP/ADD/SUB immediate.
+        0b00100 => (Some(1), 0, 0),
+        0b00101..=0b00111 => (Some(1), r8, 0),
+        0b01000 if opcode & (1 << 10) == 0 => {
+            let op = (opcode >> 6) & 0xF;
+            let cost = match op {
+                // LSL/LSR/ASR/ROR by register.
+                0b0010 | 0b0011 | 0b0100 | 0b0111 => 2,
+                // MUL Rd, Rm multiplies by Rd.
+                0b1101 => 1 + multiplier_cycles(regs[usize::from(opcode & 0x7)], true),
+                _ => 1,
+            };
+            (Some(cost), r0 | r3, 0)
+        }
+        0b01000 => {
+            // Hi register operations and BX/BLX.
+            let rm = 1 << ((opcode >> 3) & 0xF);
+            let rd = 1 << (((opcode >> 4) & 0x8) | (opcode & 0x7));
+            // MOV and BX/BLX only read Rm.
+            let sources = if (opcode >> 8) & 0x3 >= 0b10 {
+                rm
+            } else {
+                rm | rd
+            };
+            (Some(1 + pc_penalty), sources, 0)
+        }
+        // LDR Rd, [PC, #imm].
+        0b01001 => (Some(1), 0, r8),
+        0b01010 | 0b01011 => {
+            let op = (opcode >> 9) & 0x7;
+            let loads = op >= 0b011;
+            let sources = r3 | r6 | if loads { 0 } else { r0 };
+            (Some(1), sources, if loads { r0 } else { 0 })
+        }
+        0b01100..=0b10001 => {
+            let sources = r3 | if load { 0 } else { r0 };
+            (Some(1), sources, if load { r0 } else { 0 })
+        }
+        0b10010 | 0b10011 => {
+            let sources = sp | if load { 0 } else { r8 };
+            (Some(1), sources, if load { r8 } else { 0 })
+        }
+        0b10110 | 0b10111 if opcode & 0x0600 == 0x0400 => {
+            // PUSH adds LR and POP adds PC through bit 8.
+            let extra = if opcode & 0x0100 != 0 {
+                1 << if load { PC_INDEX } else { LR_INDEX }
+            } else {
+                0
+            };
+            let list = list | extra;
+            let cost = block_transfer_cycles(list) + pc_penalty;
+            if load {
+                (Some(cost), sp, list)
+            } else {
+                (Some(cost), sp | list, 0)
+            }
+        }
+        0b11000 => (Some(block_transfer_cycles(list)), r8 | list, 0),
+        0b11001 => (Some(block_transfer_cycles(list)), r8, list),
+        0b11010 | 0b11011 if opcode & 0x0E00 != 0x0E00 => {
+            let cost = if pc_written {
+                1 + BRANCH_TAKEN_PENALTY
+            } else {
+                1
+            };
+            (Some(cost), 0, 0)
+        }
+        0b11100 | 0b11101 | 0b11111 => (Some(1 + BRANCH_TAKEN_PENALTY), 0, 0),
+        // The BL/BLX prefix only sets LR.
+        0b11110 => (Some(1), 0, 0),
+        _ => (None, 0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::Memory;
+
+    #[test]
+    fn arm11_model_charges_interlocks_shifts_and_branches() {
+        let program = [
+            0xE590_1000, // ldr r1, [r0]
+            0xE281_2001, // add r2, r1, #1
+            0xE003_0492, // mul r3, r2, r4
+            0xE1A0_5615, // mov r5, r5, lsl r6
+            0x01A0_0000, // moveq r0, r0
+            0xEA00_0000, // b 0x101c
+        ];
+        let mut memory = Memory::new();
+        for (i, opcode) in program.into_iter().enumerate() {
+            memory.write_u32(FCRAM_START + 0x1000 + 4 * i as u32, opcode);
+        }
+
+        let mut simple = Arm11Cpu::new();
+        let mut timed = Arm11Cpu::new();
+        timed.set_cycle_model(CycleModel::Arm11);
+        let mut costs = Vec::new();
+        for cpu in [&mut simple, &mut timed] {
+            cpu.regs[0] = FCRAM_START + 0x2000;
+            cpu.regs[4] = 0x10;
+            cpu.regs[PC_INDEX] = FCRAM_START + 0x1000;
+            costs.push(
+                (0..program.len())
+                    .map(|_| {
+                        cpu.step(&mut memory)
+                            .unwrap_or_else(|e| panic!("step: {e}"))
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(costs[0], [3, 1, 2, 1, 1, 2]);
+        assert_eq!(
+            costs[1],
+            [
+                1 + FCRAM_WAIT_STATES,
+                1 + LOAD_USE_STALL,
+                2,
+                2,
+                1,
+                1 + BRANCH_TAKEN_PENALTY
+            ]
+        );
+        assert_eq!(timed.pc(), FCRAM_START + 0x101C);
+    }
+
+    #[test]
+    fn multiplies_terminate_early_on_small_multipliers() {
+        assert_eq!(multiplier_cycles(0x7F, true), 1);
+        assert_eq!(multiplier_cycles(0xFFFF_FF80, true), 1);
+        assert_eq!(multiplier_cycles(0xFFFF_FF80, false), 4);
+        assert_eq!(multiplier_cycles(0x1234, false), 2);
+        assert_eq!(multiplier_cycles(0x12_3456, false), 3);
+    }
+
+    #[test]
+    fn data_accesses_pay_their_region_wait_states() {
+        assert_eq!(wait_states(FCRAM_START + 0x0010_0000), FCRAM_WAIT_STATES);
+        assert_eq!(wait_states(FCRAM_START + 0x0C00_0000), FCRAM_WAIT_STATES);
+        assert_eq!(wait_states(VRAM_START + 0x40), VRAM_WAIT_STATES);
+        assert_eq!(wait_states(IO_START + 0x1000), IO_WAIT_STATES);
+        assert_eq!(wait_states(0x0800_0000), 0);
+    }
+}
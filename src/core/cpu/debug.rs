@@ -0,0 +1,229 @@
+//! Debug unit: instruction breakpoints, data watchpoints and exception
+//! catching, plus the register accessors a debugger needs.
+//!
+//! A breakpoint stops `step` before the instruction at its address runs and
+//! is stepped over once when execution resumes there; Thumb breakpoints use
+//! the halfword address. A conditional breakpoint only stops when a register
+//! holds the given value. A watchpoint fires on any access overlapping it,
+//! lets the accessing instruction complete and reports the first watched
+//! address it touched. Caught exceptions are reported after the vector has
+//! been entered.
+
+use std::collections::BTreeMap;
+
+use super::super::bus::Bus;
+use super::super::error::MemoryAccessKind;
+use super::{Arm11Cpu, CpuException, ExceptionKind, MODE_MASK, PC_INDEX, is_valid_mode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: MemoryAccessKind) -> bool {
+        match self {
+            Self::Read => access == MemoryAccessKind::Read,
+            Self::Write => access == MemoryAccessKind::Write,
+            Self::Access => access != MemoryAccessKind::Execute,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    Virtual,
+    Physical,
+}
+
+/// Watches `len` bytes of `space` from `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+    pub space: AddressSpace,
+}
+
+impl Watchpoint {
+    /// The first watched byte of a `size`-byte access at `address`, if any.
+    fn first_hit(&self, address: u32, size: u32) -> Option<u32> {
+        if address.wrapping_sub(self.start) < self.len.max(1) {
+            Some(address)
+        } else if self.start.wrapping_sub(address) < size {
+            Some(self.start)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops at a breakpoint only while R`register` equals `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakCondition {
+    pub register: usize,
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEvent {
+    Breakpoint { pc: u32 },
+    Watchpoint { kind: WatchKind, address: u32 },
+    Exception(CpuException),
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct DebugUnit {
+    breakpoints: BTreeMap<u32, Option<BreakCondition>>,
+    watchpoints: Vec<Watchpoint>,
+    catch_exceptions: bool,
+    /// One-shot breakpoint for `Emulator3ds::run_until`.
+    run_to: Option<u32>,
+    /// The breakpoint just reported, which the next step executes.
+    resume_at: Option<u32>,
+    event: Option<DebugEvent>,
+}
+
+impl DebugUnit {
+    /// Drops a pending event; breakpoints and watchpoints survive a reset.
+    pub(super) fn clear_pending(&mut self) {
+        self.resume_at = None;
+        self.event = None;
+    }
+}
+
+impl Arm11Cpu {
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        self.debug.breakpoints.insert(pc, None);
+    }
+
+    /// Replaces any breakpoint at `pc` with one that checks `condition`.
+    pub fn add_conditional_breakpoint(&mut self, pc: u32, condition: BreakCondition) {
+        self.debug.breakpoints.insert(pc, Some(condition));
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u32) -> bool {
+        self.debug.breakpoints.remove(&pc).is_some()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.debug.watchpoints.contains(&watchpoint) {
+            self.debug.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let before = self.debug.watchpoints.len();
+        self.debug.watchpoints.retain(|&w| w != watchpoint);
+        self.debug.watchpoints.len() != before
+    }
+
+    /// Reports undefined instructions, `BKPT` and aborts as debug events.
+    pub fn set_exception_catch(&mut self, enabled: bool) {
+        self.debug.catch_exceptions = enabled;
+    }
+
+    pub fn take_debug_event(&mut self) -> Option<DebugEvent> {
+        self.debug.event.take()
+    }
+
+    /// Writes R0-R15 of the current mode; writing PC leaves any cached block.
+    pub fn set_register(&mut self, index: usize, value: u32) {
+        if index == PC_INDEX {
+            self.settle_flags();
+            self.translation.leave_block();
+        }
+        self.regs[index] = value;
+    }
+
+    /// Replaces the CPSR, banking registers if the mode changes.
+    pub fn set_cpsr(&mut self, value: u32) {
+        self.settle_flags();
+        self.translation.leave_block();
+        let mode = value & MODE_MASK;
+        if is_valid_mode(mode) {
+            self.switch_mode(mode);
+        }
+        self.cpsr = (value & !MODE_MASK) | self.mode();
+    }
+
+    pub fn set_run_to(&mut self, pc: Option<u32>) {
+        self.debug.run_to = pc;
+    }
+
+    /// Lets the next step run the instruction at the current PC even if a
+    /// breakpoint sits there, as resuming after a reported one does.
+    pub fn step_over_breakpoint(&mut self) {
+        self.debug.resume_at = Some(self.pc());
+    }
+
+    /// Translates `va` as a privileged access without raising a fault.
+    pub fn debug_translate(
+        &mut self,
+        memory: &mut dyn Bus,
+        va: u32,
+        access: MemoryAccessKind,
+    ) -> Option<u32> {
+        self.mmu.translate(memory, va, access, true).ok()
+    }
+
+    /// Whether `step` should stop before running the instruction at `pc`.
+    pub(super) fn breakpoint_hit(&mut self, pc: u32) -> bool {
+        let resuming = self.debug.resume_at.take() == Some(pc);
+        let hit = self.debug.run_to == Some(pc)
+            || match self.debug.breakpoints.get(&pc) {
+                Some(None) => true,
+                Some(Some(condition)) => self
+                    .regs
+                    .get(condition.register)
+                    .is_some_and(|&value| value == condition.value),
+                None => false,
+            };
+        if resuming || !hit {
+            return false;
+        }
+        self.debug.resume_at = Some(pc);
+        self.debug.event = Some(DebugEvent::Breakpoint { pc });
+        true
+    }
+
+    pub(super) fn check_watchpoints(
+        &mut self,
+        va: u32,
+        pa: u32,
+        size: u32,
+        access: MemoryAccessKind,
+    ) {
+        if self.debug.event.is_some() {
+            return;
+        }
+        let hit = self.debug.watchpoints.iter().find_map(|w| {
+            if !w.kind.matches(access) {
+                return None;
+            }
+            let address = match w.space {
+                AddressSpace::Virtual => va,
+                AddressSpace::Physical => pa,
+            };
+            w.first_hit(address, size).map(|address| (w.kind, address))
+        });
+        if let Some((kind, address)) = hit {
+            self.debug.event = Some(DebugEvent::Watchpoint { kind, address });
+        }
+    }
+
+    pub(super) fn catch_exception(&mut self, exception: CpuException) {
+        let caught = matches!(
+            exception.kind,
+            ExceptionKind::UndefinedInstruction
+                | ExceptionKind::Breakpoint
+                | ExceptionKind::PrefetchAbort(_)
+                | ExceptionKind::DataAbort(_)
+        );
+        if self.debug.catch_exceptions && caught {
+            self.debug.event = Some(DebugEvent::Exception(exception));
+        }
+    }
+}
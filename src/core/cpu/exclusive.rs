@@ -0,0 +1,203 @@
+//! Load/store exclusive (`LDREX`/`STREX` and the B/H/D forms) and `CLREX`.
+//!
+//! The local monitor lives in the CPU; the global monitor, when the bus has
+//! one, arbitrates between cores for shareable memory. Exception entry clears
+//! the local monitor, which also covers context switches since the kernel only
+//! reschedules from SVC or IRQ.
+
+use super::super::bus::Bus;
+use super::super::error::MemoryAccessKind;
+use super::super::exclusive::reservation_tag;
+use super::{Arm11Cpu, FaultKind, LR_INDEX};
+
+const OPCODE_CLREX: u32 = 0xF57F_F01F;
+
+impl Arm11Cpu {
+    pub(super) fn clear_local_monitor(&mut self) {
+        self.exclusive_tag = None;
+    }
+
+    /// `CLREX`, one of the unconditional-space encodings.
+    pub(super) fn exec_clrex(&mut self, opcode: u32) -> bool {
+        if opcode != OPCODE_CLREX {
+            return false;
+        }
+        self.clear_local_monitor();
+        true
+    }
+
+    pub(super) fn exec_exclusive(
+        &mut self,
+        opcode: u32,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        if opcode & 0x0F80_0FF0 != 0x0180_0F90 {
+            return Ok(false);
+        }
+        let load = ((opcode >> 20) & 1) == 1;
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rt = (opcode & 0xF) as usize;
+        let (size, doubleword) = match (opcode >> 21) & 0x3 {
+            0b00 => (4, false),
+            0b01 => (4, true),
+            0b10 => (1, false),
+            _ => (2, false),
+        };
+        let address = self.regs[rn];
+        let alignment = if doubleword { 8 } else { size };
+        if address & (alignment - 1) != 0 {
+            return Err(FaultKind::Alignment);
+        }
+
+        if load {
+            if doubleword && (!rd.is_multiple_of(2) || rd == LR_INDEX) {
+                return Ok(false);
+            }
+            let pa = self.translate_va(memory, address, alignment, MemoryAccessKind::Read)?;
+            if doubleword {
+                (self.regs[rd], self.regs[rd + 1]) = self.read_physical_pair(memory, pa)?;
+            } else {
+                self.regs[rd] = self.read_physical(memory, pa, size)?;
+            }
+            self.exclusive_tag = Some(reservation_tag(pa));
+            let core = self.core_id;
+            if self.mmu.shareable(address)
+                && let Some(monitor) = memory.exclusive_monitor()
+            {
+                monitor.mark(core, pa);
+            }
+            return Ok(true);
+        }
+
+        if doubleword && (!rt.is_multiple_of(2) || rt == LR_INDEX) {
+            return Ok(false);
+        }
+        let pa = self.translate_va(memory, address, alignment, MemoryAccessKind::Write)?;
+        let core = self.core_id;
+        let locally_marked = self.exclusive_tag == Some(reservation_tag(pa));
+        let globally_marked = !self.mmu.shareable(address)
+            || memory
+                .exclusive_monitor()
+                .is_none_or(|monitor| monitor.is_marked(core, pa));
+        let passed = locally_marked && globally_marked;
+
+        self.clear_local_monitor();
+        if let Some(monitor) = memory.exclusive_monitor() {
+            monitor.clear(core);
+        }
+        if passed {
+            if doubleword {
+                let pair = (self.regs[rt], self.regs[rt + 1]);
+                self.write_physical_pair(memory, pa, pair)?;
+            } else {
+                self.write_physical(memory, pa, size, self.regs[rt])?;
+            }
+        }
+        self.regs[rd] = u32::from(!passed);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, PC_INDEX, run_opcode};
+    use crate::core::bus::{Bus, SystemBus};
+    use crate::core::memory::Memory;
+
+    #[test]
+    fn strex_succeeds_once_per_reservation() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0x100;
+        cpu.regs[0] = 0x400;
+        cpu.regs[2] = 0xDEAD_BEEF;
+        mem.write_u32(0x400, 7);
+
+        run_opcode(&mut cpu, &mut mem, 0xE190_1F9F); // ldrex r1, [r0]
+        assert_eq!(cpu.regs[1], 7);
+        run_opcode(&mut cpu, &mut mem, 0xE180_3F92); // strex r3, r2, [r0]
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!(mem.read_u32(0x400), 0xDEAD_BEEF);
+
+        cpu.regs[2] = 1;
+        run_opcode(&mut cpu, &mut mem, 0xE180_3F92); // strex r3, r2, [r0]
+        assert_eq!(cpu.regs[3], 1, "reservation is consumed by the first strex");
+        assert_eq!(mem.read_u32(0x400), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn clrex_and_exceptions_clear_the_local_monitor() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0x100;
+        cpu.regs[0] = 0x400;
+
+        run_opcode(&mut cpu, &mut mem, 0xE190_1F9F); // ldrex r1, [r0]
+        run_opcode(&mut cpu, &mut mem, 0xF57F_F01F); // clrex
+        run_opcode(&mut cpu, &mut mem, 0xE180_3F92); // strex r3, r2, [r0]
+        assert_eq!(cpu.regs[3], 1);
+
+        run_opcode(&mut cpu, &mut mem, 0xE190_1F9F); // ldrex r1, [r0]
+        run_opcode(&mut cpu, &mut mem, 0xEF00_0000); // swi #0
+        assert_eq!(cpu.exclusive_tag, None);
+    }
+
+    #[test]
+    fn sized_variants_transfer_their_width() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0x100;
+        cpu.regs[0] = 0x400;
+        mem.write_u32(0x400, 0x8899_AABB);
+        mem.write_u32(0x404, 0x1122_3344);
+
+        run_opcode(&mut cpu, &mut mem, 0xE1D0_1F9F); // ldrexb r1, [r0]
+        assert_eq!(cpu.regs[1], 0xBB);
+        run_opcode(&mut cpu, &mut mem, 0xE1F0_1F9F); // ldrexh r1, [r0]
+        assert_eq!(cpu.regs[1], 0xAABB);
+        cpu.regs[6] = 0xCC;
+        run_opcode(&mut cpu, &mut mem, 0xE1E0_3F96); // strexh r3, r6, [r0]
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!(mem.read_u32(0x400), 0x8899_00CC);
+
+        run_opcode(&mut cpu, &mut mem, 0xE1B0_4F9F); // ldrexd r4, r5, [r0]
+        assert_eq!((cpu.regs[4], cpu.regs[5]), (0x8899_00CC, 0x1122_3344));
+        cpu.regs[6] = 1;
+        cpu.regs[7] = 2;
+        run_opcode(&mut cpu, &mut mem, 0xE1A0_3F96); // strexd r3, r6, r7, [r0]
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!((mem.read_u32(0x400), mem.read_u32(0x404)), (1, 2));
+    }
+
+    #[test]
+    fn global_monitor_arbitrates_between_cores() {
+        let mut bus = SystemBus::with_memory(Memory::flat());
+        let mut core0 = Arm11Cpu::new();
+        let mut core1 = Arm11Cpu::new();
+        core1.core_id = 1;
+        core0.regs[PC_INDEX] = 0x100;
+        core1.regs[PC_INDEX] = 0x200;
+        for cpu in [&mut core0, &mut core1] {
+            cpu.regs[0] = 0x400;
+            cpu.regs[2] = cpu.core_id as u32 + 10;
+        }
+
+        run_opcode(&mut core0, &mut bus, 0xE190_1F9F); // ldrex r1, [r0]
+        run_opcode(&mut core1, &mut bus, 0xE190_1F9F); // ldrex r1, [r0]
+        run_opcode(&mut core1, &mut bus, 0xE180_3F92); // strex r3, r2, [r0]
+        run_opcode(&mut core0, &mut bus, 0xE180_3F92); // strex r3, r2, [r0]
+
+        assert_eq!(core1.regs[3], 0);
+        assert_eq!(
+            core0.regs[3], 1,
+            "core 1's store broke core 0's reservation"
+        );
+        assert_eq!(bus.read_u32(0x400), 11);
+
+        run_opcode(&mut core0, &mut bus, 0xE190_1F9F); // ldrex r1, [r0]
+        bus.write_u32(0x404, 0); // e.g. a DMA write into the granule
+        run_opcode(&mut core0, &mut bus, 0xE180_3F92); // strex r3, r2, [r0]
+        assert_eq!(core0.regs[3], 1);
+    }
+}
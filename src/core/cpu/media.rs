@@ -0,0 +1,374 @@
+//! ARMv6 media instructions: parallel add/subtract, packing, saturation,
+//! byte reversal, extension and sum-of-absolute-differences.
+
+use super::{Arm11Cpu, FLAG_Q, GE_MASK, GE_SHIFT};
+
+const PREFIX_S: u32 = 0b001;
+const PREFIX_Q: u32 = 0b010;
+const PREFIX_SH: u32 = 0b011;
+const PREFIX_U: u32 = 0b101;
+const PREFIX_UQ: u32 = 0b110;
+const PREFIX_UH: u32 = 0b111;
+
+pub(super) fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+/// Clamps `value` to a signed `bits`-wide range, reporting whether it saturated.
+pub(super) fn signed_saturate(value: i64, bits: u32) -> (i64, bool) {
+    let max = (1_i64 << (bits - 1)) - 1;
+    let min = -(1_i64 << (bits - 1));
+    if value > max {
+        (max, true)
+    } else if value < min {
+        (min, true)
+    } else {
+        (value, false)
+    }
+}
+
+/// Clamps `value` to `0..2^bits`, reporting whether it saturated.
+pub(super) fn unsigned_saturate(value: i64, bits: u32) -> (i64, bool) {
+    let max = (1_i64 << bits) - 1;
+    if value > max {
+        (max, true)
+    } else if value < 0 {
+        (0, true)
+    } else {
+        (value, false)
+    }
+}
+
+/// Computes one lane of a parallel add/subtract and whether its GE bits are set.
+fn parallel_lane(prefix: u32, a: u32, b: u32, bits: u32, add: bool) -> Option<(u32, bool)> {
+    let mask = (1_u32 << bits) - 1;
+    let signed = matches!(prefix, PREFIX_S | PREFIX_Q | PREFIX_SH);
+    let (lhs, rhs) = if signed {
+        (
+            i64::from(sign_extend(a, bits)),
+            i64::from(sign_extend(b, bits)),
+        )
+    } else {
+        (i64::from(a), i64::from(b))
+    };
+    let sum = if add { lhs + rhs } else { lhs - rhs };
+
+    let (value, ge) = match prefix {
+        PREFIX_S => (sum, sum >= 0),
+        PREFIX_U => {
+            let ge = if add { sum >= 1_i64 << bits } else { sum >= 0 };
+            (sum, ge)
+        }
+        PREFIX_Q => (signed_saturate(sum, bits).0, false),
+        PREFIX_UQ => (unsigned_saturate(sum, bits).0, false),
+        PREFIX_SH | PREFIX_UH => (sum >> 1, false),
+        _ => return None,
+    };
+    Some((value as u32 & mask, ge))
+}
+
+impl Arm11Cpu {
+    pub(super) fn exec_media(&mut self, opcode: u32) -> bool {
+        self.exec_parallel_add_sub(opcode)
+            || self.exec_sel(opcode)
+            || self.exec_pack_halfword(opcode)
+            || self.exec_saturate(opcode)
+            || self.exec_reverse(opcode)
+            || self.exec_extend(opcode)
+            || self.exec_usad8(opcode)
+    }
+
+    fn ge_flags(&self) -> u32 {
+        (self.cpsr & GE_MASK) >> GE_SHIFT
+    }
+
+    fn set_ge_flags(&mut self, ge: u32) {
+        self.cpsr = (self.cpsr & !GE_MASK) | ((ge << GE_SHIFT) & GE_MASK);
+    }
+
+    fn exec_parallel_add_sub(&mut self, opcode: u32) -> bool {
+        if opcode & 0x0F80_0010 != 0x0600_0010 {
+            return false;
+        }
+        let prefix = (opcode >> 20) & 0x7;
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+
+        // Each output lane lists the `Rm` lane it combines with and whether it adds.
+        let (bits, lanes): (u32, &[(u32, bool)]) = match (opcode >> 5) & 0x7 {
+            0b000 => (16, &[(0, true), (1, true)]),
+            0b001 => (16, &[(1, false), (0, true)]),
+            0b010 => (16, &[(1, true), (0, false)]),
+            0b011 => (16, &[(0, false), (1, false)]),
+            0b100 => (8, &[(0, true), (1, true), (2, true), (3, true)]),
+            0b111 => (8, &[(0, false), (1, false), (2, false), (3, false)]),
+            _ => return false,
+        };
+
+        let a = self.regs[rn];
+        let b = self.regs[rm];
+        let mask = (1_u32 << bits) - 1;
+        let ge_stride = bits / 8;
+        let ge_lane_mask = (1_u32 << ge_stride) - 1;
+        let mut result = 0;
+        let mut ge = 0;
+        for (lane, &(b_lane, add)) in lanes.iter().enumerate() {
+            let lane = lane as u32;
+            let lhs = (a >> (lane * bits)) & mask;
+            let rhs = (b >> (b_lane * bits)) & mask;
+            let Some((value, lane_ge)) = parallel_lane(prefix, lhs, rhs, bits, add) else {
+                return false;
+            };
+            result |= value << (lane * bits);
+            if lane_ge {
+                ge |= ge_lane_mask << (lane * ge_stride);
+            }
+        }
+
+        self.regs[rd] = result;
+        if matches!(prefix, PREFIX_S | PREFIX_U) {
+            self.set_ge_flags(ge);
+        }
+        true
+    }
+
+    fn exec_sel(&mut self, opcode: u32) -> bool {
+        if opcode & 0x0FF0_0FF0 != 0x0680_0FB0 {
+            return false;
+        }
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let ge = self.ge_flags();
+        let mut select = 0_u32;
+        for byte in 0..4 {
+            if ge & (1 << byte) != 0 {
+                select |= 0xFF << (byte * 8);
+            }
+        }
+        self.regs[rd] = (self.regs[rn] & select) | (self.regs[rm] & !select);
+        true
+    }
+
+    fn exec_pack_halfword(&mut self, opcode: u32) -> bool {
+        if opcode & 0x0FF0_0030 != 0x0680_0010 {
+            return false;
+        }
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let shift_imm = (opcode >> 7) & 0x1F;
+        let top_from_rn = ((opcode >> 6) & 1) == 1;
+
+        self.regs[rd] = if top_from_rn {
+            // PKHTB: bottom half comes from Rm arithmetically shifted right.
+            let shifted = if shift_imm == 0 {
+                ((self.regs[rm] as i32) >> 31) as u32
+            } else {
+                ((self.regs[rm] as i32) >> shift_imm) as u32
+            };
+            (self.regs[rn] & 0xFFFF_0000) | (shifted & 0xFFFF)
+        } else {
+            // PKHBT: top half comes from Rm shifted left.
+            (self.regs[rn] & 0xFFFF) | ((self.regs[rm] << shift_imm) & 0xFFFF_0000)
+        };
+        true
+    }
+
+    fn exec_saturate(&mut self, opcode: u32) -> bool {
+        let unsigned = ((opcode >> 22) & 1) == 1;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let value = self.regs[rm];
+
+        if opcode & 0x0FB0_0FF0 == 0x06A0_0F30 {
+            // SSAT16 / USAT16 saturate each halfword independently.
+            let sat_imm = (opcode >> 16) & 0xF;
+            let mut result = 0;
+            let mut saturated = false;
+            for lane in 0..2 {
+                let half = i64::from(sign_extend((value >> (lane * 16)) & 0xFFFF, 16));
+                let (lane_value, lane_sat) = if unsigned {
+                    unsigned_saturate(half, sat_imm)
+                } else {
+                    signed_saturate(half, sat_imm + 1)
+                };
+                result |= (lane_value as u32 & 0xFFFF) << (lane * 16);
+                saturated |= lane_sat;
+            }
+            self.regs[rd] = result;
+            if saturated {
+                self.cpsr |= FLAG_Q;
+            }
+            return true;
+        }
+
+        if opcode & 0x0FA0_0030 != 0x06A0_0010 {
+            return false;
+        }
+        let sat_imm = (opcode >> 16) & 0x1F;
+        let shift_imm = (opcode >> 7) & 0x1F;
+        let arithmetic_shift = ((opcode >> 6) & 1) == 1;
+        let operand = if arithmetic_shift {
+            let shift = if shift_imm == 0 { 31 } else { shift_imm };
+            i64::from((value as i32) >> shift)
+        } else {
+            i64::from((value << shift_imm) as i32)
+        };
+        let (result, saturated) = if unsigned {
+            unsigned_saturate(operand, sat_imm)
+        } else {
+            signed_saturate(operand, sat_imm + 1)
+        };
+        self.regs[rd] = result as u32;
+        if saturated {
+            self.cpsr |= FLAG_Q;
+        }
+        true
+    }
+
+    fn exec_reverse(&mut self, opcode: u32) -> bool {
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let value = self.regs[(opcode & 0xF) as usize];
+        self.regs[rd] = match opcode & 0x0FFF_0FF0 {
+            0x06BF_0F30 => value.swap_bytes(),
+            0x06BF_0FB0 => ((value & 0xFF00_FF00) >> 8) | ((value & 0x00FF_00FF) << 8),
+            0x06FF_0FB0 => (value as u16).swap_bytes() as i16 as i32 as u32,
+            _ => return false,
+        };
+        true
+    }
+
+    fn exec_extend(&mut self, opcode: u32) -> bool {
+        if opcode & 0x0F80_03F0 != 0x0680_0070 {
+            return false;
+        }
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let rotated = self.regs[rm].rotate_right(((opcode >> 10) & 0x3) * 8);
+        let accumulate = if rn == super::PC_INDEX {
+            0
+        } else {
+            self.regs[rn]
+        };
+
+        let add_halves = |lo: u32, hi: u32| {
+            (accumulate.wrapping_add(lo) & 0xFFFF) | ((accumulate >> 16).wrapping_add(hi) << 16)
+        };
+
+        self.regs[rd] = match (opcode >> 20) & 0x7 {
+            0b000 => add_halves(
+                sign_extend(rotated & 0xFF, 8) as u32,
+                sign_extend((rotated >> 16) & 0xFF, 8) as u32,
+            ),
+            0b010 => accumulate.wrapping_add(sign_extend(rotated & 0xFF, 8) as u32),
+            0b011 => accumulate.wrapping_add(sign_extend(rotated & 0xFFFF, 16) as u32),
+            0b100 => add_halves(rotated & 0xFF, (rotated >> 16) & 0xFF),
+            0b110 => accumulate.wrapping_add(rotated & 0xFF),
+            0b111 => accumulate.wrapping_add(rotated & 0xFFFF),
+            _ => return false,
+        };
+        true
+    }
+
+    fn exec_usad8(&mut self, opcode: u32) -> bool {
+        if opcode & 0x0FF0_00F0 != 0x0780_0010 {
+            return false;
+        }
+        let rd = ((opcode >> 16) & 0xF) as usize;
+        let rn = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let a = self.regs[rm];
+        let b = self.regs[rs];
+        let sum: u32 = (0..4)
+            .map(|byte| {
+                let lhs = (a >> (byte * 8)) & 0xFF;
+                let rhs = (b >> (byte * 8)) & 0xFF;
+                lhs.abs_diff(rhs)
+            })
+            .sum();
+        self.regs[rd] = if rn == super::PC_INDEX {
+            sum
+        } else {
+            self.regs[rn].wrapping_add(sum)
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, FLAG_Q, GE_MASK, GE_SHIFT, PC_INDEX};
+    use crate::core::memory::Memory;
+
+    fn parse_hex(token: &str) -> u32 {
+        u32::from_str_radix(token.trim_start_matches("0x"), 16)
+            .unwrap_or_else(|e| panic!("bad fixture value {token}: {e}"))
+    }
+
+    #[test]
+    fn media_fixture_matches_expected_results() {
+        let fixture = include_str!("../../../tests_cpu_media_fixture.txt");
+        for line in fixture
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        {
+            let values = line.split('#').next().unwrap_or_default();
+            let fields: Vec<u32> = values.split_whitespace().map(parse_hex).collect();
+            let [opcode, r1, r2, ge_in, expected, ge_out, q_out] = fields[..] else {
+                panic!("malformed fixture line: {line}");
+            };
+
+            let mut cpu = Arm11Cpu::new();
+            let mut mem = Memory::flat();
+            cpu.regs[PC_INDEX] = 0;
+            cpu.regs[1] = r1;
+            cpu.regs[2] = r2;
+            cpu.cpsr |= ge_in << GE_SHIFT;
+            mem.write_u32(0, opcode);
+
+            cpu.step(&mut mem).expect("media instruction executes");
+            assert!(cpu.last_exception().is_none(), "{line}: raised exception");
+            assert_eq!(cpu.regs[0], expected, "{line}: result");
+            assert_eq!((cpu.cpsr & GE_MASK) >> GE_SHIFT, ge_out, "{line}: GE flags");
+            assert_eq!(u32::from(cpu.cpsr & FLAG_Q != 0), q_out, "{line}: Q flag");
+        }
+    }
+
+    #[test]
+    fn halving_and_saturating_forms_leave_ge_untouched() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0;
+        cpu.regs[1] = 0x0000_7FFF;
+        cpu.regs[2] = 0x0000_0001;
+        cpu.cpsr |= 0b1010 << GE_SHIFT;
+        mem.write_u32(0, 0xE621_0F12); // qadd16 r0, r1, r2
+        mem.write_u32(4, 0xE631_3F12); // shadd16 r3, r1, r2
+
+        cpu.step(&mut mem).expect("qadd16 executes");
+        cpu.step(&mut mem).expect("shadd16 executes");
+
+        assert_eq!(cpu.regs[0], 0x0000_7FFF);
+        assert_eq!(cpu.regs[3], 0x0000_4000);
+        assert_eq!((cpu.cpsr & GE_MASK) >> GE_SHIFT, 0b1010);
+    }
+
+    #[test]
+    fn unallocated_media_encoding_is_undefined() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0;
+        mem.write_u32(0, 0xE601_0F12); // parallel add/sub with prefix 000
+
+        cpu.step(&mut mem).expect("step handles undefined");
+        assert!(matches!(
+            cpu.last_exception().map(|ex| ex.kind),
+            Some(super::super::ExceptionKind::UndefinedInstruction)
+        ));
+    }
+}
@@ -0,0 +1,423 @@
+//! Integer multiplies: 32/64-bit MUL family, the ARMv5TE/ARMv6 signed DSP
+//! multiplies and the saturating QADD/QSUB group.
+
+use super::media::signed_saturate;
+use super::{Arm11Cpu, FLAG_N, FLAG_Q, FLAG_Z, PC_INDEX};
+
+// Issue cycles per ARM1136/MPCore TRM, ignoring result interlocks.
+const CYCLES_MUL: u32 = 2;
+const CYCLES_MUL_FLAGS: u32 = 3;
+const CYCLES_MUL_LONG: u32 = 3;
+const CYCLES_MUL_LONG_FLAGS: u32 = 4;
+const CYCLES_DSP_MUL: u32 = 1;
+const CYCLES_DSP_MUL_LONG: u32 = 2;
+const CYCLES_MOST_SIGNIFICANT_MUL: u32 = 2;
+const CYCLES_SATURATING_ARITH: u32 = 1;
+
+fn halfword(value: u32, top: bool) -> i64 {
+    let half = if top { value >> 16 } else { value & 0xFFFF };
+    i64::from(half as u16 as i16)
+}
+
+fn fits_i32(value: i64) -> bool {
+    i64::from(value as i32) == value
+}
+
+impl Arm11Cpu {
+    /// Decodes the multiply encodings that live in the data-processing space.
+    pub(super) fn exec_multiply(&mut self, opcode: u32) -> Option<u32> {
+        self.exec_multiply_word(opcode)
+            .or_else(|| self.exec_multiply_long(opcode))
+            .or_else(|| self.exec_saturating_arith(opcode))
+            .or_else(|| self.exec_halfword_multiply(opcode))
+    }
+
+    /// Decodes the ARMv6 dual and most-significant-word multiplies.
+    pub(super) fn exec_media_multiply(&mut self, opcode: u32) -> Option<u32> {
+        self.exec_dual_multiply(opcode)
+            .or_else(|| self.exec_most_significant_multiply(opcode))
+    }
+
+    fn set_nz_flags(&mut self, negative: bool, zero: bool) {
+        self.set_flag(FLAG_N, negative);
+        self.set_flag(FLAG_Z, zero);
+    }
+
+    fn read_long(&self, rd_hi: usize, rd_lo: usize) -> u64 {
+        (u64::from(self.regs[rd_hi]) << 32) | u64::from(self.regs[rd_lo])
+    }
+
+    fn write_long(&mut self, rd_hi: usize, rd_lo: usize, value: u64) {
+        self.regs[rd_lo] = value as u32;
+        self.regs[rd_hi] = (value >> 32) as u32;
+    }
+
+    fn exec_multiply_word(&mut self, opcode: u32) -> Option<u32> {
+        if opcode & 0x0FC0_00F0 != 0x0000_0090 {
+            return None;
+        }
+        let accumulate = ((opcode >> 21) & 1) == 1;
+        let set_flags = ((opcode >> 20) & 1) == 1;
+        let rd = ((opcode >> 16) & 0xF) as usize;
+        let rn = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+
+        let mut result = self.regs[rm].wrapping_mul(self.regs[rs]);
+        if accumulate {
+            result = result.wrapping_add(self.regs[rn]);
+        }
+        self.regs[rd] = result;
+        if set_flags {
+            self.set_nz_flags(result & FLAG_N != 0, result == 0);
+            return Some(CYCLES_MUL_FLAGS);
+        }
+        Some(CYCLES_MUL)
+    }
+
+    fn exec_multiply_long(&mut self, opcode: u32) -> Option<u32> {
+        let rd_hi = ((opcode >> 16) & 0xF) as usize;
+        let rd_lo = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+
+        if opcode & 0x0FF0_00F0 == 0x0040_0090 {
+            // UMAAL: RdHi:RdLo = Rm * Rs + RdLo + RdHi, which can never overflow.
+            let result = u64::from(self.regs[rm]) * u64::from(self.regs[rs])
+                + u64::from(self.regs[rd_lo])
+                + u64::from(self.regs[rd_hi]);
+            self.write_long(rd_hi, rd_lo, result);
+            return Some(CYCLES_MUL_LONG);
+        }
+
+        if opcode & 0x0F80_00F0 != 0x0080_0090 {
+            return None;
+        }
+        let signed = ((opcode >> 22) & 1) == 1;
+        let accumulate = ((opcode >> 21) & 1) == 1;
+        let set_flags = ((opcode >> 20) & 1) == 1;
+
+        let mut result = if signed {
+            (i64::from(self.regs[rm] as i32) * i64::from(self.regs[rs] as i32)) as u64
+        } else {
+            u64::from(self.regs[rm]) * u64::from(self.regs[rs])
+        };
+        if accumulate {
+            result = result.wrapping_add(self.read_long(rd_hi, rd_lo));
+        }
+        self.write_long(rd_hi, rd_lo, result);
+        if set_flags {
+            self.set_nz_flags(result >> 63 != 0, result == 0);
+            return Some(CYCLES_MUL_LONG_FLAGS);
+        }
+        Some(CYCLES_MUL_LONG)
+    }
+
+    fn exec_saturating_arith(&mut self, opcode: u32) -> Option<u32> {
+        if opcode & 0x0F90_0FF0 != 0x0100_0050 {
+            return None;
+        }
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let rd = ((opcode >> 12) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let subtract = ((opcode >> 21) & 1) == 1;
+        let doubling = ((opcode >> 22) & 1) == 1;
+
+        let mut saturated = false;
+        let mut operand = i64::from(self.regs[rn] as i32);
+        if doubling {
+            let (doubled, sat) = signed_saturate(operand * 2, 32);
+            operand = doubled;
+            saturated |= sat;
+        }
+        let lhs = i64::from(self.regs[rm] as i32);
+        let (result, sat) = signed_saturate(
+            if subtract {
+                lhs - operand
+            } else {
+                lhs + operand
+            },
+            32,
+        );
+        saturated |= sat;
+        self.regs[rd] = result as u32;
+        if saturated {
+            self.cpsr |= FLAG_Q;
+        }
+        Some(CYCLES_SATURATING_ARITH)
+    }
+
+    fn exec_halfword_multiply(&mut self, opcode: u32) -> Option<u32> {
+        if opcode & 0x0F90_0090 != 0x0100_0080 {
+            return None;
+        }
+        let rd = ((opcode >> 16) & 0xF) as usize;
+        let rn = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let x_top = ((opcode >> 5) & 1) == 1;
+        let y_top = ((opcode >> 6) & 1) == 1;
+        let rs_half = halfword(self.regs[rs], y_top);
+
+        match (opcode >> 21) & 0x3 {
+            0b00 => {
+                // SMLAxy
+                let product = halfword(self.regs[rm], x_top) * rs_half;
+                let result = product + i64::from(self.regs[rn] as i32);
+                self.regs[rd] = result as u32;
+                if !fits_i32(result) {
+                    self.cpsr |= FLAG_Q;
+                }
+                Some(CYCLES_DSP_MUL)
+            }
+            0b01 => {
+                // SMLAWy when bit 5 is clear, SMULWy when it is set.
+                let product = (i64::from(self.regs[rm] as i32) * rs_half) >> 16;
+                if x_top {
+                    self.regs[rd] = product as u32;
+                } else {
+                    let result = product + i64::from(self.regs[rn] as i32);
+                    self.regs[rd] = result as u32;
+                    if !fits_i32(result) {
+                        self.cpsr |= FLAG_Q;
+                    }
+                }
+                Some(CYCLES_DSP_MUL)
+            }
+            0b10 => {
+                // SMLALxy uses Rd/Rn as RdHi/RdLo.
+                let product = halfword(self.regs[rm], x_top) * rs_half;
+                let result = self.read_long(rd, rn).wrapping_add(product as u64);
+                self.write_long(rd, rn, result);
+                Some(CYCLES_DSP_MUL_LONG)
+            }
+            _ => {
+                // SMULxy
+                self.regs[rd] = (halfword(self.regs[rm], x_top) * rs_half) as u32;
+                Some(CYCLES_DSP_MUL)
+            }
+        }
+    }
+
+    fn exec_dual_multiply(&mut self, opcode: u32) -> Option<u32> {
+        let long = match opcode & 0x0FF0_0090 {
+            0x0700_0010 => false,
+            0x0740_0010 => true,
+            _ => return None,
+        };
+        if (opcode >> 7) & 1 != 0 {
+            return None;
+        }
+        let rd = ((opcode >> 16) & 0xF) as usize;
+        let rn = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let subtract = ((opcode >> 6) & 1) == 1;
+        let exchange = ((opcode >> 5) & 1) == 1;
+
+        let rs_value = if exchange {
+            self.regs[rs].rotate_right(16)
+        } else {
+            self.regs[rs]
+        };
+        let low = halfword(self.regs[rm], false) * halfword(rs_value, false);
+        let high = halfword(self.regs[rm], true) * halfword(rs_value, true);
+        let sum = if subtract { low - high } else { low + high };
+
+        if long {
+            // SMLALD / SMLSLD accumulate into RdHi:RdLo without touching Q.
+            let result = self.read_long(rd, rn).wrapping_add(sum as u64);
+            self.write_long(rd, rn, result);
+            return Some(CYCLES_DSP_MUL_LONG);
+        }
+
+        // SMUAD / SMUSD when Rn is the PC, SMLAD / SMLSD otherwise.
+        let result = if rn == PC_INDEX {
+            sum
+        } else {
+            sum + i64::from(self.regs[rn] as i32)
+        };
+        self.regs[rd] = result as u32;
+        if !fits_i32(result) {
+            self.cpsr |= FLAG_Q;
+        }
+        Some(CYCLES_DSP_MUL)
+    }
+
+    fn exec_most_significant_multiply(&mut self, opcode: u32) -> Option<u32> {
+        let subtract = match opcode & 0x0FF0_00D0 {
+            0x0750_0010 => false,
+            0x0750_00D0 => true,
+            _ => return None,
+        };
+        let rd = ((opcode >> 16) & 0xF) as usize;
+        let rn = ((opcode >> 12) & 0xF) as usize;
+        let rs = ((opcode >> 8) & 0xF) as usize;
+        let rm = (opcode & 0xF) as usize;
+        let round = ((opcode >> 5) & 1) == 1;
+
+        let product = i64::from(self.regs[rm] as i32) * i64::from(self.regs[rs] as i32);
+        let accumulator = if rn == PC_INDEX && !subtract {
+            0
+        } else {
+            i64::from(self.regs[rn] as i32) << 32
+        };
+        let mut result = if subtract {
+            accumulator.wrapping_sub(product)
+        } else {
+            accumulator.wrapping_add(product)
+        };
+        if round {
+            result = result.wrapping_add(0x8000_0000);
+        }
+        self.regs[rd] = (result >> 32) as u32;
+        Some(CYCLES_MOST_SIGNIFICANT_MUL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, FLAG_C, FLAG_N, FLAG_Q, FLAG_Z, PC_INDEX};
+    use crate::core::memory::Memory;
+
+    fn run_one(cpu: &mut Arm11Cpu, opcode: u32) -> u32 {
+        let mut mem = Memory::flat();
+        cpu.regs[PC_INDEX] = 0;
+        mem.write_u32(0, opcode);
+        let cycles = cpu.step(&mut mem).expect("multiply executes");
+        assert!(
+            cpu.last_exception().is_none(),
+            "0x{opcode:08x} raised an exception"
+        );
+        cycles
+    }
+
+    #[test]
+    fn mul_sets_nz_and_preserves_carry() {
+        let mut cpu = Arm11Cpu::new();
+        cpu.cpsr |= FLAG_C;
+        cpu.regs[1] = 0xFFFF_FFFF;
+        cpu.regs[2] = 2;
+        let cycles = run_one(&mut cpu, 0xE010_0291); // muls r0, r1, r2
+
+        assert_eq!(cpu.regs[0], 0xFFFF_FFFE);
+        assert_ne!(cpu.cpsr & FLAG_N, 0);
+        assert_eq!(cpu.cpsr & FLAG_Z, 0);
+        assert_ne!(cpu.cpsr & FLAG_C, 0);
+        assert_eq!(cycles, 3);
+    }
+
+    #[test]
+    fn long_multiplies_produce_64_bit_results() {
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[2] = 0xFFFF_FFFF;
+        cpu.regs[3] = 0xFFFF_FFFF;
+        assert_eq!(run_one(&mut cpu, 0xE081_0392), 3); // umull r0, r1, r2, r3
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (0xFFFF_FFFE, 0x0000_0001));
+
+        run_one(&mut cpu, 0xE0C1_0392); // smull r0, r1, r2, r3
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (0, 1));
+
+        cpu.regs[0] = 0xFFFF_FFFF;
+        cpu.regs[1] = 0;
+        run_one(&mut cpu, 0xE0E1_0392); // smlal r0, r1, r2, r3
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (1, 0));
+
+        cpu.regs[0] = 0xFFFF_FFFF;
+        cpu.regs[1] = 0xFFFF_FFFF;
+        run_one(&mut cpu, 0xE041_0392); // umaal r0, r1, r2, r3
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (0xFFFF_FFFF, 0xFFFF_FFFF));
+
+        cpu.regs[2] = 0;
+        assert_eq!(run_one(&mut cpu, 0xE091_0392), 4); // umulls r0, r1, r2, r3
+        assert_ne!(cpu.cpsr & FLAG_Z, 0);
+    }
+
+    #[test]
+    fn saturating_arithmetic_sets_sticky_q() {
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[1] = 0x7FFF_FFF0;
+        cpu.regs[2] = 0x100;
+        run_one(&mut cpu, 0xE102_0051); // qadd r0, r1, r2
+        assert_eq!(cpu.regs[0], 0x7FFF_FFFF);
+        assert_ne!(cpu.cpsr & FLAG_Q, 0);
+
+        cpu.regs[1] = 5;
+        cpu.regs[2] = 0x4000_0000;
+        run_one(&mut cpu, 0xE162_0051); // qdsub r0, r1, r2
+        assert_eq!(cpu.regs[0], 0x8000_0006);
+        assert_ne!(cpu.cpsr & FLAG_Q, 0, "Q stays set once raised");
+
+        cpu.cpsr &= !FLAG_Q;
+        cpu.regs[1] = 3;
+        cpu.regs[2] = 4;
+        run_one(&mut cpu, 0xE122_0051); // qsub r0, r1, r2
+        assert_eq!(cpu.regs[0], 0xFFFF_FFFF);
+        assert_eq!(cpu.cpsr & FLAG_Q, 0);
+    }
+
+    #[test]
+    fn halfword_multiplies_select_operands_and_flag_overflow() {
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[1] = 0x0003_FFFE; // top 3, bottom -2
+        cpu.regs[2] = 0x0005_0007; // top 5, bottom 7
+        run_one(&mut cpu, 0xE160_02A1); // smultb r0, r1, r2
+        assert_eq!(cpu.regs[0], 21);
+        run_one(&mut cpu, 0xE160_02C1); // smulbt r0, r1, r2
+        assert_eq!(cpu.regs[0], (-10_i32) as u32);
+
+        cpu.regs[1] = 0x8000;
+        cpu.regs[2] = 0x8000;
+        cpu.regs[3] = 0x7FFF_FFFF;
+        run_one(&mut cpu, 0xE100_3281); // smlabb r0, r1, r2, r3
+        assert_eq!(cpu.regs[0], 0xBFFF_FFFF);
+        assert_ne!(cpu.cpsr & FLAG_Q, 0);
+
+        cpu.regs[1] = 0x0001_0000;
+        cpu.regs[2] = 0x0000_0004;
+        run_one(&mut cpu, 0xE120_02A1); // smulwb r0, r1, r2
+        assert_eq!(cpu.regs[0], 4);
+
+        cpu.regs[3] = 0xFFFF_FFFF;
+        cpu.regs[4] = 0xFFFF_FFFF;
+        cpu.regs[1] = 0x0000_0002;
+        cpu.regs[2] = 0x0000_0003;
+        assert_eq!(run_one(&mut cpu, 0xE144_3281), 2); // smlalbb r3, r4, r1, r2
+        assert_eq!((cpu.regs[4], cpu.regs[3]), (0, 5));
+    }
+
+    #[test]
+    fn dual_and_most_significant_multiplies() {
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[1] = 0x0002_0003;
+        cpu.regs[2] = 0x0004_0005;
+        run_one(&mut cpu, 0xE700_F211); // smuad r0, r1, r2
+        assert_eq!(cpu.regs[0], 23);
+        run_one(&mut cpu, 0xE700_F231); // smuadx r0, r1, r2
+        assert_eq!(cpu.regs[0], 22);
+        cpu.regs[3] = 100;
+        run_one(&mut cpu, 0xE700_3251); // smlsd r0, r1, r2, r3
+        assert_eq!(cpu.regs[0], 107);
+
+        cpu.regs[1] = 0x8000_8000;
+        cpu.regs[2] = 0x8000_8000;
+        run_one(&mut cpu, 0xE700_F211); // smuad r0, r1, r2
+        assert_eq!(cpu.regs[0], 0x8000_0000);
+        assert_ne!(cpu.cpsr & FLAG_Q, 0);
+
+        cpu.regs[3] = 0;
+        cpu.regs[4] = 0;
+        run_one(&mut cpu, 0xE744_3211); // smlald r3, r4, r1, r2
+        assert_eq!((cpu.regs[4], cpu.regs[3]), (0, 0x8000_0000));
+
+        cpu.regs[1] = 0x4000_0000;
+        cpu.regs[2] = 0x0000_0003;
+        run_one(&mut cpu, 0xE750_F211); // smmul r0, r1, r2
+        assert_eq!(cpu.regs[0], 0);
+        run_one(&mut cpu, 0xE750_F231); // smmulr r0, r1, r2
+        assert_eq!(cpu.regs[0], 1);
+        cpu.regs[3] = 10;
+        run_one(&mut cpu, 0xE750_32D1); // smmls r0, r1, r2, r3
+        assert_eq!(cpu.regs[0], 9);
+    }
+}
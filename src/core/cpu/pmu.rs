@@ -0,0 +1,288 @@
+//! ARM11 performance monitor (CP15 c15, c12): PMNC, the cycle counter CCNT
+//! and the event counters PMN0/PMN1.
+//!
+//! There is no cache or branch predictor model, so two events are
+//! approximated: an I-cache miss is a fetch from a 32-byte line other than
+//! the one just executed from or the next, and a mispredicted branch is a
+//! conditional branch going against static backward-taken/forward-not-taken
+//! prediction. TLB events come from the `Mmu` counters. Other event numbers
+//! never count.
+
+use super::super::mmu::TlbStats;
+use super::Arm11Cpu;
+
+const PMNC_ENABLE: u32 = 1;
+const PMNC_RESET_EVENT_COUNTERS: u32 = 1 << 1;
+const PMNC_RESET_CCNT: u32 = 1 << 2;
+/// CCNT counts every 64th cycle.
+const PMNC_DIVIDER: u32 = 1 << 3;
+/// Interrupt enables for PMN0, PMN1 and CCNT.
+const PMNC_IRQ_ENABLE_SHIFT: u32 = 4;
+/// Overflow flags for PMN0, PMN1 and CCNT; writing one clears a flag.
+const PMNC_OVERFLOW_SHIFT: u32 = 8;
+const PMNC_OVERFLOW_MASK: u32 = 0b111 << PMNC_OVERFLOW_SHIFT;
+const PMNC_EVT_COUNT1_SHIFT: u32 = 12;
+const PMNC_EVT_COUNT0_SHIFT: u32 = 20;
+/// E, D, the interrupt enables, X and both event selections.
+const PMNC_WRITABLE: u32 = 0x0FFF_F879;
+
+const CCNT_DIVISOR: u32 = 64;
+const CCNT_INDEX: u32 = 2;
+const CACHE_LINE_SHIFT: u32 = 5;
+
+const EVENT_ICACHE_MISS: u32 = 0x00;
+const EVENT_DATA_MICRO_TLB_MISS: u32 = 0x04;
+const EVENT_BRANCH_EXECUTED: u32 = 0x05;
+const EVENT_BRANCH_MISPREDICTED: u32 = 0x06;
+const EVENT_INSTRUCTION_EXECUTED: u32 = 0x07;
+const EVENT_SOFTWARE_PC_CHANGE: u32 = 0x0D;
+const EVENT_MAIN_TLB_MISS: u32 = 0x0F;
+const EVENT_CYCLE: u32 = 0xFF;
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Pmu {
+    pmnc: u32,
+    ccnt: u32,
+    pmn: [u32; 2],
+    /// Cycles towards the next CCNT tick while the divider is on.
+    prescaler: u32,
+    last_line: Option<u32>,
+    last_tlb: TlbStats,
+}
+
+/// What one step did, in terms of the countable events.
+#[derive(Debug, Clone, Copy, Default)]
+struct StepEvents {
+    cycles: u32,
+    instructions: u32,
+    icache_misses: u32,
+    branches: u32,
+    mispredicts: u32,
+    pc_changes: u32,
+    data_micro_tlb_misses: u32,
+    main_tlb_misses: u32,
+}
+
+impl StepEvents {
+    fn count(&self, event: u32) -> u32 {
+        match event {
+            EVENT_ICACHE_MISS => self.icache_misses,
+            EVENT_DATA_MICRO_TLB_MISS => self.data_micro_tlb_misses,
+            EVENT_BRANCH_EXECUTED => self.branches,
+            EVENT_BRANCH_MISPREDICTED => self.mispredicts,
+            EVENT_INSTRUCTION_EXECUTED => self.instructions,
+            EVENT_SOFTWARE_PC_CHANGE => self.pc_changes,
+            EVENT_MAIN_TLB_MISS => self.main_tlb_misses,
+            EVENT_CYCLE => self.cycles,
+            _ => 0,
+        }
+    }
+}
+
+impl Pmu {
+    pub(super) fn enabled(&self) -> bool {
+        self.pmnc & PMNC_ENABLE != 0
+    }
+
+    pub(super) fn read(&self, opc2: u32) -> u32 {
+        match opc2 {
+            0 => self.pmnc,
+            1 => self.ccnt,
+            2 => self.pmn[0],
+            3 => self.pmn[1],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, opc2: u32, value: u32) {
+        match opc2 {
+            0 => {
+                let flags = self.pmnc & PMNC_OVERFLOW_MASK & !value;
+                self.pmnc = (value & PMNC_WRITABLE) | flags;
+                if value & PMNC_RESET_EVENT_COUNTERS != 0 {
+                    self.pmn = [0; 2];
+                }
+                if value & PMNC_RESET_CCNT != 0 {
+                    self.ccnt = 0;
+                    self.prescaler = 0;
+                }
+            }
+            1 => self.ccnt = value,
+            2 => self.pmn[0] = value,
+            3 => self.pmn[1] = value,
+            _ => {}
+        }
+    }
+
+    /// An overflow flag is set with its interrupt enabled.
+    pub(super) fn interrupt_asserted(&self) -> bool {
+        let flags = (self.pmnc >> PMNC_OVERFLOW_SHIFT) & 0b111;
+        let enables = (self.pmnc >> PMNC_IRQ_ENABLE_SHIFT) & 0b111;
+        flags & enables != 0
+    }
+
+    fn add(&mut self, index: u32, amount: u32) {
+        let counter = match index {
+            CCNT_INDEX => &mut self.ccnt,
+            _ => &mut self.pmn[index as usize],
+        };
+        let (value, overflowed) = counter.overflowing_add(amount);
+        *counter = value;
+        if overflowed {
+            self.pmnc |= 1 << (PMNC_OVERFLOW_SHIFT + index);
+        }
+    }
+
+    fn count(&mut self, events: &StepEvents) {
+        let ticks = if self.pmnc & PMNC_DIVIDER != 0 {
+            self.prescaler += events.cycles;
+            let ticks = self.prescaler / CCNT_DIVISOR;
+            self.prescaler %= CCNT_DIVISOR;
+            ticks
+        } else {
+            events.cycles
+        };
+        self.add(CCNT_INDEX, ticks);
+        let selected = [
+            (self.pmnc >> PMNC_EVT_COUNT0_SHIFT) & 0xFF,
+            (self.pmnc >> PMNC_EVT_COUNT1_SHIFT) & 0xFF,
+        ];
+        for (index, event) in selected.into_iter().enumerate() {
+            self.add(index as u32, events.count(event));
+        }
+    }
+}
+
+impl Arm11Cpu {
+    /// Whether this core's performance monitor is requesting its overflow
+    /// interrupt.
+    pub fn pmu_interrupt_asserted(&self) -> bool {
+        self.pmu.interrupt_asserted()
+    }
+
+    /// Accesses PMNC, CCNT, PMN0 or PMN1; TLB events count from here on.
+    pub(super) fn write_pmu(&mut self, opc2: u32, value: u32) {
+        self.pmu.write(opc2, value);
+        self.pmu.last_tlb = self.mmu.tlb_stats();
+    }
+
+    /// Counts the instruction `step` just ran from `pc`, which took `cycles`.
+    pub(super) fn count_pmu_events(&mut self, pc: u32, cycles: u32) {
+        let tlb = self.mmu.tlb_stats();
+        let last_tlb = std::mem::replace(&mut self.pmu.last_tlb, tlb);
+        let mut events = StepEvents {
+            cycles,
+            data_micro_tlb_misses: tlb
+                .data_micro_misses
+                .saturating_sub(last_tlb.data_micro_misses)
+                as u32,
+            main_tlb_misses: tlb.misses.saturating_sub(last_tlb.misses) as u32,
+            ..StepEvents::default()
+        };
+
+        if let Some((opcode, thumb)) = self.cycles.retired {
+            let width = if thumb { 2 } else { 4 };
+            let line = pc >> CACHE_LINE_SHIFT;
+            let sequential = self
+                .pmu
+                .last_line
+                .is_some_and(|last| last == line || last + 1 == line);
+            self.pmu.last_line = Some(line);
+            events.instructions = 1;
+            events.icache_misses = u32::from(!sequential);
+
+            let taken = self.pc() != pc.wrapping_add(width) && !self.exception_entered;
+            match branch_kind(opcode, thumb) {
+                Some(conditional_backward) => {
+                    events.branches = 1;
+                    events.mispredicts = conditional_backward
+                        .is_some_and(|backward| taken != backward)
+                        .into();
+                }
+                None => events.pc_changes = taken.into(),
+            }
+        }
+        self.pmu.count(&events);
+    }
+}
+
+/// For a `B`/`BL`/`BLX <imm>`, whether it is conditional and if so whether it
+/// branches backwards; `None` for anything else.
+fn branch_kind(opcode: u32, thumb: bool) -> Option<Option<bool>> {
+    if thumb {
+        let opcode = opcode as u16;
+        match opcode >> 11 {
+            0b11010 | 0b11011 if opcode & 0x0E00 != 0x0E00 => Some(Some(opcode & 0x80 != 0)),
+            0b11100 | 0b11101 | 0b11111 => Some(None),
+            _ => None,
+        }
+    } else if (opcode >> 25) & 0x7 == 0b101 {
+        let cond = opcode >> 28;
+        let conditional = cond != 0xE && cond != 0xF;
+        Some(conditional.then_some(opcode & (1 << 23) != 0))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cpu::PC_INDEX;
+    use crate::core::memory::Memory;
+
+    fn run(cpu: &mut Arm11Cpu, memory: &mut Memory, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(memory).unwrap_or_else(|e| panic!("step: {e}"));
+        }
+    }
+
+    #[test]
+    fn event_counters_follow_their_selected_events() {
+        let mut memory = Memory::flat();
+        let program = [
+            0xE3A0_0003, // mov r0, #3
+            0xE250_0001, // subs r0, r0, #1
+            0x1AFF_FFFD, // bne 0x1004
+            0x0A00_0000, // beq 0x1014
+        ];
+        for (i, opcode) in program.into_iter().enumerate() {
+            memory.write_u32(0x1000 + 4 * i as u32, opcode);
+        }
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[PC_INDEX] = 0x1000;
+        let events = (EVENT_INSTRUCTION_EXECUTED << PMNC_EVT_COUNT0_SHIFT)
+            | (EVENT_BRANCH_MISPREDICTED << PMNC_EVT_COUNT1_SHIFT);
+        cpu.write_pmu(0, PMNC_ENABLE | events);
+
+        run(&mut cpu, &mut memory, 8);
+        assert_eq!(cpu.pc(), 0x1014);
+        assert_eq!(cpu.pmu.read(2), 8);
+        // The final backward BNE falls through and the forward BEQ is taken.
+        assert_eq!(cpu.pmu.read(3), 2);
+        assert_eq!(cpu.pmu.read(1), 11);
+    }
+
+    #[test]
+    fn cycle_counter_divides_overflows_and_interrupts() {
+        let mut memory = Memory::flat();
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[PC_INDEX] = 0x1000;
+        // Zeroed memory decodes as ANDEQ, which fails its condition.
+        cpu.write_pmu(0, PMNC_ENABLE | PMNC_DIVIDER);
+        run(&mut cpu, &mut memory, 130);
+        assert_eq!(cpu.pmu.read(1), 2);
+
+        cpu.write_pmu(1, u32::MAX);
+        let irq_enable = 1 << (PMNC_IRQ_ENABLE_SHIFT + CCNT_INDEX);
+        cpu.write_pmu(0, PMNC_ENABLE | irq_enable);
+        run(&mut cpu, &mut memory, 1);
+        assert_eq!(cpu.pmu.read(1), 0);
+        assert!(cpu.pmu_interrupt_asserted());
+
+        let overflow = 1 << (PMNC_OVERFLOW_SHIFT + CCNT_INDEX);
+        cpu.write_pmu(0, PMNC_ENABLE | irq_enable | overflow);
+        assert!(!cpu.pmu_interrupt_asserted());
+        assert_eq!(cpu.pmu.read(0), PMNC_ENABLE | irq_enable);
+    }
+}
@@ -0,0 +1,844 @@
+//! Thumb-1 (ARMv6) instruction decoding and execution.
+
+use super::super::bus::Bus;
+use super::super::error::Result;
+use super::media::sign_extend;
+use super::{
+    Arm11Cpu, ExceptionKind, FLAG_C, FLAG_E, FLAG_N, FaultKind, LR_INDEX, PC_INDEX, SP_INDEX,
+};
+
+const CYCLES_THUMB_LOAD_STORE: u32 = 3;
+const CYCLES_THUMB_BLOCK_TRANSFER: u32 = 4;
+const CYCLES_THUMB_EXCEPTION: u32 = 3;
+
+impl Arm11Cpu {
+    pub(super) fn step_thumb(&mut self, memory: &mut dyn Bus) -> Result<u32> {
+        let pc = self.pc();
+        match self.fetch_thumb_instruction(memory, pc) {
+            Ok(opcode) => self.execute_thumb(memory, pc, opcode),
+            Err(kind) => {
+                self.take_prefetch_abort(kind, pc, 0);
+                Ok(CYCLES_THUMB_EXCEPTION)
+            }
+        }
+    }
+
+    pub(super) fn execute_thumb(
+        &mut self,
+        memory: &mut dyn Bus,
+        pc: u32,
+        opcode: u16,
+    ) -> Result<u32> {
+        self.record_trace(pc, u32::from(opcode), true);
+        self.regs[PC_INDEX] = pc.wrapping_add(2);
+
+        if opcode & 0xFF00 == 0xDF00 {
+            self.take_exception(
+                ExceptionKind::SoftwareInterrupt,
+                pc,
+                u32::from(opcode),
+                true,
+            );
+            return Ok(CYCLES_THUMB_EXCEPTION);
+        }
+
+        if opcode & 0xFF00 == 0xBE00 {
+            self.take_breakpoint(pc, u32::from(opcode));
+            return Ok(CYCLES_THUMB_EXCEPTION);
+        }
+
+        if self.exec_thumb_shift_imm(opcode)
+            || self.exec_thumb_add_sub(opcode)
+            || self.exec_thumb_mov_cmp_add_sub_imm(opcode)
+            || self.exec_thumb_alu(opcode)
+            || self.exec_thumb_hi_reg_bx(opcode)
+            || self.exec_thumb_address(opcode)
+            || self.exec_thumb_adjust_sp(opcode)
+            || self.exec_thumb_misc(opcode)
+            || self.exec_thumb_cond_branch(opcode)
+            || self.exec_thumb_uncond_branch(opcode)
+            || self.exec_thumb_long_branch(opcode)
+        {
+            return Ok(1);
+        }
+
+        let transfer = self
+            .exec_thumb_ldr_literal(opcode, memory)
+            .and_then(|done| Ok(done || self.exec_thumb_load_store_reg(opcode, memory)?))
+            .and_then(|done| Ok(done || self.exec_thumb_load_store_imm(opcode, memory)?))
+            .and_then(|done| Ok(done || self.exec_thumb_load_store_sp(opcode, memory)?));
+        match transfer {
+            Ok(true) => return Ok(CYCLES_THUMB_LOAD_STORE),
+            Ok(false) => {}
+            Err(kind) => {
+                self.take_data_abort(kind, pc, u32::from(opcode));
+                return Ok(CYCLES_THUMB_EXCEPTION);
+            }
+        }
+
+        match self
+            .exec_thumb_push_pop(opcode, memory)
+            .and_then(|done| Ok(done || self.exec_thumb_block_transfer(opcode, memory)?))
+        {
+            Ok(true) => return Ok(CYCLES_THUMB_BLOCK_TRANSFER),
+            Ok(false) => {}
+            Err(kind) => {
+                self.take_data_abort(kind, pc, u32::from(opcode));
+                return Ok(CYCLES_THUMB_EXCEPTION);
+            }
+        }
+
+        self.take_exception(
+            ExceptionKind::UndefinedInstruction,
+            pc,
+            u32::from(opcode),
+            true,
+        );
+        Ok(CYCLES_THUMB_EXCEPTION)
+    }
+
+    /// Reads a register as a Thumb operand, where the PC reads as the
+    /// instruction address plus 4.
+    fn thumb_reg(&self, index: usize) -> u32 {
+        if index == PC_INDEX {
+            self.regs[PC_INDEX].wrapping_add(2)
+        } else {
+            self.regs[index]
+        }
+    }
+
+    fn exec_thumb_shift_imm(&mut self, opcode: u16) -> bool {
+        if (opcode >> 13) != 0 {
+            return false;
+        }
+        let op = (opcode >> 11) & 0x3;
+        let offset = u32::from((opcode >> 6) & 0x1F);
+        let rs = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let value = self.regs[rs];
+        let (result, carry) = match op {
+            0b00 => {
+                if offset == 0 {
+                    (value, self.cpsr & FLAG_C != 0)
+                } else {
+                    (value << offset, ((value >> (32 - offset)) & 1) != 0)
+                }
+            }
+            0b01 => {
+                let s = if offset == 0 { 32 } else { offset };
+                if s == 32 {
+                    (0, (value >> 31) != 0)
+                } else {
+                    (value >> s, ((value >> (s - 1)) & 1) != 0)
+                }
+            }
+            0b10 => {
+                let s = if offset == 0 { 32 } else { offset };
+                let result = if s == 32 {
+                    if (value >> 31) != 0 { u32::MAX } else { 0 }
+                } else {
+                    ((value as i32) >> s) as u32
+                };
+                (result, ((value >> (s.saturating_sub(1).min(31))) & 1) != 0)
+            }
+            _ => return false,
+        };
+
+        self.regs[rd] = result;
+        self.update_nzcv_logical(result, carry);
+        true
+    }
+
+    fn exec_thumb_add_sub(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xF800) != 0x1800 {
+            return false;
+        }
+        let immediate = ((opcode >> 10) & 1) != 0;
+        let sub = ((opcode >> 9) & 1) != 0;
+        let rn_or_imm = u32::from((opcode >> 6) & 0x7);
+        let rs = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let lhs = self.regs[rs];
+        let rhs = if immediate {
+            rn_or_imm
+        } else {
+            self.regs[rn_or_imm as usize]
+        };
+
+        self.regs[rd] = if sub {
+            self.thumb_sub_with_flags(lhs, rhs)
+        } else {
+            self.thumb_add_with_flags(lhs, rhs)
+        };
+        true
+    }
+
+    fn thumb_add_with_flags(&mut self, lhs: u32, rhs: u32) -> u32 {
+        let (res, carry) = lhs.overflowing_add(rhs);
+        let overflow = ((!(lhs ^ rhs)) & (lhs ^ res) & FLAG_N) != 0;
+        self.update_nzcv_arithmetic(res, carry, overflow);
+        res
+    }
+
+    fn thumb_sub_with_flags(&mut self, lhs: u32, rhs: u32) -> u32 {
+        let (res, borrow) = lhs.overflowing_sub(rhs);
+        let overflow = ((lhs ^ rhs) & (lhs ^ res) & FLAG_N) != 0;
+        self.update_nzcv_arithmetic(res, !borrow, overflow);
+        res
+    }
+
+    fn exec_thumb_mov_cmp_add_sub_imm(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xE000) != 0x2000 {
+            return false;
+        }
+        let op = (opcode >> 11) & 0x3;
+        let rd = usize::from((opcode >> 8) & 0x7);
+        let imm = u32::from(opcode & 0xFF);
+
+        match op {
+            0b00 => {
+                self.regs[rd] = imm;
+                self.update_nzcv_logical(imm, self.cpsr & FLAG_C != 0);
+            }
+            0b01 => {
+                self.thumb_sub_with_flags(self.regs[rd], imm);
+            }
+            0b10 => self.regs[rd] = self.thumb_add_with_flags(self.regs[rd], imm),
+            0b11 => self.regs[rd] = self.thumb_sub_with_flags(self.regs[rd], imm),
+            _ => return false,
+        }
+        true
+    }
+
+    fn exec_thumb_alu(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xFC00) != 0x4000 {
+            return false;
+        }
+        let op = (opcode >> 6) & 0xF;
+        let rm = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let lhs = self.regs[rd];
+        let rhs = self.regs[rm];
+        let carry_in = self.cpsr & FLAG_C != 0;
+
+        match op {
+            0x0 => {
+                self.regs[rd] = lhs & rhs;
+                self.update_nzcv_logical(lhs & rhs, carry_in);
+            }
+            0x1 => {
+                self.regs[rd] = lhs ^ rhs;
+                self.update_nzcv_logical(lhs ^ rhs, carry_in);
+            }
+            0x2..=0x4 | 0x7 => {
+                let shift_type = match op {
+                    0x2 => 0b00,
+                    0x3 => 0b01,
+                    0x4 => 0b10,
+                    _ => 0b11,
+                };
+                let (result, carry) = self.shift_by_register(lhs, rhs & 0xFF, shift_type);
+                self.regs[rd] = result;
+                self.update_nzcv_logical(result, carry);
+            }
+            0x5 => {
+                let (tmp, c1) = lhs.overflowing_add(rhs);
+                let (res, c2) = tmp.overflowing_add(u32::from(carry_in));
+                let overflow = ((!(lhs ^ rhs)) & (lhs ^ res) & FLAG_N) != 0;
+                self.regs[rd] = res;
+                self.update_nzcv_arithmetic(res, c1 || c2, overflow);
+            }
+            0x6 => {
+                let (tmp, b1) = lhs.overflowing_sub(rhs);
+                let (res, b2) = tmp.overflowing_sub(u32::from(!carry_in));
+                let overflow = ((lhs ^ rhs) & (lhs ^ res) & FLAG_N) != 0;
+                self.regs[rd] = res;
+                self.update_nzcv_arithmetic(res, !(b1 || b2), overflow);
+            }
+            0x8 => self.update_nzcv_logical(lhs & rhs, carry_in),
+            0x9 => self.regs[rd] = self.thumb_sub_with_flags(0, rhs),
+            0xA => {
+                self.thumb_sub_with_flags(lhs, rhs);
+            }
+            0xB => {
+                self.thumb_add_with_flags(lhs, rhs);
+            }
+            0xC => {
+                self.regs[rd] = lhs | rhs;
+                self.update_nzcv_logical(lhs | rhs, carry_in);
+            }
+            0xD => {
+                let result = lhs.wrapping_mul(rhs);
+                self.regs[rd] = result;
+                self.update_nzcv_logical(result, carry_in);
+            }
+            0xE => {
+                self.regs[rd] = lhs & !rhs;
+                self.update_nzcv_logical(lhs & !rhs, carry_in);
+            }
+            _ => {
+                self.regs[rd] = !rhs;
+                self.update_nzcv_logical(!rhs, carry_in);
+            }
+        }
+        true
+    }
+
+    fn exec_thumb_hi_reg_bx(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xFC00) != 0x4400 {
+            return false;
+        }
+        let op = (opcode >> 8) & 0x3;
+        let h1 = ((opcode >> 7) & 1) as usize;
+        let h2 = ((opcode >> 6) & 1) as usize;
+        let rs = usize::from((opcode >> 3) & 0x7) | (h2 << 3);
+        let rd = usize::from(opcode & 0x7) | (h1 << 3);
+
+        match op {
+            0b00 => {
+                let result = self.thumb_reg(rd).wrapping_add(self.thumb_reg(rs));
+                self.write_thumb_hi_reg(rd, result);
+            }
+            0b01 => {
+                self.thumb_sub_with_flags(self.thumb_reg(rd), self.thumb_reg(rs));
+            }
+            0b10 => self.write_thumb_hi_reg(rd, self.thumb_reg(rs)),
+            _ => {
+                let target = self.thumb_reg(rs);
+                if h1 == 1 {
+                    // BLX Rm
+                    self.regs[LR_INDEX] = self.regs[PC_INDEX] | 1;
+                }
+                self.set_thumb_state((target & 1) != 0);
+                self.regs[PC_INDEX] = target & !1;
+            }
+        }
+        true
+    }
+
+    fn write_thumb_hi_reg(&mut self, rd: usize, value: u32) {
+        self.regs[rd] = if rd == PC_INDEX { value & !1 } else { value };
+    }
+
+    fn exec_thumb_address(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xF000) != 0xA000 {
+            return false;
+        }
+        let rd = usize::from((opcode >> 8) & 0x7);
+        let imm = u32::from(opcode & 0xFF) << 2;
+        let base = if (opcode >> 11) & 1 != 0 {
+            self.regs[SP_INDEX]
+        } else {
+            self.thumb_reg(PC_INDEX) & !3
+        };
+        self.regs[rd] = base.wrapping_add(imm);
+        true
+    }
+
+    fn exec_thumb_adjust_sp(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xFF00) != 0xB000 {
+            return false;
+        }
+        let imm = u32::from(opcode & 0x7F) << 2;
+        self.regs[SP_INDEX] = if (opcode >> 7) & 1 != 0 {
+            self.regs[SP_INDEX].wrapping_sub(imm)
+        } else {
+            self.regs[SP_INDEX].wrapping_add(imm)
+        };
+        true
+    }
+
+    /// ARMv6 additions in the miscellaneous space: extends, byte reversal,
+    /// SETEND and CPS.
+    fn exec_thumb_misc(&mut self, opcode: u16) -> bool {
+        let rm = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let value = self.regs[rm];
+
+        match opcode & 0xFFC0 {
+            0xB200 => self.regs[rd] = sign_extend(value & 0xFFFF, 16) as u32,
+            0xB240 => self.regs[rd] = sign_extend(value & 0xFF, 8) as u32,
+            0xB280 => self.regs[rd] = value & 0xFFFF,
+            0xB2C0 => self.regs[rd] = value & 0xFF,
+            0xBA00 => self.regs[rd] = value.swap_bytes(),
+            0xBA40 => {
+                self.regs[rd] = ((value & 0xFF00_FF00) >> 8) | ((value & 0x00FF_00FF) << 8);
+            }
+            0xBAC0 => self.regs[rd] = (value as u16).swap_bytes() as i16 as i32 as u32,
+            _ => {
+                if opcode & 0xFFF7 == 0xB650 {
+                    self.set_flag(FLAG_E, (opcode >> 3) & 1 != 0);
+                    return true;
+                }
+                if opcode & 0xFFE8 == 0xB660 {
+                    let disable = (opcode >> 4) & 1 != 0;
+                    let aif = u32::from(opcode & 0x7) << 6;
+                    self.change_processor_state(Some(disable), aif, None);
+                    return true;
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn exec_thumb_cond_branch(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xF000) != 0xD000 || (opcode & 0x0E00) == 0x0E00 {
+            return false;
+        }
+        let cond = u32::from((opcode >> 8) & 0xF);
+        if !self.condition_passed(cond) {
+            self.cycles.skipped = true;
+            return true;
+        }
+        let offset = i32::from((opcode & 0xFF) as i8) << 1;
+        self.regs[PC_INDEX] = self.thumb_reg(PC_INDEX).wrapping_add(offset as u32);
+        true
+    }
+
+    fn exec_thumb_uncond_branch(&mut self, opcode: u16) -> bool {
+        if (opcode & 0xF800) != 0xE000 {
+            return false;
+        }
+        let offset = sign_extend(u32::from(opcode & 0x07FF), 11) << 1;
+        self.regs[PC_INDEX] = self.thumb_reg(PC_INDEX).wrapping_add(offset as u32);
+        true
+    }
+
+    /// BL/BLX are a prefix/suffix pair; the prefix parks the high offset in LR.
+    fn exec_thumb_long_branch(&mut self, opcode: u16) -> bool {
+        let offset = u32::from(opcode & 0x07FF);
+        match opcode & 0xF800 {
+            0xF000 => {
+                let high = (sign_extend(offset, 11) << 12) as u32;
+                self.regs[LR_INDEX] = self.thumb_reg(PC_INDEX).wrapping_add(high);
+            }
+            0xF800 => {
+                let target = self.regs[LR_INDEX].wrapping_add(offset << 1);
+                self.regs[LR_INDEX] = self.regs[PC_INDEX] | 1;
+                self.regs[PC_INDEX] = target & !1;
+            }
+            0xE800 if opcode & 1 == 0 => {
+                let target = self.regs[LR_INDEX].wrapping_add(offset << 1);
+                self.regs[LR_INDEX] = self.regs[PC_INDEX] | 1;
+                self.set_thumb_state(false);
+                self.regs[PC_INDEX] = target & !3;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn exec_thumb_ldr_literal(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        if (opcode & 0xF800) != 0x4800 {
+            return Ok(false);
+        }
+        let rd = usize::from((opcode >> 8) & 0x7);
+        let imm = u32::from(opcode & 0xFF) << 2;
+        let address = (self.thumb_reg(PC_INDEX) & !3).wrapping_add(imm);
+        self.regs[rd] = self.read_data(memory, address, 4)?;
+        Ok(true)
+    }
+
+    fn exec_thumb_load_store_reg(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        if (opcode & 0xF000) != 0x5000 {
+            return Ok(false);
+        }
+        let rm = usize::from((opcode >> 6) & 0x7);
+        let rn = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let address = self.regs[rn].wrapping_add(self.regs[rm]);
+
+        match (opcode >> 9) & 0x7 {
+            0b000 => self.write_data(memory, address, 4, self.regs[rd])?,
+            0b001 => self.write_data(memory, address, 2, self.regs[rd])?,
+            0b010 => self.write_data(memory, address, 1, self.regs[rd])?,
+            0b011 => {
+                let value = self.read_data(memory, address, 1)?;
+                self.regs[rd] = sign_extend(value, 8) as u32;
+            }
+            0b100 => self.regs[rd] = self.read_data(memory, address, 4)?,
+            0b101 => self.regs[rd] = self.read_data(memory, address, 2)?,
+            0b110 => self.regs[rd] = self.read_data(memory, address, 1)?,
+            _ => {
+                let value = self.read_data(memory, address, 2)?;
+                self.regs[rd] = sign_extend(value, 16) as u32;
+            }
+        }
+        Ok(true)
+    }
+
+    fn exec_thumb_load_store_imm(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        let size = match opcode & 0xF000 {
+            0x6000 => 4,
+            0x7000 => 1,
+            0x8000 => 2,
+            _ => return Ok(false),
+        };
+        let load = ((opcode >> 11) & 1) != 0;
+        let offset = u32::from((opcode >> 6) & 0x1F) * size;
+        let rb = usize::from((opcode >> 3) & 0x7);
+        let rd = usize::from(opcode & 0x7);
+        let address = self.regs[rb].wrapping_add(offset);
+
+        if load {
+            self.regs[rd] = self.read_data(memory, address, size)?;
+        } else {
+            self.write_data(memory, address, size, self.regs[rd])?;
+        }
+        Ok(true)
+    }
+
+    fn exec_thumb_load_store_sp(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        if (opcode & 0xF000) != 0x9000 {
+            return Ok(false);
+        }
+        let load = ((opcode >> 11) & 1) != 0;
+        let rd = usize::from((opcode >> 8) & 0x7);
+        let address = self.regs[SP_INDEX].wrapping_add(u32::from(opcode & 0xFF) << 2);
+        if load {
+            self.regs[rd] = self.read_data(memory, address, 4)?;
+        } else {
+            self.write_data(memory, address, 4, self.regs[rd])?;
+        }
+        Ok(true)
+    }
+
+    fn exec_thumb_push_pop(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        let pop = match opcode & 0xFE00 {
+            0xB400 => false,
+            0xBC00 => true,
+            _ => return Ok(false),
+        };
+        let extra = if (opcode >> 8) & 1 != 0 {
+            if pop { 1 << PC_INDEX } else { 1 << LR_INDEX }
+        } else {
+            0
+        };
+        let reg_list = u32::from(opcode & 0xFF) | extra;
+        if reg_list == 0 {
+            return Ok(false);
+        }
+
+        let span = reg_list.count_ones() * 4;
+        let sp = self.regs[SP_INDEX];
+        let mut addr = if pop { sp } else { sp.wrapping_sub(span) };
+        for reg in (0..16).filter(|reg| reg_list & (1 << reg) != 0) {
+            if pop {
+                self.regs[reg] = self.read_data(memory, addr, 4)?;
+            } else {
+                self.write_data(memory, addr, 4, self.regs[reg])?;
+            }
+            addr = addr.wrapping_add(4);
+        }
+        self.regs[SP_INDEX] = if pop {
+            sp.wrapping_add(span)
+        } else {
+            sp.wrapping_sub(span)
+        };
+
+        if pop && reg_list & (1 << PC_INDEX) != 0 {
+            self.set_thumb_state((self.regs[PC_INDEX] & 1) != 0);
+            self.regs[PC_INDEX] &= !1;
+        }
+        Ok(true)
+    }
+
+    fn exec_thumb_block_transfer(
+        &mut self,
+        opcode: u16,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<bool, FaultKind> {
+        if (opcode & 0xF000) != 0xC000 {
+            return Ok(false);
+        }
+        let load = ((opcode >> 11) & 1) != 0;
+        let rb = usize::from((opcode >> 8) & 0x7);
+        let reg_list = opcode & 0xFF;
+        if reg_list == 0 {
+            return Ok(false);
+        }
+
+        let base = self.regs[rb];
+        let mut addr = base;
+        for reg in (0..8).filter(|reg| reg_list & (1 << reg) != 0) {
+            if load {
+                self.regs[reg] = self.read_data(memory, addr, 4)?;
+            } else {
+                self.write_data(memory, addr, 4, self.regs[reg])?;
+            }
+            addr = addr.wrapping_add(4);
+        }
+        if !load || reg_list & (1 << rb) == 0 {
+            self.regs[rb] = base.wrapping_add(reg_list.count_ones() * 4);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        Arm11Cpu, ExceptionKind, FLAG_C, FLAG_E, FLAG_I, FLAG_N, FLAG_T, FLAG_Z, LR_INDEX,
+        MODE_SVC, PC_INDEX, SP_INDEX, VECTOR_PABT, VECTOR_SWI,
+    };
+    use crate::core::memory::Memory;
+
+    fn thumb_cpu(mem: &mut Memory, program: &[u16]) -> Arm11Cpu {
+        let mut cpu = Arm11Cpu::new();
+        cpu.cpsr |= FLAG_T;
+        cpu.regs[PC_INDEX] = 0;
+        for (idx, half) in program.iter().enumerate() {
+            let bytes = half.to_le_bytes();
+            mem.write_u8(idx as u32 * 2, bytes[0]);
+            mem.write_u8(idx as u32 * 2 + 1, bytes[1]);
+        }
+        cpu
+    }
+
+    fn run(cpu: &mut Arm11Cpu, mem: &mut Memory, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(mem).expect("thumb step");
+        }
+    }
+
+    #[test]
+    fn format4_alu_ops_update_flags() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0x4248, // neg r0, r1
+                0x4093, // lsl r3, r2
+                0x4354, // mul r4, r2
+                0x43CD, // mvn r5, r1
+                0x4208, // tst r0, r1
+            ],
+        );
+        cpu.regs[1] = 1;
+        cpu.regs[2] = 33;
+        cpu.regs[3] = 0x8000_0001;
+        cpu.regs[4] = 3;
+
+        run(&mut cpu, &mut mem, 1);
+        assert_eq!(cpu.regs[0], 0xFFFF_FFFF);
+        assert_ne!(cpu.cpsr & FLAG_N, 0);
+        assert_eq!(cpu.cpsr & FLAG_C, 0, "0 - 1 borrows");
+
+        run(&mut cpu, &mut mem, 1);
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!(cpu.cpsr & FLAG_C, 0, "shift by more than 32 clears carry");
+        assert_ne!(cpu.cpsr & FLAG_Z, 0);
+
+        run(&mut cpu, &mut mem, 3);
+        assert_eq!(cpu.regs[4], 99);
+        assert_eq!(cpu.regs[5], 0xFFFF_FFFE);
+        assert_eq!(cpu.cpsr & FLAG_Z, 0, "tst of 0xFFFFFFFF & 1 is non-zero");
+    }
+
+    #[test]
+    fn register_offset_and_signed_loads() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0x5250, // strh r0, [r2, r1]
+                0x5E53, // ldrsh r3, [r2, r1]
+                0x5654, // ldrsb r4, [r2, r1]
+                0x7895, // ldrb r5, [r2, #2]
+                0x8856, // ldrh r6, [r2, #2]
+            ],
+        );
+        cpu.regs[0] = 0x1234_8081;
+        cpu.regs[1] = 2;
+        cpu.regs[2] = 0x400;
+
+        run(&mut cpu, &mut mem, 5);
+        assert_eq!(mem.read_u8(0x402), 0x81);
+        assert_eq!(mem.read_u8(0x403), 0x80);
+        assert_eq!(cpu.regs[3], 0xFFFF_8081);
+        assert_eq!(cpu.regs[4], 0xFFFF_FF81);
+        assert_eq!(cpu.regs[5], 0x81);
+        assert_eq!(cpu.regs[6], 0x8081);
+    }
+
+    #[test]
+    fn push_pop_and_sp_relative_access() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0xB503, // push {r0, r1, lr}
+                0x9A01, // ldr r2, [sp, #4]
+                0xB082, // sub sp, #8
+                0xB002, // add sp, #8
+                0xBC18, // pop {r3, r4}
+                0xBD00, // pop {pc}
+            ],
+        );
+        cpu.regs[SP_INDEX] = 0x1000;
+        cpu.regs[0] = 0xAAAA;
+        cpu.regs[1] = 0xBBBB;
+        cpu.regs[LR_INDEX] = 0x201;
+
+        run(&mut cpu, &mut mem, 1);
+        assert_eq!(cpu.regs[SP_INDEX], 0x0FF4);
+        assert_eq!(mem.read_u32(0x0FFC), 0x201);
+
+        run(&mut cpu, &mut mem, 2);
+        assert_eq!(cpu.regs[2], 0xBBBB);
+        assert_eq!(cpu.regs[SP_INDEX], 0x0FEC);
+
+        run(&mut cpu, &mut mem, 3);
+        assert_eq!((cpu.regs[3], cpu.regs[4]), (0xAAAA, 0xBBBB));
+        assert_eq!(cpu.regs[SP_INDEX], 0x1000);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_ne!(
+            cpu.cpsr & FLAG_T,
+            0,
+            "pop {{pc}} with bit 0 set stays in Thumb"
+        );
+    }
+
+    #[test]
+    fn ldmia_stmia_write_back_base() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0xC006, // stmia r0!, {r1, r2}
+                0xCB03, // ldmia r3!, {r0, r1}
+                0xCD20, // ldmia r5!, {r5}
+            ],
+        );
+        cpu.regs[0] = 0x300;
+        cpu.regs[1] = 0x11;
+        cpu.regs[2] = 0x22;
+        cpu.regs[3] = 0x300;
+        cpu.regs[5] = 0x300;
+
+        run(&mut cpu, &mut mem, 3);
+        assert_eq!(cpu.regs[0], 0x11);
+        assert_eq!(cpu.regs[1], 0x22);
+        assert_eq!(cpu.regs[3], 0x308);
+        assert_eq!(cpu.regs[5], 0x11, "base in list is not written back");
+    }
+
+    #[test]
+    fn bl_pair_and_blx_suffix_link_and_switch_state() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0xF000, // bl +0x100 (prefix)
+                0xF880, // bl (suffix)
+            ],
+        );
+        run(&mut cpu, &mut mem, 2);
+        assert_eq!(cpu.pc(), 0x104);
+        assert_eq!(cpu.regs[LR_INDEX], 0x5);
+
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0x0000, // lsl r0, r0, #0
+                0xF000, // blx +0x100 (prefix)
+                0xE880, // blx (suffix)
+            ],
+        );
+        run(&mut cpu, &mut mem, 3);
+        assert_eq!(cpu.pc(), 0x104);
+        assert_eq!(cpu.regs[LR_INDEX], 0x7);
+        assert_eq!(cpu.cpsr & FLAG_T, 0, "blx suffix switches to ARM");
+    }
+
+    #[test]
+    fn pc_relative_operands_read_instruction_address_plus_four() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0x0000, // lsl r0, r0, #0
+                0xA001, // add r0, pc, #4
+                0x4901, // ldr r1, [pc, #4]
+                0xE7FF, // b +0 (next instruction)
+            ],
+        );
+        mem.write_u32(0x0C, 0xCAFE_F00D);
+
+        run(&mut cpu, &mut mem, 4);
+        assert_eq!(cpu.regs[0], 0x08, "pc is word-aligned before the add");
+        assert_eq!(cpu.regs[1], 0xCAFE_F00D);
+        assert_eq!(cpu.pc(), 0x08);
+    }
+
+    #[test]
+    fn armv6_misc_forms() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(
+            &mut mem,
+            &[
+                0xB248, // sxtb r0, r1
+                0xB28A, // uxth r2, r1
+                0xBA0B, // rev r3, r1
+                0xB658, // setend be
+                0xB672, // cpsid i
+                0x6021, // str r1, [r4]
+                0x8825, // ldrh r5, [r4]
+            ],
+        );
+        cpu.regs[1] = 0x1234_80F0;
+        cpu.regs[4] = 0x400;
+        cpu.switch_mode(MODE_SVC);
+
+        run(&mut cpu, &mut mem, 7);
+        assert_eq!(cpu.regs[0], 0xFFFF_FFF0);
+        assert_eq!(cpu.regs[2], 0x80F0);
+        assert_eq!(cpu.regs[3], 0xF080_3412);
+        assert_ne!(cpu.cpsr & FLAG_E, 0);
+        assert_ne!(cpu.cpsr & FLAG_I, 0);
+        assert_eq!(mem.read_u8(0x400), 0x12, "big-endian store");
+        assert_eq!(cpu.regs[5], 0x1234);
+    }
+
+    #[test]
+    fn swi_and_bkpt_take_exceptions_with_thumb_return_addresses() {
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(&mut mem, &[0xDF2A]); // swi #0x2a
+        run(&mut cpu, &mut mem, 1);
+        let ex = cpu.last_exception().expect("swi exception");
+        assert_eq!(ex.kind, ExceptionKind::SoftwareInterrupt);
+        assert_eq!(ex.vector, VECTOR_SWI);
+        assert_eq!(ex.return_address, 2);
+        assert_eq!(ex.swi_immediate(), 0x2A);
+
+        let mut mem = Memory::flat();
+        let mut cpu = thumb_cpu(&mut mem, &[0xBE01]); // bkpt #1
+        run(&mut cpu, &mut mem, 1);
+        let ex = cpu.last_exception().expect("bkpt exception");
+        assert_eq!(ex.kind, ExceptionKind::Breakpoint);
+        assert_eq!(ex.vector, VECTOR_PABT);
+        assert_eq!(ex.return_address, 4);
+    }
+}
@@ -0,0 +1,541 @@
+//! Block translation cache.
+//!
+//! Straight-line runs of ARM or Thumb code are decoded once into a compact IR
+//! keyed by virtual address, physical address and instruction set; ops carry
+//! absolute PCs and branch targets, so an alias of the same physical code
+//! gets a block of its own. Data-processing ops keep
+//! their operand and shift forms pre-decoded, branches their resolved target,
+//! and a backwards liveness pass defers flag updates that a later op
+//! overwrites before anything reads them. Everything else stays a raw opcode
+//! handed to the interpreter, so a cached step only ever saves the fetch and
+//! decode; `step` still retires one instruction at a time.
+//!
+//! Blocks are dropped when a write lands in their page, on TLB invalidation,
+//! on CP15 instruction-cache maintenance and when SCTLR, TTBR0 or TTBR1
+//! change. Buses that cannot report writes
+//! to code pages always interpret.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::super::bus::Bus;
+use super::super::code_pages::{CODE_PAGE_SIZE, code_page};
+use super::super::error::Result;
+use super::{
+    AluInstr, Arm11Cpu, FLAG_C, FLAG_N, FLAG_V, FLAG_Z, FlagUpdate, LR_INDEX, Operand2, PC_INDEX,
+};
+
+const MAX_BLOCK_OPS: usize = 32;
+const COND_AL: u32 = 0xE;
+const FLAGS_NZCV: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpKind {
+    Alu {
+        cond: u32,
+        instr: AluInstr,
+        update: FlagUpdate,
+    },
+    Branch {
+        cond: u32,
+        link: bool,
+        target: u32,
+    },
+    Interpret,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CachedOp {
+    pc: u32,
+    opcode: u32,
+    kind: OpKind,
+}
+
+#[derive(Debug)]
+struct Block {
+    page: u32,
+    thumb: bool,
+    ops: Vec<CachedOp>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct TranslationCache {
+    enabled: bool,
+    /// Keyed by `(va, pa, thumb)`.
+    blocks: HashMap<(u32, u32, bool), Rc<Block>>,
+    /// The block being executed and the index of its next op.
+    cursor: Option<(Rc<Block>, usize)>,
+}
+
+impl TranslationCache {
+    pub(super) fn new() -> Self {
+        Self {
+            enabled: true,
+            blocks: HashMap::new(),
+            cursor: None,
+        }
+    }
+
+    pub(super) fn flush(&mut self) {
+        self.blocks.clear();
+        self.cursor = None;
+    }
+
+    pub(super) fn leave_block(&mut self) {
+        self.cursor = None;
+    }
+
+    fn invalidate_page(&mut self, page: u32) {
+        self.blocks.retain(|_, block| block.page != page);
+        if self
+            .cursor
+            .as_ref()
+            .is_some_and(|(block, _)| block.page == page)
+        {
+            self.cursor = None;
+        }
+    }
+
+    fn next_in_block(&self, pc: u32, thumb: bool) -> Option<(Rc<Block>, usize)> {
+        let (block, index) = self.cursor.as_ref()?;
+        let op = block.ops.get(*index)?;
+        (block.thumb == thumb && op.pc == pc).then(|| (Rc::clone(block), *index))
+    }
+}
+
+impl Arm11Cpu {
+    /// Switches between the translation cache and plain interpretation, for
+    /// differential testing.
+    pub fn set_translation_cache_enabled(&mut self, enabled: bool) {
+        self.settle_flags();
+        self.translation.flush();
+        self.translation.enabled = enabled;
+    }
+
+    /// Executes the next instruction from the cache, or returns `None` when
+    /// it has to be interpreted (cache disabled, untracked bus, or a fetch
+    /// that would fault).
+    pub(super) fn step_translated(&mut self, memory: &mut dyn Bus) -> Option<Result<u32>> {
+        if !self.translation.enabled {
+            return None;
+        }
+        let core = self.core_id;
+        for page in memory.code_pages()?.take_dirty(core) {
+            self.translation.invalidate_page(page);
+        }
+
+        let pc = self.pc();
+        let thumb = self.is_thumb();
+        let (block, index) = match self.translation.next_in_block(pc, thumb) {
+            Some(position) => position,
+            None => {
+                self.settle_flags();
+                self.translation.leave_block();
+                (self.enter_block(memory, pc, thumb)?, 0)
+            }
+        };
+
+        let op = block.ops[index];
+        let continues = index + 1 < block.ops.len();
+        self.translation.cursor = continues.then(|| (block, index + 1));
+        Some(self.exec_cached_op(memory, op, thumb))
+    }
+
+    fn enter_block(&mut self, memory: &mut dyn Bus, pc: u32, thumb: bool) -> Option<Rc<Block>> {
+        let alignment = if thumb { 1 } else { 3 };
+        if pc & alignment != 0 {
+            return None;
+        }
+        let pa = self
+            .mmu
+            .translate_instruction(memory, pc, self.is_privileged())
+            .ok()?;
+        if let Some(block) = self.translation.blocks.get(&(pc, pa, thumb)) {
+            return Some(Rc::clone(block));
+        }
+
+        let block = Rc::new(translate_block(memory, pc, pa, thumb)?);
+        let core = self.core_id;
+        memory.code_pages()?.watch(core, pa);
+        self.translation
+            .blocks
+            .insert((pc, pa, thumb), Rc::clone(&block));
+        Some(block)
+    }
+
+    fn exec_cached_op(&mut self, memory: &mut dyn Bus, op: CachedOp, thumb: bool) -> Result<u32> {
+        match op.kind {
+            OpKind::Alu {
+                cond,
+                instr,
+                update,
+            } => {
+                self.record_trace(op.pc, op.opcode, thumb);
+                let width = if thumb { 2 } else { 4 };
+                self.regs[PC_INDEX] = op.pc.wrapping_add(width);
+                if cond != COND_AL {
+                    self.settle_flags();
+                    if !self.condition_passed(cond) {
+                        self.cycles.skipped = true;
+                        return Ok(1);
+                    }
+                }
+                self.exec_alu(instr, update);
+                Ok(1)
+            }
+            OpKind::Branch { cond, link, target } => {
+                self.settle_flags();
+                self.record_trace(op.pc, op.opcode, thumb);
+                self.regs[PC_INDEX] = op.pc.wrapping_add(4);
+                if !self.condition_passed(cond) {
+                    self.cycles.skipped = true;
+                    return Ok(1);
+                }
+                if link {
+                    self.regs[LR_INDEX] = op.pc.wrapping_add(4);
+                }
+                self.regs[PC_INDEX] = target;
+                Ok(2)
+            }
+            OpKind::Interpret => {
+                self.settle_flags();
+                if thumb {
+                    self.execute_thumb(memory, op.pc, op.opcode as u16)
+                } else {
+                    self.execute_arm(memory, op.pc, op.opcode)
+                }
+            }
+        }
+    }
+}
+
+/// Decodes from `pa` until a control-flow change, a page boundary or
+/// `MAX_BLOCK_OPS`.
+fn translate_block(memory: &mut dyn Bus, va: u32, pa: u32, thumb: bool) -> Option<Block> {
+    let width = if thumb { 2 } else { 4 };
+    let page = code_page(pa);
+    let mut ops = Vec::new();
+    let mut offset = 0;
+    while ops.len() < MAX_BLOCK_OPS && (pa - page) + offset < CODE_PAGE_SIZE {
+        let addr = pa + offset;
+        let (op, ends_block) = if thumb {
+            let Ok(opcode) = memory.fetch_u16(addr) else {
+                break;
+            };
+            decode_thumb(va + offset, opcode)
+        } else {
+            let Ok(opcode) = memory.fetch_u32(addr) else {
+                break;
+            };
+            decode_arm(va + offset, opcode)
+        };
+        ops.push(op);
+        offset += width;
+        if ends_block {
+            break;
+        }
+    }
+    if ops.is_empty() {
+        return None;
+    }
+    defer_dead_flags(&mut ops);
+    Some(Block { page, thumb, ops })
+}
+
+/// Walks the block backwards and defers every flag update whose flags are all
+/// overwritten before being read. Flags are assumed live at the block exit.
+fn defer_dead_flags(ops: &mut [CachedOp]) {
+    let mut live = FLAGS_NZCV;
+    for op in ops.iter_mut().rev() {
+        match &mut op.kind {
+            OpKind::Alu {
+                cond: COND_AL,
+                instr,
+                update,
+            } => {
+                let written = instr.flags_written();
+                if written != 0 && live & written == 0 {
+                    *update = FlagUpdate::Defer;
+                }
+                live = (live & !written) | instr.flags_read();
+            }
+            _ => live = FLAGS_NZCV,
+        }
+    }
+}
+
+/// Returns the op and whether the block has to end after it.
+fn decode_arm(pc: u32, opcode: u32) -> (CachedOp, bool) {
+    let cond = opcode >> 28;
+    let op = |kind| CachedOp { pc, opcode, kind };
+    if cond == 0xF {
+        return (op(OpKind::Interpret), true);
+    }
+
+    if (opcode >> 25) & 0x7 == 0b101 {
+        let mut offset = ((opcode & 0x00FF_FFFF) << 2) as i32;
+        if (offset & 0x0200_0000) != 0 {
+            offset |= !0x03FF_FFFF;
+        }
+        let kind = OpKind::Branch {
+            cond,
+            link: ((opcode >> 24) & 1) == 1,
+            target: pc.wrapping_add(8).wrapping_add(offset as u32),
+        };
+        return (op(kind), true);
+    }
+
+    let immediate = ((opcode >> 25) & 1) == 1;
+    let status_space = (opcode >> 23) & 0x3 == 0b10 && ((opcode >> 20) & 1) == 0;
+    if (opcode >> 26) & 0x3 == 0b00
+        && (immediate || opcode & 0x10 == 0)
+        && !status_space
+        && let Some(instr) = AluInstr::decode_arm(opcode)
+        && instr.rd != PC_INDEX
+    {
+        let kind = OpKind::Alu {
+            cond,
+            instr,
+            update: FlagUpdate::Write,
+        };
+        return (op(kind), false);
+    }
+
+    (op(OpKind::Interpret), !arm_falls_through(opcode))
+}
+
+/// Whether an interpreted ARM instruction always continues at the next word
+/// in the same mode. Anything not known to is treated as ending the block.
+fn arm_falls_through(opcode: u32) -> bool {
+    let rn = (opcode >> 16) & 0xF;
+    let rd = (opcode >> 12) & 0xF;
+    let load = ((opcode >> 20) & 1) == 1;
+    match (opcode >> 25) & 0x7 {
+        // Single data transfers; media instructions share the encoding space.
+        0b010 => rn != 15 && !(load && rd == 15),
+        0b011 => rn != 15 && rd != 15,
+        0b100 => rn != 15 && !(load && opcode & (1 << 15) != 0),
+        0b000 => {
+            let status_space = (opcode >> 23) & 0x3 == 0b10 && !load;
+            let multiply = opcode & 0x0F00_00F0 == 0x0000_0090;
+            let extra_transfer = opcode & 0x90 == 0x90 && opcode & 0x60 != 0;
+            let register_shift = opcode & 0x90 == 0x10;
+            (multiply || extra_transfer || (register_shift && !status_space))
+                && rn != 15
+                && rd != 15
+        }
+        _ => false,
+    }
+}
+
+fn decode_thumb(pc: u32, opcode: u16) -> (CachedOp, bool) {
+    let op = |kind| CachedOp {
+        pc,
+        opcode: u32::from(opcode),
+        kind,
+    };
+    if let Some(instr) = decode_thumb_alu(opcode) {
+        let kind = OpKind::Alu {
+            cond: COND_AL,
+            instr,
+            update: FlagUpdate::Write,
+        };
+        return (op(kind), false);
+    }
+    (op(OpKind::Interpret), !thumb_falls_through(opcode))
+}
+
+/// Formats 1-3 (shift by immediate, three-operand add/subtract and the 8-bit
+/// immediate ops) as ARM data-processing equivalents. All of them set flags.
+fn decode_thumb_alu(opcode: u16) -> Option<AluInstr> {
+    let low = |shift: u16| usize::from((opcode >> shift) & 0x7);
+    let (op, rn, rd, operand) = match opcode >> 11 {
+        0b00000..=0b00010 => (
+            0xD,
+            0,
+            low(0),
+            Operand2::ShiftedRegister {
+                rm: low(3),
+                shift_type: u32::from((opcode >> 11) & 0x3),
+                shift_imm: u32::from((opcode >> 6) & 0x1F),
+            },
+        ),
+        0b00011 => {
+            let operand = if ((opcode >> 10) & 1) == 1 {
+                Operand2::Immediate {
+                    value: u32::from((opcode >> 6) & 0x7),
+                    carry: None,
+                }
+            } else {
+                Operand2::ShiftedRegister {
+                    rm: low(6),
+                    shift_type: 0,
+                    shift_imm: 0,
+                }
+            };
+            let op = if ((opcode >> 9) & 1) == 1 { 0x2 } else { 0x4 };
+            (op, low(3), low(0), operand)
+        }
+        0b00100..=0b00111 => {
+            let op = match (opcode >> 11) & 0x3 {
+                0b00 => 0xD,
+                0b01 => 0xA,
+                0b10 => 0x4,
+                _ => 0x2,
+            };
+            let operand = Operand2::Immediate {
+                value: u32::from(opcode & 0xFF),
+                carry: None,
+            };
+            (op, low(8), low(8), operand)
+        }
+        _ => return None,
+    };
+    Some(AluInstr {
+        op,
+        set_flags: true,
+        rn,
+        rd,
+        operand,
+    })
+}
+
+/// Thumb counterpart of [`arm_falls_through`].
+fn thumb_falls_through(opcode: u16) -> bool {
+    match opcode >> 8 {
+        // Format-4 ALU, PC-relative load, register and immediate loads/stores,
+        // SP-relative transfers and ADD to PC/SP.
+        0x40..=0x43 | 0x48..=0xAF => true,
+        // SP adjust, extends, PUSH, REV and POP without PC.
+        0xB0 | 0xB2 | 0xB4 | 0xB5 | 0xBA | 0xBC => true,
+        // LDMIA/STMIA.
+        0xC0..=0xCF => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, ExceptionKind, FLAG_N, PC_INDEX, SP_INDEX};
+    use crate::core::bus::{Bus, SystemBus};
+    use crate::core::memory::Memory;
+
+    fn load(bus: &mut SystemBus, base: u32, program: &[u32]) {
+        for (i, opcode) in program.iter().enumerate() {
+            bus.write_u32(base + 4 * i as u32, *opcode);
+        }
+    }
+
+    fn run_pair(program: &[u32], thumb: bool, steps: usize) -> (Arm11Cpu, Arm11Cpu) {
+        let mut cached_bus = SystemBus::with_memory(Memory::flat());
+        let mut plain_bus = SystemBus::with_memory(Memory::flat());
+        load(&mut cached_bus, 0x1000, program);
+        load(&mut plain_bus, 0x1000, program);
+        let mut cached = Arm11Cpu::new();
+        let mut plain = Arm11Cpu::new();
+        plain.set_translation_cache_enabled(false);
+        for cpu in [&mut cached, &mut plain] {
+            cpu.regs[PC_INDEX] = 0x1000;
+            cpu.regs[SP_INDEX] = 0x8000;
+            cpu.set_thumb_state(thumb);
+            cpu.enable_instruction_trace(256);
+        }
+        for step in 0..steps {
+            let a = cached.step(&mut cached_bus).expect("cached step");
+            let b = plain.step(&mut plain_bus).expect("interpreted step");
+            assert_eq!(a, b, "cycles at step {step}");
+            assert_eq!(cached.regs, plain.regs, "registers at step {step}");
+            assert_eq!(cached.cpsr(), plain.cpsr(), "cpsr at step {step}");
+        }
+        assert_eq!(cached.instruction_trace(), plain.instruction_trace());
+        (cached, plain)
+    }
+
+    #[test]
+    fn arm_loop_matches_interpreter() {
+        let program = [
+            0xE3A0_000A, // mov r0, #10
+            0xE3A0_1000, // mov r1, #0
+            0xE091_1000, // adds r1, r1, r0
+            0xE1B0_2081, // movs r2, r1, lsl #1
+            0xE250_0001, // subs r0, r0, #1
+            0x1AFF_FFFB, // bne -> adds
+            0xE59D_3000, // ldr r3, [sp]
+            0xE320_F003, // halt
+        ];
+        let (cached, _) = run_pair(&program, false, 40);
+        assert_eq!(cached.regs[1], 55);
+        assert!(cached.translation.blocks.len() >= 2);
+    }
+
+    #[test]
+    fn thumb_block_matches_interpreter() {
+        let program = [
+            0x2105_2003, // movs r0, #3 ; movs r1, #5
+            0x1A42_1840, // adds r0, r0, r1 ; subs r2, r0, r1
+            0x4348_0040, // lsls r0, r0, #1 ; muls r0, r1
+            0xD1FB_2A00, // cmp r2, #0 ; bne -> lsls
+            0xE7FE_E7FE, // b . ; b .
+        ];
+        run_pair(&program, true, 24);
+    }
+
+    #[test]
+    fn dead_flags_are_recovered_on_exception_entry() {
+        let mut bus = SystemBus::with_memory(Memory::flat());
+        load(
+            &mut bus,
+            0x1000,
+            &[
+                0xE250_0001, // subs r0, r0, #1  (flags dead)
+                0xE290_1001, // adds r1, r0, #1
+            ],
+        );
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[PC_INDEX] = 0x1000;
+        cpu.step(&mut bus).expect("subs");
+        assert!(cpu.pending_flags.is_some(), "subs flags were deferred");
+        assert_ne!(cpu.cpsr() & FLAG_N, 0, "0 - 1 is negative");
+
+        cpu.take_exception(ExceptionKind::SoftwareInterrupt, 0x1004, 0, true);
+        assert_eq!(cpu.pending_flags, None);
+        assert_ne!(cpu.spsr_svc & FLAG_N, 0, "SPSR captured the deferred flags");
+    }
+
+    #[test]
+    fn writes_to_code_pages_invalidate_blocks() {
+        let mut bus = SystemBus::with_memory(Memory::flat());
+        load(&mut bus, 0x1000, &[0xE3A0_0001, 0xE320_F003]); // mov r0, #1 ; halt
+        let mut cpu = Arm11Cpu::new();
+        cpu.regs[PC_INDEX] = 0x1000;
+        cpu.step(&mut bus).expect("first pass");
+        assert_eq!(cpu.regs[0], 1);
+
+        bus.write_u32(0x1000, 0xE3A0_0002); // mov r0, #2
+        cpu.regs[PC_INDEX] = 0x1000;
+        cpu.step(&mut bus).expect("second pass");
+        assert_eq!(cpu.regs[0], 2, "stale block was not reused");
+    }
+
+    #[test]
+    fn aliases_of_one_physical_page_keep_their_own_pcs() {
+        let mut bus = SystemBus::with_memory(Memory::flat());
+        load(&mut bus, 0x0010_0000, &[0xE3A0_0001, 0xE320_F003]); // mov r0, #1 ; halt
+        // VA 0x00100000 and 0x00200000 both map PA 0x00100000.
+        bus.write_u32(0x4004, 0x0010_0C02);
+        bus.write_u32(0x4008, 0x0010_0C02);
+        let mut cpu = Arm11Cpu::new();
+        cpu.enter_address_space(0x4000);
+        cpu.regs[PC_INDEX] = 0x0010_0000;
+        cpu.step(&mut bus).expect("first alias");
+        assert_eq!(cpu.pc(), 0x0010_0004);
+
+        cpu.regs[PC_INDEX] = 0x0020_0000;
+        cpu.step(&mut bus).expect("second alias");
+        assert_eq!(cpu.pc(), 0x0020_0004);
+        assert_eq!(cpu.translation.blocks.len(), 2);
+
+        cpu.enter_address_space(0x4000);
+        assert!(cpu.translation.blocks.is_empty(), "TTBR0 write flushes");
+    }
+}
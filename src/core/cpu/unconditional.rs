@@ -0,0 +1,178 @@
+//! The ARM cond=0xF space: `CPS`, `SETEND`, `SRS`, `RFE`, `BLX <imm>`,
+//! `CLREX`, and `PLD` and the barriers as no-ops. Everything else in it is
+//! undefined.
+//!
+//! `SRS`/`RFE` move a two-word return frame (LR then SPSR, PC then CPSR)
+//! using the same P/U addressing as `LDM`/`STM`. `SRS` needs an SPSR and `RFE`
+//! needs privilege; where the architecture leaves them unpredictable (User or
+//! System mode for `SRS`, User mode for `RFE`) they are no-ops, like `CPS`.
+
+use super::super::bus::Bus;
+use super::{
+    Arm11Cpu, ExceptionKind, FLAG_A, FLAG_E, FLAG_F, FLAG_I, FLAG_T, FaultKind, LR_INDEX,
+    MODE_MASK, MODE_SYS, MODE_USR, PC_INDEX, SP_INDEX, is_valid_mode,
+};
+
+const CYCLES_PSR: u32 = 1;
+const CYCLES_BRANCH: u32 = 2;
+const CYCLES_FRAME: u32 = 3;
+const CYCLES_UNDEFINED: u32 = 3;
+
+impl Arm11Cpu {
+    /// Executes a cond=0xF opcode at `pc`, taking the undefined instruction
+    /// exception for encodings ARMv6 doesn't define.
+    pub(super) fn exec_unconditional(
+        &mut self,
+        opcode: u32,
+        pc: u32,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<u32, FaultKind> {
+        if self.exec_clrex(opcode) {
+            return Ok(CYCLES_PSR);
+        }
+
+        // `PLD` is only a hint, and the `DSB`/`DMB`/`ISB` encodings have
+        // nothing to order on an in-order interpreter.
+        let barrier = matches!(
+            opcode & 0xFFFF_FFF0,
+            0xF57F_F040 | 0xF57F_F050 | 0xF57F_F060
+        );
+        let preload = opcode & 0xFD70_F000 == 0xF550_F000 && opcode & 0x0200_0010 != 0x0200_0010;
+        if barrier || preload {
+            return Ok(CYCLES_PSR);
+        }
+
+        if opcode & 0xFE00_0000 == 0xFA00_0000 {
+            self.exec_blx_immediate(opcode, pc);
+            return Ok(CYCLES_BRANCH);
+        }
+
+        if opcode & 0xFFF1_FE20 == 0xF100_0000 {
+            let imod = (opcode >> 18) & 0x3;
+            let disable = match imod {
+                0b10 => Some(false),
+                0b11 => Some(true),
+                _ => None,
+            };
+            let mode = ((opcode >> 17) & 1 == 1).then_some(opcode & MODE_MASK);
+            self.change_processor_state(disable, opcode & (FLAG_A | FLAG_I | FLAG_F), mode);
+            return Ok(CYCLES_PSR);
+        }
+
+        if opcode & 0xFFFF_FDFF == 0xF101_0000 {
+            self.set_flag(FLAG_E, (opcode >> 9) & 1 == 1);
+            return Ok(CYCLES_PSR);
+        }
+
+        if opcode & 0xFE5F_FFE0 == 0xF84D_0500 {
+            return self.exec_srs(opcode, memory).map(|()| CYCLES_FRAME);
+        }
+
+        if opcode & 0xFE50_FFFF == 0xF810_0A00 {
+            return self.exec_rfe(opcode, memory).map(|()| CYCLES_FRAME);
+        }
+
+        self.take_exception(ExceptionKind::UndefinedInstruction, pc, opcode, true);
+        Ok(CYCLES_UNDEFINED)
+    }
+
+    /// `BLX <imm>`: links and branches to a Thumb target, with H as bit 1 of
+    /// the offset.
+    fn exec_blx_immediate(&mut self, opcode: u32, pc: u32) {
+        let offset = (((opcode & 0x00FF_FFFF) << 8) as i32 >> 6) as u32 | ((opcode >> 23) & 2);
+        self.regs[LR_INDEX] = pc.wrapping_add(4);
+        self.set_thumb_state(true);
+        self.regs[PC_INDEX] = pc.wrapping_add(8).wrapping_add(offset);
+    }
+
+    /// `CPS` on both instruction sets: sets or clears the A/I/F bits in
+    /// `aif` and optionally switches mode. Ignored in User mode.
+    pub(super) fn change_processor_state(
+        &mut self,
+        disable: Option<bool>,
+        aif: u32,
+        mode: Option<u32>,
+    ) {
+        if !self.is_privileged() {
+            return;
+        }
+        if let Some(disable) = disable {
+            self.set_flag(aif, disable);
+        }
+        if let Some(mode) = mode.filter(|&mode| is_valid_mode(mode)) {
+            self.switch_mode(mode);
+        }
+    }
+
+    /// `SRS<mode>`: stores the current mode's LR and SPSR on `mode`'s stack.
+    fn exec_srs(
+        &mut self,
+        opcode: u32,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<(), FaultKind> {
+        let Some(spsr) = self.current_spsr() else {
+            return Ok(());
+        };
+        let mode = opcode & MODE_MASK;
+        if !is_valid_mode(mode) {
+            return Ok(());
+        }
+        let base = *self.stack_pointer_mut(mode);
+        let (start, write_back) = frame_addresses(opcode, base);
+        self.write_data(memory, start, 4, self.regs[LR_INDEX])?;
+        self.write_data(memory, start.wrapping_add(4), 4, spsr)?;
+        if (opcode >> 21) & 1 == 1 {
+            *self.stack_pointer_mut(mode) = write_back;
+        }
+        Ok(())
+    }
+
+    /// `RFE`: loads PC and CPSR from the frame at `Rn`.
+    fn exec_rfe(
+        &mut self,
+        opcode: u32,
+        memory: &mut dyn Bus,
+    ) -> std::result::Result<(), FaultKind> {
+        if !self.is_privileged() {
+            return Ok(());
+        }
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let (start, write_back) = frame_addresses(opcode, self.regs[rn]);
+        let target = self.read_data(memory, start, 4)?;
+        let cpsr = self.read_data(memory, start.wrapping_add(4), 4)?;
+        if (opcode >> 21) & 1 == 1 {
+            self.regs[rn] = write_back;
+        }
+
+        let mode = cpsr & MODE_MASK;
+        if is_valid_mode(mode) {
+            self.switch_mode(mode);
+        }
+        self.cpsr = (cpsr & !MODE_MASK) | self.mode();
+        let alignment = if cpsr & FLAG_T != 0 { 1 } else { 3 };
+        self.regs[PC_INDEX] = target & !alignment;
+        Ok(())
+    }
+
+    /// The live SP when `mode` shares the current bank, else its banked copy.
+    fn stack_pointer_mut(&mut self, mode: u32) -> &mut u32 {
+        let bank = |mode| if mode == MODE_SYS { MODE_USR } else { mode };
+        if bank(mode) == bank(self.mode()) {
+            &mut self.regs[SP_INDEX]
+        } else {
+            self.banked_sp_lr_mut(mode).0
+        }
+    }
+}
+
+/// Returns the lower address of a two-word frame and the written-back base.
+fn frame_addresses(opcode: u32, base: u32) -> (u32, u32) {
+    let pre_index = (opcode >> 24) & 1 == 1;
+    let add = (opcode >> 23) & 1 == 1;
+    match (pre_index, add) {
+        (false, false) => (base.wrapping_sub(4), base.wrapping_sub(8)),
+        (false, true) => (base, base.wrapping_add(8)),
+        (true, false) => (base.wrapping_sub(8), base.wrapping_sub(8)),
+        (true, true) => (base.wrapping_add(4), base.wrapping_add(8)),
+    }
+}
@@ -0,0 +1,1198 @@
+//! VFPv2 floating-point coprocessor (CP10 single, CP11 double) as found in
+//! the VFP11 unit of each ARM11 MPCore.
+//!
+//! Arithmetic runs on `f64` values paired with the sign of the rounding
+//! error, which is enough to honour every FPSCR rounding mode as well as
+//! flush-to-zero and default-NaN. Exception enable bits are stored but never
+//! trap; only the cumulative flags are raised.
+
+use super::super::bus::Bus;
+use super::{
+    Arm11Cpu, ExceptionKind, FLAG_C, FLAG_N, FLAG_V, FLAG_Z, FaultKind, LR_INDEX, MODE_UND,
+    PC_INDEX,
+};
+
+const FPSID_VFP11: u32 = 0x4101_20B4;
+
+const FPEXC_EN: u32 = 1 << 30;
+const FPEXC_WRITABLE: u32 = 0xC000_0000;
+
+const FPSCR_NZCV: u32 = FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+const FPSCR_DN: u32 = 1 << 25;
+const FPSCR_FZ: u32 = 1 << 24;
+const FPSCR_WRITABLE: u32 = 0xF3F7_9F9F;
+
+const FPSCR_IOC: u32 = 1 << 0;
+const FPSCR_DZC: u32 = 1 << 1;
+const FPSCR_OFC: u32 = 1 << 2;
+const FPSCR_UFC: u32 = 1 << 3;
+const FPSCR_IXC: u32 = 1 << 4;
+const FPSCR_IDC: u32 = 1 << 7;
+
+const VFP_REG_SYSTEM_ID: usize = 0;
+const VFP_REG_STATUS: usize = 1;
+const VFP_REG_EXCEPTION: usize = 8;
+
+// Issue cycles per the VFP11 TRM, ignoring result latency.
+const CYCLES_VFP_OP: u32 = 1;
+const CYCLES_VFP_MUL_DOUBLE: u32 = 2;
+const CYCLES_VFP_DIV_SINGLE: u32 = 15;
+const CYCLES_VFP_DIV_DOUBLE: u32 = 29;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Precision {
+    Single,
+    Double,
+}
+
+impl Precision {
+    fn sign_bit(self) -> u64 {
+        match self {
+            Self::Single => 1 << 31,
+            Self::Double => 1 << 63,
+        }
+    }
+
+    fn exponent_mask(self) -> u64 {
+        match self {
+            Self::Single => 0x7F80_0000,
+            Self::Double => 0x7FF0_0000_0000_0000,
+        }
+    }
+
+    fn fraction_mask(self) -> u64 {
+        match self {
+            Self::Single => 0x007F_FFFF,
+            Self::Double => 0x000F_FFFF_FFFF_FFFF,
+        }
+    }
+
+    fn quiet_bit(self) -> u64 {
+        match self {
+            Self::Single => 1 << 22,
+            Self::Double => 1 << 51,
+        }
+    }
+
+    fn max_finite(self) -> u64 {
+        self.exponent_mask() - 1
+    }
+
+    fn default_nan(self) -> u64 {
+        self.exponent_mask() | self.quiet_bit()
+    }
+
+    /// Registers per short-vector bank: S0-S7 or D0-D3 and so on.
+    fn bank_size(self) -> usize {
+        match self {
+            Self::Single => 8,
+            Self::Double => 4,
+        }
+    }
+
+    fn is_nan(self, bits: u64) -> bool {
+        bits & self.exponent_mask() == self.exponent_mask() && bits & self.fraction_mask() != 0
+    }
+
+    fn is_signalling(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & self.quiet_bit() == 0
+    }
+
+    fn is_subnormal(self, bits: u64) -> bool {
+        bits & self.exponent_mask() == 0 && bits & self.fraction_mask() != 0
+    }
+
+    fn is_infinite(self, bits: u64) -> bool {
+        bits & !self.sign_bit() == self.exponent_mask()
+    }
+
+    fn to_f64(self, bits: u64) -> f64 {
+        match self {
+            Self::Single => f64::from(f32::from_bits(bits as u32)),
+            Self::Double => f64::from_bits(bits),
+        }
+    }
+
+    fn zero(self, negative: bool) -> u64 {
+        if negative { self.sign_bit() } else { 0 }
+    }
+
+    /// Steps one ulp up or down, crossing zero through the smallest subnormal.
+    fn next_toward(self, bits: u64, up: bool) -> u64 {
+        let negative = bits & self.sign_bit() != 0;
+        if bits & !self.sign_bit() == 0 {
+            return if up { 1 } else { self.sign_bit() | 1 };
+        }
+        if negative == up { bits - 1 } else { bits + 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rounding {
+    Nearest,
+    PlusInfinity,
+    MinusInfinity,
+    Zero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataOp {
+    MulAdd,
+    MulSub,
+    NegMulAdd,
+    NegMulSub,
+    Mul,
+    NegMul,
+    Add,
+    Sub,
+    Div,
+    Copy,
+    Abs,
+    Neg,
+    Sqrt,
+}
+
+pub(super) enum VfpOutcome {
+    Executed(u32),
+    Disabled,
+    Undefined,
+    Abort(FaultKind),
+}
+
+/// Adds with an exact error term (Knuth's TwoSum).
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    if !sum.is_finite() {
+        return (sum, 0.0);
+    }
+    let b_virtual = sum - a;
+    let a_virtual = sum - b_virtual;
+    (sum, (a - a_virtual) + (b - b_virtual))
+}
+
+/// Register file and control registers of the VFP11.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct VfpState {
+    regs: [u32; 32],
+    fpscr: u32,
+    fpexc: u32,
+}
+
+impl VfpState {
+    pub(super) fn new() -> Self {
+        Self {
+            regs: [0; 32],
+            fpscr: 0,
+            fpexc: 0,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.fpexc & FPEXC_EN != 0
+    }
+
+    fn read(&self, reg: usize, precision: Precision) -> u64 {
+        match precision {
+            Precision::Single => u64::from(self.regs[reg]),
+            Precision::Double => {
+                u64::from(self.regs[reg * 2]) | (u64::from(self.regs[reg * 2 + 1]) << 32)
+            }
+        }
+    }
+
+    fn write(&mut self, reg: usize, precision: Precision, bits: u64) {
+        match precision {
+            Precision::Single => self.regs[reg] = bits as u32,
+            Precision::Double => {
+                self.regs[reg * 2] = bits as u32;
+                self.regs[reg * 2 + 1] = (bits >> 32) as u32;
+            }
+        }
+    }
+
+    fn rounding(&self) -> Rounding {
+        match (self.fpscr >> 22) & 0x3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::PlusInfinity,
+            2 => Rounding::MinusInfinity,
+            _ => Rounding::Zero,
+        }
+    }
+
+    /// Elements per vector operation when writing `dest`; bank 0 is scalar.
+    fn vector_length(&self, dest: usize, precision: Precision) -> usize {
+        if dest < precision.bank_size() {
+            1
+        } else {
+            ((self.fpscr >> 16) & 0x7) as usize + 1
+        }
+    }
+
+    fn vector_stride(&self) -> usize {
+        if (self.fpscr >> 20) & 0x3 == 0b11 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn raise(&mut self, flags: u32) {
+        self.fpscr |= flags;
+    }
+
+    /// Unpacks an operand, flushing subnormals when FZ is set.
+    fn operand(&mut self, bits: u64, precision: Precision) -> f64 {
+        if self.fpscr & FPSCR_FZ != 0 && precision.is_subnormal(bits) {
+            self.raise(FPSCR_IDC);
+            return if bits & precision.sign_bit() != 0 {
+                -0.0
+            } else {
+                0.0
+            };
+        }
+        precision.to_f64(bits)
+    }
+
+    /// Picks the NaN result for an operation: the first signalling NaN
+    /// (quietened), else the first quiet NaN.
+    fn process_nans(&mut self, operands: &[u64], precision: Precision) -> Option<u64> {
+        let nan = operands
+            .iter()
+            .copied()
+            .find(|&bits| precision.is_signalling(bits))
+            .or_else(|| {
+                operands
+                    .iter()
+                    .copied()
+                    .find(|&bits| precision.is_nan(bits))
+            })?;
+        if precision.is_signalling(nan) {
+            self.raise(FPSCR_IOC);
+        }
+        Some(if self.fpscr & FPSCR_DN != 0 {
+            precision.default_nan()
+        } else {
+            nan | precision.quiet_bit()
+        })
+    }
+
+    fn invalid(&mut self, precision: Precision) -> u64 {
+        self.raise(FPSCR_IOC);
+        precision.default_nan()
+    }
+
+    fn overflow(&mut self, negative: bool, precision: Precision) -> u64 {
+        self.raise(FPSCR_OFC | FPSCR_IXC);
+        let to_infinity = match self.rounding() {
+            Rounding::Nearest => true,
+            Rounding::PlusInfinity => !negative,
+            Rounding::MinusInfinity => negative,
+            Rounding::Zero => false,
+        };
+        let magnitude = if to_infinity {
+            precision.exponent_mask()
+        } else {
+            precision.max_finite()
+        };
+        magnitude | precision.zero(negative)
+    }
+
+    /// Rounds `value` (the round-to-nearest `f64` result, with `err` carrying
+    /// the sign of `exact - value`) into `precision` under the FPSCR mode.
+    fn round(&mut self, value: f64, err: f64, finite_inputs: bool, precision: Precision) -> u64 {
+        let negative = value.is_sign_negative();
+        if value.is_infinite() {
+            if finite_inputs {
+                return self.overflow(negative, precision);
+            }
+            return precision.exponent_mask() | precision.zero(negative);
+        }
+
+        let (bits, err) = match precision {
+            Precision::Double => (value.to_bits(), err),
+            Precision::Single => {
+                let narrowed = value as f32;
+                if narrowed.is_infinite() {
+                    return self.overflow(negative, precision);
+                }
+                let narrowing_err = value - f64::from(narrowed);
+                let err = if narrowing_err != 0.0 {
+                    narrowing_err
+                } else {
+                    err
+                };
+                (u64::from(narrowed.to_bits()), err)
+            }
+        };
+
+        let magnitude = bits & !precision.sign_bit();
+        let bits = match self.rounding() {
+            Rounding::PlusInfinity if err > 0.0 => precision.next_toward(bits, true),
+            Rounding::MinusInfinity if err < 0.0 => precision.next_toward(bits, false),
+            Rounding::Zero if magnitude != 0 && err != 0.0 && (err < 0.0) != negative => {
+                precision.next_toward(bits, negative)
+            }
+            _ => bits,
+        };
+        if precision.is_infinite(bits) {
+            return self.overflow(negative, precision);
+        }
+
+        let tiny =
+            precision.is_subnormal(bits) || (bits & !precision.sign_bit() == 0 && err != 0.0);
+        if tiny && self.fpscr & FPSCR_FZ != 0 {
+            self.raise(FPSCR_UFC);
+            return precision.zero(negative);
+        }
+        if err != 0.0 {
+            self.raise(FPSCR_IXC);
+            if tiny {
+                self.raise(FPSCR_UFC);
+            }
+        }
+        bits
+    }
+
+    fn arith(&mut self, op: Arith, a: u64, b: u64, precision: Precision) -> u64 {
+        if let Some(nan) = self.process_nans(&[a, b], precision) {
+            return nan;
+        }
+        let x = self.operand(a, precision);
+        let y = self.operand(b, precision);
+        let finite_inputs = x.is_finite() && y.is_finite();
+
+        let (value, err) = match op {
+            Arith::Add | Arith::Sub => {
+                let y = if op == Arith::Sub { -y } else { y };
+                let (sum, err) = two_sum(x, y);
+                // An exact zero sum is -0 only when rounding towards minus
+                // infinity or when both addends are -0.
+                let both_positive_zero =
+                    x == 0.0 && y == 0.0 && x.is_sign_positive() && y.is_sign_positive();
+                if sum == 0.0
+                    && err == 0.0
+                    && !both_positive_zero
+                    && self.rounding() == Rounding::MinusInfinity
+                {
+                    (-0.0, 0.0)
+                } else {
+                    (sum, err)
+                }
+            }
+            Arith::Mul => {
+                let product = x * y;
+                let err = if product.is_finite() {
+                    x.mul_add(y, -product)
+                } else {
+                    0.0
+                };
+                (product, err)
+            }
+            Arith::Div => {
+                if y == 0.0 && x.is_finite() && x != 0.0 {
+                    self.raise(FPSCR_DZC);
+                    let negative = x.is_sign_negative() != y.is_sign_negative();
+                    return precision.exponent_mask() | precision.zero(negative);
+                }
+                let quotient = x / y;
+                let err = if quotient.is_finite() && y.is_finite() && y != 0.0 {
+                    (-quotient).mul_add(y, x) * y.signum()
+                } else {
+                    0.0
+                };
+                (quotient, err)
+            }
+        };
+        if value.is_nan() {
+            return self.invalid(precision);
+        }
+        self.round(value, err, finite_inputs, precision)
+    }
+
+    fn sqrt(&mut self, a: u64, precision: Precision) -> u64 {
+        if let Some(nan) = self.process_nans(&[a], precision) {
+            return nan;
+        }
+        let x = self.operand(a, precision);
+        if x == 0.0 {
+            return precision.zero(x.is_sign_negative());
+        }
+        if x < 0.0 {
+            return self.invalid(precision);
+        }
+        let root = x.sqrt();
+        let err = if root.is_finite() {
+            -root.mul_add(root, -x)
+        } else {
+            0.0
+        };
+        self.round(root, err, true, precision)
+    }
+
+    fn compare(&mut self, a: u64, b: u64, precision: Precision, signal_quiet_nans: bool) {
+        let nzcv = if precision.is_nan(a) || precision.is_nan(b) {
+            if signal_quiet_nans || precision.is_signalling(a) || precision.is_signalling(b) {
+                self.raise(FPSCR_IOC);
+            }
+            0b0011
+        } else {
+            let x = self.operand(a, precision);
+            let y = self.operand(b, precision);
+            if x == y {
+                0b0110
+            } else if x < y {
+                0b1000
+            } else {
+                0b0010
+            }
+        };
+        self.fpscr = (self.fpscr & !FPSCR_NZCV) | (nzcv << 28);
+    }
+
+    fn convert(&mut self, a: u64, from: Precision, to: Precision) -> u64 {
+        if from.is_nan(a) {
+            if from.is_signalling(a) {
+                self.raise(FPSCR_IOC);
+            }
+            if self.fpscr & FPSCR_DN != 0 {
+                return to.default_nan();
+            }
+            let fraction = match from {
+                Precision::Single => (a & from.fraction_mask()) << 29,
+                Precision::Double => a & from.fraction_mask(),
+            };
+            let fraction = match to {
+                Precision::Single => fraction >> 29,
+                Precision::Double => fraction,
+            };
+            let negative = a & from.sign_bit() != 0;
+            return to.default_nan() | fraction | to.zero(negative);
+        }
+        let x = self.operand(a, from);
+        self.round(x, 0.0, x.is_finite(), to)
+    }
+
+    fn int_to_float(&mut self, value: u32, signed: bool, precision: Precision) -> u64 {
+        let x = if signed {
+            f64::from(value as i32)
+        } else {
+            f64::from(value)
+        };
+        self.round(x, 0.0, true, precision)
+    }
+
+    fn float_to_int(
+        &mut self,
+        a: u64,
+        precision: Precision,
+        signed: bool,
+        round_to_zero: bool,
+    ) -> u32 {
+        if precision.is_nan(a) {
+            self.raise(FPSCR_IOC);
+            return 0;
+        }
+        let x = self.operand(a, precision);
+        let rounding = if round_to_zero {
+            Rounding::Zero
+        } else {
+            self.rounding()
+        };
+        let rounded = match rounding {
+            Rounding::Nearest => x.round_ties_even(),
+            Rounding::PlusInfinity => x.ceil(),
+            Rounding::MinusInfinity => x.floor(),
+            Rounding::Zero => x.trunc(),
+        };
+        let (min, max) = if signed {
+            (f64::from(i32::MIN), f64::from(i32::MAX))
+        } else {
+            (0.0, f64::from(u32::MAX))
+        };
+        if rounded < min || rounded > max {
+            self.raise(FPSCR_IOC);
+            let saturated = if rounded < min { min } else { max };
+            return if signed {
+                saturated as i32 as u32
+            } else {
+                saturated as u32
+            };
+        }
+        if rounded != x {
+            self.raise(FPSCR_IXC);
+        }
+        if signed {
+            rounded as i32 as u32
+        } else {
+            rounded as u32
+        }
+    }
+
+    fn data_op(&mut self, op: DataOp, d: u64, n: u64, m: u64, precision: Precision) -> u64 {
+        let sign = precision.sign_bit();
+        match op {
+            DataOp::MulAdd => {
+                let product = self.arith(Arith::Mul, n, m, precision);
+                self.arith(Arith::Add, d, product, precision)
+            }
+            DataOp::MulSub => {
+                let product = self.arith(Arith::Mul, n, m, precision);
+                self.arith(Arith::Add, d, product ^ sign, precision)
+            }
+            DataOp::NegMulAdd => {
+                let product = self.arith(Arith::Mul, n, m, precision);
+                self.arith(Arith::Add, d ^ sign, product, precision)
+            }
+            DataOp::NegMulSub => {
+                let product = self.arith(Arith::Mul, n, m, precision);
+                self.arith(Arith::Add, d ^ sign, product ^ sign, precision)
+            }
+            DataOp::Mul => self.arith(Arith::Mul, n, m, precision),
+            DataOp::NegMul => self.arith(Arith::Mul, n, m, precision) ^ sign,
+            DataOp::Add => self.arith(Arith::Add, n, m, precision),
+            DataOp::Sub => self.arith(Arith::Sub, n, m, precision),
+            DataOp::Div => self.arith(Arith::Div, n, m, precision),
+            DataOp::Copy => m,
+            DataOp::Abs => m & !sign,
+            DataOp::Neg => m ^ sign,
+            DataOp::Sqrt => self.sqrt(m, precision),
+        }
+    }
+}
+
+/// Register number for a 4-bit field plus its extra bit: `Vx:X` for single
+/// precision, `Vx` for double (VFPv2 has D0-D15 only).
+fn vfp_reg(field: u32, extra: u32, precision: Precision) -> usize {
+    match precision {
+        Precision::Single => ((field << 1) | extra) as usize,
+        Precision::Double => field as usize,
+    }
+}
+
+fn step_in_bank(reg: usize, stride: usize, bank: usize) -> usize {
+    (reg & !(bank - 1)) | ((reg + stride) & (bank - 1))
+}
+
+impl Arm11Cpu {
+    pub fn vfp_enabled(&self) -> bool {
+        self.vfp.enabled()
+    }
+
+    /// S0-S31; D`n` is S`2n` (low word) and S`2n+1`.
+    pub fn vfp_register(&self, index: usize) -> u32 {
+        self.vfp.regs[index]
+    }
+
+    pub fn set_vfp_register(&mut self, index: usize, value: u32) {
+        self.vfp.regs[index] = value;
+    }
+
+    pub fn fpscr(&self) -> u32 {
+        self.vfp.fpscr
+    }
+
+    pub fn set_fpscr(&mut self, value: u32) {
+        self.vfp.fpscr = value & FPSCR_WRITABLE;
+    }
+
+    /// Sets FPEXC.EN and returns from a pending `VfpDisabled` trap to re-run
+    /// the faulting instruction, which is how the 3DS kernel lazily hands the
+    /// VFP to a thread.
+    pub fn enable_vfp_and_retry(&mut self) {
+        self.vfp.fpexc |= FPEXC_EN;
+        if let Some(exception) = self.last_exception
+            && exception.kind == ExceptionKind::VfpDisabled
+            && self.mode() == MODE_UND
+        {
+            let resume = self.regs[LR_INDEX].wrapping_sub(4);
+            self.restore_cpsr_from_spsr();
+            self.regs[PC_INDEX] = resume;
+        }
+    }
+
+    /// Decodes CP10/CP11 instructions; other coprocessors return `None`.
+    pub(super) fn exec_vfp(&mut self, opcode: u32, memory: &mut dyn Bus) -> Option<VfpOutcome> {
+        let precision = match (opcode >> 8) & 0xF {
+            10 => Precision::Single,
+            11 => Precision::Double,
+            _ => return None,
+        };
+        let class = (opcode >> 24) & 0xF;
+        if !(0xC..=0xE).contains(&class) {
+            return None;
+        }
+        if !self
+            .cp15
+            .coprocessor_allowed((opcode >> 8) & 0xF, self.is_privileged())
+        {
+            return Some(VfpOutcome::Undefined);
+        }
+
+        if class == 0xE && opcode & 0x10 != 0 {
+            return Some(self.exec_vfp_register_transfer(opcode, precision));
+        }
+        if !self.vfp.enabled() {
+            return Some(VfpOutcome::Disabled);
+        }
+        Some(if class == 0xE {
+            self.exec_vfp_data_processing(opcode, precision)
+        } else if opcode & 0x0FE0_0000 == 0x0C40_0000 {
+            self.exec_vfp_two_register_transfer(opcode, precision)
+        } else {
+            self.exec_vfp_load_store(opcode, memory, precision)
+        })
+    }
+
+    fn exec_vfp_register_transfer(&mut self, opcode: u32, precision: Precision) -> VfpOutcome {
+        let opc1 = (opcode >> 21) & 0x7;
+        let to_arm = ((opcode >> 20) & 1) == 1;
+        let vn = (opcode >> 16) & 0xF;
+        let rt = ((opcode >> 12) & 0xF) as usize;
+        let n = (opcode >> 7) & 1;
+
+        if precision == Precision::Single && opc1 == 0b111 {
+            return self.exec_vfp_system_transfer(vn as usize, rt, to_arm);
+        }
+        if !self.vfp.enabled() {
+            return VfpOutcome::Disabled;
+        }
+        let reg = match (precision, opc1) {
+            // FMSR / FMRS
+            (Precision::Single, 0) => vfp_reg(vn, n, precision),
+            // FMDLR / FMRDL and FMDHR / FMRDH address one half of Dn.
+            (Precision::Double, 0 | 1) => vn as usize * 2 + opc1 as usize,
+            _ => return VfpOutcome::Undefined,
+        };
+        if to_arm {
+            self.regs[rt] = self.vfp.regs[reg];
+        } else {
+            self.vfp.regs[reg] = self.regs[rt];
+        }
+        VfpOutcome::Executed(CYCLES_VFP_OP)
+    }
+
+    fn exec_vfp_system_transfer(&mut self, reg: usize, rt: usize, to_arm: bool) -> VfpOutcome {
+        match reg {
+            VFP_REG_SYSTEM_ID => {}
+            VFP_REG_STATUS if !self.vfp.enabled() => return VfpOutcome::Disabled,
+            VFP_REG_STATUS => {}
+            VFP_REG_EXCEPTION if !self.is_privileged() => return VfpOutcome::Undefined,
+            VFP_REG_EXCEPTION => {}
+            _ => return VfpOutcome::Undefined,
+        }
+
+        if to_arm {
+            let value = match reg {
+                VFP_REG_SYSTEM_ID => FPSID_VFP11,
+                VFP_REG_STATUS => self.vfp.fpscr,
+                _ => self.vfp.fpexc,
+            };
+            if rt == PC_INDEX {
+                // FMSTAT copies the FPSCR flags into the CPSR.
+                self.cpsr = (self.cpsr & !FPSCR_NZCV) | (value & FPSCR_NZCV);
+            } else {
+                self.regs[rt] = value;
+            }
+        } else {
+            let value = self.regs[rt];
+            match reg {
+                VFP_REG_STATUS => self.vfp.fpscr = value & FPSCR_WRITABLE,
+                VFP_REG_EXCEPTION => self.vfp.fpexc = value & FPEXC_WRITABLE,
+                _ => {}
+            }
+        }
+        VfpOutcome::Executed(CYCLES_VFP_OP)
+    }
+
+    fn exec_vfp_two_register_transfer(&mut self, opcode: u32, precision: Precision) -> VfpOutcome {
+        if opcode & 0xD0 != 0x10 {
+            return VfpOutcome::Undefined;
+        }
+        let to_arm = ((opcode >> 20) & 1) == 1;
+        let rt2 = ((opcode >> 16) & 0xF) as usize;
+        let rt = ((opcode >> 12) & 0xF) as usize;
+        let m = (opcode >> 5) & 1;
+        let vm = opcode & 0xF;
+
+        // FMDRR / FMRRD move Dm; FMSRR / FMRRS move Sm and Sm+1.
+        let first = match precision {
+            Precision::Double => vm as usize * 2,
+            Precision::Single => vfp_reg(vm, m, precision),
+        };
+        if first == 31 {
+            return VfpOutcome::Undefined;
+        }
+        if to_arm {
+            self.regs[rt] = self.vfp.regs[first];
+            self.regs[rt2] = self.vfp.regs[first + 1];
+        } else {
+            self.vfp.regs[first] = self.regs[rt];
+            self.vfp.regs[first + 1] = self.regs[rt2];
+        }
+        VfpOutcome::Executed(CYCLES_VFP_OP)
+    }
+
+    fn exec_vfp_load_store(
+        &mut self,
+        opcode: u32,
+        memory: &mut dyn Bus,
+        precision: Precision,
+    ) -> VfpOutcome {
+        let pre_index = ((opcode >> 24) & 1) == 1;
+        let add = ((opcode >> 23) & 1) == 1;
+        let d = (opcode >> 22) & 1;
+        let write_back = ((opcode >> 21) & 1) == 1;
+        let load = ((opcode >> 20) & 1) == 1;
+        let rn = ((opcode >> 16) & 0xF) as usize;
+        let vd = (opcode >> 12) & 0xF;
+        let imm8 = opcode & 0xFF;
+
+        let first = match precision {
+            Precision::Single => vfp_reg(vd, d, precision),
+            Precision::Double => vd as usize * 2,
+        };
+        let words_per_reg = match precision {
+            Precision::Single => 1,
+            Precision::Double => 2,
+        };
+        let base = if rn == PC_INDEX {
+            self.regs[PC_INDEX].wrapping_add(4) & !3
+        } else {
+            self.regs[rn]
+        };
+        let offset = imm8 * 4;
+
+        let (address, words, new_base) = match (pre_index, add, write_back) {
+            // FLDS / FSTS / FLDD / FSTD
+            (true, _, false) => {
+                let address = if add {
+                    base.wrapping_add(offset)
+                } else {
+                    base.wrapping_sub(offset)
+                };
+                (address, words_per_reg, None)
+            }
+            // FLDM / FSTM increment-after; an odd double count is the X form.
+            (false, true, _) => (
+                base,
+                imm8 / words_per_reg * words_per_reg,
+                write_back.then(|| base.wrapping_add(offset)),
+            ),
+            // FLDM / FSTM decrement-before with writeback.
+            (true, false, true) => {
+                let address = base.wrapping_sub(offset);
+                (address, imm8 / words_per_reg * words_per_reg, Some(address))
+            }
+            _ => return VfpOutcome::Undefined,
+        };
+        let words = words as usize;
+        if words == 0 || first + words > self.vfp.regs.len() {
+            return VfpOutcome::Undefined;
+        }
+
+        for i in 0..words {
+            let addr = address.wrapping_add(i as u32 * 4);
+            let result = if load {
+                self.read_data(memory, addr, 4)
+                    .map(|value| self.vfp.regs[first + i] = value)
+            } else {
+                self.write_data(memory, addr, 4, self.vfp.regs[first + i])
+            };
+            if let Err(kind) = result {
+                return VfpOutcome::Abort(kind);
+            }
+        }
+        if let Some(new_base) = new_base {
+            self.regs[rn] = new_base;
+        }
+        VfpOutcome::Executed(CYCLES_VFP_OP + words.div_ceil(2) as u32)
+    }
+
+    fn exec_vfp_data_processing(&mut self, opcode: u32, precision: Precision) -> VfpOutcome {
+        let opc = (((opcode >> 23) & 1) << 3)
+            | (((opcode >> 21) & 1) << 2)
+            | (((opcode >> 20) & 1) << 1)
+            | ((opcode >> 6) & 1);
+        let d = (opcode >> 22) & 1;
+        let vn = (opcode >> 16) & 0xF;
+        let vd = (opcode >> 12) & 0xF;
+        let n = (opcode >> 7) & 1;
+        let m = (opcode >> 5) & 1;
+        let vm = opcode & 0xF;
+
+        let op = match opc {
+            0b0000 => DataOp::MulAdd,
+            0b0001 => DataOp::MulSub,
+            0b0010 => DataOp::NegMulAdd,
+            0b0011 => DataOp::NegMulSub,
+            0b0100 => DataOp::Mul,
+            0b0101 => DataOp::NegMul,
+            0b0110 => DataOp::Add,
+            0b0111 => DataOp::Sub,
+            0b1000 => DataOp::Div,
+            0b1111 => match (vn << 1) | n {
+                0b00000 => DataOp::Copy,
+                0b00001 => DataOp::Abs,
+                0b00010 => DataOp::Neg,
+                0b00011 => DataOp::Sqrt,
+                ext => return self.exec_vfp_scalar_extension(ext, opcode, precision),
+            },
+            _ => return VfpOutcome::Undefined,
+        };
+
+        let bank = precision.bank_size();
+        let stride = self.vfp.vector_stride();
+        let mut dest = vfp_reg(vd, d, precision);
+        let mut lhs = vfp_reg(vn, n, precision);
+        let mut rhs = vfp_reg(vm, m, precision);
+        let length = self.vfp.vector_length(dest, precision);
+        let scalar_rhs = rhs < bank;
+
+        let per_element = match (op, precision) {
+            (DataOp::Div | DataOp::Sqrt, Precision::Single) => CYCLES_VFP_DIV_SINGLE,
+            (DataOp::Div | DataOp::Sqrt, Precision::Double) => CYCLES_VFP_DIV_DOUBLE,
+            (
+                DataOp::MulAdd
+                | DataOp::MulSub
+                | DataOp::NegMulAdd
+                | DataOp::NegMulSub
+                | DataOp::Mul
+                | DataOp::NegMul,
+                Precision::Double,
+            ) => CYCLES_VFP_MUL_DOUBLE,
+            _ => CYCLES_VFP_OP,
+        };
+
+        for _ in 0..length {
+            let result = self.vfp.data_op(
+                op,
+                self.vfp.read(dest, precision),
+                self.vfp.read(lhs, precision),
+                self.vfp.read(rhs, precision),
+                precision,
+            );
+            self.vfp.write(dest, precision, result);
+            dest = step_in_bank(dest, stride, bank);
+            lhs = step_in_bank(lhs, stride, bank);
+            if !scalar_rhs {
+                rhs = step_in_bank(rhs, stride, bank);
+            }
+        }
+        VfpOutcome::Executed(per_element * length as u32)
+    }
+
+    /// Compares and conversions, which ignore the vector length.
+    fn exec_vfp_scalar_extension(
+        &mut self,
+        ext: u32,
+        opcode: u32,
+        precision: Precision,
+    ) -> VfpOutcome {
+        let d = (opcode >> 22) & 1;
+        let vd = (opcode >> 12) & 0xF;
+        let m = (opcode >> 5) & 1;
+        let vm = opcode & 0xF;
+        let single_d = vfp_reg(vd, d, Precision::Single);
+        let single_m = vfp_reg(vm, m, Precision::Single);
+        let dest = vfp_reg(vd, d, precision);
+        let source = vfp_reg(vm, m, precision);
+
+        match ext {
+            // FCMP / FCMPE / FCMPZ / FCMPEZ
+            0b01000..=0b01011 => {
+                let lhs = self.vfp.read(dest, precision);
+                let rhs = if ext & 0b10 != 0 {
+                    0
+                } else {
+                    self.vfp.read(source, precision)
+                };
+                self.vfp.compare(lhs, rhs, precision, ext & 1 != 0);
+            }
+            // FCVTDS (CP10) / FCVTSD (CP11)
+            0b01111 => match precision {
+                Precision::Single => {
+                    let value = self.vfp.read(single_m, Precision::Single);
+                    let result = self
+                        .vfp
+                        .convert(value, Precision::Single, Precision::Double);
+                    self.vfp.write(vd as usize, Precision::Double, result);
+                }
+                Precision::Double => {
+                    let value = self.vfp.read(vm as usize, Precision::Double);
+                    let result = self
+                        .vfp
+                        .convert(value, Precision::Double, Precision::Single);
+                    self.vfp.write(single_d, Precision::Single, result);
+                }
+            },
+            // FUITO / FSITO
+            0b10000 | 0b10001 => {
+                let value = self.vfp.regs[single_m];
+                let result = self.vfp.int_to_float(value, ext & 1 != 0, precision);
+                self.vfp.write(dest, precision, result);
+            }
+            // FTOUI / FTOUIZ / FTOSI / FTOSIZ
+            0b11000..=0b11011 => {
+                let value = self.vfp.read(source, precision);
+                let signed = ext & 0b10 != 0;
+                let result = self
+                    .vfp
+                    .float_to_int(value, precision, signed, ext & 1 != 0);
+                self.vfp.regs[single_d] = result;
+            }
+            _ => return VfpOutcome::Undefined,
+        }
+        VfpOutcome::Executed(CYCLES_VFP_OP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arm11Cpu, ExceptionKind, FLAG_N, MODE_SVC, MODE_USR, PC_INDEX, run_opcode};
+    use super::{FPEXC_EN, FPSCR_DN, FPSCR_FZ, FPSCR_IOC, FPSCR_IXC, FPSCR_UFC, Precision};
+    use crate::core::memory::Memory;
+
+    fn vfp_cpu() -> Arm11Cpu {
+        let mut cpu = Arm11Cpu::new();
+        cpu.vfp.fpexc = FPEXC_EN;
+        cpu
+    }
+
+    fn set_s(cpu: &mut Arm11Cpu, reg: usize, value: f32) {
+        cpu.vfp.regs[reg] = value.to_bits();
+    }
+
+    fn s(cpu: &Arm11Cpu, reg: usize) -> f32 {
+        f32::from_bits(cpu.vfp.regs[reg])
+    }
+
+    fn set_d(cpu: &mut Arm11Cpu, reg: usize, value: f64) {
+        cpu.vfp.write(reg, Precision::Double, value.to_bits());
+    }
+
+    fn d(cpu: &Arm11Cpu, reg: usize) -> f64 {
+        f64::from_bits(cpu.vfp.read(reg, Precision::Double))
+    }
+
+    #[test]
+    fn single_and_double_arithmetic() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        set_s(&mut cpu, 1, 1.5);
+        set_s(&mut cpu, 2, 2.25);
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0A81); // fadds s0, s1, s2
+        assert_eq!(s(&cpu, 0), 3.75);
+        run_opcode(&mut cpu, &mut mem, 0xEE20_0A81); // fmuls s0, s1, s2
+        assert_eq!(s(&cpu, 0), 3.375);
+        run_opcode(&mut cpu, &mut mem, 0xEE00_0A81); // fmacs s0, s1, s2
+        assert_eq!(s(&cpu, 0), 6.75);
+
+        set_d(&mut cpu, 1, 1.0);
+        set_d(&mut cpu, 2, 3.0);
+        run_opcode(&mut cpu, &mut mem, 0xEE81_0B02); // fdivd d0, d1, d2
+        assert_eq!(d(&cpu, 0), 1.0 / 3.0);
+        assert_ne!(cpu.vfp.fpscr & FPSCR_IXC, 0);
+        run_opcode(&mut cpu, &mut mem, 0xEEB1_0BC2); // fsqrtd d0, d2
+        assert_eq!(d(&cpu, 0), 3.0_f64.sqrt());
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn rounding_modes_direct_inexact_results() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        set_s(&mut cpu, 1, 1.0);
+        set_s(&mut cpu, 2, 3.0);
+        let nearest = 1.0_f32 / 3.0;
+
+        let below = f32::from_bits(nearest.to_bits() - 1);
+
+        cpu.vfp.fpscr = 1 << 22; // round towards plus infinity
+        run_opcode(&mut cpu, &mut mem, 0xEE80_0A81); // fdivs s0, s1, s2
+        assert_eq!(s(&cpu, 0), nearest, "1/3 rounds up to nearest");
+
+        cpu.vfp.fpscr = 2 << 22; // round towards minus infinity
+        run_opcode(&mut cpu, &mut mem, 0xEE80_0A81);
+        assert_eq!(s(&cpu, 0), below);
+
+        cpu.vfp.fpscr = 3 << 22; // round towards zero
+        run_opcode(&mut cpu, &mut mem, 0xEE80_0A81);
+        assert_eq!(s(&cpu, 0), below);
+
+        set_s(&mut cpu, 1, f32::MAX);
+        set_s(&mut cpu, 2, 2.0);
+        run_opcode(&mut cpu, &mut mem, 0xEE20_0A81); // fmuls s0, s1, s2
+        assert_eq!(s(&cpu, 0), f32::MAX, "round to zero saturates overflow");
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn nan_handling_and_default_nan_mode() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        cpu.vfp.regs[1] = 0x7F80_0001; // signalling NaN
+        set_s(&mut cpu, 2, 1.0);
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0A81); // fadds s0, s1, s2
+        assert_eq!(cpu.vfp.regs[0], 0x7FC0_0001, "sNaN is quietened");
+        assert_ne!(cpu.vfp.fpscr & FPSCR_IOC, 0);
+
+        cpu.vfp.fpscr = FPSCR_DN;
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0A81);
+        assert_eq!(cpu.vfp.regs[0], 0x7FC0_0000);
+
+        set_s(&mut cpu, 1, f32::INFINITY);
+        set_s(&mut cpu, 2, f32::INFINITY);
+        cpu.vfp.fpscr = 0;
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0AC1); // fsubs s0, s1, s2
+        assert_eq!(cpu.vfp.regs[0], 0x7FC0_0000);
+        assert_ne!(cpu.vfp.fpscr & FPSCR_IOC, 0);
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn flush_to_zero_replaces_subnormals() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        set_s(&mut cpu, 1, f32::MIN_POSITIVE);
+        set_s(&mut cpu, 2, 0.5);
+        run_opcode(&mut cpu, &mut mem, 0xEE20_0A81); // fmuls s0, s1, s2
+        assert_eq!(s(&cpu, 0), f32::MIN_POSITIVE / 2.0, "gradual underflow");
+
+        cpu.vfp.fpscr = FPSCR_FZ;
+        run_opcode(&mut cpu, &mut mem, 0xEE20_0A81);
+        assert_eq!(cpu.vfp.regs[0], 0);
+        assert_ne!(cpu.vfp.fpscr & FPSCR_UFC, 0);
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn short_vectors_iterate_within_banks() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        for i in 0..4 {
+            set_s(&mut cpu, 8 + i, i as f32);
+            set_s(&mut cpu, 16 + i, 10.0 * i as f32);
+        }
+        set_s(&mut cpu, 0, 100.0);
+        cpu.vfp.fpscr = 3 << 16; // LEN = 4, stride 1
+
+        run_opcode(&mut cpu, &mut mem, 0xEE34_CA08); // fadds s24, s8, s16
+        let sums: Vec<f32> = (24..28).map(|reg| s(&cpu, reg)).collect();
+        assert_eq!(sums, [0.0, 11.0, 22.0, 33.0]);
+
+        run_opcode(&mut cpu, &mut mem, 0xEE24_CA00); // fmuls s24, s8, s0 (scalar s0)
+        let products: Vec<f32> = (24..28).map(|reg| s(&cpu, reg)).collect();
+        assert_eq!(products, [0.0, 100.0, 200.0, 300.0]);
+
+        run_opcode(&mut cpu, &mut mem, 0xEE30_0A08); // fadds s0, s0, s16 stays scalar
+        assert_eq!(s(&cpu, 0), 100.0);
+        assert_eq!(s(&cpu, 1), 0.0);
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn compares_and_conversions() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        set_s(&mut cpu, 0, -2.5);
+        set_s(&mut cpu, 1, 1.0);
+        run_opcode(&mut cpu, &mut mem, 0xEEB4_0A60); // fcmps s0, s1
+        run_opcode(&mut cpu, &mut mem, 0xEEF1_FA10); // fmstat
+        assert_ne!(cpu.cpsr & FLAG_N, 0, "s0 < s1 sets N");
+
+        run_opcode(&mut cpu, &mut mem, 0xEEBD_1A40); // ftosis s2, s0 (round to nearest even)
+        assert_eq!(cpu.vfp.regs[2] as i32, -2);
+        run_opcode(&mut cpu, &mut mem, 0xEEBD_1AC0); // ftosizs s2, s0
+        assert_eq!(cpu.vfp.regs[2] as i32, -2);
+        run_opcode(&mut cpu, &mut mem, 0xEEBC_1AC0); // ftouizs s2, s0
+        assert_eq!(cpu.vfp.regs[2], 0);
+        assert_ne!(cpu.vfp.fpscr & FPSCR_IOC, 0);
+
+        cpu.vfp.regs[3] = (-7_i32) as u32;
+        run_opcode(&mut cpu, &mut mem, 0xEEB8_2BE1); // fsitod d2, s3
+        assert_eq!(d(&cpu, 2), -7.0);
+        run_opcode(&mut cpu, &mut mem, 0xEEB7_2AC0); // fcvtds d2, s0
+        assert_eq!(d(&cpu, 2), -2.5);
+        set_d(&mut cpu, 3, 0.1);
+        run_opcode(&mut cpu, &mut mem, 0xEEF7_0BC3); // fcvtsd s1, d3
+        assert_eq!(s(&cpu, 1), 0.1_f32);
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn register_and_memory_transfers() {
+        let mut cpu = vfp_cpu();
+        let mut mem = Memory::flat();
+        cpu.regs[0] = 0x3F80_0000;
+        cpu.regs[1] = 0x1234_5678;
+        run_opcode(&mut cpu, &mut mem, 0xEE00_0A10); // fmsr s0, r0
+        run_opcode(&mut cpu, &mut mem, 0xEC41_0B11); // fmdrr d1, r0, r1
+        assert_eq!(cpu.vfp.regs[0], 0x3F80_0000);
+        assert_eq!(
+            (cpu.vfp.regs[2], cpu.vfp.regs[3]),
+            (0x3F80_0000, 0x1234_5678)
+        );
+
+        cpu.regs[2] = 0x400;
+        run_opcode(&mut cpu, &mut mem, 0xECA2_0A04); // fstmias r2!, {s0-s3}
+        assert_eq!(cpu.regs[2], 0x410);
+        assert_eq!(mem.read_u32(0x40C), 0x1234_5678);
+        run_opcode(&mut cpu, &mut mem, 0xED32_2B04); // fldmdbd r2!, {d2-d3}
+        assert_eq!(cpu.regs[2], 0x400);
+        assert_eq!(cpu.vfp.regs[4..8], cpu.vfp.regs[0..4]);
+
+        run_opcode(&mut cpu, &mut mem, 0xED92_4A03); // flds s8, [r2, #12]
+        assert_eq!(cpu.vfp.regs[8], 0x1234_5678);
+        run_opcode(&mut cpu, &mut mem, 0xEE14_3A10); // fmrs r3, s8
+        assert_eq!(cpu.regs[3], 0x1234_5678);
+        assert_eq!(cpu.last_exception(), None);
+    }
+
+    #[test]
+    fn disabled_vfp_traps_until_enabled() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        mem.write_u32(0, 0xEE30_0A81); // fadds s0, s1, s2
+
+        cpu.step(&mut mem).expect("trap");
+        let ex = cpu.last_exception().expect("vfp trap");
+        assert_eq!(ex.kind, ExceptionKind::VfpDisabled);
+
+        cpu.enable_vfp_and_retry();
+        assert_eq!(cpu.mode(), MODE_USR);
+        assert_eq!(cpu.pc(), 0);
+        assert!(cpu.vfp_enabled());
+
+        set_s(&mut cpu, 1, 2.0);
+        cpu.step(&mut mem).expect("retry");
+        assert_eq!(s(&cpu, 0), 2.0);
+        assert_eq!(cpu.regs[PC_INDEX], 4);
+    }
+
+    #[test]
+    fn fpexc_is_privileged() {
+        let mut cpu = Arm11Cpu::new();
+        let mut mem = Memory::flat();
+        cpu.regs[0] = FPEXC_EN;
+        mem.write_u32(0, 0xEEE8_0A10); // fmxr fpexc, r0
+        cpu.step(&mut mem).expect("user fmxr");
+        assert_eq!(
+            cpu.last_exception().map(|ex| ex.kind),
+            Some(ExceptionKind::UndefinedInstruction)
+        );
+
+        let mut cpu = Arm11Cpu::new();
+        cpu.switch_mode(MODE_SVC);
+        cpu.regs[0] = FPEXC_EN;
+        cpu.step(&mut mem).expect("svc fmxr");
+        assert!(cpu.vfp_enabled());
+    }
+}
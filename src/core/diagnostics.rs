@@ -61,12 +61,25 @@ pub enum StructuredError {
         handle_id: u32,
         result_code: u32,
     },
+    /// `pc` is `None` when the write did not come from a core, e.g. DMA.
+    ReadOnlyWrite {
+        pc: Option<u32>,
+        address: u32,
+    },
+    /// A physical address no memory or device answers.
+    UnmappedAccess {
+        pc: Option<u32>,
+        address: u32,
+        access: MemoryAccessKind,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FaultSnapshot {
     pub cycle: u64,
     pub error: StructuredError,
+    /// Disassembly of the faulting instruction, when it was fetched.
+    pub instruction: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
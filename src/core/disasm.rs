@@ -0,0 +1,1187 @@
+//! ARM and Thumb disassembly into lowercase UAL text for traces and fault
+//! reports.
+//!
+//! Covers the ARMv6 instruction sets the interpreter decodes: data processing,
+//! loads and stores, multiplies, the media extensions, exclusives, system and
+//! coprocessor instructions and VFPv2. Branch targets are resolved from `pc`.
+//! PC-relative loads keep their `[pc, #imm]` form. Encodings outside those
+//! sets print as `.inst 0x...` (ARM) or `.inst.n 0x...` (Thumb).
+//!
+//! Thumb-1 `BL`/`BLX` are two separately traced halfwords. The prefix prints
+//! as the `add lr, pc, #imm` it performs and the suffix as `bl [lr, #imm]`.
+
+const REG_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "", "",
+];
+
+const DATA_OPS: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr",
+    "mov", "bic", "mvn",
+];
+
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+const THUMB_ALU_OPS: [&str; 16] = [
+    "ands", "eors", "lsls", "lsrs", "asrs", "adcs", "sbcs", "rors", "tst", "rsbs", "cmp", "cmn",
+    "orrs", "muls", "bics", "mvns",
+];
+
+/// Disassembles one instruction fetched at `pc`.
+pub fn disassemble(pc: u32, opcode: u32, thumb: bool) -> String {
+    if thumb {
+        disassemble_thumb(pc, opcode as u16)
+    } else {
+        disassemble_arm(pc, opcode)
+    }
+}
+
+pub fn disassemble_arm(pc: u32, opcode: u32) -> String {
+    if opcode >> 28 == 0xF {
+        return arm_unconditional(pc, opcode).unwrap_or_else(|| arm_unknown(opcode));
+    }
+    let cond = CONDITIONS[(opcode >> 28) as usize];
+    let text = match (opcode >> 25) & 0x7 {
+        0b000 => arm_misc(opcode, cond)
+            .or_else(|| arm_multiply_or_extra(opcode, cond))
+            .or_else(|| arm_data_processing(opcode, cond)),
+        0b001 => arm_msr_immediate(opcode, cond).or_else(|| arm_data_processing(opcode, cond)),
+        0b011 if opcode & 0x10 != 0 => arm_media(opcode, cond),
+        0b010 | 0b011 => Some(arm_single_transfer(opcode, cond)),
+        0b100 => Some(arm_block_transfer(opcode, cond)),
+        0b101 => {
+            let link = if opcode & (1 << 24) != 0 { "l" } else { "" };
+            let target = branch_target(pc.wrapping_add(8), opcode & 0x00FF_FFFF, 24, 2);
+            Some(format!("b{link}{cond} {}", address(target)))
+        }
+        0b111 if opcode & (1 << 24) != 0 => {
+            Some(format!("svc{cond} {}", hex(opcode & 0x00FF_FFFF)))
+        }
+        _ => arm_coprocessor(opcode, cond),
+    };
+    text.unwrap_or_else(|| arm_unknown(opcode))
+}
+
+pub fn disassemble_thumb(pc: u32, opcode: u16) -> String {
+    let op = u32::from(opcode);
+    let rd = reg(op & 7);
+    let rs = reg((op >> 3) & 7);
+    let text = match op >> 11 {
+        0b00000..=0b00010 => {
+            let kind = (op >> 11) & 3;
+            let amount = (op >> 6) & 0x1F;
+            Some(if kind == 0 && amount == 0 {
+                format!("movs {rd}, {rs}")
+            } else {
+                let amount = if amount == 0 { 32 } else { amount };
+                format!("{}s {rd}, {rs}, #{amount}", SHIFTS[kind as usize])
+            })
+        }
+        0b00011 => {
+            let name = if op & (1 << 9) != 0 { "subs" } else { "adds" };
+            let field = (op >> 6) & 7;
+            let operand = if op & (1 << 10) != 0 {
+                format!("#{field}")
+            } else {
+                reg(field).to_string()
+            };
+            Some(format!("{name} {rd}, {rs}, {operand}"))
+        }
+        0b00100..=0b00111 => {
+            let name = ["movs", "cmp", "adds", "subs"][((op >> 11) & 3) as usize];
+            Some(format!("{name} {}, #{}", reg((op >> 8) & 7), op & 0xFF))
+        }
+        0b01000 => thumb_data_or_hi_register(op),
+        0b01001 => Some(format!(
+            "ldr {}, [pc, #{}]",
+            reg((op >> 8) & 7),
+            (op & 0xFF) * 4
+        )),
+        0b01010 | 0b01011 => {
+            let name = [
+                "str", "strh", "strb", "ldrsb", "ldr", "ldrh", "ldrb", "ldrsh",
+            ][((op >> 9) & 7) as usize];
+            Some(format!("{name} {rd}, [{rs}, {}]", reg((op >> 6) & 7)))
+        }
+        0b01100..=0b10001 => {
+            let (name, scale) = match op >> 11 {
+                0b01100 => ("str", 4),
+                0b01101 => ("ldr", 4),
+                0b01110 => ("strb", 1),
+                0b01111 => ("ldrb", 1),
+                0b10000 => ("strh", 2),
+                _ => ("ldrh", 2),
+            };
+            let offset = ((op >> 6) & 0x1F) * scale;
+            Some(format!(
+                "{name} {rd}, {}",
+                memory_operand(rs, offset_imm(true, offset, false))
+            ))
+        }
+        0b10010 | 0b10011 => {
+            let name = if op & (1 << 11) != 0 { "ldr" } else { "str" };
+            Some(format!(
+                "{name} {}, {}",
+                reg((op >> 8) & 7),
+                memory_operand("sp", offset_imm(true, (op & 0xFF) * 4, false))
+            ))
+        }
+        0b10100 | 0b10101 => {
+            let base = if op & (1 << 11) != 0 { "sp" } else { "pc" };
+            Some(format!(
+                "add {}, {base}, #{}",
+                reg((op >> 8) & 7),
+                (op & 0xFF) * 4
+            ))
+        }
+        0b10110 | 0b10111 => thumb_misc(op),
+        0b11000 | 0b11001 => {
+            let rn = (op >> 8) & 7;
+            let list = op & 0xFF;
+            let (name, write_back) = if op & (1 << 11) != 0 {
+                ("ldm", list & (1 << rn) == 0)
+            } else {
+                ("stm", true)
+            };
+            let bang = if write_back { "!" } else { "" };
+            Some(format!("{name} {}{bang}, {}", reg(rn), register_list(list)))
+        }
+        0b11010 | 0b11011 => match (op >> 8) & 0xF {
+            0xE => None,
+            0xF => Some(format!("svc {}", hex(op & 0xFF))),
+            cond => {
+                let target = branch_target(pc.wrapping_add(4), op & 0xFF, 8, 1);
+                Some(format!(
+                    "b{} {}",
+                    CONDITIONS[cond as usize],
+                    address(target)
+                ))
+            }
+        },
+        0b11100 => {
+            let target = branch_target(pc.wrapping_add(4), op & 0x7FF, 11, 1);
+            Some(format!("b {}", address(target)))
+        }
+        0b11110 => {
+            let offset = (op & 0x7FF) << 12;
+            let offset = ((offset << 9) as i32) >> 9;
+            let name = if offset < 0 { "sub" } else { "add" };
+            Some(format!("{name} lr, pc, {}", imm(offset.unsigned_abs())))
+        }
+        _ => {
+            // 0b11101 is the BLX suffix (bit 0 clear), 0b11111 the BL suffix.
+            let exchange = op >> 11 == 0b11101;
+            if exchange && op & 1 != 0 {
+                None
+            } else {
+                let name = if exchange { "blx" } else { "bl" };
+                Some(format!("{name} [lr, {}]", imm((op & 0x7FF) * 2)))
+            }
+        }
+    };
+    text.unwrap_or_else(|| format!(".inst.n {opcode:#06x}"))
+}
+
+fn reg(index: u32) -> &'static str {
+    REG_NAMES[(index & 0xF) as usize]
+}
+
+fn hex(value: u32) -> String {
+    format!("#{value:#x}")
+}
+
+/// Small immediates print in decimal, the rest in hex.
+fn imm(value: u32) -> String {
+    if value < 0x100 {
+        format!("#{value}")
+    } else {
+        hex(value)
+    }
+}
+
+fn address(target: u32) -> String {
+    format!("0x{target:08x}")
+}
+
+fn arm_unknown(opcode: u32) -> String {
+    format!(".inst {opcode:#010x}")
+}
+
+/// `base + sign_extend(field, bits) << shift`.
+fn branch_target(base: u32, field: u32, bits: u32, shift: u32) -> u32 {
+    let unused = 32 - bits;
+    let offset = ((field << unused) as i32) >> unused;
+    base.wrapping_add((offset << shift) as u32)
+}
+
+fn register_list(mask: u32) -> String {
+    let registers: Vec<&str> = (0..16)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(reg)
+        .collect();
+    format!("{{{}}}", registers.join(", "))
+}
+
+/// The offset of an immediate-indexed access; a zero pre-indexed offset is
+/// left out.
+fn offset_imm(add: bool, value: u32, post_indexed: bool) -> Option<String> {
+    (value != 0 || !add || post_indexed).then(|| {
+        let sign = if add { "" } else { "-" };
+        format!("#{sign}{value}")
+    })
+}
+
+fn memory_operand(base: &str, offset: Option<String>) -> String {
+    match offset {
+        Some(offset) => format!("[{base}, {offset}]"),
+        None => format!("[{base}]"),
+    }
+}
+
+/// Formats an ARM addressing mode from its P and W bits.
+fn indexed_operand(rn: u32, offset: Option<String>, pre_index: bool, write_back: bool) -> String {
+    if !pre_index {
+        return format!(
+            "[{}], {}",
+            reg(rn),
+            offset.unwrap_or_else(|| "#0".to_string())
+        );
+    }
+    let bang = if write_back { "!" } else { "" };
+    format!("{}{bang}", memory_operand(reg(rn), offset))
+}
+
+/// An immediate-shifted register; `rm, lsl #0` is just `rm`.
+fn shifted_register(rm: u32, kind: u32, amount: u32) -> String {
+    match (kind, amount) {
+        (0, 0) => reg(rm).to_string(),
+        (3, 0) => format!("{}, rrx", reg(rm)),
+        (_, 0) => format!("{}, {} #32", reg(rm), SHIFTS[kind as usize]),
+        _ => format!("{}, {} #{amount}", reg(rm), SHIFTS[kind as usize]),
+    }
+}
+
+fn rotated_immediate(opcode: u32) -> u32 {
+    (opcode & 0xFF).rotate_right(((opcode >> 8) & 0xF) * 2)
+}
+
+fn psr_fields(mask: u32) -> String {
+    [(8, 'f'), (4, 's'), (2, 'x'), (1, 'c')]
+        .iter()
+        .filter(|(bit, _)| mask & bit != 0)
+        .map(|&(_, name)| name)
+        .collect()
+}
+
+fn psr_name(opcode: u32) -> &'static str {
+    if opcode & (1 << 22) != 0 {
+        "spsr"
+    } else {
+        "cpsr"
+    }
+}
+
+fn arm_unconditional(pc: u32, opcode: u32) -> Option<String> {
+    if opcode == 0xF57F_F01F {
+        return Some("clrex".to_string());
+    }
+    if opcode & 0xFFF1_FE20 == 0xF100_0000 {
+        let aif: String = [(1 << 8, 'a'), (1 << 7, 'i'), (1 << 6, 'f')]
+            .iter()
+            .filter(|(bit, _)| opcode & bit != 0)
+            .map(|&(_, name)| name)
+            .collect();
+        let mode = (opcode >> 17) & 1 == 1;
+        return match (opcode >> 18) & 0x3 {
+            imod @ (0b10 | 0b11) => {
+                let name = if imod == 0b11 { "cpsid" } else { "cpsie" };
+                Some(if mode {
+                    format!("{name} {aif}, #{}", opcode & 0x1F)
+                } else {
+                    format!("{name} {aif}")
+                })
+            }
+            0b00 if mode => Some(format!("cps #{}", opcode & 0x1F)),
+            _ => None,
+        };
+    }
+    if opcode & 0xFFFF_FDFF == 0xF101_0000 {
+        let endian = if opcode & (1 << 9) != 0 { "be" } else { "le" };
+        return Some(format!("setend {endian}"));
+    }
+    if opcode & 0xFE5F_FFE0 == 0xF84D_0500 {
+        let bang = if opcode & (1 << 21) != 0 { "!" } else { "" };
+        return Some(format!(
+            "srs{} sp{bang}, #{}",
+            block_suffix(opcode),
+            opcode & 0x1F
+        ));
+    }
+    if opcode & 0xFE50_FFFF == 0xF810_0A00 {
+        let bang = if opcode & (1 << 21) != 0 { "!" } else { "" };
+        return Some(format!(
+            "rfe{} {}{bang}",
+            block_suffix(opcode),
+            reg(opcode >> 16)
+        ));
+    }
+    if opcode & 0xFD70_F000 == 0xF550_F000 {
+        let offset = if opcode & (1 << 25) == 0 {
+            offset_imm(opcode & (1 << 23) != 0, opcode & 0xFFF, false)
+        } else {
+            Some(register_offset(opcode))
+        };
+        return Some(format!("pld {}", memory_operand(reg(opcode >> 16), offset)));
+    }
+    if opcode & 0xFE00_0000 == 0xFA00_0000 {
+        let target = branch_target(pc.wrapping_add(8), opcode & 0x00FF_FFFF, 24, 2)
+            .wrapping_add((opcode >> 23) & 2);
+        return Some(format!("blx {}", address(target)));
+    }
+    None
+}
+
+/// `ia`/`ib`/`da`/`db` from the P and U bits; `ia` is the UAL default.
+fn block_suffix(opcode: u32) -> &'static str {
+    match ((opcode >> 24) & 1, (opcode >> 23) & 1) {
+        (0, 0) => "da",
+        (0, _) => "",
+        (_, 0) => "db",
+        _ => "ib",
+    }
+}
+
+/// Miscellaneous instructions in the data-processing space with S clear.
+fn arm_misc(opcode: u32, cond: &str) -> Option<String> {
+    if opcode & 0x0190_0000 != 0x0100_0000 {
+        return None;
+    }
+    let rd = reg(opcode >> 12);
+    let rn = reg(opcode >> 16);
+    let rs = reg(opcode >> 8);
+    let rm = reg(opcode);
+    match opcode & 0x0FFF_FFF0 {
+        0x012F_FF10 => return Some(format!("bx{cond} {rm}")),
+        0x012F_FF20 => return Some(format!("bxj{cond} {rm}")),
+        0x012F_FF30 => return Some(format!("blx{cond} {rm}")),
+        _ => {}
+    }
+    if opcode & 0x0FFF_0FF0 == 0x016F_0F10 {
+        return Some(format!("clz{cond} {rd}, {rm}"));
+    }
+    if opcode & 0x0FF0_00F0 == 0x0120_0070 {
+        return Some(format!(
+            "bkpt {}",
+            hex(((opcode >> 4) & 0xFFF0) | (opcode & 0xF))
+        ));
+    }
+    if opcode & 0x0FBF_0FFF == 0x010F_0000 {
+        return Some(format!("mrs{cond} {rd}, {}", psr_name(opcode)));
+    }
+    if opcode & 0x0FB0_FFF0 == 0x0120_F000 {
+        return Some(format!(
+            "msr{cond} {}_{}, {rm}",
+            psr_name(opcode),
+            psr_fields(opcode >> 16)
+        ));
+    }
+    if opcode & 0x0F90_0FF0 == 0x0100_0050 {
+        let name = ["qadd", "qsub", "qdadd", "qdsub"][((opcode >> 21) & 3) as usize];
+        return Some(format!("{name}{cond} {rd}, {rm}, {rn}"));
+    }
+    if opcode & 0x0F90_0090 == 0x0100_0080 {
+        let x = if opcode & (1 << 5) != 0 { "t" } else { "b" };
+        let y = if opcode & (1 << 6) != 0 { "t" } else { "b" };
+        // Rd sits in bits 19:16 and the accumulator in 15:12.
+        return Some(match (opcode >> 21) & 3 {
+            0 => format!("smla{x}{y}{cond} {rn}, {rm}, {rs}, {rd}"),
+            1 if opcode & (1 << 5) == 0 => format!("smlaw{y}{cond} {rn}, {rm}, {rs}, {rd}"),
+            1 => format!("smulw{y}{cond} {rn}, {rm}, {rs}"),
+            2 => format!("smlal{x}{y}{cond} {rd}, {rn}, {rm}, {rs}"),
+            _ => format!("smul{x}{y}{cond} {rn}, {rm}, {rs}"),
+        });
+    }
+    None
+}
+
+/// Multiplies, swaps, exclusives and the halfword/doubleword transfers, all
+/// of which have bits 7 and 4 set.
+fn arm_multiply_or_extra(opcode: u32, cond: &str) -> Option<String> {
+    if opcode & 0x90 != 0x90 {
+        return None;
+    }
+    let rd = reg(opcode >> 12);
+    let rn = reg(opcode >> 16);
+    let rs = reg(opcode >> 8);
+    let rm = reg(opcode);
+    let s = if opcode & (1 << 20) != 0 { "s" } else { "" };
+
+    if opcode & 0x60 == 0 {
+        if opcode & 0x0FC0_00F0 == 0x0000_0090 {
+            // MUL/MLA keep Rd in bits 19:16.
+            return Some(if opcode & (1 << 21) != 0 {
+                format!("mla{s}{cond} {rn}, {rm}, {rs}, {rd}")
+            } else {
+                format!("mul{s}{cond} {rn}, {rm}, {rs}")
+            });
+        }
+        if opcode & 0x0FF0_00F0 == 0x0040_0090 {
+            return Some(format!("umaal{cond} {rd}, {rn}, {rm}, {rs}"));
+        }
+        if opcode & 0x0F80_00F0 == 0x0080_0090 {
+            let name = ["umull", "umlal", "smull", "smlal"][((opcode >> 21) & 3) as usize];
+            return Some(format!("{name}{s}{cond} {rd}, {rn}, {rm}, {rs}"));
+        }
+        if opcode & 0x0FB0_0FF0 == 0x0100_0090 {
+            let b = if opcode & (1 << 22) != 0 { "b" } else { "" };
+            return Some(format!("swp{b}{cond} {rd}, {rm}, [{rn}]"));
+        }
+        let size = ["", "d", "b", "h"][((opcode >> 21) & 3) as usize];
+        if opcode & 0x0F90_0FFF == 0x0190_0F9F {
+            return Some(if size == "d" {
+                format!("ldrexd{cond} {rd}, {}, [{rn}]", reg((opcode >> 12) + 1))
+            } else {
+                format!("ldrex{size}{cond} {rd}, [{rn}]")
+            });
+        }
+        if opcode & 0x0F90_0FF0 == 0x0180_0F90 {
+            return Some(if size == "d" {
+                format!("strexd{cond} {rd}, {rm}, {}, [{rn}]", reg(opcode + 1))
+            } else {
+                format!("strex{size}{cond} {rd}, {rm}, [{rn}]")
+            });
+        }
+        return None;
+    }
+
+    let load = opcode & (1 << 20) != 0;
+    let name = match (load, (opcode >> 5) & 3) {
+        (true, 1) => "ldrh",
+        (true, 2) => "ldrsb",
+        (true, _) => "ldrsh",
+        (false, 1) => "strh",
+        (false, 2) => "ldrd",
+        (false, _) => "strd",
+    };
+    let pre_index = opcode & (1 << 24) != 0;
+    let add = opcode & (1 << 23) != 0;
+    let offset = if opcode & (1 << 22) != 0 {
+        offset_imm(add, ((opcode >> 4) & 0xF0) | (opcode & 0xF), !pre_index)
+    } else {
+        let sign = if add { "" } else { "-" };
+        Some(format!("{sign}{rm}"))
+    };
+    let operand = indexed_operand(opcode >> 16, offset, pre_index, opcode & (1 << 21) != 0);
+    Some(if name.ends_with('d') {
+        format!("{name}{cond} {rd}, {}, {operand}", reg((opcode >> 12) + 1))
+    } else {
+        format!("{name}{cond} {rd}, {operand}")
+    })
+}
+
+fn arm_msr_immediate(opcode: u32, cond: &str) -> Option<String> {
+    if opcode & 0x0FFF_FF00 == 0x0320_F000 {
+        return Some(match opcode & 0xFF {
+            0 => format!("nop{cond}"),
+            1 => format!("yield{cond}"),
+            2 => format!("wfe{cond}"),
+            3 => format!("wfi{cond}"),
+            4 => format!("sev{cond}"),
+            _ => return None,
+        });
+    }
+    if opcode & 0x0FB0_F000 == 0x0320_F000 {
+        return Some(format!(
+            "msr{cond} {}_{}, {}",
+            psr_name(opcode),
+            psr_fields(opcode >> 16),
+            imm(rotated_immediate(opcode))
+        ));
+    }
+    if opcode & 0x0190_0000 == 0x0100_0000 {
+        // The immediate forms of the test opcodes with S clear.
+        return Some(arm_unknown(opcode));
+    }
+    None
+}
+
+fn arm_data_processing(opcode: u32, cond: &str) -> Option<String> {
+    let op = (opcode >> 21) & 0xF;
+    let set_flags = opcode & (1 << 20) != 0;
+    let s = if set_flags { "s" } else { "" };
+    let name = DATA_OPS[op as usize];
+    let rd = reg(opcode >> 12);
+    let rn = reg(opcode >> 16);
+    let rm = opcode & 0xF;
+    let kind = (opcode >> 5) & 3;
+    let by_register = opcode & (1 << 25) == 0 && opcode & 0x10 != 0;
+
+    let operand = if opcode & (1 << 25) != 0 {
+        imm(rotated_immediate(opcode))
+    } else if by_register {
+        format!(
+            "{}, {} {}",
+            reg(rm),
+            SHIFTS[kind as usize],
+            reg(opcode >> 8)
+        )
+    } else {
+        shifted_register(rm, kind, (opcode >> 7) & 0x1F)
+    };
+
+    Some(match op {
+        0x8..=0xB if !set_flags => return None,
+        0x8..=0xB => format!("{name}{cond} {rn}, {operand}"),
+        // Shifted moves take their UAL shift mnemonics.
+        0xD if opcode & (1 << 25) == 0 && operand != reg(rm) => {
+            let amount = (opcode >> 7) & 0x1F;
+            if by_register {
+                format!(
+                    "{}{s}{cond} {rd}, {}, {}",
+                    SHIFTS[kind as usize],
+                    reg(rm),
+                    reg(opcode >> 8)
+                )
+            } else if kind == 3 && amount == 0 {
+                format!("rrx{s}{cond} {rd}, {}", reg(rm))
+            } else {
+                let amount = if amount == 0 { 32 } else { amount };
+                format!(
+                    "{}{s}{cond} {rd}, {}, #{amount}",
+                    SHIFTS[kind as usize],
+                    reg(rm)
+                )
+            }
+        }
+        0xD | 0xF => format!("{name}{s}{cond} {rd}, {operand}"),
+        _ => format!("{name}{s}{cond} {rd}, {rn}, {operand}"),
+    })
+}
+
+/// The register offset of an `LDR`/`STR`/`PLD`, with its sign and shift.
+fn register_offset(opcode: u32) -> String {
+    let sign = if opcode & (1 << 23) != 0 { "" } else { "-" };
+    let register = shifted_register(opcode & 0xF, (opcode >> 5) & 3, (opcode >> 7) & 0x1F);
+    format!("{sign}{register}")
+}
+
+fn arm_single_transfer(opcode: u32, cond: &str) -> String {
+    let pre_index = opcode & (1 << 24) != 0;
+    let write_back = opcode & (1 << 21) != 0;
+    let name = if opcode & (1 << 20) != 0 {
+        "ldr"
+    } else {
+        "str"
+    };
+    let byte = if opcode & (1 << 22) != 0 { "b" } else { "" };
+    let user = if !pre_index && write_back { "t" } else { "" };
+    let offset = if opcode & (1 << 25) == 0 {
+        offset_imm(opcode & (1 << 23) != 0, opcode & 0xFFF, !pre_index)
+    } else {
+        Some(register_offset(opcode))
+    };
+    format!(
+        "{name}{byte}{user}{cond} {}, {}",
+        reg(opcode >> 12),
+        indexed_operand(opcode >> 16, offset, pre_index, write_back)
+    )
+}
+
+fn arm_block_transfer(opcode: u32, cond: &str) -> String {
+    let load = opcode & (1 << 20) != 0;
+    let write_back = opcode & (1 << 21) != 0;
+    let rn = (opcode >> 16) & 0xF;
+    let list = register_list(opcode & 0xFFFF);
+    let user = if opcode & (1 << 22) != 0 { "^" } else { "" };
+    let suffix = block_suffix(opcode);
+
+    let stack = rn == 13 && write_back && user.is_empty();
+    if stack && load && suffix.is_empty() {
+        return format!("pop{cond} {list}");
+    }
+    if stack && !load && suffix == "db" {
+        return format!("push{cond} {list}");
+    }
+    let name = if load { "ldm" } else { "stm" };
+    let bang = if write_back { "!" } else { "" };
+    format!("{name}{suffix}{cond} {}{bang}, {list}{user}", reg(rn))
+}
+
+fn arm_media(opcode: u32, cond: &str) -> Option<String> {
+    let rd = reg(opcode >> 12);
+    let rn = reg(opcode >> 16);
+    let rs = reg(opcode >> 8);
+    let rm = reg(opcode);
+
+    if opcode & 0x0F80_0010 == 0x0600_0010 {
+        let prefix = match (opcode >> 20) & 7 {
+            0b001 => "s",
+            0b010 => "q",
+            0b011 => "sh",
+            0b101 => "u",
+            0b110 => "uq",
+            0b111 => "uh",
+            _ => return None,
+        };
+        let op = match (opcode >> 5) & 7 {
+            0b000 => "add16",
+            0b001 => "asx",
+            0b010 => "sax",
+            0b011 => "sub16",
+            0b100 => "add8",
+            0b111 => "sub8",
+            _ => return None,
+        };
+        return Some(format!("{prefix}{op}{cond} {rd}, {rn}, {rm}"));
+    }
+
+    if opcode & 0x0F80_0010 == 0x0700_0010 {
+        // Media multiplies keep Rd in bits 19:16 and Ra in 15:12.
+        let swap = if opcode & (1 << 5) != 0 { "x" } else { "" };
+        let accumulate = (opcode >> 12) & 0xF != 0xF;
+        return match ((opcode >> 20) & 7, (opcode >> 6) & 3) {
+            (0b000, 0b00) if accumulate => {
+                Some(format!("smlad{swap}{cond} {rn}, {rm}, {rs}, {rd}"))
+            }
+            (0b000, 0b00) => Some(format!("smuad{swap}{cond} {rn}, {rm}, {rs}")),
+            (0b000, 0b01) if accumulate => {
+                Some(format!("smlsd{swap}{cond} {rn}, {rm}, {rs}, {rd}"))
+            }
+            (0b000, 0b01) => Some(format!("smusd{swap}{cond} {rn}, {rm}, {rs}")),
+            (0b100, 0b00) => Some(format!("smlald{swap}{cond} {rd}, {rn}, {rm}, {rs}")),
+            (0b100, 0b01) => Some(format!("smlsld{swap}{cond} {rd}, {rn}, {rm}, {rs}")),
+            (0b101, 0b00) if accumulate => Some(format!(
+                "smmla{}{cond} {rn}, {rm}, {rs}, {rd}",
+                round(opcode)
+            )),
+            (0b101, 0b00) => Some(format!("smmul{}{cond} {rn}, {rm}, {rs}", round(opcode))),
+            (0b101, 0b11) => Some(format!(
+                "smmls{}{cond} {rn}, {rm}, {rs}, {rd}",
+                round(opcode)
+            )),
+            _ => None,
+        };
+    }
+    if opcode & 0x0FF0_00F0 == 0x0780_0010 {
+        return Some(if (opcode >> 12) & 0xF != 0xF {
+            format!("usada8{cond} {rn}, {rm}, {rs}, {rd}")
+        } else {
+            format!("usad8{cond} {rn}, {rm}, {rs}")
+        });
+    }
+
+    if opcode & 0x0FF0_0030 == 0x0680_0010 {
+        let amount = (opcode >> 7) & 0x1F;
+        return Some(if opcode & (1 << 6) != 0 {
+            let amount = if amount == 0 { 32 } else { amount };
+            format!("pkhtb{cond} {rd}, {rn}, {rm}, asr #{amount}")
+        } else if amount == 0 {
+            format!("pkhbt{cond} {rd}, {rn}, {rm}")
+        } else {
+            format!("pkhbt{cond} {rd}, {rn}, {rm}, lsl #{amount}")
+        });
+    }
+    if opcode & 0x0FB0_0FF0 == 0x06A0_0F30 {
+        let unsigned = opcode & (1 << 22) != 0;
+        let bits = ((opcode >> 16) & 0xF) + u32::from(!unsigned);
+        let name = if unsigned { "usat16" } else { "ssat16" };
+        return Some(format!("{name}{cond} {rd}, #{bits}, {rm}"));
+    }
+    if opcode & 0x0FA0_0030 == 0x06A0_0010 {
+        let unsigned = opcode & (1 << 22) != 0;
+        let bits = ((opcode >> 16) & 0x1F) + u32::from(!unsigned);
+        let name = if unsigned { "usat" } else { "ssat" };
+        let kind = if opcode & (1 << 6) != 0 { 2 } else { 0 };
+        let amount = (opcode >> 7) & 0x1F;
+        let operand = if kind == 0 && amount == 0 {
+            rm.to_string()
+        } else {
+            shifted_register(opcode & 0xF, kind, amount)
+        };
+        return Some(format!("{name}{cond} {rd}, #{bits}, {operand}"));
+    }
+    if opcode & 0x0FF0_0FF0 == 0x0680_0FB0 {
+        return Some(format!("sel{cond} {rd}, {rn}, {rm}"));
+    }
+    match opcode & 0x0FFF_0FF0 {
+        0x06BF_0F30 => return Some(format!("rev{cond} {rd}, {rm}")),
+        0x06BF_0FB0 => return Some(format!("rev16{cond} {rd}, {rm}")),
+        0x06FF_0FB0 => return Some(format!("revsh{cond} {rd}, {rm}")),
+        _ => {}
+    }
+    if opcode & 0x0F80_03F0 == 0x0680_0070 {
+        let name = match (opcode >> 20) & 7 {
+            0b000 => "xtb16",
+            0b010 => "xtb",
+            0b011 => "xth",
+            0b100 => "xtb16",
+            0b110 => "xtb",
+            0b111 => "xth",
+            _ => return None,
+        };
+        let sign = if opcode & (1 << 22) != 0 { "u" } else { "s" };
+        let rotation = match (opcode >> 10) & 3 {
+            0 => String::new(),
+            r => format!(", ror #{}", r * 8),
+        };
+        return Some(if (opcode >> 16) & 0xF == 0xF {
+            format!("{sign}{name}{cond} {rd}, {rm}{rotation}")
+        } else {
+            let (x, rest) = name.split_at(2);
+            format!("{sign}{x}a{rest}{cond} {rd}, {rn}, {rm}{rotation}")
+        });
+    }
+    None
+}
+
+fn round(opcode: u32) -> &'static str {
+    if opcode & (1 << 5) != 0 { "r" } else { "" }
+}
+
+fn arm_coprocessor(opcode: u32, cond: &str) -> Option<String> {
+    let cp = (opcode >> 8) & 0xF;
+    if matches!(cp, 10 | 11)
+        && let Some(text) = vfp(opcode, cond)
+    {
+        return Some(text);
+    }
+    let crn = (opcode >> 16) & 0xF;
+    let rt = (opcode >> 12) & 0xF;
+    let crm = opcode & 0xF;
+    let opc2 = (opcode >> 5) & 7;
+
+    if (opcode >> 24) & 0xF == 0xE {
+        if opcode & 0x10 == 0 {
+            return Some(format!(
+                "cdp{cond} p{cp}, {}, c{rt}, c{crn}, c{crm}, {opc2}",
+                (opcode >> 20) & 0xF
+            ));
+        }
+        let load = opcode & (1 << 20) != 0;
+        let name = if load { "mrc" } else { "mcr" };
+        let target = if load && rt == 15 {
+            "APSR_nzcv"
+        } else {
+            reg(rt)
+        };
+        return Some(format!(
+            "{name}{cond} p{cp}, {}, {target}, c{crn}, c{crm}, {opc2}",
+            (opcode >> 21) & 7
+        ));
+    }
+    if opcode & 0x0FE0_0000 == 0x0C40_0000 {
+        let name = if opcode & (1 << 20) != 0 {
+            "mrrc"
+        } else {
+            "mcrr"
+        };
+        return Some(format!(
+            "{name}{cond} p{cp}, {}, {}, {}, c{crm}",
+            (opcode >> 4) & 0xF,
+            reg(rt),
+            reg(crn)
+        ));
+    }
+    let pre_index = opcode & (1 << 24) != 0;
+    let write_back = opcode & (1 << 21) != 0;
+    if !pre_index && !write_back && opcode & (1 << 23) == 0 {
+        return None;
+    }
+    let name = if opcode & (1 << 20) != 0 {
+        "ldc"
+    } else {
+        "stc"
+    };
+    let long = if opcode & (1 << 22) != 0 { "l" } else { "" };
+    let operand = if !pre_index && !write_back {
+        format!("[{}], {{{}}}", reg(crn), opcode & 0xFF)
+    } else {
+        let offset = offset_imm(opcode & (1 << 23) != 0, (opcode & 0xFF) * 4, !pre_index);
+        indexed_operand(crn, offset, pre_index, write_back)
+    };
+    Some(format!("{name}{long}{cond} p{cp}, c{rt}, {operand}"))
+}
+
+/// A VFP register: `S` registers take their low bit from `extra`.
+fn vfp_reg(double: bool, field: u32, extra: u32) -> String {
+    if double {
+        format!("d{field}")
+    } else {
+        format!("s{}", (field << 1) | extra)
+    }
+}
+
+fn vfp(opcode: u32, cond: &str) -> Option<String> {
+    let double = (opcode >> 8) & 0xF == 11;
+    let size = if double { "f64" } else { "f32" };
+    let vd = (opcode >> 12) & 0xF;
+    let d = (opcode >> 22) & 1;
+    let vn = (opcode >> 16) & 0xF;
+    let n = (opcode >> 7) & 1;
+    let vm = opcode & 0xF;
+    let m = (opcode >> 5) & 1;
+    let dest = vfp_reg(double, vd, d);
+    let lhs = vfp_reg(double, vn, n);
+    let rhs = vfp_reg(double, vm, m);
+    let rt = reg(opcode >> 12);
+
+    match (opcode >> 24) & 0xF {
+        0xE if opcode & 0x10 != 0 => {
+            let to_arm = opcode & (1 << 20) != 0;
+            match (double, (opcode >> 21) & 7) {
+                (false, 0b111) => {
+                    let system = match vn {
+                        0 => "fpsid",
+                        1 => "fpscr",
+                        8 => "fpexc",
+                        _ => return None,
+                    };
+                    Some(if !to_arm {
+                        format!("vmsr{cond} {system}, {rt}")
+                    } else if (opcode >> 12) & 0xF == 15 {
+                        format!("vmrs{cond} APSR_nzcv, {system}")
+                    } else {
+                        format!("vmrs{cond} {rt}, {system}")
+                    })
+                }
+                (false, 0) if to_arm => Some(format!("vmov{cond} {rt}, {lhs}")),
+                (false, 0) => Some(format!("vmov{cond} {lhs}, {rt}")),
+                (true, half @ (0 | 1)) if to_arm => {
+                    Some(format!("vmov{cond}.32 {rt}, d{vn}[{half}]"))
+                }
+                (true, half @ (0 | 1)) => Some(format!("vmov{cond}.32 d{vn}[{half}], {rt}")),
+                _ => None,
+            }
+        }
+        0xE => {
+            let opc =
+                ((opcode >> 20) & 0b1000) | ((opcode >> 19) & 0b100) | ((opcode >> 19) & 0b10);
+            let opc = opc | ((opcode >> 6) & 1);
+            let name = match opc {
+                0b0000 => "vmla",
+                0b0001 => "vmls",
+                0b0010 => "vnmls",
+                0b0011 => "vnmla",
+                0b0100 => "vmul",
+                0b0101 => "vnmul",
+                0b0110 => "vadd",
+                0b0111 => "vsub",
+                0b1000 => "vdiv",
+                0b1111 => return vfp_extension(opcode, cond, double),
+                _ => return None,
+            };
+            Some(format!("{name}{cond}.{size} {dest}, {lhs}, {rhs}"))
+        }
+        _ if opcode & 0x0FE0_0000 == 0x0C40_0000 => {
+            if opcode & 0xD0 != 0x10 {
+                return None;
+            }
+            let rt2 = reg(opcode >> 16);
+            let registers = if double {
+                format!("d{vm}")
+            } else {
+                let first = (vm << 1) | m;
+                format!("s{first}, s{}", first + 1)
+            };
+            Some(if opcode & (1 << 20) != 0 {
+                format!("vmov{cond} {rt}, {rt2}, {registers}")
+            } else {
+                format!("vmov{cond} {registers}, {rt}, {rt2}")
+            })
+        }
+        _ => {
+            let pre_index = opcode & (1 << 24) != 0;
+            let add = opcode & (1 << 23) != 0;
+            let write_back = opcode & (1 << 21) != 0;
+            let load = opcode & (1 << 20) != 0;
+            let rn = (opcode >> 16) & 0xF;
+            let imm8 = opcode & 0xFF;
+            if pre_index && !write_back {
+                let name = if load { "vldr" } else { "vstr" };
+                let offset = offset_imm(add, imm8 * 4, false);
+                return Some(format!(
+                    "{name}{cond} {dest}, {}",
+                    memory_operand(reg(rn), offset)
+                ));
+            }
+            let suffix = match (pre_index, add, write_back) {
+                (false, true, _) => "ia",
+                (true, false, true) => "db",
+                _ => return None,
+            };
+            let count = if double { imm8 / 2 } else { imm8 };
+            if count == 0 {
+                return None;
+            }
+            let list = if count == 1 {
+                format!("{{{dest}}}")
+            } else {
+                let first = if double { vd } else { (vd << 1) | d };
+                let prefix = if double { 'd' } else { 's' };
+                format!("{{{prefix}{first}-{prefix}{}}}", first + count - 1)
+            };
+            if rn == 13 && write_back && (load, suffix) == (true, "ia") {
+                return Some(format!("vpop{cond} {list}"));
+            }
+            if rn == 13 && write_back && (load, suffix) == (false, "db") {
+                return Some(format!("vpush{cond} {list}"));
+            }
+            let name = if load { "vldm" } else { "vstm" };
+            let bang = if write_back { "!" } else { "" };
+            Some(format!("{name}{suffix}{cond} {}{bang}, {list}", reg(rn)))
+        }
+    }
+}
+
+/// The `opc == 0b1111` data-processing extensions selected by Vn:N.
+fn vfp_extension(opcode: u32, cond: &str, double: bool) -> Option<String> {
+    let size = if double { "f64" } else { "f32" };
+    let vd = (opcode >> 12) & 0xF;
+    let d = (opcode >> 22) & 1;
+    let vm = opcode & 0xF;
+    let m = (opcode >> 5) & 1;
+    let dest = vfp_reg(double, vd, d);
+    let source = vfp_reg(double, vm, m);
+    let single_dest = vfp_reg(false, vd, d);
+    let single_source = vfp_reg(false, vm, m);
+
+    let ext = (((opcode >> 16) & 0xF) << 1) | ((opcode >> 7) & 1);
+    Some(match ext {
+        0b00000 => format!("vmov{cond}.{size} {dest}, {source}"),
+        0b00001 => format!("vabs{cond}.{size} {dest}, {source}"),
+        0b00010 => format!("vneg{cond}.{size} {dest}, {source}"),
+        0b00011 => format!("vsqrt{cond}.{size} {dest}, {source}"),
+        0b01000 => format!("vcmp{cond}.{size} {dest}, {source}"),
+        0b01001 => format!("vcmpe{cond}.{size} {dest}, {source}"),
+        0b01010 => format!("vcmp{cond}.{size} {dest}, #0"),
+        0b01011 => format!("vcmpe{cond}.{size} {dest}, #0"),
+        0b01111 if double => format!("vcvt{cond}.f32.f64 {single_dest}, d{vm}"),
+        0b01111 => format!("vcvt{cond}.f64.f32 d{vd}, {single_source}"),
+        0b10000 => format!("vcvt{cond}.{size}.u32 {dest}, {single_source}"),
+        0b10001 => format!("vcvt{cond}.{size}.s32 {dest}, {single_source}"),
+        0b11000..=0b11011 => {
+            // Bit 0 selects round-towards-zero; otherwise FPSCR rounding.
+            let round = if ext & 1 == 0 { "r" } else { "" };
+            let sign = if ext & 0b10 != 0 { "s32" } else { "u32" };
+            format!("vcvt{round}{cond}.{sign}.{size} {single_dest}, {source}")
+        }
+        _ => return None,
+    })
+}
+
+fn thumb_data_or_hi_register(op: u32) -> Option<String> {
+    let rd = reg(op & 7);
+    let rs = reg((op >> 3) & 7);
+    if op & 0xFC00 == 0x4000 {
+        let alu = (op >> 6) & 0xF;
+        let name = THUMB_ALU_OPS[alu as usize];
+        return Some(match alu {
+            0x9 => format!("{name} {rd}, {rs}, #0"),
+            0xD => format!("{name} {rd}, {rs}, {rd}"),
+            _ => format!("{name} {rd}, {rs}"),
+        });
+    }
+    let rd = ((op >> 4) & 8) | (op & 7);
+    let rm = reg((op >> 3) & 0xF);
+    Some(match (op >> 8) & 3 {
+        0 => format!("add {}, {rm}", reg(rd)),
+        1 => format!("cmp {}, {rm}", reg(rd)),
+        2 => format!("mov {}, {rm}", reg(rd)),
+        _ if op & 0x80 != 0 => format!("blx {rm}"),
+        _ => format!("bx {rm}"),
+    })
+}
+
+fn thumb_misc(op: u32) -> Option<String> {
+    let rd = reg(op & 7);
+    let rm = reg((op >> 3) & 7);
+    if op & 0xFF00 == 0xB000 {
+        let name = if op & 0x80 != 0 { "sub" } else { "add" };
+        return Some(format!("{name} sp, sp, #{}", (op & 0x7F) * 4));
+    }
+    if op & 0xFF00 == 0xB200 {
+        let name = ["sxth", "sxtb", "uxth", "uxtb"][((op >> 6) & 3) as usize];
+        return Some(format!("{name} {rd}, {rm}"));
+    }
+    if op & 0xF600 == 0xB400 {
+        let pop = op & (1 << 11) != 0;
+        let extra = match (pop, op & 0x100 != 0) {
+            (_, false) => 0,
+            (true, true) => 1 << 15,
+            (false, true) => 1 << 14,
+        };
+        let name = if pop { "pop" } else { "push" };
+        return Some(format!("{name} {}", register_list((op & 0xFF) | extra)));
+    }
+    if op & 0xFFF7 == 0xB650 {
+        let endian = if op & 8 != 0 { "be" } else { "le" };
+        return Some(format!("setend {endian}"));
+    }
+    if op & 0xFFE8 == 0xB660 {
+        let name = if op & 0x10 != 0 { "cpsid" } else { "cpsie" };
+        let aif: String = [(4, 'a'), (2, 'i'), (1, 'f')]
+            .iter()
+            .filter(|(bit, _)| op & bit != 0)
+            .map(|&(_, name)| name)
+            .collect();
+        return Some(format!("{name} {aif}"));
+    }
+    if op & 0xFF00 == 0xBA00 {
+        let name = match (op >> 6) & 3 {
+            0 => "rev",
+            1 => "rev16",
+            3 => "revsh",
+            _ => return None,
+        };
+        return Some(format!("{name} {rd}, {rm}"));
+    }
+    if op & 0xFF00 == 0xBE00 {
+        return Some(format!("bkpt {}", hex(op & 0xFF)));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_hex(token: &str) -> u32 {
+        u32::from_str_radix(token.trim_start_matches("0x"), 16)
+            .unwrap_or_else(|e| panic!("bad fixture value {token}: {e}"))
+    }
+
+    #[test]
+    fn arm_encodings_disassemble_to_ual() {
+        let cases = [
+            (0x0010_0000, 0xE59F_000C, "ldr r0, [pc, #12]"),
+            (0, 0xE3A0_0417, "mov r0, #0x17000000"),
+            (0, 0xE250_0001, "subs r0, r0, #1"),
+            (0x0010_0008, 0x1AFF_FFFD, "bne 0x00100004"),
+            (0x0010_0000, 0xEB00_0002, "bl 0x00100010"),
+            (0, 0xE12F_FF1E, "bx lr"),
+            (0, 0xE1A0_1102, "lsl r1, r2, #2"),
+            (0, 0xE1B0_0061, "rrxs r0, r1"),
+            (0, 0xE081_0312, "add r0, r1, r2, lsl r3"),
+            (0, 0xE351_0000, "cmp r1, #0"),
+            (0, 0xE92D_4010, "push {r4, lr}"),
+            (0, 0xE8BD_8010, "pop {r4, pc}"),
+            (0, 0xE891_000C, "ldm r1, {r2, r3}"),
+            (0, 0xE5A1_0004, "str r0, [r1, #4]!"),
+            (0, 0xE411_2004, "ldr r2, [r1], #-4"),
+            (0, 0xE7D1_0102, "ldrb r0, [r1, r2, lsl #2]"),
+            (0, 0xE1D1_00B2, "ldrh r0, [r1, #2]"),
+            (0, 0xE1C2_00D8, "ldrd r0, r1, [r2, #8]"),
+            (0, 0xE002_0291, "mul r2, r1, r2"),
+            (0, 0xE0C1_0392, "smull r0, r1, r2, r3"),
+            (0, 0xE1B4_0F9F, "ldrexd r0, r1, [r4]"),
+            (0, 0xE180_2F91, "strex r2, r1, [r0]"),
+            (0, 0xE10F_0000, "mrs r0, cpsr"),
+            (0, 0xE129_F001, "msr cpsr_fc, r1"),
+            (0, 0xE320_F003, "wfi"),
+            (0, 0xEF00_0011, "svc #0x11"),
+            (0, 0xEE10_2F10, "mrc p15, 0, r2, c0, c0, 0"),
+            (0, 0xEE10_0FB0, "mrc p15, 0, r0, c0, c0, 5"),
+            (0, 0xF57F_F01F, "clrex"),
+            (0, 0xF102_0013, "cps #19"),
+            (0, 0xF10C_0080, "cpsid i"),
+            (0, 0xF101_0200, "setend be"),
+            (0, 0xF96D_0513, "srsdb sp!, #19"),
+            (0, 0xF8BD_0A00, "rfe sp!"),
+            (0, 0xE6BF_0F31, "rev r0, r1"),
+            (0, 0xE6AF_0471, "sxtb r0, r1, ror #8"),
+            (0, 0xE700_F211, "smuad r0, r1, r2"),
+            (0, 0xEE30_0A01, "vadd.f32 s0, s0, s2"),
+            (0, 0xEEB4_0BC1, "vcmpe.f64 d0, d1"),
+            (0, 0xEEF1_FA10, "vmrs APSR_nzcv, fpscr"),
+            (0, 0xED9F_0A02, "vldr s0, [pc, #8]"),
+            (0, 0xECBD_8B04, "vpop {d8-d9}"),
+            (0, 0xEEB8_0AC0, "vcvt.f32.s32 s0, s0"),
+            (0, 0xE7F0_00F0, ".inst 0xe7f000f0"),
+        ];
+        for (pc, opcode, expected) in cases {
+            assert_eq!(disassemble_arm(pc, opcode), expected, "{opcode:#010x}");
+        }
+    }
+
+    #[test]
+    fn thumb_encodings_disassemble_to_ual() {
+        let cases = [
+            (0, 0x2005, "movs r0, #5"),
+            (0, 0x0088, "lsls r0, r1, #2"),
+            (0, 0x1888, "adds r0, r1, r2"),
+            (0, 0x4248, "rsbs r0, r1, #0"),
+            (0, 0x4348, "muls r0, r1, r0"),
+            (0, 0x4770, "bx lr"),
+            (0, 0x46C0, "mov r8, r8"),
+            (0, 0x4803, "ldr r0, [pc, #12]"),
+            (0, 0x6848, "ldr r0, [r1, #4]"),
+            (0, 0x5888, "ldr r0, [r1, r2]"),
+            (0, 0x9001, "str r0, [sp, #4]"),
+            (0, 0xA001, "add r0, pc, #4"),
+            (0, 0xB082, "sub sp, sp, #8"),
+            (0, 0xB510, "push {r4, lr}"),
+            (0, 0xBD10, "pop {r4, pc}"),
+            (0, 0xC906, "ldm r1, {r1, r2}"),
+            (0x0010_0000, 0xD0FE, "beq 0x00100000"),
+            (0x0010_0000, 0xE7FE, "b 0x00100000"),
+            (0, 0xDF11, "svc #0x11"),
+            (0, 0xF000, "add lr, pc, #0"),
+            (0, 0xF7FF, "sub lr, pc, #0x1000"),
+            (0, 0xF802, "bl [lr, #4]"),
+            (0, 0xE802, "blx [lr, #4]"),
+            (0, 0xB672, "cpsid i"),
+            (0, 0xB658, "setend be"),
+            (0, 0xBA08, "rev r0, r1"),
+            (0, 0xBE01, "bkpt #0x1"),
+            (0, 0xDE00, ".inst.n 0xde00"),
+        ];
+        for (pc, opcode, expected) in cases {
+            assert_eq!(disassemble_thumb(pc, opcode), expected, "{opcode:#06x}");
+        }
+    }
+
+    #[test]
+    fn trace_fixture_annotations_match_disassembly() {
+        let fixture = include_str!("../../tests_cpu_trace_fixture.txt");
+        for line in fixture
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        {
+            let (values, annotation) = line
+                .split_once('#')
+                .unwrap_or_else(|| panic!("unannotated fixture line: {line}"));
+            let fields: Vec<u32> = values.split_whitespace().map(parse_hex).collect();
+            let [pc, opcode, thumb] = fields[..] else {
+                panic!("malformed fixture line: {line}");
+            };
+            assert_eq!(
+                disassemble(pc, opcode, thumb != 0),
+                annotation.trim(),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn media_fixture_annotations_match_disassembly() {
+        let fixture = include_str!("../../tests_cpu_media_fixture.txt");
+        for line in fixture
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        {
+            let Some((values, annotation)) = line.split_once('#') else {
+                continue;
+            };
+            let opcode = values
+                .split_whitespace()
+                .next()
+                .map(parse_hex)
+                .unwrap_or_else(|| panic!("malformed fixture line: {line}"));
+            assert_eq!(disassemble_arm(0, opcode), annotation.trim(), "{line}");
+        }
+    }
+}
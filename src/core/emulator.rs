@@ -1,13 +1,27 @@
-use super::bus::{Bus, SystemBus};
-use super::cpu::{Arm11Cpu, CpuException, CpuRunState, ExceptionKind};
+use std::collections::VecDeque;
+
+use super::bus::{AccessViolation, AccessViolationPolicy, Bus, BusDevice, SystemBus};
+use super::cpu::{
+    Arm11Cpu, BreakCondition, CpuException, CpuRunState, CycleModel, DebugEvent, ExceptionKind,
+    WatchKind, Watchpoint,
+};
+use super::disasm::disassemble;
 use super::dma::{DmaEngine, DmaTransfer, DmaTransferKind};
 use super::dsp::Dsp;
-use super::error::{EmulatorError, Result};
+use super::error::{EmulatorError, MemoryAccessKind, Result};
 use super::fs::TitlePackage;
 use super::fs::VirtualFileSystem;
+use super::gic::{
+    GIC_CPU_INTERFACE_BASE, GIC_CPU_INTERFACE_SIZE, GIC_DISTRIBUTOR_BASE, GIC_DISTRIBUTOR_SIZE, Gic,
+};
 use super::irq::{IrqController, IrqLine};
-use super::kernel::{Kernel, ServiceEvent};
+use super::kernel::{
+    IpcDispatch, Kernel, MAX_CORES, ServiceCall, ServiceEvent, ThreadId, ThreadStart,
+};
 use super::loader::{install_process_image, parse_process_image_from_rom};
+use super::memory::{ConsoleModel, Memory};
+use super::memory_hooks::{HookRange, MemoryHook, MemoryHookId};
+use super::mmu::TlbStats;
 use super::pica::PicaGpu;
 use super::scheduler::{ScheduledDeviceEvent, Scheduler};
 use super::timing::{DriftCorrectionPolicy, TimingModel, TimingSnapshot};
@@ -16,21 +30,42 @@ use super::trace::{
     StructuredError, TraceCategory, TracePayload, TraceRecord,
 };
 
+/// Kernel process the HLE syscalls run on behalf of.
+const TITLE_PROCESS_ID: u32 = 1;
+
 #[derive(Debug, Clone, Copy)]
 pub struct EmulatorConfig {
     pub max_cycle_budget: u32,
+    /// ARM11 cores sharing the bus: 2 on the Old 3DS, 4 on the New 3DS.
+    pub core_count: usize,
+    /// Instructions a core runs before the next core takes its turn.
+    pub core_quantum: u32,
+    /// How instruction costs are counted; `CycleModel::Arm11` is slower to
+    /// emulate but tracks real instruction timing.
+    pub cycle_model: CycleModel,
+    /// Selects the physical memory map; the New 3DS has 256 MiB of FCRAM and
+    /// 4 MiB of extra VRAM.
+    pub model: ConsoleModel,
+    /// What happens to writes to read-only memory and accesses to unmapped
+    /// memory; logged and faulted ones land in the fault snapshots.
+    pub access_violation_policy: AccessViolationPolicy,
 }
 
 impl Default for EmulatorConfig {
     fn default() -> Self {
         Self {
             max_cycle_budget: 5_000_000,
+            core_count: 2,
+            core_quantum: 1_000,
+            cycle_model: CycleModel::Simple,
+            model: ConsoleModel::Old3ds,
+            access_violation_policy: AccessViolationPolicy::Ignore,
         }
     }
 }
 
 pub struct Emulator3ds {
-    cpu: Arm11Cpu,
+    cores: Vec<Arm11Cpu>,
     bus: SystemBus,
     gpu: PicaGpu,
     dsp: Dsp,
@@ -48,10 +83,25 @@ pub struct Emulator3ds {
     ipc_trace: RingBuffer<TraceRecord>,
     service_trace: RingBuffer<TraceRecord>,
     mmu_fault_trace: RingBuffer<TraceRecord>,
+    memory_access_trace: RingBuffer<TraceRecord>,
     gpu_trace: RingBuffer<TraceRecord>,
     fault_snapshots: RingBuffer<FaultSnapshot>,
     boot_profiler: BootCheckpointProfiler,
     last_gpu_trace_len: usize,
+    fiq_pending: bool,
+    /// Cycles each core still owes the core that kept time this slice.
+    core_debt: Vec<i64>,
+    /// Whether each core has a thread, whether it is running or waiting in
+    /// `WFI`.
+    core_occupied: Vec<bool>,
+    /// Threads waiting for their core to go idle.
+    thread_queue: VecDeque<ThreadStart>,
+    /// Exceptions the cores entered during the current slice.
+    entered_exceptions: Vec<(usize, CpuException)>,
+    /// The first debug stop raised during the current run.
+    stop: Option<StopReason>,
+    /// SVC name `run_until_service_call` is waiting for.
+    service_stop: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -66,6 +116,63 @@ pub struct EmulatorState {
     pub service_calls: usize,
 }
 
+/// Why `run_until_stop` and the other debugger-facing runs returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The cycle budget ran out.
+    BudgetExhausted,
+    /// Every core halted with no thread waiting to start.
+    Halted,
+    /// `step_instruction` retired its instruction.
+    Stepped,
+    Breakpoint {
+        core: usize,
+        pc: u32,
+    },
+    /// The watched access has completed; `address` is in the watchpoint's
+    /// address space.
+    Watchpoint {
+        core: usize,
+        kind: WatchKind,
+        address: u32,
+    },
+    /// A caught exception, reported once its vector has been entered.
+    Exception {
+        core: usize,
+        exception: CpuException,
+    },
+    /// The awaited SVC, reported once the kernel has handled it.
+    ServiceCall {
+        core: usize,
+        event: ServiceEvent,
+    },
+}
+
+impl StopReason {
+    fn from_debug_event(core: usize, event: DebugEvent) -> Self {
+        match event {
+            DebugEvent::Breakpoint { pc } => Self::Breakpoint { core, pc },
+            DebugEvent::Watchpoint { kind, address } => Self::Watchpoint {
+                core,
+                kind,
+                address,
+            },
+            DebugEvent::Exception(exception) => Self::Exception { core, exception },
+        }
+    }
+
+    /// The core the stop happened on, if it is tied to one.
+    pub fn core(&self) -> Option<usize> {
+        match *self {
+            Self::Breakpoint { core, .. }
+            | Self::Watchpoint { core, .. }
+            | Self::Exception { core, .. }
+            | Self::ServiceCall { core, .. } => Some(core),
+            _ => None,
+        }
+    }
+}
+
 impl Default for Emulator3ds {
     fn default() -> Self {
         Self::new()
@@ -78,15 +185,28 @@ impl Emulator3ds {
     }
 
     pub fn with_config(config: EmulatorConfig) -> Self {
-        Self {
-            cpu: Arm11Cpu::new(),
-            bus: SystemBus::new(),
+        let core_count = config.core_count.clamp(1, MAX_CORES);
+        let mut kernel = Kernel::new();
+        kernel.set_core_count(core_count);
+        let mut bus = SystemBus::with_memory(Memory::with_model(config.model));
+        bus.set_violation_policy(config.access_violation_policy);
+        let mut emulator = Self {
+            cores: (0..core_count)
+                .map(|core| {
+                    let mut cpu = Arm11Cpu::with_core_id(core);
+                    cpu.set_cycle_model(config.cycle_model);
+                    // Only the latest entry is needed; `cpu_trace` keeps the history.
+                    cpu.enable_instruction_trace(1);
+                    cpu
+                })
+                .collect(),
+            bus,
             gpu: PicaGpu::new(),
             dsp: Dsp::new(),
             scheduler: Scheduler::new(),
             irq: IrqController::new(),
             dma: DmaEngine::new(),
-            kernel: Kernel::new(),
+            kernel,
             timing: TimingModel::new(),
             rom_loaded: false,
             vfs: VirtualFileSystem::default(),
@@ -97,21 +217,62 @@ impl Emulator3ds {
             ipc_trace: RingBuffer::new(256),
             service_trace: RingBuffer::new(256),
             mmu_fault_trace: RingBuffer::new(128),
+            memory_access_trace: RingBuffer::new(512),
             gpu_trace: RingBuffer::new(512),
             fault_snapshots: RingBuffer::new(128),
             boot_profiler: BootCheckpointProfiler::new(),
             last_gpu_trace_len: 0,
+            fiq_pending: false,
+            core_debt: vec![0; core_count],
+            core_occupied: vec![false; core_count],
+            thread_queue: VecDeque::new(),
+            entered_exceptions: Vec::new(),
+            stop: None,
+            service_stop: None,
+        };
+        emulator.reset_cores(0);
+        emulator
+    }
+
+    /// Core 0 starts at `entry`; the others park until an IPI or a thread
+    /// wakes them. The GIC belongs to the cluster, so it resets with the
+    /// cores.
+    fn reset_cores(&mut self, entry: u32) {
+        let (distributor, cpu_interface) = Gic::new(self.cores.len()).into_devices();
+        self.bus.map_mmio_device(
+            GIC_DISTRIBUTOR_BASE,
+            GIC_DISTRIBUTOR_SIZE,
+            Box::new(distributor),
+        );
+        self.bus.map_mmio_device(
+            GIC_CPU_INTERFACE_BASE,
+            GIC_CPU_INTERFACE_SIZE,
+            Box::new(cpu_interface),
+        );
+        for cpu in &mut self.cores {
+            cpu.reset(entry);
+            if cpu.core_id() != 0 {
+                cpu.park();
+            }
         }
+        for (core, occupied) in self.core_occupied.iter_mut().enumerate() {
+            *occupied = core == 0;
+        }
+        self.core_debt.fill(0);
+        self.thread_queue.clear();
+        self.entered_exceptions.clear();
+        self.stop = None;
     }
 
     pub fn reset(&mut self) {
         self.bus.memory_mut().clear_writable();
         self.scheduler.reset();
         self.irq.reset();
+        self.fiq_pending = false;
         self.dma.reset();
         self.timing.reset();
         self.kernel.reset_runtime();
-        self.cpu.reset(0);
+        self.reset_cores(0);
         self.rom_loaded = false;
         self.vfs = VirtualFileSystem::default();
         self.frame_callbacks = 0;
@@ -122,11 +283,17 @@ impl Emulator3ds {
     pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
         self.bus.memory_mut().clear_writable();
         let loaded = parse_process_image_from_rom(rom)?;
-        install_process_image(self.bus.memory_mut(), &loaded.process)?;
+        let mapping = install_process_image(self.bus.memory_mut(), &loaded.process)?;
         self.vfs = loaded.vfs;
-        self.cpu.reset(loaded.process.entrypoint);
+        self.reset_cores(loaded.process.entrypoint);
+        for cpu in &mut self.cores {
+            cpu.enter_address_space(mapping.page_table);
+        }
+        let entry = loaded.process.entrypoint;
+        self.cores[0].start_thread(entry, 0, mapping.stack_top);
         self.scheduler.reset();
         self.irq.reset();
+        self.fiq_pending = false;
         self.dma.reset();
         self.timing.reset();
         self.frame_callbacks = 0;
@@ -177,6 +344,7 @@ impl Emulator3ds {
         self.ipc_trace.clear();
         self.service_trace.clear();
         self.mmu_fault_trace.clear();
+        self.memory_access_trace.clear();
         self.gpu_trace.clear();
         self.fault_snapshots.clear();
         self.boot_profiler.reset();
@@ -194,16 +362,53 @@ impl Emulator3ds {
             TraceCategory::GpuCommand => self.gpu_trace.push(rec),
             TraceCategory::Irq => self.service_trace.push(rec),
             TraceCategory::Timer => self.service_trace.push(rec),
+            TraceCategory::MemoryAccess => self.memory_access_trace.push(rec),
         }
     }
 
-    fn record_fault(&mut self, error: StructuredError) {
+    /// Files the violations the bus reported as fault snapshots. `core` made
+    /// them while stepping the instruction at `pc`; `None` for DMA and host
+    /// accesses, which are filed as soon as they are made so the bus never
+    /// holds on to them.
+    fn record_access_violations(&mut self, source: Option<(usize, u32)>) {
+        for violation in self.bus.take_violations() {
+            let pc = source.map(|(_, pc)| pc);
+            let error = match violation {
+                AccessViolation::ReadOnlyWrite { address } => {
+                    StructuredError::ReadOnlyWrite { pc, address }
+                }
+                AccessViolation::Unmapped { address, access } => StructuredError::UnmappedAccess {
+                    pc,
+                    address,
+                    access,
+                },
+            };
+            let instruction = source.and_then(|(core, _)| self.faulting_instruction(core));
+            self.record_fault(error, instruction);
+        }
+    }
+
+    fn record_fault(&mut self, error: StructuredError, instruction: Option<String>) {
         self.fault_snapshots.push(FaultSnapshot {
             cycle: self.scheduler.cycles(),
             error,
+            instruction,
         });
     }
 
+    /// Disassembles the instruction `core` last executed, unless its latest
+    /// exception is a prefetch abort and the faulting opcode was never fetched.
+    fn faulting_instruction(&self, core: usize) -> Option<String> {
+        let cpu = &self.cores[core];
+        if let Some(exception) = cpu.last_exception()
+            && matches!(exception.kind, ExceptionKind::PrefetchAbort(_))
+        {
+            return None;
+        }
+        let entry = cpu.instruction_trace().last()?;
+        Some(disassemble(entry.pc, entry.opcode, entry.thumb))
+    }
+
     fn schedule_boot_events(&mut self) {
         self.scheduler
             .schedule_in(16, ScheduledDeviceEvent::TimerExpiry);
@@ -240,10 +445,11 @@ impl Emulator3ds {
                     .schedule_in(4_000_000, ScheduledDeviceEvent::VBlank);
             }
             ScheduledDeviceEvent::DmaCompletion { channel } => {
-                if self
+                let completed = self
                     .dma
-                    .complete_transfer(channel, &mut self.bus, &mut self.gpu)
-                {
+                    .complete_transfer(channel, &mut self.bus, &mut self.gpu);
+                self.record_access_violations(None);
+                if completed {
                     self.irq.raise(IrqLine::Dma0);
                     self.record_trace(
                         TraceCategory::Irq,
@@ -256,6 +462,10 @@ impl Emulator3ds {
             ScheduledDeviceEvent::ServiceWake { pid } => {
                 self.kernel.on_scheduler_wake(pid);
             }
+            ScheduledDeviceEvent::Device { base, token } => {
+                self.bus.fire_device_event(base, token);
+                self.route_device_requests();
+            }
         }
     }
 
@@ -264,191 +474,661 @@ impl Emulator3ds {
             return Err(EmulatorError::RomNotLoaded);
         }
 
-        let capped_budget = budget.min(self.config.max_cycle_budget);
+        let mut remaining = budget.min(self.config.max_cycle_budget);
         let mut executed = 0;
+        self.stop = None;
 
-        for _ in 0..capped_budget {
-            if self.cpu.interrupts_enabled()
-                && let Some(line) = self.irq.next_pending()
-            {
-                self.irq.clear(line);
-                self.cpu.enter_irq(line);
-            }
+        while remaining > 0 {
+            let (steps, consumed) = self.run_cpu_slice(remaining)?;
+            remaining -= steps;
+            executed += consumed;
+            self.service_devices(consumed)?;
 
-            let consumed = self.cpu.step(&mut self.bus)?;
-            if let Some(entry) = self.cpu.take_last_instruction_trace() {
-                self.record_trace(
-                    TraceCategory::CpuFetchDecode,
-                    TracePayload::CpuFetchDecode {
-                        pc: entry.pc,
-                        opcode: entry.opcode,
-                        thumb: entry.thumb,
-                    },
-                );
+            if self.stop.is_some() {
+                break;
+            }
+            if self.cores_idle() {
                 self.boot_profiler
-                    .mark(BootCheckpoint::FirstInstruction, self.scheduler.cycles());
+                    .mark(BootCheckpoint::CpuHalted, self.scheduler.cycles());
+                break;
             }
-            self.scheduler.tick(consumed);
-            self.kernel.tick(consumed);
-            let timing_tick = self.timing.tick(consumed);
-            self.kernel.pump_ipc_events(1);
-            if let Some((command_id, handle_id, result_code)) = self.kernel.take_last_ipc_dispatch()
+        }
+
+        Ok(executed)
+    }
+
+    /// Runs one round-robin turn until the next scheduler or timing
+    /// deadline, an exception entry or a halt, whichever comes first.
+    ///
+    /// The lowest-numbered running core keeps time: it runs up to one
+    /// quantum, ticking the scheduler per instruction so trace records keep
+    /// exact cycle stamps. Every other core then runs, in index order, until
+    /// it has caught up on those cycles; any overshoot is carried to the next
+    /// turn. Nothing outside the cores changes state between deadlines, so
+    /// devices are serviced once per slice; queued IPC or GPU work keeps the
+    /// slice to a single instruction so it is picked up exactly where it was
+    /// before.
+    fn run_cpu_slice(&mut self, max_steps: u32) -> Result<(u32, u32)> {
+        self.entered_exceptions.clear();
+        self.start_queued_threads();
+        let limit = if self.kernel.has_pending_ipc() || self.gpu.has_pending_commands() {
+            0
+        } else {
+            let now = self.scheduler.cycles();
+            self.scheduler
+                .next_deadline()
+                .map_or(u64::MAX, |at| at.saturating_sub(now))
+                .min(self.timing.cycles_until_next_event())
+        };
+
+        let lead = self
+            .cores
+            .iter()
+            .position(|cpu| cpu.run_state() == CpuRunState::Running)
+            .unwrap_or(0);
+        let max_steps = max_steps.min(self.config.core_quantum.max(1));
+        let mut steps = 0;
+        let mut consumed = 0_u32;
+        loop {
+            let cycles = self.step_core(lead)?;
+            self.scheduler.tick(cycles);
+            consumed = consumed.saturating_add(cycles);
+            steps += 1;
+
+            let cpu = &self.cores[lead];
+            if steps == max_steps
+                || u64::from(consumed) >= limit
+                || cpu.exception_entered()
+                || cpu.run_state() == CpuRunState::Halted
+                || self.stop.is_some()
             {
-                self.record_trace(
-                    TraceCategory::Ipc,
-                    TracePayload::Ipc {
-                        command_id,
-                        handle_id,
-                        result_code,
-                    },
-                );
-                self.boot_profiler
-                    .mark(BootCheckpoint::FirstIpcDispatch, self.scheduler.cycles());
-                if result_code != 0 {
-                    let err = StructuredError::ServiceCallFailure {
-                        pc: self.cpu.pc(),
-                        service_command_id: command_id,
-                        handle_id,
-                        result_code,
-                    };
-                    self.record_fault(err.clone());
-                    self.kernel.report_error(err);
-                    return Err(EmulatorError::ServiceCallError {
-                        pc: self.cpu.pc(),
-                        service_command_id: command_id,
-                        handle_id,
-                        result_code,
-                    });
-                }
+                break;
             }
-            if let Some(imm24) = self.kernel.take_last_service_imm24() {
-                self.record_trace(
-                    TraceCategory::ServiceCall,
-                    TracePayload::ServiceCall { imm24 },
-                );
-                self.boot_profiler
-                    .mark(BootCheckpoint::FirstServiceCall, self.scheduler.cycles());
+        }
+
+        for core in (0..self.cores.len()).filter(|&core| core != lead) {
+            if self.stop.is_some() {
+                break;
             }
+            self.run_follower(core, consumed)?;
+        }
+        Ok((steps, consumed))
+    }
 
-            for event in self.scheduler.drain_due_events() {
-                self.handle_scheduled_event(event);
+    fn run_follower(&mut self, core: usize, cycles: u32) -> Result<()> {
+        self.core_debt[core] += i64::from(cycles);
+        while self.core_debt[core] > 0 {
+            if self.cores[core].run_state() == CpuRunState::Halted
+                && !self.interrupt_deliverable(core)
+            {
+                // A parked core doesn't bank time to burn once it wakes.
+                self.core_debt[core] = 0;
+                break;
+            }
+            let used = self.step_core(core)?;
+            self.core_debt[core] -= i64::from(used);
+            if self.cores[core].exception_entered() || self.stop.is_some() {
+                break;
             }
+        }
+        Ok(())
+    }
+
+    fn step_core(&mut self, core: usize) -> Result<u32> {
+        self.deliver_interrupts(core);
+        self.bus
+            .memory_hooks_mut()
+            .set_cycle(self.scheduler.cycles());
+        self.bus.set_current_core(core);
+        let cpu = &mut self.cores[core];
+        let pc = cpu.pc();
+        let cycles = cpu.step(&mut self.bus)?;
+        self.irq.set_private(
+            core,
+            IrqLine::PerformanceMonitor,
+            cpu.pmu_interrupt_asserted(),
+        );
+        let trace = cpu.take_last_instruction_trace();
+        self.record_access_violations(Some((core, pc)));
+        let cpu = &mut self.cores[core];
+        let entered = cpu.last_exception().filter(|_| cpu.exception_entered());
+        if let Some(event) = cpu.take_debug_event() {
+            self.stop
+                .get_or_insert(StopReason::from_debug_event(core, event));
+        }
+        if let Some(exception) = entered {
+            self.entered_exceptions.push((core, exception));
+        }
+        if let Some(entry) = trace {
+            self.record_trace(
+                TraceCategory::CpuFetchDecode,
+                TracePayload::CpuFetchDecode {
+                    pc: entry.pc,
+                    opcode: entry.opcode,
+                    thumb: entry.thumb,
+                },
+            );
+            self.boot_profiler
+                .mark(BootCheckpoint::FirstInstruction, self.scheduler.cycles());
+        }
+        for access in self.bus.memory_hooks_mut().take_traced() {
+            self.record_trace(
+                TraceCategory::MemoryAccess,
+                TracePayload::MemoryAccess {
+                    core: access.core,
+                    pc: access.pc,
+                    va: access.va,
+                    pa: access.pa,
+                    width: access.width,
+                    value: access.value,
+                    access: access.kind,
+                },
+            );
+        }
+        self.route_device_requests();
+        Ok(cycles)
+    }
 
-            for fifo_words in self.kernel.drain_gpu_handoff() {
-                self.gpu.enqueue_gsp_fifo_words(&fifo_words);
+    /// Device interrupts and the FIQ line are wired to core 0; the GIC
+    /// drives each core's IPI line.
+    fn deliver_interrupts(&mut self, core: usize) {
+        let cpu = &mut self.cores[core];
+        if core == 0 && self.fiq_pending && cpu.fast_interrupts_enabled() {
+            self.fiq_pending = false;
+            cpu.enter_fiq();
+        } else if cpu.interrupts_enabled() {
+            if let Some(line) = self.irq.next_private(core) {
+                cpu.enter_irq(line);
+            } else if core == 0
+                && let Some(line) = self.irq.next_pending()
+            {
+                self.irq.clear(line);
+                cpu.enter_irq(line);
             }
-            self.gpu.tick(self.scheduler.cycles());
-            let new_gpu_writes: Vec<_> = self
-                .gpu
-                .trace()
-                .iter()
-                .skip(self.last_gpu_trace_len)
-                .map(|w| (w.reg, w.value))
-                .collect();
-            for (reg, value) in new_gpu_writes {
+        }
+    }
+
+    fn interrupt_deliverable(&self, core: usize) -> bool {
+        let cpu = &self.cores[core];
+        let device = core == 0
+            && ((self.fiq_pending && cpu.fast_interrupts_enabled())
+                || (cpu.interrupts_enabled() && self.irq.next_pending().is_some()));
+        device || (cpu.interrupts_enabled() && self.irq.next_private(core).is_some())
+    }
+
+    /// Raises the interrupts and schedules the events memory-mapped devices
+    /// asked for.
+    fn route_device_requests(&mut self) {
+        for line in self.bus.take_device_irqs() {
+            self.irq.raise(line);
+            self.record_trace(
+                TraceCategory::Irq,
+                TracePayload::IrqRaised { line: line as u8 },
+            );
+        }
+        for irq in self.bus.take_private_irqs() {
+            self.irq.set_private(irq.core, irq.line, irq.asserted);
+            if irq.asserted {
                 self.record_trace(
-                    TraceCategory::GpuCommand,
-                    TracePayload::GpuCommand { reg, value },
+                    TraceCategory::Irq,
+                    TracePayload::IrqRaised {
+                        line: irq.line as u8,
+                    },
                 );
-                self.boot_profiler
-                    .mark(BootCheckpoint::FirstGpuCommand, self.scheduler.cycles());
             }
-            self.last_gpu_trace_len = self.gpu.trace().len();
-            for event in self.gpu.take_events() {
-                match event {
-                    super::pica::GpuEvent::FrameComplete => {
-                        self.irq.raise(IrqLine::Gpu);
-                        self.record_trace(
-                            TraceCategory::Irq,
-                            TracePayload::IrqRaised {
-                                line: IrqLine::Gpu as u8,
-                            },
-                        );
-                        self.kernel.signal_gpu_frame_complete();
-                    }
-                }
-            }
-            if timing_tick.video_frames > 0 {
-                self.gpu.present(timing_tick.video_frames);
-                self.frame_callbacks = self
-                    .frame_callbacks
-                    .saturating_add(timing_tick.video_frames);
-                self.boot_profiler
-                    .mark(BootCheckpoint::FirstFramePresent, self.scheduler.cycles());
+        }
+        for event in self.bus.take_device_events() {
+            self.scheduler.schedule_in(
+                event.delay_cycles,
+                ScheduledDeviceEvent::Device {
+                    base: event.base,
+                    token: event.token,
+                },
+            );
+        }
+    }
+
+    /// Starts queued threads on cores without a thread, in queue order. A
+    /// core waiting in `WFI` still has one.
+    fn start_queued_threads(&mut self) {
+        let mut waiting = VecDeque::new();
+        while let Some(start) = self.thread_queue.pop_front() {
+            if let Some(core) = self.free_core(&start) {
+                self.core_occupied[core] = true;
+                self.cores[core].start_thread(start.entry, start.argument, start.stack_top);
+                self.kernel.thread_started(start.thread, core);
+            } else {
+                waiting.push_back(start);
             }
-            if timing_tick.audio_samples > 0 {
-                self.dsp.produce_samples(timing_tick.audio_samples);
-                self.audio_callbacks = self
-                    .audio_callbacks
-                    .saturating_add(timing_tick.audio_samples);
+        }
+        self.thread_queue = waiting;
+    }
+
+    /// The thread's preferred core if it is free, else the lowest free core
+    /// its affinity mask allows.
+    fn free_core(&self, start: &ThreadStart) -> Option<usize> {
+        if !self.core_occupied[start.core] {
+            return Some(start.core);
+        }
+        (0..self.cores.len())
+            .find(|&core| start.affinity_mask & (1 << core) != 0 && !self.core_occupied[core])
+    }
+
+    /// Every core is halted and no queued thread has a free core to start on.
+    fn cores_idle(&self) -> bool {
+        self.cores
+            .iter()
+            .all(|cpu| cpu.run_state() == CpuRunState::Halted)
+            && self
+                .thread_queue
+                .iter()
+                .all(|start| self.free_core(start).is_none())
+    }
+
+    fn service_devices(&mut self, consumed: u32) -> Result<()> {
+        self.kernel.tick(consumed);
+        let timing_tick = self.timing.tick(consumed);
+        self.kernel.pump_ipc_events(1);
+        if let Some(IpcDispatch {
+            core,
+            command_id,
+            handle_id,
+            result_code,
+        }) = self.kernel.take_last_ipc_dispatch()
+        {
+            self.record_trace(
+                TraceCategory::Ipc,
+                TracePayload::Ipc {
+                    command_id,
+                    handle_id,
+                    result_code,
+                },
+            );
+            self.boot_profiler
+                .mark(BootCheckpoint::FirstIpcDispatch, self.scheduler.cycles());
+            if result_code != 0 {
+                let err = StructuredError::ServiceCallFailure {
+                    pc: self.cores[core].pc(),
+                    service_command_id: command_id,
+                    handle_id,
+                    result_code,
+                };
+                self.record_fault(err.clone(), None);
+                self.kernel.report_error(err);
+                return Err(EmulatorError::ServiceCallError {
+                    pc: self.cores[core].pc(),
+                    service_command_id: command_id,
+                    handle_id,
+                    result_code,
+                });
             }
-            executed += consumed;
+        }
+        if let Some(imm24) = self.kernel.take_last_service_imm24() {
+            self.record_trace(
+                TraceCategory::ServiceCall,
+                TracePayload::ServiceCall { imm24 },
+            );
+            self.boot_profiler
+                .mark(BootCheckpoint::FirstServiceCall, self.scheduler.cycles());
+        }
 
-            if let Some(exception) = self.cpu.last_exception()
-                && exception.kind == ExceptionKind::SoftwareInterrupt
-            {
-                self.kernel.handle_swi(exception.fault_opcode & 0x00FF_FFFF);
-                for event in self.kernel.take_pending_schedule_events() {
-                    self.scheduler.schedule_in(
-                        event.delay_cycles,
-                        ScheduledDeviceEvent::ServiceWake { pid: event.pid },
+        for event in self.scheduler.drain_due_events() {
+            self.handle_scheduled_event(event);
+        }
+
+        for fifo_words in self.kernel.drain_gpu_handoff() {
+            self.gpu.enqueue_gsp_fifo_words(&fifo_words);
+        }
+        self.gpu.tick(self.scheduler.cycles());
+        let new_gpu_writes: Vec<_> = self
+            .gpu
+            .trace()
+            .iter()
+            .skip(self.last_gpu_trace_len)
+            .map(|w| (w.reg, w.value))
+            .collect();
+        for (reg, value) in new_gpu_writes {
+            self.record_trace(
+                TraceCategory::GpuCommand,
+                TracePayload::GpuCommand { reg, value },
+            );
+            self.boot_profiler
+                .mark(BootCheckpoint::FirstGpuCommand, self.scheduler.cycles());
+        }
+        self.last_gpu_trace_len = self.gpu.trace().len();
+        for event in self.gpu.take_events() {
+            match event {
+                super::pica::GpuEvent::FrameComplete => {
+                    self.irq.raise(IrqLine::Gpu);
+                    self.record_trace(
+                        TraceCategory::Irq,
+                        TracePayload::IrqRaised {
+                            line: IrqLine::Gpu as u8,
+                        },
                     );
+                    self.kernel.signal_gpu_frame_complete();
                 }
             }
+        }
+        if timing_tick.video_frames > 0 {
+            self.gpu.present(timing_tick.video_frames);
+            self.frame_callbacks = self
+                .frame_callbacks
+                .saturating_add(timing_tick.video_frames);
+            self.boot_profiler
+                .mark(BootCheckpoint::FirstFramePresent, self.scheduler.cycles());
+        }
+        if timing_tick.audio_samples > 0 {
+            self.dsp.produce_samples(timing_tick.audio_samples);
+            self.audio_callbacks = self
+                .audio_callbacks
+                .saturating_add(timing_tick.audio_samples);
+        }
 
-            if let Some(fault) = self.cpu.take_last_mmu_fault() {
-                self.record_trace(
-                    TraceCategory::MmuFault,
-                    TracePayload::MmuFault {
-                        va: fault.va,
-                        pa: fault.pa,
-                        access: fault.access,
-                    },
-                );
-                let err = StructuredError::MmuFault {
-                    pc: self.cpu.pc(),
+        // `last_exception` is sticky, so only act on exceptions entered during
+        // this slice.
+        for (core, exception) in std::mem::take(&mut self.entered_exceptions) {
+            let cpu = &mut self.cores[core];
+            match exception.kind {
+                ExceptionKind::SoftwareInterrupt => {
+                    let args = cpu.regs()[..5].to_vec();
+                    self.kernel
+                        .handle_swi(core, exception.swi_immediate(), &args);
+                    if let Some([r0, r1]) = self.kernel.take_syscall_output() {
+                        cpu.set_register(0, r0);
+                        cpu.set_register(1, r1);
+                    }
+                    for event in self.kernel.take_pending_schedule_events() {
+                        self.scheduler.schedule_in(
+                            event.delay_cycles,
+                            ScheduledDeviceEvent::ServiceWake { pid: event.pid },
+                        );
+                    }
+                    self.thread_queue.extend(self.kernel.take_thread_starts());
+                    if self
+                        .kernel
+                        .last_service_call()
+                        .is_some_and(|event| event.call == ServiceCall::ExitThread)
+                    {
+                        cpu.park();
+                        self.core_occupied[core] = false;
+                    }
+                    if let Some(event) = self.kernel.last_service_call()
+                        && self.service_stop.as_deref() == Some(event.call.name())
+                    {
+                        self.stop
+                            .get_or_insert(StopReason::ServiceCall { core, event });
+                    }
+                }
+                // The kernel hands out the VFP lazily on a thread's first use.
+                ExceptionKind::VfpDisabled if !cpu.vfp_enabled() => cpu.enable_vfp_and_retry(),
+                _ => {}
+            }
+        }
+
+        for core in 0..self.cores.len() {
+            self.report_mmu_fault(core)?;
+        }
+
+        Ok(())
+    }
+
+    fn report_mmu_fault(&mut self, core: usize) -> Result<()> {
+        let pc = self.cores[core].pc();
+        if let Some(fault) = self.cores[core].take_last_mmu_fault() {
+            self.record_trace(
+                TraceCategory::MmuFault,
+                TracePayload::MmuFault {
                     va: fault.va,
                     pa: fault.pa,
                     access: fault.access,
-                };
-                self.record_fault(err);
-                return Err(match fault.kind {
-                    super::cpu::FaultKind::Translation => EmulatorError::MmuTranslationFault {
-                        pc: self.cpu.pc(),
+                },
+            );
+            let err = StructuredError::MmuFault {
+                pc,
+                va: fault.va,
+                pa: fault.pa,
+                access: fault.access,
+            };
+            self.record_fault(err, self.faulting_instruction(core));
+            return Err(match fault.kind {
+                // Bus errors are filed by the bus, never as MMU faults.
+                super::cpu::FaultKind::Translation | super::cpu::FaultKind::External => {
+                    EmulatorError::MmuTranslationFault {
+                        pc,
                         va: fault.va,
                         pa: fault.pa,
                         access: fault.access,
-                    },
-                    super::cpu::FaultKind::Domain => EmulatorError::MmuDomainFault {
-                        pc: self.cpu.pc(),
-                        va: fault.va,
-                        pa: fault.pa,
-                        domain: 0,
-                        access: fault.access,
-                    },
-                    super::cpu::FaultKind::Permission => EmulatorError::MmuPermissionFault {
-                        pc: self.cpu.pc(),
-                        va: fault.va,
-                        pa: fault.pa,
-                        access: fault.access,
-                    },
-                    super::cpu::FaultKind::Alignment => EmulatorError::AlignmentFault {
-                        pc: self.cpu.pc(),
-                        va: fault.va,
-                        pa: fault.pa,
-                        access: fault.access,
-                    },
-                });
-            }
+                    }
+                }
+                super::cpu::FaultKind::Domain => EmulatorError::MmuDomainFault {
+                    pc,
+                    va: fault.va,
+                    pa: fault.pa,
+                    domain: 0,
+                    access: fault.access,
+                },
+                super::cpu::FaultKind::Permission => EmulatorError::MmuPermissionFault {
+                    pc,
+                    va: fault.va,
+                    pa: fault.pa,
+                    access: fault.access,
+                },
+                super::cpu::FaultKind::Alignment => EmulatorError::AlignmentFault {
+                    pc,
+                    va: fault.va,
+                    pa: fault.pa,
+                    access: fault.access,
+                },
+            });
+        }
 
-            if self.cpu.run_state() == CpuRunState::Halted {
-                self.boot_profiler
-                    .mark(BootCheckpoint::CpuHalted, self.scheduler.cycles());
-                break;
+        Ok(())
+    }
+
+    /// Maps `device` over `size` bytes of the physical address space from
+    /// `base`, ahead of any memory there.
+    pub fn map_mmio_device(&mut self, base: u32, size: u32, device: Box<dyn BusDevice>) {
+        self.bus.map_mmio_device(base, size, device);
+    }
+
+    /// Asserts the FIQ line; it is taken ahead of any IRQ at the next
+    /// instruction boundary with FIQs unmasked.
+    pub fn raise_fiq(&mut self) {
+        self.fiq_pending = true;
+    }
+
+    /// Runs the CPU purely interpreted when disabled; used to diff the
+    /// translation cache against the interpreter.
+    pub fn set_translation_cache_enabled(&mut self, enabled: bool) {
+        for cpu in &mut self.cores {
+            cpu.set_translation_cache_enabled(enabled);
+        }
+    }
+
+    /// Creates a thread in the title process. `processor` is a core index,
+    /// or -1 (any core) / -2 (the process default) as in `svcCreateThread`.
+    /// The thread starts once a core it may run on has no other thread.
+    pub fn create_thread(
+        &mut self,
+        entry: u32,
+        argument: u32,
+        stack_top: u32,
+        processor: i32,
+    ) -> Option<ThreadId> {
+        let thread =
+            self.kernel
+                .create_thread(TITLE_PROCESS_ID, entry, argument, stack_top, processor)?;
+        self.thread_queue.extend(self.kernel.take_thread_starts());
+        Some(thread)
+    }
+
+    /// Pins a thread to the cores in `mask`; a thread that hasn't started
+    /// yet moves to an allowed core.
+    pub fn set_thread_affinity(&mut self, thread: ThreadId, mask: u32) -> bool {
+        if !self.kernel.set_thread_affinity(thread, mask) {
+            return false;
+        }
+        if let Some((mask, core)) = self.kernel.thread_affinity(thread) {
+            for start in &mut self.thread_queue {
+                if start.thread == thread {
+                    start.core = core;
+                    start.affinity_mask = mask;
+                }
             }
         }
+        true
+    }
 
-        Ok(executed)
+    pub fn thread_affinity(&self, thread: ThreadId) -> Option<(u32, usize)> {
+        self.kernel.thread_affinity(thread)
+    }
+
+    pub fn core_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    pub(crate) fn core(&self, core: usize) -> Option<&Arm11Cpu> {
+        self.cores.get(core)
+    }
+
+    pub(crate) fn core_mut(&mut self, core: usize) -> Option<&mut Arm11Cpu> {
+        self.cores.get_mut(core)
+    }
+
+    /// Sets an instruction breakpoint on every core.
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        for cpu in &mut self.cores {
+            cpu.add_breakpoint(pc);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u32) -> bool {
+        self.cores
+            .iter_mut()
+            .fold(false, |removed, cpu| cpu.remove_breakpoint(pc) | removed)
+    }
+
+    /// Sets a data watchpoint on every core.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        for cpu in &mut self.cores {
+            cpu.add_watchpoint(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.cores.iter_mut().fold(false, |removed, cpu| {
+            cpu.remove_watchpoint(watchpoint) | removed
+        })
+    }
+
+    /// Calls `hook` with every core load and store overlapping one of
+    /// `ranges`, or with all of them when `ranges` is empty.
+    pub fn add_memory_hook(
+        &mut self,
+        ranges: Vec<HookRange>,
+        hook: Box<dyn MemoryHook>,
+    ) -> MemoryHookId {
+        self.bus.memory_hooks_mut().add(ranges, hook)
+    }
+
+    pub fn remove_memory_hook(&mut self, id: MemoryHookId) -> bool {
+        self.bus.memory_hooks_mut().remove(id)
+    }
+
+    /// Records the core loads and stores overlapping `ranges` (all of them
+    /// when empty) under [`TraceCategory::MemoryAccess`]; `None` stops.
+    pub fn set_memory_access_trace(&mut self, ranges: Option<Vec<HookRange>>) {
+        self.bus.memory_hooks_mut().set_trace(ranges);
+    }
+
+    /// Makes undefined instructions, `BKPT` and aborts stop the run as debug
+    /// events once their vector has been entered.
+    pub fn set_exception_catch(&mut self, enabled: bool) {
+        for cpu in &mut self.cores {
+            cpu.set_exception_catch(enabled);
+        }
+    }
+
+    /// Sets a breakpoint on every core that only stops while `condition`
+    /// holds.
+    pub fn add_conditional_breakpoint(&mut self, pc: u32, condition: BreakCondition) {
+        for cpu in &mut self.cores {
+            cpu.add_conditional_breakpoint(pc, condition);
+        }
+    }
+
+    /// Runs up to `budget` cycles like `run_cycles`, stopping early at the
+    /// first breakpoint, watchpoint or caught exception. Resuming from a
+    /// reported breakpoint runs its instruction rather than stopping again.
+    pub fn run_until_stop(&mut self, budget: u32) -> Result<StopReason> {
+        self.run_cycles(budget)?;
+        Ok(self.stop.take().unwrap_or(if self.cores_idle() {
+            StopReason::Halted
+        } else {
+            StopReason::BudgetExhausted
+        }))
+    }
+
+    /// Retires one instruction on the core keeping time, even one with a
+    /// breakpoint on it; the other cores catch up on the cycles it took.
+    pub fn step_instruction(&mut self) -> Result<StopReason> {
+        for cpu in &mut self.cores {
+            cpu.step_over_breakpoint();
+        }
+        Ok(match self.run_until_stop(1)? {
+            StopReason::BudgetExhausted => StopReason::Stepped,
+            reason => reason,
+        })
+    }
+
+    /// Runs until a core is about to execute `pc`, reported as a breakpoint
+    /// there, or until something else stops the run first. Returns at once
+    /// if a core is already at `pc`.
+    pub fn run_until(&mut self, pc: u32, budget: u32) -> Result<StopReason> {
+        for cpu in &mut self.cores {
+            cpu.set_run_to(Some(pc));
+        }
+        let result = self.run_until_stop(budget);
+        for cpu in &mut self.cores {
+            cpu.set_run_to(None);
+        }
+        result
+    }
+
+    /// Runs until the kernel has handled an SVC named `name` (see
+    /// `ServiceCall::name`), or until something else stops the run first.
+    pub fn run_until_service_call(&mut self, name: &str, budget: u32) -> Result<StopReason> {
+        self.service_stop = Some(name.to_string());
+        let result = self.run_until_stop(budget);
+        self.service_stop = None;
+        result
+    }
+
+    /// Reads a byte through `core`'s MMU without faulting.
+    pub fn read_virtual_u8(&mut self, core: usize, va: u32) -> Option<u8> {
+        let pa =
+            self.cores
+                .get_mut(core)?
+                .debug_translate(&mut self.bus, va, MemoryAccessKind::Read)?;
+        let value = self.bus.read_u8_checked(pa).ok();
+        self.record_access_violations(None);
+        value
+    }
+
+    /// Writes a byte through `core`'s MMU; cached blocks on the page are
+    /// dropped like on any other store.
+    pub fn write_virtual_u8(&mut self, core: usize, va: u32, value: u8) -> bool {
+        let Some(pa) = self
+            .cores
+            .get_mut(core)
+            .and_then(|cpu| cpu.debug_translate(&mut self.bus, va, MemoryAccessKind::Write))
+        else {
+            return false;
+        };
+        let written = self.bus.write_u8_checked(pa, value).is_ok();
+        self.record_access_violations(None);
+        written
     }
 
     pub fn set_wasm_drift_policy(&mut self, policy: DriftCorrectionPolicy) {
@@ -493,6 +1173,7 @@ impl Emulator3ds {
 
     pub fn write_phys_u8(&mut self, addr: u32, value: u8) {
         self.bus.write_u8(addr, value);
+        self.record_access_violations(None);
     }
 
     pub fn read_phys_u32(&self, addr: u32) -> u32 {
@@ -501,12 +1182,25 @@ impl Emulator3ds {
 
     pub fn write_phys_u32(&mut self, addr: u32, value: u32) {
         self.bus.write_u32(addr, value);
+        self.record_access_violations(None);
     }
 
+    /// Same as [`Self::reserved_memory_bytes`].
     pub fn mapped_memory_bytes(&self) -> usize {
+        self.reserved_memory_bytes()
+    }
+
+    /// Bytes of guest physical memory in the memory map, touched or not.
+    pub fn reserved_memory_bytes(&self) -> usize {
         self.bus.memory().len_mapped_bytes()
     }
 
+    /// Bytes of guest physical memory the host has actually allocated; RAM
+    /// pages are allocated on their first non-zero write.
+    pub fn resident_memory_bytes(&self) -> usize {
+        self.bus.memory().resident_bytes()
+    }
+
     pub fn frame_rgba(&self) -> Vec<u8> {
         self.gpu.frame_u8()
     }
@@ -520,14 +1214,27 @@ impl Emulator3ds {
     }
 
     pub fn state(&self) -> EmulatorState {
+        self.state_of(&self.cores[0])
+    }
+
+    /// TLB hit and miss counts for `core`, for profiling.
+    pub fn tlb_stats(&self, core: usize) -> Option<TlbStats> {
+        self.cores.get(core).map(Arm11Cpu::tlb_stats)
+    }
+
+    pub fn core_state(&self, core: usize) -> Option<EmulatorState> {
+        self.cores.get(core).map(|cpu| self.state_of(cpu))
+    }
+
+    fn state_of(&self, cpu: &Arm11Cpu) -> EmulatorState {
         EmulatorState {
-            pc: self.cpu.pc(),
-            cpsr: self.cpu.cpsr(),
+            pc: cpu.pc(),
+            cpsr: cpu.cpsr(),
             cycles: self.scheduler.cycles(),
-            cpu_state: self.cpu.run_state(),
-            registers: *self.cpu.regs(),
+            cpu_state: cpu.run_state(),
+            registers: *cpu.regs(),
             audio_samples: self.dsp.samples().len(),
-            last_exception: self.cpu.last_exception(),
+            last_exception: cpu.last_exception(),
             service_calls: self.kernel.service_call_count(),
         }
     }
@@ -540,6 +1247,7 @@ impl Emulator3ds {
             TraceCategory::MmuFault => self.mmu_fault_trace.recent(limit),
             TraceCategory::GpuCommand => self.gpu_trace.recent(limit),
             TraceCategory::Irq | TraceCategory::Timer => self.service_trace.recent(limit),
+            TraceCategory::MemoryAccess => self.memory_access_trace.recent(limit),
         }
     }
 
@@ -553,8 +1261,26 @@ impl Emulator3ds {
 
     pub fn diagnostics_json(&self) -> String {
         let checkpoints = self.boot_checkpoint_snapshot();
+        let recent_instructions = self
+            .recent_trace_slice(TraceCategory::CpuFetchDecode, 16)
+            .iter()
+            .filter_map(|record| match record.payload {
+                TracePayload::CpuFetchDecode { pc, opcode, thumb } => Some(format!(
+                    "\"0x{pc:08x}: {}\"",
+                    disassemble(pc, opcode, thumb)
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let last_fault_instruction = self
+            .recent_fault_snapshots(1)
+            .pop()
+            .and_then(|snapshot| snapshot.instruction)
+            .map(|text| format!("\"{text}\""))
+            .unwrap_or_else(|| "null".to_string());
         format!(
-            r#"{{"cpu_trace":{},"ipc_trace":{},"service_trace":{},"mmu_fault_trace":{},"gpu_trace":{},"fault_snapshots":{},"boot_events":{},"boot_divergence_at":{}}}"#,
+            r#"{{"cpu_trace":{},"ipc_trace":{},"service_trace":{},"mmu_fault_trace":{},"gpu_trace":{},"fault_snapshots":{},"boot_events":{},"boot_divergence_at":{},"recent_instructions":[{}],"last_fault_instruction":{}}}"#,
             self.recent_trace_slice(TraceCategory::CpuFetchDecode, 32)
                 .len(),
             self.recent_trace_slice(TraceCategory::Ipc, 32).len(),
@@ -567,7 +1293,9 @@ impl Emulator3ds {
             checkpoints
                 .divergence_at
                 .map(|v| v.to_string())
-                .unwrap_or_else(|| "null".to_string())
+                .unwrap_or_else(|| "null".to_string()),
+            recent_instructions,
+            last_fault_instruction
         )
     }
 
@@ -611,45 +1339,16 @@ impl Emulator3ds {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     use super::*;
+    use crate::core::bus::{AccessWidth, DeviceContext};
+    use crate::core::cpu::AddressSpace;
+    use crate::core::memory::{BIOS_START, FCRAM_START};
+    use crate::core::memory_hooks::MemoryAccess;
     use crate::core::pica::PicaCommandBufferPacket;
-
-    fn valid_rom() -> Vec<u8> {
-        let mut rom = vec![0_u8; 0x5000];
-        rom[0x100..0x104].copy_from_slice(b"NCSD");
-        rom[0x120..0x124].copy_from_slice(&1u32.to_le_bytes());
-        rom[0x124..0x128].copy_from_slice(&0x20u32.to_le_bytes());
-
-        let ncch = 0x200;
-        rom[ncch + 0x100..ncch + 0x104].copy_from_slice(b"NCCH");
-        rom[ncch + 0x180..ncch + 0x184].copy_from_slice(&0x400u32.to_le_bytes());
-        rom[ncch + 0x1A8..ncch + 0x1AC].copy_from_slice(&3u32.to_le_bytes());
-        rom[ncch + 0x1AC..ncch + 0x1B0].copy_from_slice(&2u32.to_le_bytes());
-
-        let ex = ncch + 0x200;
-        rom[ex..ex + 4].copy_from_slice(&0x0010_0000u32.to_le_bytes());
-        rom[ex + 0x10..ex + 0x14].copy_from_slice(&0x0010_0000u32.to_le_bytes());
-        rom[ex + 0x18..ex + 0x1C].copy_from_slice(&0x20u32.to_le_bytes());
-        rom[ex + 0x20..ex + 0x24].copy_from_slice(&0x0010_1000u32.to_le_bytes());
-        rom[ex + 0x28..ex + 0x2C].copy_from_slice(&0x20u32.to_le_bytes());
-        rom[ex + 0x30..ex + 0x34].copy_from_slice(&0x0010_2000u32.to_le_bytes());
-        rom[ex + 0x38..ex + 0x3C].copy_from_slice(&0x20u32.to_le_bytes());
-        rom[ex + 0x3C..ex + 0x40].copy_from_slice(&0x10u32.to_le_bytes());
-        rom[ex + 0x1C..ex + 0x20].copy_from_slice(&0x2000u32.to_le_bytes());
-        rom[ex + 0x40..ex + 0x44].copy_from_slice(&0x8000u32.to_le_bytes());
-
-        let exefs = 0x800;
-        rom[exefs..exefs + 5].copy_from_slice(b".code");
-        rom[exefs + 8..exefs + 12].copy_from_slice(&0u32.to_le_bytes());
-        rom[exefs + 12..exefs + 16].copy_from_slice(&0x60u32.to_le_bytes());
-        rom[exefs + 0x200..exefs + 0x260].fill(0);
-
-        rom
-    }
-
-    fn write_insn(rom: &mut [u8], offset: usize, opcode: u32) {
-        rom[offset..offset + 4].copy_from_slice(&opcode.to_le_bytes());
-    }
+    use crate::core::test_rom::{valid_rom, write_insn};
 
     #[test]
     fn wasm_memory_mapping_handles_high_rom_addresses() {
@@ -691,6 +1390,102 @@ mod tests {
         assert_eq!(state.pc, 0x0010_001C);
     }
 
+    #[test]
+    fn pmu_counter_overflow_raises_a_private_irq() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3E0_0000); // mvn r0, #0
+        write_insn(&mut rom, 0xA04, 0xEE0F_0F3C); // mcr p15, 0, r0, c15, c12, 1 (CCNT)
+        write_insn(&mut rom, 0xA08, 0xE3A0_0041); // mov r0, #0x41 (E, CCNT IRQ)
+        write_insn(&mut rom, 0xA0C, 0xEE0F_0F1C); // mcr p15, 0, r0, c15, c12, 0 (PMNC)
+        write_insn(&mut rom, 0xA10, 0xE1A0_0000); // NOP
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT in IRQ vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        emu.irq
+            .set_enabled_mask(1 << IrqLine::PerformanceMonitor as u32);
+
+        emu.run_cycles(12)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let state = emu.state();
+        let exception = state
+            .last_exception
+            .unwrap_or_else(|| panic!("expected PMU IRQ"));
+        assert_eq!(
+            exception.kind,
+            ExceptionKind::Interrupt(IrqLine::PerformanceMonitor)
+        );
+        assert_eq!(state.pc, 0x0010_001C);
+    }
+
+    #[test]
+    fn mmio_device_schedules_an_event_that_raises_an_irq() {
+        struct Doorbell;
+
+        impl BusDevice for Doorbell {
+            fn write(
+                &mut self,
+                _offset: u32,
+                _width: AccessWidth,
+                value: u64,
+                ctx: &mut DeviceContext,
+            ) {
+                ctx.schedule_event(4, value as u32);
+            }
+
+            fn handle_event(&mut self, token: u32, ctx: &mut DeviceContext) {
+                if token == 1 {
+                    ctx.raise_irq(IrqLine::Gpu);
+                }
+            }
+        }
+
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_157B); // mov r1, #0x1EC00000
+        write_insn(&mut rom, 0xA04, 0xE3A0_0001); // mov r0, #1
+        write_insn(&mut rom, 0xA08, 0xE5C1_0003); // strb r0, [r1, #3]
+        write_insn(&mut rom, 0xA0C, 0xEAFF_FFFE); // b .
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT in IRQ vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        // The kernel's IO window maps VA 0x1EC00000 to PA 0x10100000.
+        emu.map_mmio_device(0x1010_0000, 0x2000, Box::new(Doorbell));
+        emu.irq.set_enabled_mask(1 << IrqLine::Gpu as u32);
+
+        emu.run_cycles(12)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let exception = emu
+            .state()
+            .last_exception
+            .unwrap_or_else(|| panic!("expected device IRQ"));
+        assert_eq!(exception.kind, ExceptionKind::Interrupt(IrqLine::Gpu));
+    }
+
+    #[test]
+    fn fiq_preempts_pending_irq() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE1A0_0000); // NOP
+        write_insn(&mut rom, 0xA1C, 0xE320_F003); // HALT in FIQ vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+
+        emu.irq.raise(IrqLine::Timer0);
+        emu.raise_fiq();
+        emu.run_cycles(8)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let state = emu.state();
+        let exception = state
+            .last_exception
+            .unwrap_or_else(|| panic!("expected FIQ exception"));
+        assert_eq!(exception.kind, ExceptionKind::FastInterrupt);
+        assert_eq!(state.pc, 0x0010_0020);
+    }
+
     #[test]
     fn dma_completion_signals_irq_and_copies_memory() {
         let mut emu = Emulator3ds::new();
@@ -700,15 +1495,17 @@ mod tests {
         emu.load_rom(&rom)
             .unwrap_or_else(|e| panic!("load works: {e}"));
 
-        emu.write_phys_u32(0x0000_0080, 0x1122_3344);
-        emu.write_phys_u32(0x0000_0084, 0x5566_7788);
-        emu.queue_dma_memcpy(0, 0x0000_0080, 0x0000_0100, 2);
+        let src = FCRAM_START + 0x0100_0080;
+        let dst = FCRAM_START + 0x0100_0100;
+        emu.write_phys_u32(src, 0x1122_3344);
+        emu.write_phys_u32(src + 4, 0x5566_7788);
+        emu.queue_dma_memcpy(0, src, dst, 2);
 
         emu.run_cycles(8)
             .unwrap_or_else(|e| panic!("run works: {e}"));
 
-        assert_eq!(emu.read_phys_u32(0x0000_0100), 0x1122_3344);
-        assert_eq!(emu.read_phys_u32(0x0000_0104), 0x5566_7788);
+        assert_eq!(emu.read_phys_u32(dst), 0x1122_3344);
+        assert_eq!(emu.read_phys_u32(dst + 4), 0x5566_7788);
         let exception = emu
             .state()
             .last_exception
@@ -719,6 +1516,262 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn sliced_run_matches_single_stepping() {
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE1A0_0000); // NOP
+        write_insn(&mut rom, 0xA04, 0xEAFF_FFFE); // B .
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT in IRQ vector
+        let boot = |rom: &[u8]| {
+            let mut emu = Emulator3ds::new();
+            emu.load_rom(rom)
+                .unwrap_or_else(|e| panic!("load works: {e}"));
+            emu
+        };
+
+        let mut sliced = boot(&rom);
+        sliced
+            .run_cycles(64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        let mut stepped = boot(&rom);
+        for _ in 0..64 {
+            stepped
+                .run_cycles(1)
+                .unwrap_or_else(|e| panic!("run works: {e}"));
+            if stepped.state().cpu_state == CpuRunState::Halted {
+                break;
+            }
+        }
+
+        assert_eq!(sliced.state(), stepped.state());
+        assert!(matches!(
+            sliced.state().last_exception.map(|e| e.kind),
+            Some(ExceptionKind::Interrupt(IrqLine::Timer0))
+        ));
+        for category in [TraceCategory::CpuFetchDecode, TraceCategory::Irq] {
+            assert_eq!(
+                sliced.recent_trace_slice(category, 64),
+                stepped.recent_trace_slice(category, 64)
+            );
+        }
+    }
+
+    #[test]
+    fn translation_cache_matches_interpreter() {
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_0010); // mov r0, #16
+        write_insn(&mut rom, 0xA04, 0xE250_0001); // subs r0, r0, #1
+        write_insn(&mut rom, 0xA08, 0x1AFF_FFFD); // bne -> subs
+        write_insn(&mut rom, 0xA0C, 0xEAFF_FFFE); // b .
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT in IRQ vector
+        let run = |cached: bool| {
+            let mut emu = Emulator3ds::new();
+            emu.set_translation_cache_enabled(cached);
+            emu.load_rom(&rom)
+                .unwrap_or_else(|e| panic!("load works: {e}"));
+            emu.run_cycles(64)
+                .unwrap_or_else(|e| panic!("run works: {e}"));
+            emu
+        };
+
+        let cached = run(true);
+        let interpreted = run(false);
+        assert_eq!(cached.state(), interpreted.state());
+        assert_eq!(
+            cached.recent_trace_slice(TraceCategory::CpuFetchDecode, 64),
+            interpreted.recent_trace_slice(TraceCategory::CpuFetchDecode, 64)
+        );
+    }
+
+    #[test]
+    fn ipi_wakes_a_parked_core_which_reads_its_cpu_id() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_0417); // mov r0, #0x17000000
+        write_insn(&mut rom, 0xA04, 0xE380_060E); // orr r0, r0, #0xE00000
+        write_insn(&mut rom, 0xA08, 0xE380_0C1F); // orr r0, r0, #0x1F00
+        write_insn(&mut rom, 0xA0C, 0xE3A0_1802); // mov r1, #0x20000 (core 1)
+        write_insn(&mut rom, 0xA10, 0xE580_1000); // str r1, [r0]
+        write_insn(&mut rom, 0xA14, 0xEAFF_FFFE); // b .
+        write_insn(&mut rom, 0xA18, 0xEE10_2FB0); // mrc p15, 0, r2, c0, c0, 5
+        write_insn(&mut rom, 0xA1C, 0xE320_F003); // HALT
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        assert_eq!(emu.core_count(), 2);
+        assert_eq!(
+            emu.core_state(1).map(|s| s.cpu_state),
+            Some(CpuRunState::Halted),
+            "secondary cores boot parked"
+        );
+
+        emu.run_cycles(8)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let core1 = emu.core_state(1).unwrap_or_else(|| panic!("core 1"));
+        assert_eq!(
+            core1.last_exception.map(|e| e.kind),
+            Some(ExceptionKind::Interrupt(IrqLine::Ipi))
+        );
+        assert_eq!(core1.registers[2], 1);
+        assert_eq!(core1.cpu_state, CpuRunState::Halted);
+        assert_eq!(emu.state().pc, 0x0010_0014, "core 0 keeps spinning");
+    }
+
+    #[test]
+    fn ipi_handler_acknowledges_the_sgi_and_its_sender() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        // Core 0 sends SGI 3 to core 1, then falls into the IRQ handler.
+        write_insn(&mut rom, 0xA00, 0xE3A0_0417); // mov r0, #0x17000000
+        write_insn(&mut rom, 0xA04, 0xE380_060E); // orr r0, r0, #0xE00000
+        write_insn(&mut rom, 0xA08, 0xE380_0C1F); // orr r0, r0, #0x1F00
+        write_insn(&mut rom, 0xA0C, 0xE3A0_1802); // mov r1, #0x20000 (core 1)
+        write_insn(&mut rom, 0xA10, 0xE381_1003); // orr r1, r1, #3
+        write_insn(&mut rom, 0xA14, 0xE580_1000); // str r1, [r0]
+        write_insn(&mut rom, 0xA18, 0xEA00_03F8); // b 0x101000 (the IRQ vector)
+        // The handler, at the start of the RO segment, reads `ICCIAR`.
+        write_insn(&mut rom, 0xA20, 0xE3A0_0417); // mov r0, #0x17000000
+        write_insn(&mut rom, 0xA24, 0xE380_060E); // orr r0, r0, #0xE00000
+        write_insn(&mut rom, 0xA28, 0xE380_0C01); // orr r0, r0, #0x100
+        write_insn(&mut rom, 0xA2C, 0xE590_200C); // ldr r2, [r0, #0xC]
+        write_insn(&mut rom, 0xA30, 0xE320_F003); // HALT
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+
+        emu.run_cycles(32)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let core1 = emu.core_state(1).unwrap_or_else(|| panic!("core 1"));
+        assert_eq!(
+            core1.last_exception.map(|e| e.kind),
+            Some(ExceptionKind::Interrupt(IrqLine::Ipi))
+        );
+        assert_eq!(core1.registers[2], 3, "SGI 3 from core 0");
+        assert_eq!(core1.cpu_state, CpuRunState::Halted);
+        assert_eq!(emu.state().registers[2], 1023, "core 0's bank is spurious");
+    }
+
+    #[test]
+    fn console_model_selects_how_much_fcram_exists() {
+        let extended = FCRAM_START + 0x0C00_0000;
+        for (model, expected) in [(ConsoleModel::Old3ds, 0), (ConsoleModel::New3ds, 0xCAFE)] {
+            let mut emu = Emulator3ds::with_config(EmulatorConfig {
+                model,
+                ..EmulatorConfig::default()
+            });
+            emu.load_rom(&valid_rom())
+                .unwrap_or_else(|e| panic!("load works: {e}"));
+            emu.write_phys_u32(extended, 0xCAFE);
+            assert_eq!(emu.read_phys_u32(extended), expected, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn threads_start_on_the_core_they_are_pinned_to() {
+        let mut emu = Emulator3ds::with_config(EmulatorConfig {
+            core_count: 4,
+            ..EmulatorConfig::default()
+        });
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE320_F003); // HALT
+        write_insn(&mut rom, 0xA08, 0xEE10_2FB0); // mrc p15, 0, r2, c0, c0, 5
+        write_insn(&mut rom, 0xA0C, 0xE320_F003); // HALT
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT in IRQ vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+
+        let pinned = emu.create_thread(0x0010_0008, 7, 0x8000, 2);
+        let moved = emu.create_thread(0x0010_0008, 9, 0x9000, -1);
+        assert_eq!(emu.create_thread(0x0010_0008, 0, 0, 4), None);
+        let (Some(pinned), Some(moved)) = (pinned, moved) else {
+            panic!("threads are created");
+        };
+        assert_eq!(emu.thread_affinity(pinned), Some((0b0100, 2)));
+        assert!(!emu.set_thread_affinity(moved, 0b1_0000));
+        assert!(emu.set_thread_affinity(moved, 0b1000));
+        assert_eq!(emu.thread_affinity(moved), Some((0b1000, 3)));
+
+        emu.run_cycles(32)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        for (core, argument, stack_top) in [(2, 7, 0x8000), (3, 9, 0x9000)] {
+            let state = emu
+                .core_state(core)
+                .unwrap_or_else(|| panic!("core {core}"));
+            assert_eq!(state.registers[0], argument);
+            assert_eq!(state.registers[2], core as u32);
+            assert_eq!(state.registers[13], stack_top);
+            assert_eq!(state.cpu_state, CpuRunState::Halted);
+        }
+        assert_eq!(emu.core_state(1).map(|s| s.pc), Some(0x0010_0000));
+    }
+
+    #[test]
+    fn any_core_threads_start_on_an_idle_core_while_core_0_is_busy() {
+        let mut emu = Emulator3ds::with_config(EmulatorConfig {
+            core_count: 3,
+            ..EmulatorConfig::default()
+        });
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xEAFF_FFFE); // b .
+        write_insn(&mut rom, 0xA08, 0xEE10_2FB0); // mrc p15, 0, r2, c0, c0, 5
+        write_insn(&mut rom, 0xA0C, 0xE320_F003); // WFI
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // WFI in IRQ vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        let any = emu.create_thread(0x0010_0008, 5, 0x8000, -1);
+        let default = emu.create_thread(0x0010_0008, 6, 0x9000, -2);
+        let (Some(any), Some(default)) = (any, default) else {
+            panic!("threads are created");
+        };
+
+        emu.run_cycles(32)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        for (core, argument, stack_top) in [(1, 5, 0x8000), (2, 6, 0x9000)] {
+            let state = emu
+                .core_state(core)
+                .unwrap_or_else(|| panic!("core {core}"));
+            assert_eq!(state.registers[0], argument);
+            assert_eq!(state.registers[2], core as u32);
+            assert_eq!(state.registers[13], stack_top);
+        }
+        assert_eq!(emu.thread_affinity(any), Some((0b111, 1)));
+        assert_eq!(emu.thread_affinity(default), Some((0b111, 2)));
+    }
+
+    #[test]
+    fn queued_threads_wait_for_the_running_thread_to_exit() {
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE320_F003); // WFI
+        write_insn(&mut rom, 0xA04, 0xEF00_0009); // svc ExitThread
+        write_insn(&mut rom, 0xA08, 0xE320_F003); // WFI
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // WFI in IRQ vector
+        let mut emu = Emulator3ds::new();
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        emu.create_thread(0x0010_0008, 7, 0x8000, 0)
+            .unwrap_or_else(|| panic!("thread is created"));
+
+        emu.run_cycles(32)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        let state = emu.state();
+        assert_eq!(state.cpu_state, CpuRunState::Halted);
+        assert_eq!(state.pc, 0x0010_0004, "the main thread waits in WFI");
+        assert_ne!(state.registers[0], 7);
+
+        write_insn(&mut rom, 0xA00, 0xE320_F000); // NOP
+        let mut emu = Emulator3ds::new();
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        emu.create_thread(0x0010_0008, 7, 0x8000, 0)
+            .unwrap_or_else(|| panic!("thread is created"));
+        emu.run_cycles(32)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        let state = emu.state();
+        assert_eq!(state.registers[0], 7);
+        assert_eq!(state.registers[13], 0x8000);
+        assert_eq!(state.pc, 0x0010_000C);
+    }
+
     #[test]
     fn gpu_kernel_timing_and_fs_pipeline_work() {
         let mut emu = Emulator3ds::new();
@@ -747,4 +1800,362 @@ mod tests {
         let frame = emu.frame_rgba();
         assert_eq!(frame.len(), PicaGpu::WIDTH * PicaGpu::HEIGHT * 4);
     }
+
+    #[test]
+    fn fault_snapshots_and_diagnostics_carry_disassembly() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_0901); // mov r0, #0x4000
+        write_insn(&mut rom, 0xA04, 0xE3A0_0001); // mov r0, #1
+        write_insn(&mut rom, 0xA08, 0xE510_1001); // ldr r1, [r0, #-1]
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        // The process has nothing mapped at VA 0, so the load faults.
+        let err = emu.run_cycles(16).err();
+        assert!(matches!(
+            err,
+            Some(EmulatorError::MmuTranslationFault { va: 0, .. })
+        ));
+        let snapshot = emu
+            .recent_fault_snapshots(1)
+            .pop()
+            .unwrap_or_else(|| panic!("expected a fault snapshot"));
+        assert_eq!(snapshot.instruction.as_deref(), Some("ldr r1, [r0, #-1]"));
+
+        let json = emu.diagnostics_json();
+        assert!(json.contains(r#""0x00100000: mov r0, #0x4000""#), "{json}");
+        assert!(json.contains(r#""0x00100004: mov r0, #1""#), "{json}");
+        assert!(
+            json.contains(r#""last_fault_instruction":"ldr r1, [r0, #-1]""#),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn breakpoints_steps_and_run_until_report_stop_reasons() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_0000); // mov r0, #0
+        write_insn(&mut rom, 0xA04, 0xE280_0001); // add r0, r0, #1
+        write_insn(&mut rom, 0xA08, 0xE350_0003); // cmp r0, #3
+        write_insn(&mut rom, 0xA0C, 0x1AFF_FFFC); // bne 0x00100004
+        write_insn(&mut rom, 0xA10, 0xE3A0_1601); // mov r1, #0x100000
+        write_insn(&mut rom, 0xA14, 0xE381_101D); // orr r1, r1, #0x1d
+        write_insn(&mut rom, 0xA18, 0xE12F_FF11); // bx r1
+        write_insn(&mut rom, 0xA1C, 0xE7FE_2207); // movs r2, #7; b .
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        let run = |result: Result<StopReason>| result.unwrap_or_else(|e| panic!("run works: {e}"));
+        // Keep the timer IRQ out of the way; the Thumb code sits in its vector.
+        let cpsr = emu.cores[0].cpsr();
+        emu.cores[0].set_cpsr(cpsr | 0x80);
+
+        emu.add_conditional_breakpoint(
+            0x0010_0008,
+            BreakCondition {
+                register: 0,
+                value: 2,
+            },
+        );
+        assert_eq!(
+            run(emu.run_until_stop(64)),
+            StopReason::Breakpoint {
+                core: 0,
+                pc: 0x0010_0008
+            }
+        );
+        assert_eq!(emu.state().registers[0], 2);
+
+        assert_eq!(run(emu.step_instruction()), StopReason::Stepped);
+        assert_eq!(emu.state().pc, 0x0010_000C);
+
+        assert_eq!(
+            run(emu.run_until(0x0010_001C, 64)),
+            StopReason::Breakpoint {
+                core: 0,
+                pc: 0x0010_001C
+            }
+        );
+        assert_eq!(emu.state().registers[0], 3);
+        assert_ne!(emu.state().cpsr & 0x20, 0);
+
+        emu.add_breakpoint(0x0010_001E);
+        let thumb_breakpoint = StopReason::Breakpoint {
+            core: 0,
+            pc: 0x0010_001E,
+        };
+        assert_eq!(run(emu.run_until_stop(64)), thumb_breakpoint);
+        assert_eq!(emu.state().registers[2], 7);
+        // `b .` comes straight back to the breakpoint.
+        assert_eq!(run(emu.run_until_stop(64)), thumb_breakpoint);
+        assert!(emu.remove_breakpoint(0x0010_001E));
+        assert_eq!(run(emu.run_until_stop(64)), StopReason::BudgetExhausted);
+    }
+
+    #[test]
+    fn run_until_service_call_stops_after_the_named_svc() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xEF00_0023); // svc #0x23 (CreateEvent)
+        write_insn(&mut rom, 0xA08, 0xEF00_0001); // svc #1 (GetTick) in SWI vector
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+
+        let stop = emu
+            .run_until_service_call("GetTick", 64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert_eq!(
+            stop,
+            StopReason::ServiceCall {
+                core: 0,
+                event: ServiceEvent {
+                    call: ServiceCall::GetTick,
+                    argument: 1,
+                },
+            }
+        );
+        assert_eq!(emu.state().service_calls, 2);
+    }
+
+    #[test]
+    fn physical_watchpoints_see_through_the_mmu() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_2601); // mov r2, #0x100000
+        write_insn(&mut rom, 0xA04, 0xE592_1000); // ldr r1, [r2]
+        write_insn(&mut rom, 0xA08, 0xE320_F003); // HALT
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+
+        // VA 0x00100000 is backed by the APPLICATION region at the start of
+        // FCRAM; nothing is mapped at the same number as a virtual address.
+        for space in [AddressSpace::Virtual, AddressSpace::Physical] {
+            emu.add_watchpoint(Watchpoint {
+                start: FCRAM_START,
+                len: 4,
+                kind: WatchKind::Read,
+                space,
+            });
+        }
+        let stop = emu
+            .run_until_stop(64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert_eq!(
+            stop,
+            StopReason::Watchpoint {
+                core: 0,
+                kind: WatchKind::Read,
+                address: FCRAM_START,
+            }
+        );
+        let state = emu.state();
+        assert_eq!(state.pc, 0x0010_0008);
+        assert_eq!(state.registers[1], 0xE3A0_2601);
+    }
+
+    #[test]
+    fn failed_ipc_reports_the_pc_of_the_core_that_sent_it() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xEAFF_FFFE); // b .
+        write_insn(&mut rom, 0xA08, 0xE1B0_F00E); // movs pc, lr (SWI vector)
+        write_insn(&mut rom, 0xA10, 0xEF00_0032); // svc 0x32 (SendSyncRequest)
+        write_insn(&mut rom, 0xA14, 0xEAFF_FFFE); // b .
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        assert!(emu.create_thread(0x0010_0010, 0, 0x8000, 1).is_some());
+        let stop = emu
+            .run_until_service_call("SendSyncRequest", 64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert!(matches!(stop, StopReason::ServiceCall { core: 1, .. }));
+
+        // No session has this handle.
+        emu.kernel.queue_ipc_command(1, 0xDEAD, vec![0x0001_0000]);
+        let err = emu.run_cycles(16).map(|_| ());
+        let pc = emu.core_state(1).map_or(0, |s| s.pc);
+        assert_ne!(pc, emu.state().pc);
+        assert!(
+            matches!(err, Err(EmulatorError::ServiceCallError { pc: at, handle_id: 0xDEAD, .. }) if at == pc),
+            "{err:?}"
+        );
+        let snapshots = emu.recent_fault_snapshots(1);
+        assert!(matches!(
+            snapshots[0].error,
+            StructuredError::ServiceCallFailure { pc: at, .. } if at == pc
+        ));
+    }
+
+    #[test]
+    fn watchpoints_fire_on_wider_accesses_that_overlap_them() {
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_2601); // mov r2, #0x100000
+        write_insn(&mut rom, 0xA04, 0xE1C2_22B0); // strh r2, [r2, #0x20]
+        write_insn(&mut rom, 0xA08, 0xE582_2020); // str r2, [r2, #0x20]
+        write_insn(&mut rom, 0xA0C, 0xE320_F003); // HALT
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        emu.add_watchpoint(Watchpoint {
+            start: 0x0010_0022,
+            len: 2,
+            kind: WatchKind::Write,
+            space: AddressSpace::Virtual,
+        });
+
+        let stop = emu
+            .run_until_stop(64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert_eq!(
+            stop,
+            StopReason::Watchpoint {
+                core: 0,
+                kind: WatchKind::Write,
+                address: 0x0010_0022,
+            }
+        );
+        assert_eq!(emu.state().pc, 0x0010_000C, "the halfword store missed it");
+    }
+
+    #[test]
+    fn memory_hooks_and_trace_report_core_accesses() {
+        struct Stores(Rc<RefCell<Vec<MemoryAccess>>>);
+
+        impl MemoryHook for Stores {
+            fn on_access(&mut self, access: &MemoryAccess) {
+                self.0.borrow_mut().push(*access);
+            }
+        }
+
+        let mut emu = Emulator3ds::new();
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_2601); // mov r2, #0x100000
+        write_insn(&mut rom, 0xA04, 0xE592_1000); // ldr r1, [r2]
+        write_insn(&mut rom, 0xA08, 0xE5C2_1020); // strb r1, [r2, #0x20]
+        write_insn(&mut rom, 0xA0C, 0xE320_F003); // HALT
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        let stores = Rc::new(RefCell::new(Vec::new()));
+        emu.add_memory_hook(
+            vec![HookRange {
+                start: FCRAM_START + 0x20,
+                len: 4,
+                space: AddressSpace::Physical,
+            }],
+            Box::new(Stores(Rc::clone(&stores))),
+        );
+        emu.set_memory_access_trace(Some(Vec::new()));
+
+        emu.run_until_stop(64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+
+        let store = MemoryAccess {
+            core: 0,
+            pc: 0x0010_0008,
+            va: 0x0010_0020,
+            pa: FCRAM_START + 0x20,
+            width: AccessWidth::Byte,
+            value: 0x01,
+            kind: MemoryAccessKind::Write,
+            cycle: stores.borrow().first().map_or(0, |access| access.cycle),
+        };
+        assert_eq!(*stores.borrow(), [store]);
+        let traced: Vec<_> = emu
+            .recent_trace_slice(TraceCategory::MemoryAccess, 8)
+            .into_iter()
+            .map(|record| record.payload)
+            .collect();
+        assert_eq!(
+            traced,
+            [
+                TracePayload::MemoryAccess {
+                    core: 0,
+                    pc: 0x0010_0004,
+                    va: 0x0010_0000,
+                    pa: FCRAM_START,
+                    width: AccessWidth::Word,
+                    value: 0xE3A0_2601,
+                    access: MemoryAccessKind::Read,
+                },
+                TracePayload::MemoryAccess {
+                    core: 0,
+                    pc: store.pc,
+                    va: store.va,
+                    pa: store.pa,
+                    width: store.width,
+                    value: store.value,
+                    access: store.kind,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn access_violations_are_logged_or_faulted_per_policy() {
+        let mut rom = valid_rom();
+        write_insn(&mut rom, 0xA00, 0xE3A0_1417); // mov r1, #0x17000000
+        write_insn(&mut rom, 0xA04, 0xE381_160E); // orr r1, r1, #0xE00000
+        write_insn(&mut rom, 0xA08, 0xE381_1A02); // orr r1, r1, #0x2000
+        write_insn(&mut rom, 0xA0C, 0xE581_0000); // str r0, [r1]
+        // Also the DABT vector.
+        write_insn(&mut rom, 0xA10, 0xEE16_2F10); // mrc p15, 0, r2, c6, c0, 0 (DFAR)
+        write_insn(&mut rom, 0xA14, 0xEE15_3F10); // mrc p15, 0, r3, c5, c0, 0 (DFSR)
+        write_insn(&mut rom, 0xA18, 0xE320_F003); // HALT
+        for policy in [AccessViolationPolicy::Log, AccessViolationPolicy::Fault] {
+            let mut emu = Emulator3ds::with_config(EmulatorConfig {
+                access_violation_policy: policy,
+                ..EmulatorConfig::default()
+            });
+            emu.load_rom(&rom)
+                .unwrap_or_else(|e| panic!("load works: {e}"));
+            // The boot ROM is read-only; host writes have no PC and are filed
+            // without waiting for a step.
+            emu.write_phys_u32(BIOS_START, 1);
+            assert_eq!(emu.recent_fault_snapshots(4).len(), 1);
+
+            emu.run_until_stop(64)
+                .unwrap_or_else(|e| panic!("run works: {e}"));
+
+            // The MPCore window is a whole section but only 8 KiB answer.
+            let snapshots = emu.recent_fault_snapshots(4);
+            let errors: Vec<_> = snapshots.iter().map(|s| s.error.clone()).collect();
+            assert_eq!(
+                errors,
+                [
+                    StructuredError::ReadOnlyWrite {
+                        pc: None,
+                        address: BIOS_START,
+                    },
+                    StructuredError::UnmappedAccess {
+                        pc: Some(0x0010_000C),
+                        address: 0x17E0_2000,
+                        access: MemoryAccessKind::Write,
+                    },
+                ],
+                "{policy:?}"
+            );
+            assert_eq!(snapshots[1].instruction.as_deref(), Some("str r0, [r1]"));
+            let aborted = emu
+                .state()
+                .last_exception
+                .is_some_and(|e| matches!(e.kind, ExceptionKind::DataAbort(_)));
+            assert_eq!(aborted, policy == AccessViolationPolicy::Fault);
+            // A precise external abort on a write, at the violating address.
+            let (dfar, dfsr) = match policy {
+                AccessViolationPolicy::Fault => (0x17E0_2000, (1 << 11) | 0b01000),
+                _ => (0, 0),
+            };
+            assert_eq!(emu.state().registers[2], dfar, "{policy:?}");
+            assert_eq!(emu.state().registers[3], dfsr, "{policy:?}");
+        }
+
+        // By default violations are dropped without a trace.
+        let mut emu = Emulator3ds::new();
+        emu.load_rom(&rom)
+            .unwrap_or_else(|e| panic!("load works: {e}"));
+        emu.write_phys_u32(BIOS_START, 1);
+        emu.run_until_stop(64)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert!(emu.recent_fault_snapshots(4).is_empty());
+        assert_eq!(emu.state().last_exception, None);
+    }
 }
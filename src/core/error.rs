@@ -18,6 +18,9 @@ pub enum EmulatorError {
     MemoryOutOfBounds {
         address: u32,
     },
+    ReadOnlyMemory {
+        address: u32,
+    },
     InvalidInstruction {
         pc: u32,
         opcode: u32,
@@ -33,6 +36,7 @@ pub enum EmulatorError {
     UnsupportedNcchCrypto,
     MissingCodeSection,
     InvalidSectionLayout,
+    InvalidProcessLayout,
     EntrypointOutsideText,
     MmuTranslationFault {
         pc: u32,
@@ -78,6 +82,9 @@ impl Display for EmulatorError {
             Self::MemoryOutOfBounds { address } => {
                 write!(f, "memory access out of bounds at 0x{address:08x}")
             }
+            Self::ReadOnlyMemory { address } => {
+                write!(f, "write to read-only memory at 0x{address:08x}")
+            }
             Self::InvalidInstruction { pc, opcode } => {
                 write!(f, "invalid instruction 0x{opcode:08x} at PC=0x{pc:08x}")
             }
@@ -102,6 +109,12 @@ impl Display for EmulatorError {
             }
             Self::MissingCodeSection => write!(f, "ExeFS is missing required .code section"),
             Self::InvalidSectionLayout => write!(f, "invalid text/ro/data/bss section layout"),
+            Self::InvalidProcessLayout => {
+                write!(
+                    f,
+                    "process image and stack don't fit the title's address space"
+                )
+            }
             Self::EntrypointOutsideText => {
                 write!(f, "entrypoint does not lie inside the text segment")
             }
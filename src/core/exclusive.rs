@@ -0,0 +1,65 @@
+/// Size of the exclusive reservation granule, large enough for `LDREXD`.
+pub const EXCLUSIVE_GRANULE: u32 = 8;
+
+pub fn reservation_tag(pa: u32) -> u32 {
+    pa & !(EXCLUSIVE_GRANULE - 1)
+}
+
+/// Global exclusive monitor shared by every core attached to a bus.
+///
+/// Each core holds at most one reservation. Any write that lands in a
+/// reserved granule, from a core or from DMA, clears it for every core.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlobalMonitor {
+    reservations: Vec<Option<u32>>,
+}
+
+impl GlobalMonitor {
+    pub fn mark(&mut self, core: usize, pa: u32) {
+        if self.reservations.len() <= core {
+            self.reservations.resize(core + 1, None);
+        }
+        self.reservations[core] = Some(reservation_tag(pa));
+    }
+
+    pub fn is_marked(&self, core: usize, pa: u32) -> bool {
+        self.reservations.get(core).copied().flatten() == Some(reservation_tag(pa))
+    }
+
+    pub fn clear(&mut self, core: usize) {
+        if let Some(reservation) = self.reservations.get_mut(core) {
+            *reservation = None;
+        }
+    }
+
+    pub fn observe_write(&mut self, pa: u32) {
+        let tag = reservation_tag(pa);
+        for reservation in &mut self.reservations {
+            if *reservation == Some(tag) {
+                *reservation = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_clear_every_core_reserving_the_granule() {
+        let mut monitor = GlobalMonitor::default();
+        monitor.mark(0, 0x1000);
+        monitor.mark(1, 0x1004);
+        monitor.mark(2, 0x2000);
+        assert!(monitor.is_marked(1, 0x1000), "same granule");
+
+        monitor.observe_write(0x1007);
+        assert!(!monitor.is_marked(0, 0x1000));
+        assert!(!monitor.is_marked(1, 0x1004));
+        assert!(monitor.is_marked(2, 0x2000));
+
+        monitor.clear(2);
+        assert!(!monitor.is_marked(2, 0x2000));
+    }
+}
@@ -0,0 +1,773 @@
+//! GDB remote serial protocol stub.
+//!
+//! The stub speaks RSP over any [`GdbTransport`], so native builds can back
+//! it with a TCP socket and WASM builds with a JS bridge. It is polled:
+//! [`GdbStub::poll`] answers whatever packets have arrived and, while the
+//! target runs, advances the emulator by at most one cycle budget.
+//!
+//! Each core is a GDB thread (`core + 1`); `Hg` selects the one whose
+//! registers and MMU are used. Stepping retires one instruction on the core
+//! keeping time, the lowest-numbered running one. The register layout is the
+//! `target.xml` below: R0-R15, CPSR (25), D0-D15 (26-41) and FPSCR (42).
+//! Software and hardware breakpoints are both kept by the cores' debug
+//! units rather than patched into memory.
+
+use std::collections::BTreeMap;
+
+use super::cpu::{AddressSpace, CpuException, ExceptionKind, WatchKind, Watchpoint};
+use super::emulator::{Emulator3ds, StopReason};
+use super::error::EmulatorError;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGSEGV: u8 = 11;
+
+const REG_CPSR: usize = 25;
+const REG_D0: usize = 26;
+const REG_FPSCR: usize = 42;
+const VFP_DOUBLES: usize = 16;
+
+/// Largest packet the stub accepts or sends, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+    r#"<target version="1.0"><architecture>arm</architecture>"#,
+    r#"<feature name="org.gnu.gdb.arm.core">"#,
+    r#"<reg name="r0" bitsize="32"/><reg name="r1" bitsize="32"/>"#,
+    r#"<reg name="r2" bitsize="32"/><reg name="r3" bitsize="32"/>"#,
+    r#"<reg name="r4" bitsize="32"/><reg name="r5" bitsize="32"/>"#,
+    r#"<reg name="r6" bitsize="32"/><reg name="r7" bitsize="32"/>"#,
+    r#"<reg name="r8" bitsize="32"/><reg name="r9" bitsize="32"/>"#,
+    r#"<reg name="r10" bitsize="32"/><reg name="r11" bitsize="32"/>"#,
+    r#"<reg name="r12" bitsize="32"/><reg name="sp" bitsize="32" type="data_ptr"/>"#,
+    r#"<reg name="lr" bitsize="32"/><reg name="pc" bitsize="32" type="code_ptr"/>"#,
+    r#"<reg name="cpsr" bitsize="32" regnum="25"/></feature>"#,
+    r#"<feature name="org.gnu.gdb.arm.vfp">"#,
+    r#"<reg name="d0" bitsize="64" type="ieee_double"/><reg name="d1" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d2" bitsize="64" type="ieee_double"/><reg name="d3" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d4" bitsize="64" type="ieee_double"/><reg name="d5" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d6" bitsize="64" type="ieee_double"/><reg name="d7" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d8" bitsize="64" type="ieee_double"/><reg name="d9" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d10" bitsize="64" type="ieee_double"/><reg name="d11" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d12" bitsize="64" type="ieee_double"/><reg name="d13" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="d14" bitsize="64" type="ieee_double"/><reg name="d15" bitsize="64" type="ieee_double"/>"#,
+    r#"<reg name="fpscr" bitsize="32" type="int" group="float"/></feature></target>"#,
+);
+
+/// The byte stream a debugger is attached through.
+pub trait GdbTransport {
+    /// The next received byte, or `None` when nothing is waiting.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    fn write(&mut self, bytes: &[u8]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Receive {
+    Idle,
+    Packet,
+    Checksum(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakpointKind {
+    Software,
+    Hardware,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HaltReason {
+    Signal(u8),
+    Breakpoint(BreakpointKind),
+    Watchpoint { kind: WatchKind, address: u32 },
+}
+
+pub struct GdbStub<T: GdbTransport> {
+    transport: T,
+    receive: Receive,
+    packet: Vec<u8>,
+    checksum: [u8; 2],
+    no_ack: bool,
+    attached: bool,
+    resume: Option<Resume>,
+    core: usize,
+    last_stop: HaltReason,
+    breakpoints: BTreeMap<u32, BreakpointKind>,
+    /// Set through `Z2`-`Z4`, so they can be cleared on detach.
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl<T: GdbTransport> GdbStub<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            receive: Receive::Idle,
+            packet: Vec::new(),
+            checksum: [0; 2],
+            no_ack: false,
+            attached: true,
+            resume: None,
+            core: 0,
+            last_stop: HaltReason::Signal(SIGTRAP),
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Whether the target runs between polls.
+    pub fn is_running(&self) -> bool {
+        self.resume.is_some()
+    }
+
+    /// False once the debugger has detached or killed the session.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Answers the packets received so far, then runs the target for up to
+    /// `budget` cycles if it was resumed.
+    pub fn poll(&mut self, emu: &mut Emulator3ds, budget: u32) {
+        if !self.attached {
+            return;
+        }
+        emu.set_exception_catch(true);
+        while let Some(byte) = self.transport.read_byte() {
+            self.receive_byte(emu, byte);
+            if !self.attached {
+                return;
+            }
+        }
+
+        let Some(resume) = self.resume else {
+            return;
+        };
+        let result = match resume {
+            Resume::Step => emu.step_instruction(),
+            Resume::Continue => emu.run_until_stop(budget),
+        };
+        let halt = match result {
+            Ok(stop) => {
+                if let Some(core) = stop.core() {
+                    self.core = core;
+                }
+                self.halt_for_stop(stop)
+            }
+            Err(err) => Some(HaltReason::Signal(error_signal(&err))),
+        };
+        if let Some(halt) = halt {
+            self.stop(halt);
+        }
+    }
+
+    fn receive_byte(&mut self, emu: &mut Emulator3ds, byte: u8) {
+        match (self.receive, byte) {
+            (Receive::Idle, b'$') => {
+                self.packet.clear();
+                self.receive = Receive::Packet;
+            }
+            (Receive::Idle, 0x03) if self.resume.is_some() => {
+                self.stop(HaltReason::Signal(SIGINT));
+            }
+            (Receive::Idle, _) => {}
+            (Receive::Packet, b'#') => self.receive = Receive::Checksum(0),
+            (Receive::Packet, _) => self.packet.push(byte),
+            (Receive::Checksum(0), _) => {
+                self.checksum[0] = byte;
+                self.receive = Receive::Checksum(1);
+            }
+            (Receive::Checksum(_), _) => {
+                self.checksum[1] = byte;
+                self.receive = Receive::Idle;
+                self.finish_packet(emu);
+            }
+        }
+    }
+
+    fn finish_packet(&mut self, emu: &mut Emulator3ds) {
+        let expected = std::str::from_utf8(&self.checksum)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        let actual = self.packet.iter().fold(0_u8, |sum, &b| sum.wrapping_add(b));
+        if !self.no_ack {
+            if expected != Some(actual) {
+                self.transport.write(b"-");
+                return;
+            }
+            self.transport.write(b"+");
+        }
+        let packet = std::mem::take(&mut self.packet);
+        if let Some(reply) = self.handle_packet(emu, &packet) {
+            self.send(reply.as_bytes());
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        let mut frame = Vec::with_capacity(data.len() + 4);
+        frame.push(b'$');
+        for &byte in data {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                frame.extend([b'}', byte ^ 0x20]);
+            } else {
+                frame.push(byte);
+            }
+        }
+        let checksum = frame[1..].iter().fold(0_u8, |sum, &b| sum.wrapping_add(b));
+        frame.extend(format!("#{checksum:02x}").bytes());
+        self.transport.write(&frame);
+    }
+
+    fn stop(&mut self, reason: HaltReason) {
+        self.resume = None;
+        self.last_stop = reason;
+        let reply = self.stop_reply();
+        self.send(reply.as_bytes());
+    }
+
+    fn stop_reply(&self) -> String {
+        let (signal, detail) = match self.last_stop {
+            HaltReason::Signal(signal) => (signal, String::new()),
+            HaltReason::Breakpoint(BreakpointKind::Software) => (SIGTRAP, "swbreak:;".into()),
+            HaltReason::Breakpoint(BreakpointKind::Hardware) => (SIGTRAP, "hwbreak:;".into()),
+            HaltReason::Watchpoint { kind, address } => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                (SIGTRAP, format!("{name}:{address:x};"))
+            }
+        };
+        format!("T{signal:02x}thread:{:x};{detail}", self.core + 1)
+    }
+
+    /// `None` while the target should keep running.
+    fn halt_for_stop(&self, stop: StopReason) -> Option<HaltReason> {
+        Some(match stop {
+            StopReason::BudgetExhausted | StopReason::Halted => return None,
+            StopReason::Breakpoint { pc, .. } => HaltReason::Breakpoint(
+                self.breakpoints
+                    .get(&pc)
+                    .copied()
+                    .unwrap_or(BreakpointKind::Hardware),
+            ),
+            StopReason::Watchpoint { kind, address, .. } => {
+                HaltReason::Watchpoint { kind, address }
+            }
+            StopReason::Exception { exception, .. } => {
+                HaltReason::Signal(exception_signal(exception))
+            }
+            StopReason::Stepped | StopReason::ServiceCall { .. } => HaltReason::Signal(SIGTRAP),
+        })
+    }
+
+    fn handle_packet(&mut self, emu: &mut Emulator3ds, packet: &[u8]) -> Option<String> {
+        let (&command, args) = packet.split_first()?;
+        let args = &*String::from_utf8_lossy(args);
+        let reply = match command {
+            b'?' => self.stop_reply(),
+            b'g' => self.read_registers(emu),
+            b'G' => self.write_registers(emu, args),
+            b'p' => parse_hex(args)
+                .and_then(|reg| self.read_register(emu, reg as usize))
+                .unwrap_or_else(|| "E01".into()),
+            b'P' => self.write_register(emu, args),
+            b'm' => self.read_memory(emu, args),
+            b'M' => self.write_memory(emu, args),
+            b'c' | b's' => {
+                if let Some(pc) = parse_hex(args)
+                    && let Some(cpu) = emu.core_mut(self.core)
+                {
+                    cpu.set_register(15, pc);
+                }
+                return self.resume(if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                });
+            }
+            b'Z' | b'z' => self.update_breakpoint(emu, command == b'Z', args),
+            b'H' => match args.get(1..).map(parse_thread) {
+                Some(Some(thread)) if thread < emu.core_count() => {
+                    self.core = thread;
+                    "OK".into()
+                }
+                Some(Some(_)) => "E01".into(),
+                // Thread 0 ("any") and -1 ("all") keep the current core.
+                _ => "OK".into(),
+            },
+            b'T' => match parse_thread(args) {
+                Some(thread) if thread < emu.core_count() => "OK".into(),
+                _ => "E01".into(),
+            },
+            b'D' => {
+                self.detach(emu);
+                "OK".into()
+            }
+            b'k' => {
+                self.detach(emu);
+                return None;
+            }
+            b'q' => self.query(emu, args),
+            b'Q' if args == "StartNoAckMode" => {
+                self.send(b"OK");
+                self.no_ack = true;
+                return None;
+            }
+            b'v' => return self.v_packet(args),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn resume(&mut self, resume: Resume) -> Option<String> {
+        self.resume = Some(resume);
+        None
+    }
+
+    /// Clears everything the debugger set, for `D` and `k`.
+    fn detach(&mut self, emu: &mut Emulator3ds) {
+        for pc in std::mem::take(&mut self.breakpoints).into_keys() {
+            emu.remove_breakpoint(pc);
+        }
+        for watchpoint in std::mem::take(&mut self.watchpoints) {
+            emu.remove_watchpoint(watchpoint);
+        }
+        emu.set_exception_catch(false);
+        self.resume = None;
+        self.attached = false;
+    }
+
+    fn query(&self, emu: &Emulator3ds, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+            );
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            return xfer_chunk(TARGET_XML, range);
+        }
+        match args {
+            "Attached" => "1".into(),
+            "C" => format!("QC{:x}", self.core + 1),
+            "fThreadInfo" => {
+                let threads: Vec<String> = (1..=emu.core_count())
+                    .map(|thread| format!("{thread:x}"))
+                    .collect();
+                format!("m{}", threads.join(","))
+            }
+            "sThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    fn v_packet(&mut self, args: &str) -> Option<String> {
+        if args == "Cont?" {
+            return Some("vCont;c;C;s;S".into());
+        }
+        let actions = args.strip_prefix("Cont;")?;
+        let step = actions
+            .split(';')
+            .any(|action| action.starts_with('s') || action.starts_with('S'));
+        self.resume(if step { Resume::Step } else { Resume::Continue })
+    }
+
+    fn read_registers(&self, emu: &Emulator3ds) -> String {
+        (0..16)
+            .chain([REG_CPSR])
+            .chain(REG_D0..=REG_FPSCR)
+            .filter_map(|reg| self.read_register(emu, reg))
+            .collect()
+    }
+
+    fn read_register(&self, emu: &Emulator3ds, reg: usize) -> Option<String> {
+        let cpu = emu.core(self.core)?;
+        Some(match reg {
+            0..=15 => hex_le(u64::from(cpu.regs()[reg]), 4),
+            REG_CPSR => hex_le(u64::from(cpu.cpsr()), 4),
+            REG_FPSCR => hex_le(u64::from(cpu.fpscr()), 4),
+            _ if (REG_D0..REG_D0 + VFP_DOUBLES).contains(&reg) => {
+                let low = (reg - REG_D0) * 2;
+                let value =
+                    u64::from(cpu.vfp_register(low)) | (u64::from(cpu.vfp_register(low + 1)) << 32);
+                hex_le(value, 8)
+            }
+            _ => return None,
+        })
+    }
+
+    fn write_registers(&self, emu: &mut Emulator3ds, args: &str) -> String {
+        let mut offset = 0;
+        for reg in (0..16).chain([REG_CPSR]).chain(REG_D0..=REG_FPSCR) {
+            let width = if (REG_D0..REG_FPSCR).contains(&reg) {
+                16
+            } else {
+                8
+            };
+            let Some(field) = args.get(offset..offset + width) else {
+                break;
+            };
+            if !self.store_register(emu, reg, field) {
+                return "E01".into();
+            }
+            offset += width;
+        }
+        "OK".into()
+    }
+
+    fn write_register(&self, emu: &mut Emulator3ds, args: &str) -> String {
+        let stored = args.split_once('=').is_some_and(|(reg, value)| {
+            parse_hex(reg).is_some_and(|reg| self.store_register(emu, reg as usize, value))
+        });
+        if stored { "OK".into() } else { "E01".into() }
+    }
+
+    fn store_register(&self, emu: &mut Emulator3ds, reg: usize, field: &str) -> bool {
+        let Some(value) = parse_hex_le(field) else {
+            return false;
+        };
+        let Some(cpu) = emu.core_mut(self.core) else {
+            return false;
+        };
+        match reg {
+            0..=15 => cpu.set_register(reg, value as u32),
+            REG_CPSR => cpu.set_cpsr(value as u32),
+            REG_FPSCR => cpu.set_fpscr(value as u32),
+            _ if (REG_D0..REG_D0 + VFP_DOUBLES).contains(&reg) => {
+                let low = (reg - REG_D0) * 2;
+                cpu.set_vfp_register(low, value as u32);
+                cpu.set_vfp_register(low + 1, (value >> 32) as u32);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_memory(&self, emu: &mut Emulator3ds, args: &str) -> String {
+        let Some((address, len)) = parse_range(args) else {
+            return "E01".into();
+        };
+        let len = len.min(PACKET_SIZE as u32 / 2);
+        let mut reply = String::new();
+        for offset in 0..len {
+            match emu.read_virtual_u8(self.core, address.wrapping_add(offset)) {
+                Some(byte) => reply.push_str(&format!("{byte:02x}")),
+                None => break,
+            }
+        }
+        if reply.is_empty() && len > 0 {
+            "E14".into()
+        } else {
+            reply
+        }
+    }
+
+    fn write_memory(&self, emu: &mut Emulator3ds, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".into();
+        };
+        let Some((address, len)) = parse_range(range) else {
+            return "E01".into();
+        };
+        for offset in 0..len {
+            let index = offset as usize * 2;
+            let Some(byte) = data
+                .get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            else {
+                return "E01".into();
+            };
+            if !emu.write_virtual_u8(self.core, address.wrapping_add(offset), byte) {
+                return "E14".into();
+            }
+        }
+        "OK".into()
+    }
+
+    fn update_breakpoint(&mut self, emu: &mut Emulator3ds, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(address), Some(len)) = (
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+        ) else {
+            return "E01".into();
+        };
+        let watch = |kind| Watchpoint {
+            start: address,
+            len,
+            kind,
+            space: AddressSpace::Virtual,
+        };
+        match (kind, insert) {
+            (0 | 1, true) => {
+                let kind = if kind == 0 {
+                    BreakpointKind::Software
+                } else {
+                    BreakpointKind::Hardware
+                };
+                self.breakpoints.insert(address, kind);
+                emu.add_breakpoint(address);
+            }
+            (0 | 1, false) => {
+                self.breakpoints.remove(&address);
+                emu.remove_breakpoint(address);
+            }
+            (2..=4, true) => {
+                let watchpoint = watch(watch_kind(kind));
+                self.watchpoints.push(watchpoint);
+                emu.add_watchpoint(watchpoint);
+            }
+            (2..=4, false) => {
+                let watchpoint = watch(watch_kind(kind));
+                if let Some(index) = self.watchpoints.iter().position(|&w| w == watchpoint) {
+                    self.watchpoints.remove(index);
+                }
+                emu.remove_watchpoint(watchpoint);
+            }
+            _ => return String::new(),
+        }
+        "OK".into()
+    }
+}
+
+fn exception_signal(exception: CpuException) -> u8 {
+    match exception.kind {
+        ExceptionKind::UndefinedInstruction => SIGILL,
+        ExceptionKind::PrefetchAbort(_) | ExceptionKind::DataAbort(_) => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+/// Faults the CPU reports as errors rather than through its vectors.
+fn error_signal(error: &EmulatorError) -> u8 {
+    match error {
+        EmulatorError::MmuTranslationFault { .. }
+        | EmulatorError::MmuDomainFault { .. }
+        | EmulatorError::MmuPermissionFault { .. }
+        | EmulatorError::AlignmentFault { .. } => SIGSEGV,
+        _ => SIGABRT,
+    }
+}
+
+fn watch_kind(z_type: u32) -> WatchKind {
+    match z_type {
+        2 => WatchKind::Write,
+        3 => WatchKind::Read,
+        _ => WatchKind::Access,
+    }
+}
+
+/// Serves `offset,length` of an `qXfer` object: `m` while more follows,
+/// `l` for the last chunk.
+fn xfer_chunk(object: &str, range: &str) -> String {
+    let Some((offset, len)) = parse_range(range) else {
+        return "E01".into();
+    };
+    let start = (offset as usize).min(object.len());
+    let end = start.saturating_add(len as usize).min(object.len());
+    let marker = if end == object.len() { 'l' } else { 'm' };
+    format!("{marker}{}", &object[start..end])
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// `addr,length` as sent by `m`, `M` and `qXfer`.
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let (address, len) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(len)?))
+}
+
+/// A thread ID as a core index; `None` for 0 ("any") and -1 ("all").
+fn parse_thread(text: &str) -> Option<usize> {
+    let thread = usize::from_str_radix(text, 16).ok()?;
+    thread.checked_sub(1)
+}
+
+/// Register values travel as target-order (little-endian) hex bytes.
+fn hex_le(value: u64, bytes: usize) -> String {
+    value.to_le_bytes()[..bytes]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn parse_hex_le(text: &str) -> Option<u64> {
+    if !text.len().is_multiple_of(2) || text.len() > 16 {
+        return None;
+    }
+    let mut bytes = [0_u8; 8];
+    for (i, byte) in bytes.iter_mut().enumerate().take(text.len() / 2) {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::core::test_rom::boot;
+
+    #[derive(Default)]
+    struct Loopback {
+        inbox: VecDeque<u8>,
+        outbox: Vec<u8>,
+    }
+
+    impl GdbTransport for Loopback {
+        fn read_byte(&mut self) -> Option<u8> {
+            self.inbox.pop_front()
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.outbox.extend_from_slice(bytes);
+        }
+    }
+
+    /// Sends one packet and returns the reply packets it produced.
+    fn exchange(stub: &mut GdbStub<Loopback>, emu: &mut Emulator3ds, packet: &str) -> Vec<String> {
+        let checksum = packet.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+        let frame = format!("${packet}#{checksum:02x}");
+        stub.transport_mut().inbox.extend(frame.bytes());
+        stub.poll(emu, 8);
+        replies(stub)
+    }
+
+    fn replies(stub: &mut GdbStub<Loopback>) -> Vec<String> {
+        let output = String::from_utf8(std::mem::take(&mut stub.transport_mut().outbox))
+            .unwrap_or_else(|e| panic!("stub output is text: {e}"));
+        output
+            .split('$')
+            .skip(1)
+            .map(|frame| {
+                let (data, checksum) = frame
+                    .split_once('#')
+                    .unwrap_or_else(|| panic!("unterminated packet: {frame}"));
+                let sum = data.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+                assert_eq!(format!("{sum:02x}"), checksum[..2], "checksum of {data}");
+                data.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn registers_memory_and_target_description() {
+        let mut emu = boot(&[0xE3A0_0005, 0xE320_F003]); // mov r0, #5; wfi
+        let mut stub = GdbStub::new(Loopback::default());
+
+        let supported = exchange(&mut stub, &mut emu, "qSupported:swbreak+");
+        assert!(supported[0].contains("qXfer:features:read+"));
+        let xml = exchange(&mut stub, &mut emu, "qXfer:features:read:target.xml:0,fff");
+        assert!(xml[0].starts_with("l<?xml"));
+        assert!(xml[0].contains("org.gnu.gdb.arm.vfp"));
+
+        assert_eq!(exchange(&mut stub, &mut emu, "s"), ["T05thread:1;"]);
+        let regs = exchange(&mut stub, &mut emu, "g");
+        assert_eq!(regs[0].len(), (17 * 4 + 16 * 8 + 4) * 2);
+        assert_eq!(&regs[0][..8], "05000000");
+        assert_eq!(&regs[0][15 * 8..16 * 8], "04001000");
+
+        assert_eq!(
+            exchange(&mut stub, &mut emu, "P1a=0000f03f00000000"),
+            ["OK"]
+        );
+        assert_eq!(exchange(&mut stub, &mut emu, "p1a"), ["0000f03f00000000"]);
+        assert_eq!(exchange(&mut stub, &mut emu, "P2=78563412"), ["OK"]);
+        assert_eq!(emu.state().registers[2], 0x1234_5678);
+
+        assert_eq!(exchange(&mut stub, &mut emu, "m100000,4"), ["0500a0e3"]);
+        assert_eq!(exchange(&mut stub, &mut emu, "M102000,2:abcd"), ["OK"]);
+        assert_eq!(exchange(&mut stub, &mut emu, "m102000,2"), ["abcd"]);
+        assert_eq!(exchange(&mut stub, &mut emu, "qfThreadInfo"), ["m1,2"]);
+    }
+
+    #[test]
+    fn breakpoints_watchpoints_and_exceptions_stop_the_target() {
+        let mut emu = boot(&[
+            0xE3A0_0601, // mov r0, #0x100000
+            0xE280_0A02, // add r0, r0, #0x2000
+            0xE580_0000, // str r0, [r0]
+            0xE1A0_0000, // nop
+            0xE7F0_00F0, // udf #0
+        ]);
+        let mut stub = GdbStub::new(Loopback::default());
+
+        assert_eq!(exchange(&mut stub, &mut emu, "Z0,10000c,4"), ["OK"]);
+        assert_eq!(exchange(&mut stub, &mut emu, "Z2,102000,4"), ["OK"]);
+
+        assert_eq!(
+            exchange(&mut stub, &mut emu, "c"),
+            ["T05thread:1;watch:102000;"]
+        );
+        assert_eq!(emu.state().pc, 0x0010_000C);
+        assert_eq!(
+            exchange(&mut stub, &mut emu, "vCont;c"),
+            ["T05thread:1;swbreak:;"]
+        );
+        assert_eq!(emu.state().pc, 0x0010_000C);
+
+        // Resuming steps over the breakpoint; the UDF stops in its vector.
+        assert_eq!(exchange(&mut stub, &mut emu, "c"), ["T04thread:1;"]);
+        assert_eq!(emu.state().pc, 0x0010_0004);
+        assert_eq!(exchange(&mut stub, &mut emu, "?"), ["T04thread:1;"]);
+
+        assert_eq!(exchange(&mut stub, &mut emu, "D"), ["OK"]);
+        assert!(!stub.is_attached());
+    }
+
+    #[test]
+    fn kill_clears_watchpoints_and_exception_catch() {
+        let mut emu = boot(&[
+            0xE3A0_0601, // mov r0, #0x100000
+            0xE280_0A02, // add r0, r0, #0x2000
+            0xE580_0000, // str r0, [r0]
+            0xE7F0_00F0, // udf #0
+        ]);
+        let mut stub = GdbStub::new(Loopback::default());
+
+        assert_eq!(exchange(&mut stub, &mut emu, "\u{e9}"), [""]);
+        assert_eq!(exchange(&mut stub, &mut emu, "Z2,102000,4"), ["OK"]);
+        assert!(exchange(&mut stub, &mut emu, "k").is_empty());
+        assert!(!stub.is_attached());
+
+        let stop = emu
+            .run_until_stop(16)
+            .unwrap_or_else(|e| panic!("run works: {e}"));
+        assert!(
+            !matches!(
+                stop,
+                StopReason::Watchpoint { .. } | StopReason::Exception { .. }
+            ),
+            "stopped after kill: {stop:?}"
+        );
+    }
+
+    #[test]
+    fn interrupt_halts_a_running_target() {
+        let mut emu = boot(&[0xEAFF_FFFE]); // b .
+        let mut stub = GdbStub::new(Loopback::default());
+
+        assert!(exchange(&mut stub, &mut emu, "c").is_empty());
+        assert!(stub.is_running());
+        stub.transport_mut().inbox.push_back(0x03);
+        stub.poll(&mut emu, 8);
+        assert_eq!(replies(&mut stub), ["T02thread:1;"]);
+        assert!(!stub.is_running());
+    }
+}
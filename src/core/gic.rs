@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::bus::{AccessWidth, BusDevice, DeviceContext};
+use super::irq::IrqLine;
+use super::memory::MPCORE_START;
+
+/// Each core sees its own bank of the CPU interface 256 bytes into the
+/// private region.
+pub const GIC_CPU_INTERFACE_BASE: u32 = MPCORE_START + 0x100;
+pub const GIC_CPU_INTERFACE_SIZE: u32 = 0x100;
+/// The MPCore interrupt distributor sits 4 KiB into the private region.
+pub const GIC_DISTRIBUTOR_BASE: u32 = MPCORE_START + 0x1000;
+pub const GIC_DISTRIBUTOR_SIZE: u32 = 0x1000;
+
+/// Interrupt IDs the distributor has state for: 16 SGIs, 16 private and 96
+/// shared peripheral interrupts.
+const INTERRUPT_LINES: usize = 128;
+const SGI_COUNT: usize = 16;
+/// IDs below this are banked per core; their targets read as the core
+/// making the access.
+const PRIVATE_LINES: u32 = 32;
+
+const CONTROL: u32 = 0x000;
+const CONTROLLER_TYPE: u32 = 0x004;
+const SET_ENABLE: u32 = 0x100;
+const CLEAR_ENABLE: u32 = 0x180;
+const SET_PENDING: u32 = 0x200;
+const CLEAR_PENDING: u32 = 0x280;
+const PRIORITY: u32 = 0x400;
+const TARGETS: u32 = 0x800;
+const CONFIG: u32 = 0xC00;
+/// `ICDSGIR`: bits 25:24 pick the target filter, 23:16 the target cores,
+/// 3:0 the ID.
+const SOFTWARE_INTERRUPT: u32 = 0xF00;
+
+const CPU_CONTROL: u32 = 0x00;
+const PRIORITY_MASK: u32 = 0x04;
+const BINARY_POINT: u32 = 0x08;
+/// `ICCIAR`: reading it acknowledges the interrupt it returns, with the
+/// ID in bits 9:0 and for an SGI the sending core in bits 12:10.
+const ACKNOWLEDGE: u32 = 0x0C;
+/// `ICCEOIR`: writing back an `ICCIAR` value retires that interrupt.
+const END_OF_INTERRUPT: u32 = 0x10;
+const RUNNING_PRIORITY: u32 = 0x14;
+
+/// What `ICCIAR` returns when nothing can be acknowledged.
+const SPURIOUS_INTERRUPT: u32 = 1023;
+/// The running priority of a core with no active interrupt.
+const IDLE_PRIORITY: u8 = 0xFF;
+
+const BITMAP_BYTES: u32 = (INTERRUPT_LINES / 8) as u32;
+
+/// One core's bank of the CPU interface.
+struct CpuInterface {
+    enabled: bool,
+    priority_mask: u8,
+    binary_point: u8,
+    /// The cores each SGI ID is pending from, as a bitmask.
+    sgi_sources: [u8; SGI_COUNT],
+    /// Acknowledged interrupts waiting for `ICCEOIR`, as their `ICCIAR`
+    /// value and priority; the innermost is last.
+    active: Vec<(u32, u8)>,
+    /// Whether the core's IPI line is asserted.
+    signalled: bool,
+}
+
+impl CpuInterface {
+    fn running_priority(&self) -> u8 {
+        self.active
+            .last()
+            .map_or(IDLE_PRIORITY, |&(_, priority)| priority)
+    }
+}
+
+/// The MPCore GIC: the distributor's enable, pending, priority, target and
+/// configuration state, and a CPU interface per core. Guests reach it
+/// through [`GicDistributor`] and [`GicCpuInterface`], which share it.
+///
+/// Only SGIs are forwarded to the cores so far; each core's IPI line is
+/// asserted while it has an enabled SGI that beats both its priority mask
+/// and its running priority.
+pub struct Gic {
+    core_count: usize,
+    enabled: bool,
+    enable: [u8; INTERRUPT_LINES / 8],
+    pending: [u8; INTERRUPT_LINES / 8],
+    priority: [u8; INTERRUPT_LINES],
+    targets: [u8; INTERRUPT_LINES],
+    config: [u8; INTERRUPT_LINES / 4],
+    cpus: Vec<CpuInterface>,
+}
+
+impl Gic {
+    /// Starts enabled with the SGIs unmasked and every CPU interface
+    /// passing them, the way the 3DS kernel leaves the GIC before a title
+    /// runs.
+    pub fn new(core_count: usize) -> Self {
+        let core_count = core_count.max(1);
+        let mut enable = [0; INTERRUPT_LINES / 8];
+        enable[..SGI_COUNT / 8].fill(0xFF);
+        Self {
+            core_count,
+            enabled: true,
+            enable,
+            pending: [0; INTERRUPT_LINES / 8],
+            priority: [0; INTERRUPT_LINES],
+            targets: [0; INTERRUPT_LINES],
+            config: [0; INTERRUPT_LINES / 4],
+            cpus: (0..core_count)
+                .map(|_| CpuInterface {
+                    enabled: true,
+                    priority_mask: 0xF0,
+                    binary_point: 0,
+                    sgi_sources: [0; SGI_COUNT],
+                    active: Vec::new(),
+                    signalled: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// The two register windows, to be mapped at [`GIC_DISTRIBUTOR_BASE`]
+    /// and [`GIC_CPU_INTERFACE_BASE`].
+    pub fn into_devices(self) -> (GicDistributor, GicCpuInterface) {
+        let gic = Rc::new(RefCell::new(self));
+        (GicDistributor(gic.clone()), GicCpuInterface(gic))
+    }
+
+    fn read_byte(&self, offset: u32, core: usize) -> u8 {
+        let index = |base: u32| (offset - base) as usize;
+        match offset {
+            CONTROL => u8::from(self.enabled),
+            // CPU count - 1 in bits 7:5, lines / 32 - 1 in bits 4:0.
+            CONTROLLER_TYPE => {
+                (((self.core_count - 1) as u8) << 5) | (INTERRUPT_LINES / 32 - 1) as u8
+            }
+            o if (SET_ENABLE..SET_ENABLE + BITMAP_BYTES).contains(&o) => {
+                self.enable[index(SET_ENABLE)]
+            }
+            o if (CLEAR_ENABLE..CLEAR_ENABLE + BITMAP_BYTES).contains(&o) => {
+                self.enable[index(CLEAR_ENABLE)]
+            }
+            o if (SET_PENDING..SET_PENDING + BITMAP_BYTES).contains(&o) => {
+                self.pending[index(SET_PENDING)]
+            }
+            o if (CLEAR_PENDING..CLEAR_PENDING + BITMAP_BYTES).contains(&o) => {
+                self.pending[index(CLEAR_PENDING)]
+            }
+            o if (PRIORITY..PRIORITY + INTERRUPT_LINES as u32).contains(&o) => {
+                self.priority[index(PRIORITY)]
+            }
+            o if (TARGETS..TARGETS + PRIVATE_LINES).contains(&o) => 1 << core,
+            o if (TARGETS..TARGETS + INTERRUPT_LINES as u32).contains(&o) => {
+                self.targets[index(TARGETS)]
+            }
+            o if (CONFIG..CONFIG + INTERRUPT_LINES as u32 / 4).contains(&o) => {
+                self.config[index(CONFIG)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, offset: u32, value: u8) {
+        let index = |base: u32| (offset - base) as usize;
+        match offset {
+            CONTROL => self.enabled = value & 1 != 0,
+            o if (SET_ENABLE..SET_ENABLE + BITMAP_BYTES).contains(&o) => {
+                self.enable[index(SET_ENABLE)] |= value;
+            }
+            o if (CLEAR_ENABLE..CLEAR_ENABLE + BITMAP_BYTES).contains(&o) => {
+                self.enable[index(CLEAR_ENABLE)] &= !value;
+            }
+            o if (SET_PENDING..SET_PENDING + BITMAP_BYTES).contains(&o) => {
+                self.pending[index(SET_PENDING)] |= value;
+            }
+            o if (CLEAR_PENDING..CLEAR_PENDING + BITMAP_BYTES).contains(&o) => {
+                self.pending[index(CLEAR_PENDING)] &= !value;
+            }
+            o if (PRIORITY..PRIORITY + INTERRUPT_LINES as u32).contains(&o) => {
+                self.priority[index(PRIORITY)] = value;
+            }
+            // Private interrupts always target the core they belong to.
+            o if (TARGETS..TARGETS + PRIVATE_LINES).contains(&o) => {}
+            o if (TARGETS..TARGETS + INTERRUPT_LINES as u32).contains(&o) => {
+                self.targets[index(TARGETS)] = value;
+            }
+            o if (CONFIG..CONFIG + INTERRUPT_LINES as u32 / 4).contains(&o) => {
+                self.config[index(CONFIG)] = value;
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes an `ICDSGIR` write by `sender` pending on the cores it targets.
+    fn send_software_interrupt(&mut self, sender: usize, request: u32) {
+        let all_cores = (1_u32 << self.core_count) - 1;
+        let targets = match (request >> 24) & 0x3 {
+            0b00 => (request >> 16) & all_cores,
+            0b01 => all_cores & !(1 << sender),
+            0b10 => 1 << sender,
+            _ => 0,
+        };
+        let id = (request & 0xF) as usize;
+        for (core, cpu) in self.cpus.iter_mut().enumerate() {
+            if targets & (1 << core) != 0 {
+                cpu.sgi_sources[id] |= 1 << sender;
+            }
+        }
+    }
+
+    /// The `ICCIAR` value and priority of the SGI `core` would acknowledge
+    /// now: the enabled one with the lowest priority value, then the lowest
+    /// ID, then the lowest sending core.
+    fn next_software_interrupt(&self, core: usize) -> Option<(u32, u8)> {
+        let cpu = &self.cpus[core];
+        if !self.enabled || !cpu.enabled {
+            return None;
+        }
+        let (id, priority) = (0..SGI_COUNT)
+            .filter(|&id| cpu.sgi_sources[id] != 0 && self.enable[id / 8] & (1 << (id % 8)) != 0)
+            .map(|id| (id, self.priority[id]))
+            .min_by_key(|&(id, priority)| (priority, id))?;
+        if priority >= cpu.priority_mask || priority >= cpu.running_priority() {
+            return None;
+        }
+        let source = cpu.sgi_sources[id].trailing_zeros();
+        Some((id as u32 | (source << 10), priority))
+    }
+
+    fn acknowledge(&mut self, core: usize) -> u32 {
+        let Some((value, priority)) = self.next_software_interrupt(core) else {
+            return SPURIOUS_INTERRUPT;
+        };
+        let cpu = &mut self.cpus[core];
+        cpu.sgi_sources[(value & 0xF) as usize] &= !(1 << (value >> 10));
+        cpu.active.push((value, priority));
+        value
+    }
+
+    /// Retires the active interrupt `value` names, if there is one.
+    fn end_of_interrupt(&mut self, core: usize, value: u32) {
+        let active = &mut self.cpus[core].active;
+        if let Some(index) = active.iter().rposition(|&(v, _)| v == value & 0x1FFF) {
+            active.remove(index);
+        }
+    }
+
+    /// Drives each core's IPI line to match what it could acknowledge.
+    fn update_lines(&mut self, ctx: &mut DeviceContext) {
+        for core in 0..self.core_count {
+            let asserted = self.next_software_interrupt(core).is_some();
+            if self.cpus[core].signalled != asserted {
+                self.cpus[core].signalled = asserted;
+                ctx.drive_private_irq(core, IrqLine::Ipi, asserted);
+            }
+        }
+    }
+}
+
+/// The distributor's register window onto a [`Gic`]. `ICDSGIR` writes
+/// send SGIs from the core making the access.
+pub struct GicDistributor(Rc<RefCell<Gic>>);
+
+impl BusDevice for GicDistributor {
+    fn read(&mut self, offset: u32, width: AccessWidth, ctx: &mut DeviceContext) -> u64 {
+        let gic = self.0.borrow();
+        (0..width.bytes()).fold(0, |value, i| {
+            value | (u64::from(gic.read_byte(offset.wrapping_add(i), ctx.core())) << (8 * i))
+        })
+    }
+
+    fn write(&mut self, offset: u32, width: AccessWidth, value: u64, ctx: &mut DeviceContext) {
+        let mut gic = self.0.borrow_mut();
+        if offset == SOFTWARE_INTERRUPT {
+            // `ICDSGIR` only takes word writes; a disabled distributor
+            // forwards nothing.
+            if width == AccessWidth::Word && gic.enabled {
+                gic.send_software_interrupt(ctx.core(), value as u32);
+            }
+        } else {
+            for i in 0..width.bytes() {
+                gic.write_byte(offset.wrapping_add(i), (value >> (8 * i)) as u8);
+            }
+        }
+        gic.update_lines(ctx);
+    }
+}
+
+/// The CPU interface's register window onto a [`Gic`], banked by the core
+/// making the access. Registers are word-sized; other accesses are
+/// ignored.
+pub struct GicCpuInterface(Rc<RefCell<Gic>>);
+
+impl BusDevice for GicCpuInterface {
+    fn read(&mut self, offset: u32, width: AccessWidth, ctx: &mut DeviceContext) -> u64 {
+        if width != AccessWidth::Word {
+            return 0;
+        }
+        let mut gic = self.0.borrow_mut();
+        let core = ctx.core();
+        let value = match offset {
+            CPU_CONTROL => u32::from(gic.cpus[core].enabled),
+            PRIORITY_MASK => u32::from(gic.cpus[core].priority_mask),
+            BINARY_POINT => u32::from(gic.cpus[core].binary_point),
+            ACKNOWLEDGE => {
+                let value = gic.acknowledge(core);
+                gic.update_lines(ctx);
+                value
+            }
+            RUNNING_PRIORITY => u32::from(gic.cpus[core].running_priority()),
+            _ => 0,
+        };
+        u64::from(value)
+    }
+
+    fn write(&mut self, offset: u32, width: AccessWidth, value: u64, ctx: &mut DeviceContext) {
+        if width != AccessWidth::Word {
+            return;
+        }
+        let mut gic = self.0.borrow_mut();
+        let core = ctx.core();
+        let value = value as u32;
+        match offset {
+            CPU_CONTROL => gic.cpus[core].enabled = value & 1 != 0,
+            // The MPCore implements the top four priority bits.
+            PRIORITY_MASK => gic.cpus[core].priority_mask = value as u8 & 0xF0,
+            BINARY_POINT => gic.cpus[core].binary_point = value as u8 & 0x7,
+            END_OF_INTERRUPT => gic.end_of_interrupt(core, value),
+            _ => {}
+        }
+        gic.update_lines(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::bus::{Bus, PrivateIrq, SystemBus};
+
+    fn bus_with_gic(core_count: usize) -> SystemBus {
+        let mut bus = SystemBus::default();
+        let (distributor, cpu_interface) = Gic::new(core_count).into_devices();
+        bus.map_mmio_device(
+            GIC_DISTRIBUTOR_BASE,
+            GIC_DISTRIBUTOR_SIZE,
+            Box::new(distributor),
+        );
+        bus.map_mmio_device(
+            GIC_CPU_INTERFACE_BASE,
+            GIC_CPU_INTERFACE_SIZE,
+            Box::new(cpu_interface),
+        );
+        bus
+    }
+
+    fn ipi(core: usize, asserted: bool) -> PrivateIrq {
+        PrivateIrq {
+            core,
+            line: IrqLine::Ipi,
+            asserted,
+        }
+    }
+
+    #[test]
+    fn software_interrupt_register_takes_only_word_writes() {
+        let mut bus = bus_with_gic(2);
+        let sgir = GIC_DISTRIBUTOR_BASE + SOFTWARE_INTERRUPT;
+        assert_eq!(bus.write_u8_checked(sgir, 0x01), Ok(()));
+        assert_eq!(bus.write_u16_checked(sgir, 0x0001), Ok(()));
+        assert_eq!(bus.take_private_irqs(), []);
+
+        assert_eq!(bus.write_u32_checked(sgir, 0x0002_0003), Ok(()));
+        assert_eq!(bus.take_private_irqs(), [ipi(1, true)]);
+        assert_eq!(bus.read_u32_checked(sgir), Ok(0), "write-only");
+        assert_eq!(bus.memory().read_u32_checked(sgir), Ok(0));
+
+        assert_eq!(
+            bus.write_u32_checked(GIC_DISTRIBUTOR_BASE + CONTROL, 0),
+            Ok(())
+        );
+        assert_eq!(bus.take_private_irqs(), [ipi(1, false)]);
+        assert_eq!(bus.write_u32_checked(sgir, 0x0001_0003), Ok(()));
+        assert_eq!(bus.take_private_irqs(), [], "distributor disabled");
+    }
+
+    #[test]
+    fn registers_hold_state_at_any_width() {
+        let mut bus = bus_with_gic(4);
+        let base = GIC_DISTRIBUTOR_BASE;
+        assert_eq!(bus.read_u32_checked(base + CONTROLLER_TYPE), Ok(0x63));
+        assert_eq!(bus.write_u32_checked(base + CONTROLLER_TYPE, 0), Ok(()));
+        assert_eq!(bus.read_u8_checked(base + CONTROLLER_TYPE), Ok(0x63));
+
+        assert_eq!(bus.read_u32_checked(base + SET_ENABLE), Ok(0xFFFF));
+        assert_eq!(bus.write_u32_checked(base + SET_ENABLE + 4, 0x8001), Ok(()));
+        assert_eq!(bus.write_u8_checked(base + CLEAR_ENABLE + 4, 0x01), Ok(()));
+        assert_eq!(bus.write_u8_checked(base + CLEAR_ENABLE + 1, 0x80), Ok(()));
+        assert_eq!(bus.read_u32_checked(base + SET_ENABLE), Ok(0x7FFF));
+        assert_eq!(bus.read_u32_checked(base + CLEAR_ENABLE + 4), Ok(0x8000));
+
+        assert_eq!(bus.write_u8_checked(base + PRIORITY + 33, 0xA0), Ok(()));
+        assert_eq!(bus.write_u16_checked(base + TARGETS + 34, 0x0201), Ok(()));
+        assert_eq!(bus.read_u32_checked(base + PRIORITY + 32), Ok(0xA000));
+        assert_eq!(bus.read_u32_checked(base + TARGETS + 32), Ok(0x0201_0000));
+
+        // Private interrupts target whichever core asks.
+        bus.set_current_core(2);
+        assert_eq!(bus.write_u8_checked(base + TARGETS + 5, 0x01), Ok(()));
+        assert_eq!(bus.read_u32_checked(base + TARGETS + 4), Ok(0x0404_0404));
+    }
+
+    #[test]
+    fn cpu_interface_acknowledges_sgis_with_their_sender() {
+        let mut bus = bus_with_gic(4);
+        let sgir = GIC_DISTRIBUTOR_BASE + SOFTWARE_INTERRUPT;
+        let iar = GIC_CPU_INTERFACE_BASE + ACKNOWLEDGE;
+        let eoir = GIC_CPU_INTERFACE_BASE + END_OF_INTERRUPT;
+        bus.set_current_core(3);
+        assert_eq!(bus.write_u32_checked(sgir, 0x0002_0005), Ok(()));
+        bus.set_current_core(0);
+        assert_eq!(bus.write_u32_checked(sgir, 0x0002_0005), Ok(()));
+        assert_eq!(bus.write_u32_checked(sgir, 0x0002_0002), Ok(()));
+        assert_eq!(bus.take_private_irqs(), [ipi(1, true)]);
+
+        // Equal priorities go by ID, then by sender; each stays active, and
+        // holds off the rest, until its EOI.
+        bus.set_current_core(1);
+        assert_eq!(bus.read_u32_checked(iar), Ok(0x002));
+        assert_eq!(bus.take_private_irqs(), [ipi(1, false)]);
+        assert_eq!(bus.read_u32_checked(iar), Ok(SPURIOUS_INTERRUPT));
+        assert_eq!(bus.write_u32_checked(eoir, 0x002), Ok(()));
+        assert_eq!(bus.take_private_irqs(), [ipi(1, true)]);
+        assert_eq!(bus.read_u32_checked(iar), Ok(0x005));
+        assert_eq!(bus.write_u32_checked(eoir, 0x005), Ok(()));
+        assert_eq!(bus.read_u32_checked(iar), Ok(0xC05));
+        assert_eq!(bus.write_u32_checked(eoir, 0xC05), Ok(()));
+        assert_eq!(bus.read_u32_checked(iar), Ok(SPURIOUS_INTERRUPT));
+        assert_eq!(
+            bus.take_private_irqs(),
+            [ipi(1, false), ipi(1, true), ipi(1, false)]
+        );
+
+        // A disabled SGI stays pending until it is enabled again, and one
+        // at or below the priority mask isn't signalled.
+        let base = GIC_DISTRIBUTOR_BASE;
+        assert_eq!(bus.write_u8_checked(base + CLEAR_ENABLE, 0x02), Ok(()));
+        assert_eq!(bus.write_u32_checked(sgir, 0x0002_0001), Ok(()));
+        assert_eq!(bus.take_private_irqs(), []);
+        assert_eq!(bus.read_u32_checked(iar), Ok(SPURIOUS_INTERRUPT));
+        assert_eq!(bus.write_u8_checked(base + PRIORITY + 1, 0xF0), Ok(()));
+        assert_eq!(bus.write_u8_checked(base + SET_ENABLE, 0x02), Ok(()));
+        assert_eq!(bus.take_private_irqs(), []);
+        let pmr = GIC_CPU_INTERFACE_BASE + PRIORITY_MASK;
+        assert_eq!(bus.write_u32_checked(pmr, 0xFF), Ok(()));
+        assert_eq!(bus.read_u32_checked(pmr), Ok(0xF0));
+        assert_eq!(bus.write_u8_checked(base + PRIORITY + 1, 0xE0), Ok(()));
+        assert_eq!(bus.take_private_irqs(), [ipi(1, true)]);
+        assert_eq!(bus.read_u32_checked(iar), Ok(0x401));
+        let rpr = GIC_CPU_INTERFACE_BASE + RUNNING_PRIORITY;
+        assert_eq!(bus.read_u32_checked(rpr), Ok(0xE0));
+    }
+}
@@ -7,6 +7,7 @@ pub const RESULT_OK: u32 = 0;
 pub const RESULT_NOT_FOUND: u32 = 0xD8A1_83F8;
 pub const RESULT_INVALID_HANDLE: u32 = 0xD8A1_83FA;
 pub const RESULT_INVALID_COMMAND: u32 = 0xD8A1_8404;
+pub const RESULT_OUT_OF_RANGE: u32 = 0xD8E0_07FD;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KernelObjectType {
@@ -15,6 +16,7 @@ pub enum KernelObjectType {
     Event,
     Archive,
     File,
+    Thread,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
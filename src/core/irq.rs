@@ -4,6 +4,11 @@ pub enum IrqLine {
     VBlank = 1,
     Dma0 = 2,
     Gpu = 3,
+    /// The GIC has a software-generated interrupt for the core to
+    /// acknowledge through `ICCIAR`.
+    Ipi = 4,
+    /// A core's performance monitor counter overflowed.
+    PerformanceMonitor = 5,
 }
 
 impl IrqLine {
@@ -16,6 +21,8 @@ impl IrqLine {
 pub struct IrqController {
     enabled: u32,
     pending: u32,
+    /// Per-core level-sensitive private interrupts, as `IrqLine` bits.
+    private_pending: Vec<u32>,
 }
 
 impl Default for IrqController {
@@ -29,11 +36,13 @@ impl IrqController {
         Self {
             enabled: u32::MAX,
             pending: 0,
+            private_pending: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
         self.pending = 0;
+        self.private_pending.clear();
     }
 
     pub fn set_enabled_mask(&mut self, mask: u32) {
@@ -48,6 +57,26 @@ impl IrqController {
         self.pending &= !line.bit();
     }
 
+    /// Drives a core's private interrupt `line`, which stays pending until
+    /// its source deasserts it.
+    pub fn set_private(&mut self, core: usize, line: IrqLine, asserted: bool) {
+        if self.private_pending.len() <= core {
+            self.private_pending.resize(core + 1, 0);
+        }
+        if asserted {
+            self.private_pending[core] |= line.bit();
+        } else {
+            self.private_pending[core] &= !line.bit();
+        }
+    }
+
+    pub fn next_private(&self, core: usize) -> Option<IrqLine> {
+        let pending = self.private_pending.get(core).copied().unwrap_or(0) & self.enabled;
+        [IrqLine::PerformanceMonitor, IrqLine::Ipi]
+            .into_iter()
+            .find(|line| pending & line.bit() != 0)
+    }
+
     pub fn next_pending(&self) -> Option<IrqLine> {
         let active = self.pending & self.enabled;
         if active & IrqLine::Timer0.bit() != 0 {
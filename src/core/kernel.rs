@@ -4,16 +4,28 @@ use super::diagnostics::StructuredError;
 use super::fs::{ArchiveHandle, FileHandle, VirtualFileSystem};
 use super::ipc::{
     Handle, IpcEvent, IpcMessage, KernelObjectType, ProcessId, RESULT_INVALID_COMMAND,
-    RESULT_INVALID_HANDLE, RESULT_NOT_FOUND, service_name_from_words,
+    RESULT_INVALID_HANDLE, RESULT_NOT_FOUND, RESULT_OK, RESULT_OUT_OF_RANGE,
+    service_name_from_words,
 };
 use super::pica::PicaCommandBufferPacket;
 use super::services::{ServiceRegistry, ServiceRuntime, ServiceTarget};
 
 const KERNEL_PROCESS_ID: ProcessId = 1;
 
+/// An ARM11 MPCore cluster has at most four cores.
+pub(super) const MAX_CORES: usize = 4;
+
+/// `svcCreateThread` processor IDs that leave the choice to the kernel.
+const PROCESSOR_ANY: i32 = -1;
+const PROCESSOR_DEFAULT: i32 = -2;
+
+pub type ThreadId = u32;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServiceCall {
     Yield,
+    CreateThread,
+    ExitThread,
     GetTick,
     SendSyncRequest,
     CreateEvent,
@@ -22,6 +34,23 @@ pub enum ServiceCall {
     Unknown(u32),
 }
 
+impl ServiceCall {
+    /// The SVC name without its `svc` prefix, e.g. `"SendSyncRequest"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yield => "Yield",
+            Self::CreateThread => "CreateThread",
+            Self::ExitThread => "ExitThread",
+            Self::GetTick => "GetTick",
+            Self::SendSyncRequest => "SendSyncRequest",
+            Self::CreateEvent => "CreateEvent",
+            Self::DuplicateHandle => "DuplicateHandle",
+            Self::CloseHandle => "CloseHandle",
+            Self::Unknown(_) => "Unknown",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ServiceEvent {
     pub call: ServiceCall,
@@ -35,6 +64,7 @@ enum KernelObject {
     Event(IpcEvent),
     Archive(ArchiveHandle),
     File(FileHandle),
+    Thread(ThreadId),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -43,6 +73,16 @@ pub struct IpcResponse {
     pub words: Vec<u32>,
 }
 
+/// An IPC request the kernel has answered, and the core whose thread sent
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpcDispatch {
+    pub core: usize,
+    pub command_id: u16,
+    pub handle_id: Handle,
+    pub result_code: u32,
+}
+
 #[derive(Debug, Clone)]
 struct IpcRequest {
     session_handle: Handle,
@@ -56,6 +96,26 @@ struct ProcessState {
     pending_responses: VecDeque<IpcResponse>,
     last_result_code: u32,
     blocked_on_ipc: bool,
+    /// The core of the thread that last called `svcSendSyncRequest`.
+    ipc_core: usize,
+}
+
+/// A created thread waiting for a core to go idle. It prefers `core` but
+/// may start on any idle core in `affinity_mask`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStart {
+    pub thread: ThreadId,
+    pub core: usize,
+    pub affinity_mask: u32,
+    pub entry: u32,
+    pub argument: u32,
+    pub stack_top: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ThreadState {
+    affinity_mask: u32,
+    ideal_core: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -74,11 +134,19 @@ pub struct Kernel {
     registry: ServiceRegistry,
     service_runtime: ServiceRuntime,
     vfs: VirtualFileSystem,
-    last_ipc: Option<(u16, Handle, u32)>,
+    last_ipc: Option<IpcDispatch>,
     last_service_imm24: Option<u32>,
     last_error: Option<StructuredError>,
     pending_schedule_events: VecDeque<KernelScheduleEvent>,
     gpu_frame_completions: u64,
+    core_count: usize,
+    next_thread_id: ThreadId,
+    threads: HashMap<ThreadId, ThreadState>,
+    pending_thread_starts: VecDeque<ThreadStart>,
+    /// R0 and R1 for the guest after the last syscall, if it returns any.
+    syscall_output: Option<[u32; 2]>,
+    /// The core whose SWI is being handled.
+    syscall_core: usize,
 }
 
 impl Kernel {
@@ -91,6 +159,8 @@ impl Kernel {
                 ..ServiceRuntime::default()
             },
             vfs: VirtualFileSystem::default(),
+            core_count: 1,
+            next_thread_id: 1,
             ..Self::default()
         };
         kernel.ensure_process(KERNEL_PROCESS_ID);
@@ -110,15 +180,25 @@ impl Kernel {
     }
 
     pub fn reset_runtime(&mut self) {
+        let core_count = self.core_count;
         *self = Self::new();
+        self.core_count = core_count;
+    }
+
+    /// Number of cores threads can be scheduled on, at most [`MAX_CORES`].
+    pub fn set_core_count(&mut self, core_count: usize) {
+        self.core_count = core_count.clamp(1, MAX_CORES);
     }
 
     pub fn tick(&mut self, cycles: u32) {
         self.ticks = self.ticks.saturating_add(u64::from(cycles));
     }
 
-    pub fn handle_swi(&mut self, imm24: u32) {
-        let call = self.dispatch_syscall(1, imm24, &[]);
+    /// Handles an SWI `core` took; IPC requests it sends are answered on
+    /// that core.
+    pub fn handle_swi(&mut self, core: usize, imm24: u32, args: &[u32]) {
+        self.syscall_core = core;
+        let call = self.dispatch_syscall(1, imm24, args);
         self.svc_log.push(ServiceEvent {
             call,
             argument: imm24,
@@ -128,9 +208,28 @@ impl Kernel {
 
     pub fn dispatch_syscall(&mut self, pid: ProcessId, imm24: u32, args: &[u32]) -> ServiceCall {
         self.ensure_process(pid);
+        self.syscall_output = None;
         match imm24 {
             0x00 => ServiceCall::Yield,
             0x01 => ServiceCall::GetTick,
+            0x08 => {
+                // r0 priority, r1 entry, r2 argument, r3 stack top, r4 processor;
+                // returns the result in r0 and the thread handle in r1.
+                if let [_, entry, argument, stack_top, processor, ..] = *args {
+                    let output =
+                        match self.create_thread(pid, entry, argument, stack_top, processor as i32)
+                        {
+                            Some(thread) => [
+                                RESULT_OK,
+                                self.allocate_handle(pid, KernelObject::Thread(thread)),
+                            ],
+                            None => [RESULT_OUT_OF_RANGE, 0],
+                        };
+                    self.syscall_output = Some(output);
+                }
+                ServiceCall::CreateThread
+            }
+            0x09 => ServiceCall::ExitThread,
             0x23 => {
                 let _ = self.create_event(pid, "svc:event");
                 ServiceCall::CreateEvent
@@ -148,6 +247,10 @@ impl Kernel {
                 ServiceCall::CloseHandle
             }
             0x32 => {
+                let core = self.syscall_core;
+                if let Some(proc_state) = self.processes.get_mut(&pid) {
+                    proc_state.ipc_core = core;
+                }
                 if self
                     .processes
                     .get(&pid)
@@ -169,6 +272,91 @@ impl Kernel {
         }
     }
 
+    /// Creates a thread pinned to `processor`, or free to run on any core
+    /// for the "any"/"default" IDs. Returns `None` for a core that doesn't
+    /// exist.
+    pub fn create_thread(
+        &mut self,
+        pid: ProcessId,
+        entry: u32,
+        argument: u32,
+        stack_top: u32,
+        processor: i32,
+    ) -> Option<ThreadId> {
+        let all_cores = (1_u32 << self.core_count) - 1;
+        let (affinity_mask, ideal_core) = match processor {
+            PROCESSOR_ANY | PROCESSOR_DEFAULT => (all_cores, 0),
+            core if (0..self.core_count as i32).contains(&core) => (1 << core, core as usize),
+            _ => return None,
+        };
+        self.ensure_process(pid);
+        let thread = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.threads.insert(
+            thread,
+            ThreadState {
+                affinity_mask,
+                ideal_core,
+            },
+        );
+        self.pending_thread_starts.push_back(ThreadStart {
+            thread,
+            core: ideal_core,
+            affinity_mask,
+            entry,
+            argument,
+            stack_top,
+        });
+        Some(thread)
+    }
+
+    /// Restricts `thread` to the cores in `mask`, moving it to the lowest
+    /// allowed core if its current one is excluded.
+    pub fn set_thread_affinity(&mut self, thread: ThreadId, mask: u32) -> bool {
+        let all_cores = (1_u32 << self.core_count) - 1;
+        let Some(state) = self.threads.get_mut(&thread) else {
+            return false;
+        };
+        if mask == 0 || mask & !all_cores != 0 {
+            return false;
+        }
+        state.affinity_mask = mask;
+        if mask & (1 << state.ideal_core) == 0 {
+            state.ideal_core = mask.trailing_zeros() as usize;
+        }
+        let core = state.ideal_core;
+        for start in &mut self.pending_thread_starts {
+            if start.thread == thread {
+                start.core = core;
+                start.affinity_mask = mask;
+            }
+        }
+        true
+    }
+
+    /// Records the core a thread was started on.
+    pub fn thread_started(&mut self, thread: ThreadId, core: usize) {
+        if let Some(state) = self.threads.get_mut(&thread) {
+            state.ideal_core = core;
+        }
+    }
+
+    /// The thread's allowed-core mask and the core it is scheduled on.
+    pub fn thread_affinity(&self, thread: ThreadId) -> Option<(u32, usize)> {
+        self.threads
+            .get(&thread)
+            .map(|state| (state.affinity_mask, state.ideal_core))
+    }
+
+    /// The R0/R1 values the last syscall hands back to the guest.
+    pub fn take_syscall_output(&mut self) -> Option<[u32; 2]> {
+        self.syscall_output.take()
+    }
+
+    pub fn take_thread_starts(&mut self) -> Vec<ThreadStart> {
+        self.pending_thread_starts.drain(..).collect()
+    }
+
     pub fn take_pending_schedule_events(&mut self) -> Vec<KernelScheduleEvent> {
         self.pending_schedule_events.drain(..).collect()
     }
@@ -180,6 +368,12 @@ impl Kernel {
         self.pump_ipc_events(1);
     }
 
+    pub fn has_pending_ipc(&self) -> bool {
+        self.processes
+            .values()
+            .any(|p| !p.blocked_on_ipc && !p.pending_requests.is_empty())
+    }
+
     pub fn pump_ipc_events(&mut self, budget: usize) {
         for _ in 0..budget {
             let mut selected: Option<(ProcessId, IpcRequest)> = None;
@@ -195,10 +389,15 @@ impl Kernel {
             let Some((pid, req)) = selected else {
                 break;
             };
-            let cmd_id = req.message.command_id;
+            let command_id = req.message.command_id;
             let handle_id = req.session_handle;
             let (result_code, words) = self.dispatch_request(pid, req);
-            self.last_ipc = Some((cmd_id, handle_id, result_code));
+            self.last_ipc = Some(IpcDispatch {
+                core: self.processes.get(&pid).map_or(0, |p| p.ipc_core),
+                command_id,
+                handle_id,
+                result_code,
+            });
             if let Some(proc_state) = self.processes.get_mut(&pid) {
                 proc_state.last_result_code = result_code;
                 proc_state
@@ -268,7 +467,7 @@ impl Kernel {
         self.svc_log.len()
     }
 
-    pub fn take_last_ipc_dispatch(&mut self) -> Option<(u16, Handle, u32)> {
+    pub fn take_last_ipc_dispatch(&mut self) -> Option<IpcDispatch> {
         self.last_ipc.take()
     }
 
@@ -329,6 +528,7 @@ impl Kernel {
             KernelObject::Event(_) => KernelObjectType::Event,
             KernelObject::Archive(_) => KernelObjectType::Archive,
             KernelObject::File(_) => KernelObjectType::File,
+            KernelObject::Thread(_) => KernelObjectType::Thread,
         };
         Some(kind)
     }
@@ -602,4 +802,50 @@ mod tests {
         let failed = kernel.pop_ipc_response(pid).expect("response after close");
         assert_eq!(failed.result_code, RESULT_INVALID_HANDLE);
     }
+
+    #[test]
+    fn create_thread_syscall_honours_processor_ids() {
+        let mut kernel = Kernel::new();
+        kernel.set_core_count(2);
+        let call = kernel.dispatch_syscall(1, 0x08, &[0x30, 0x0010_0000, 5, 0x8000, 1]);
+        assert_eq!(call, ServiceCall::CreateThread);
+        kernel.dispatch_syscall(1, 0x08, &[0x30, 0x0010_0000, 6, 0x8000, 2]);
+        kernel.dispatch_syscall(1, 0x08, &[0x30, 0x0010_0000, 7, 0x8000, -2_i32 as u32]);
+
+        let starts = kernel.take_thread_starts();
+        assert_eq!(
+            starts
+                .iter()
+                .map(|s| (s.argument, s.core))
+                .collect::<Vec<_>>(),
+            [(5, 1), (7, 0)],
+            "processor 2 doesn't exist on a two-core system"
+        );
+        assert_eq!(kernel.thread_affinity(starts[0].thread), Some((0b10, 1)));
+        assert_eq!(kernel.thread_affinity(starts[1].thread), Some((0b11, 0)));
+        assert!(kernel.set_thread_affinity(starts[1].thread, 0b10));
+        assert_eq!(kernel.thread_affinity(starts[1].thread), Some((0b10, 1)));
+    }
+
+    #[test]
+    fn create_thread_syscall_returns_a_result_and_thread_handle() {
+        let mut kernel = Kernel::new();
+        kernel.set_core_count(64);
+        kernel.dispatch_syscall(1, 0x08, &[0x30, 0x0010_0000, 5, 0x8000, 3]);
+        let [result, handle] = kernel.take_syscall_output().expect("syscall output");
+        assert_eq!(result, RESULT_OK);
+        assert_eq!(
+            kernel.handle_type(1, handle),
+            Some(KernelObjectType::Thread)
+        );
+
+        kernel.dispatch_syscall(1, 0x08, &[0x30, 0x0010_0000, 5, 0x8000, 4]);
+        assert_eq!(
+            kernel.take_syscall_output(),
+            Some([RESULT_OUT_OF_RANGE, 0]),
+            "the core count is clamped to the MPCore's four cores"
+        );
+        kernel.dispatch_syscall(1, 0x00, &[]);
+        assert_eq!(kernel.take_syscall_output(), None);
+    }
 }
@@ -17,6 +17,7 @@ pub use romfs::{RomFs, RomFsFile, normalize_path};
 
 #[cfg(test)]
 mod tests {
+    use super::process::{APPLICATION_BASE, ProcessImage};
     use super::*;
     use crate::core::memory::Memory;
 
@@ -66,11 +67,19 @@ mod tests {
         assert_eq!(loaded.process.service_access, vec!["ndm:u".to_string()]);
 
         let mut mem = Memory::new();
-        install_process_image(&mut mem, &loaded.process).expect("map works");
-        assert_eq!(mem.read_u8(0x0010_0000), 0x11);
-        assert_eq!(mem.read_u8(0x0010_1000), 0x22);
-        assert_eq!(mem.read_u8(0x0010_2000), 0x33);
-        assert_eq!(mem.read_u8(0x0010_2020), 0x00);
+        let mapping = install_process_image(&mut mem, &loaded.process).expect("map works");
+        assert_eq!(mem.read_u8(APPLICATION_BASE), 0x11);
+        assert_eq!(mem.read_u8(APPLICATION_BASE + 0x1000), 0x22);
+        assert_eq!(mem.read_u8(APPLICATION_BASE + 0x2000), 0x33);
+        assert_eq!(mem.read_u8(APPLICATION_BASE + 0x2020), 0x00);
+        // One section for the image at 0x00100000, one for the stack.
+        let entry = |va: u32| mem.read_u32(mapping.page_table + (va >> 20) * 4);
+        assert_eq!(entry(0x0010_0000) & 0xFFF0_0000, APPLICATION_BASE);
+        assert_eq!(
+            entry(mapping.stack_top - 1) & 0xFFF0_0000,
+            APPLICATION_BASE + 0x0010_0000
+        );
+        assert_eq!(entry(0x0020_0000), 0);
     }
 
     #[test]
@@ -96,4 +105,38 @@ mod tests {
             Err(crate::core::error::EmulatorError::InvalidSectionLayout)
         ));
     }
+
+    #[test]
+    fn rejects_process_layouts_that_do_not_fit() {
+        let loaded = parse_process_image_from_rom(&valid_rom_fixture()).expect("valid fixture");
+        let mut mem = Memory::new();
+        let install = |mem: &mut Memory, edit: fn(&mut ProcessImage)| {
+            let mut process = loaded.process.clone();
+            edit(&mut process);
+            install_process_image(mem, &process)
+        };
+        let layout_error = Err(crate::core::error::EmulatorError::InvalidProcessLayout);
+
+        // Past the stack top, then too big to multiply into a byte count.
+        let oversized = install(&mut mem, |p| p.stack_size = 0x1000_0001);
+        assert_eq!(oversized, layout_error);
+        let overflowing = install(&mut mem, |p| p.stack_size = u32::MAX);
+        assert_eq!(overflowing, layout_error);
+        // Fits the address space but not the APPLICATION region.
+        let too_large = install(&mut mem, |p| p.stack_size = 0x0800_0000);
+        assert_eq!(too_large, layout_error);
+        // Image sections running into the stack, then into a kernel window.
+        let into_stack = install(&mut mem, |p| {
+            for segment in [&mut p.text, &mut p.ro, &mut p.data] {
+                segment.virtual_address += 0x0FE0_0000;
+            }
+        });
+        assert_eq!(into_stack, layout_error);
+        let into_window = install(&mut mem, |p| {
+            for segment in [&mut p.text, &mut p.ro, &mut p.data] {
+                segment.virtual_address += 0x1EB0_0000;
+            }
+        });
+        assert_eq!(into_window, layout_error);
+    }
 }
@@ -1,11 +1,37 @@
 use crate::core::error::{EmulatorError, Result};
 use crate::core::fs::VirtualFileSystem;
-use crate::core::memory::Memory;
+use crate::core::memory::{
+    DSP_RAM_START, FCRAM_START, IO_START, MPCORE_START, Memory, VRAM_SIZE, VRAM_START,
+};
 
 use super::exefs::ExeFs;
 use super::ncsd::{NcsdPartition, RomImage};
 use super::romfs::RomFs;
 
+/// Physical base of the FCRAM APPLICATION region, where the kernel places a
+/// title's code, data and stack.
+pub const APPLICATION_BASE: u32 = FCRAM_START;
+/// The title's first-level translation table, in the kernel's FCRAM BASE
+/// region.
+pub const PROCESS_PAGE_TABLE: u32 = FCRAM_START + 0x0700_0000;
+/// The main thread's stack grows down from here.
+pub const STACK_TOP: u32 = 0x1000_0000;
+
+const SECTION_SIZE: u32 = 1 << 20;
+const L1_TABLE_SIZE: u32 = 16 * 1024;
+/// A full-access, shareable section in domain 0.
+const SECTION_DESCRIPTOR: u32 = (1 << 16) | (0b11 << 10) | 0b10;
+/// The kernel's fixed windows onto the hardware as `(va, pa, size)`: the
+/// MPCore private region, the IO registers, VRAM, and DSP RAM with AXI WRAM.
+/// The kernel keeps them privileged; they are user-accessible here because
+/// titles run without a kernel underneath them.
+const KERNEL_WINDOWS: [(u32, u32, u32); 4] = [
+    (0x17E0_0000, MPCORE_START, SECTION_SIZE),
+    (0x1EC0_0000, IO_START + 0x0010_0000, 4 * SECTION_SIZE),
+    (0x1F00_0000, VRAM_START, VRAM_SIZE as u32),
+    (0x1FF0_0000, DSP_RAM_START, SECTION_SIZE),
+];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProcessSegment {
     pub virtual_address: u32,
@@ -25,6 +51,13 @@ pub struct ProcessImage {
     pub service_access: Vec<String>,
 }
 
+/// Where [`install_process_image`] put a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessMapping {
+    pub page_table: u32,
+    pub stack_top: u32,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TitleImageLayout {
     pub partitions: Vec<NcsdPartition>,
@@ -113,23 +146,102 @@ pub fn parse_process_image(image: &RomImage) -> Result<LoadedProcessImage> {
     })
 }
 
-pub fn install_process_image(memory: &mut Memory, image: &ProcessImage) -> Result<()> {
-    map_segment(memory, &image.text)?;
-    map_segment(memory, &image.ro)?;
-    map_segment(memory, &image.data)?;
+/// Copies the image into the APPLICATION region and builds the translation
+/// table mapping it, the stack below [`STACK_TOP`] and the kernel's hardware
+/// windows at their virtual addresses. The kernel maps titles with 4 KiB
+/// pages; 1 MiB sections give the same addresses with a much smaller table.
+/// Fails with [`EmulatorError::InvalidProcessLayout`] for a layout that
+/// doesn't fit.
+pub fn install_process_image(memory: &mut Memory, image: &ProcessImage) -> Result<ProcessMapping> {
+    let segments = [&image.text, &image.ro, &image.data];
+    let image_start = segments
+        .iter()
+        .map(|segment| segment.virtual_address)
+        .min()
+        .unwrap_or_default()
+        & !(SECTION_SIZE - 1);
+    let bss_start = image
+        .data
+        .virtual_address
+        .checked_add(image.data.size)
+        .ok_or(EmulatorError::InvalidProcessLayout)?;
+    let mut image_end = bss_start
+        .checked_add(image.bss_size)
+        .ok_or(EmulatorError::InvalidProcessLayout)?;
+    for segment in segments {
+        let end = segment
+            .virtual_address
+            .checked_add(segment.size)
+            .ok_or(EmulatorError::InvalidProcessLayout)?;
+        image_end = image_end.max(end);
+    }
+    let image_sections = (image_end - image_start).div_ceil(SECTION_SIZE);
+    let stack_sections = image.stack_size.div_ceil(SECTION_SIZE).max(1);
+    let stack_base = stack_sections
+        .checked_mul(SECTION_SIZE)
+        .and_then(|stack_bytes| STACK_TOP.checked_sub(stack_bytes))
+        .ok_or(EmulatorError::InvalidProcessLayout)?;
+    validate_layout(image_start, image_sections, stack_base, stack_sections)?;
+    let physical = |va: u32| APPLICATION_BASE.wrapping_add(va.wrapping_sub(image_start));
 
-    if image.bss_size > 0 {
-        let bss_start = image.data.virtual_address.wrapping_add(image.data.size);
-        for offset in 0..image.bss_size {
-            memory.write_u8_checked(bss_start.wrapping_add(offset), 0)?;
+    for segment in segments {
+        for (idx, b) in segment.bytes.iter().enumerate() {
+            memory.write_u8_checked(physical(segment.virtual_address) + idx as u32, *b)?;
         }
     }
-    Ok(())
+    for offset in 0..image.bss_size {
+        memory.write_u8_checked(physical(bss_start) + offset, 0)?;
+    }
+
+    for offset in (0..L1_TABLE_SIZE).step_by(4) {
+        memory.write_u32_checked(PROCESS_PAGE_TABLE + offset, 0)?;
+    }
+    let stack_base = STACK_TOP - stack_sections * SECTION_SIZE;
+    let sections = (0..image_sections)
+        .map(|i| image_start + i * SECTION_SIZE)
+        .chain((0..stack_sections).map(|i| stack_base + i * SECTION_SIZE));
+    for (i, va) in sections.enumerate() {
+        let pa = APPLICATION_BASE + i as u32 * SECTION_SIZE;
+        let entry = PROCESS_PAGE_TABLE + (va >> 20) * 4;
+        memory.write_u32_checked(entry, pa | SECTION_DESCRIPTOR)?;
+    }
+    for (va, pa, size) in KERNEL_WINDOWS {
+        for offset in (0..size).step_by(SECTION_SIZE as usize) {
+            let entry = PROCESS_PAGE_TABLE + ((va + offset) >> 20) * 4;
+            memory.write_u32_checked(entry, (pa + offset) | SECTION_DESCRIPTOR)?;
+        }
+    }
+    Ok(ProcessMapping {
+        page_table: PROCESS_PAGE_TABLE,
+        stack_top: STACK_TOP,
+    })
 }
 
-fn map_segment(memory: &mut Memory, segment: &ProcessSegment) -> Result<()> {
-    for (idx, b) in segment.bytes.iter().enumerate() {
-        memory.write_u8_checked(segment.virtual_address.wrapping_add(idx as u32), *b)?;
+/// Checks that the image and stack fit in the APPLICATION region below the
+/// page table, and that their virtual ranges miss each other and the kernel
+/// windows.
+fn validate_layout(
+    image_start: u32,
+    image_sections: u32,
+    stack_base: u32,
+    stack_sections: u32,
+) -> Result<()> {
+    let application_sections = (PROCESS_PAGE_TABLE - APPLICATION_BASE) / SECTION_SIZE;
+    if image_sections + stack_sections > application_sections {
+        return Err(EmulatorError::InvalidProcessLayout);
+    }
+    let range = |start: u32, size: u64| (u64::from(start), u64::from(start) + size);
+    let section_bytes = u64::from(SECTION_SIZE);
+    let image = range(image_start, u64::from(image_sections) * section_bytes);
+    let stack = range(stack_base, u64::from(stack_sections) * section_bytes);
+    let overlaps = |a: (u64, u64), b: (u64, u64)| a.0 < b.1 && b.0 < a.1;
+    let hits_window = |ours| {
+        KERNEL_WINDOWS
+            .iter()
+            .any(|&(va, _, size)| overlaps(ours, range(va, u64::from(size))))
+    };
+    if overlaps(image, stack) || hits_window(image) || hits_window(stack) {
+        return Err(EmulatorError::InvalidProcessLayout);
     }
     Ok(())
 }
@@ -1,46 +1,104 @@
 use super::error::{EmulatorError, Result};
 
-/// 3DS physical memory map segments represented in WASM-safe host buffers.
+/// 3DS physical memory map segments represented in WASM-safe host buffers,
+/// as the ARM11 sees them on an Old or New 3DS.
 ///
 /// Instead of trying to index a single `Vec<u8>` by raw 32-bit physical address
 /// (which fails for sparse/high addresses in WebAssembly), we maintain a list
 /// of mapped ranges and translate addresses to segment-local offsets.
 ///
 /// This keeps allocations proportional to actual mapped regions rather than
-/// the full 4 GiB physical address space.
-pub const FCRAM_START: u32 = 0x0000_0000;
+/// the full 4 GiB physical address space. Lookups go through a flat table of
+/// one byte per 4 KiB page naming the segment behind it (1 MiB in total), so
+/// an access is one index instead of a scan over the segments. Segment
+/// contents are split into 4 KiB pages allocated on their first non-zero
+/// write, so untouched FCRAM costs nothing and reads as zero.
+pub const FCRAM_START: u32 = 0x2000_0000;
 pub const FCRAM_SIZE: usize = 128 * 1024 * 1024;
+/// The New 3DS doubles FCRAM, continuing from [`FCRAM_START`].
+pub const N3DS_FCRAM_SIZE: usize = 256 * 1024 * 1024;
 
-pub const VRAM_START: u32 = 0x1F00_0000;
-pub const VRAM_SIZE: usize = 1024 * 1024;
+pub const VRAM_START: u32 = 0x1800_0000;
+pub const VRAM_SIZE: usize = 6 * 1024 * 1024;
+/// The New 3DS's additional VRAM-like block.
+pub const N3DS_VRAM_START: u32 = 0x1F00_0000;
+pub const N3DS_VRAM_SIZE: usize = 4 * 1024 * 1024;
 
-pub const IO_START: u32 = 0x1010_0000;
-pub const IO_SIZE: usize = 1024 * 1024;
+pub const IO_START: u32 = 0x1000_0000;
+pub const IO_SIZE: usize = 5 * 1024 * 1024;
 
-pub const BIOS_START: u32 = 0x1FFF_0000;
+/// MPCore private region: SCU, GIC and the private timers.
+pub const MPCORE_START: u32 = 0x17E0_0000;
+pub const MPCORE_SIZE: usize = 8 * 1024;
+
+pub const DSP_RAM_START: u32 = 0x1FF0_0000;
+pub const DSP_RAM_SIZE: usize = 512 * 1024;
+
+pub const AXI_WRAM_START: u32 = 0x1FF8_0000;
+pub const AXI_WRAM_SIZE: usize = 512 * 1024;
+
+/// The ARM11 boot ROM.
+pub const BIOS_START: u32 = 0x0000_0000;
 pub const BIOS_SIZE: usize = 64 * 1024;
 
+/// Window for a raw ROM image; hardware has no ARM11 mapping here.
 pub const ROM_START: u32 = 0x0800_0000;
 
+const PAGE_SHIFT: u32 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_COUNT: usize = 1 << (32 - PAGE_SHIFT);
+/// Page table entry for a page no segment covers.
+const UNMAPPED: u8 = u8::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum SegmentKind {
     Fcram,
     Vram,
     Io,
+    DspRam,
+    AxiWram,
     Bios,
     Rom,
 }
 
+/// Which console's physical memory map to build.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsoleModel {
+    #[default]
+    Old3ds,
+    New3ds,
+}
+
+impl ConsoleModel {
+    pub fn fcram_size(self) -> usize {
+        match self {
+            ConsoleModel::Old3ds => FCRAM_SIZE,
+            ConsoleModel::New3ds => N3DS_FCRAM_SIZE,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Segment {
     kind: SegmentKind,
     start: u32,
     end_exclusive: u32,
     writable: bool,
-    data: Vec<u8>,
+    /// Backing pages; `None` until first written and reads as zero.
+    pages: Vec<Option<Box<[u8]>>>,
 }
 
 impl Segment {
+    fn new(kind: SegmentKind, start: u32, size: usize, writable: bool) -> Self {
+        Self {
+            kind,
+            start,
+            end_exclusive: start.saturating_add(size as u32),
+            writable,
+            pages: vec![None; size.div_ceil(PAGE_SIZE)],
+        }
+    }
+
     fn contains(&self, addr: u32) -> bool {
         self.start <= addr && addr < self.end_exclusive
     }
@@ -48,11 +106,50 @@ impl Segment {
     fn offset_of(&self, addr: u32) -> usize {
         (addr - self.start) as usize
     }
+
+    fn len(&self) -> usize {
+        (self.end_exclusive - self.start) as usize
+    }
+
+    /// The `N` bytes at `offset`, unless they run past the segment or
+    /// across a page boundary.
+    fn read<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        let within = offset % PAGE_SIZE;
+        if within + N > PAGE_SIZE || offset + N > self.len() {
+            return None;
+        }
+        match &self.pages[offset / PAGE_SIZE] {
+            Some(page) => page[within..within + N].try_into().ok(),
+            None => Some([0; N]),
+        }
+    }
+
+    /// Stores `bytes` at `offset` under the same limits as [`Self::read`].
+    /// The caller checks that the segment is writable.
+    fn write<const N: usize>(&mut self, offset: usize, bytes: [u8; N]) -> bool {
+        let within = offset % PAGE_SIZE;
+        if within + N > PAGE_SIZE || offset + N > self.len() {
+            return false;
+        }
+        let page = &mut self.pages[offset / PAGE_SIZE];
+        if page.is_none() && bytes == [0; N] {
+            return true;
+        }
+        let page = page.get_or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+        page[within..within + N].copy_from_slice(&bytes);
+        true
+    }
+
+    fn resident_bytes(&self) -> usize {
+        self.pages.iter().flatten().count() * PAGE_SIZE
+    }
 }
 
 #[derive(Clone)]
 pub struct Memory {
     segments: Vec<Segment>,
+    /// Index into `segments` for every physical page, or [`UNMAPPED`].
+    page_table: Box<[u8]>,
 }
 
 impl Default for Memory {
@@ -62,74 +159,129 @@ impl Default for Memory {
 }
 
 impl Memory {
+    /// The Old 3DS memory map.
     pub fn new() -> Self {
-        let mut memory = Self { segments: vec![] };
-        memory.map_fixed_segment(SegmentKind::Fcram, FCRAM_START, FCRAM_SIZE, true);
-        memory.map_fixed_segment(SegmentKind::Vram, VRAM_START, VRAM_SIZE, true);
-        memory.map_fixed_segment(SegmentKind::Io, IO_START, IO_SIZE, true);
+        Self::with_model(ConsoleModel::Old3ds)
+    }
+
+    pub fn with_model(model: ConsoleModel) -> Self {
+        let mut memory = Self::empty();
         memory.map_fixed_segment(SegmentKind::Bios, BIOS_START, BIOS_SIZE, false);
+        memory.map_fixed_segment(SegmentKind::Io, IO_START, IO_SIZE, true);
+        memory.map_fixed_segment(SegmentKind::Io, MPCORE_START, MPCORE_SIZE, true);
+        memory.map_fixed_segment(SegmentKind::Vram, VRAM_START, VRAM_SIZE, true);
+        if model == ConsoleModel::New3ds {
+            memory.map_fixed_segment(SegmentKind::Vram, N3DS_VRAM_START, N3DS_VRAM_SIZE, true);
+        }
+        memory.map_fixed_segment(SegmentKind::DspRam, DSP_RAM_START, DSP_RAM_SIZE, true);
+        memory.map_fixed_segment(SegmentKind::AxiWram, AXI_WRAM_START, AXI_WRAM_SIZE, true);
+        memory.map_fixed_segment(SegmentKind::Fcram, FCRAM_START, model.fcram_size(), true);
+        memory
+    }
+
+    /// Plain RAM over the bottom [`FCRAM_SIZE`] bytes and nothing else, for
+    /// running CPU code with the MMU off at whatever low addresses it likes.
+    pub fn flat() -> Self {
+        let mut memory = Self::empty();
+        memory.map_fixed_segment(SegmentKind::Fcram, 0, FCRAM_SIZE, true);
         memory
     }
 
+    fn empty() -> Self {
+        Self {
+            segments: vec![],
+            page_table: vec![UNMAPPED; PAGE_COUNT].into_boxed_slice(),
+        }
+    }
+
     fn map_fixed_segment(&mut self, kind: SegmentKind, start: u32, size: usize, writable: bool) {
-        let end_exclusive = start.saturating_add(size as u32);
-        self.segments.push(Segment {
-            kind,
-            start,
-            end_exclusive,
-            writable,
-            data: vec![0; size],
-        });
+        self.segments
+            .push(Segment::new(kind, start, size, writable));
         self.segments.sort_by_key(|s| s.start);
+        self.rebuild_page_table();
     }
 
     pub fn map_rom(&mut self, rom_bytes: &[u8]) {
         self.segments.retain(|s| s.kind != SegmentKind::Rom);
-        let end_exclusive = ROM_START.saturating_add(rom_bytes.len() as u32);
-        self.segments.push(Segment {
-            kind: SegmentKind::Rom,
-            start: ROM_START,
-            end_exclusive,
-            writable: false,
-            data: rom_bytes.to_vec(),
-        });
+        let mut rom = Segment::new(SegmentKind::Rom, ROM_START, rom_bytes.len(), false);
+        for (page, chunk) in rom.pages.iter_mut().zip(rom_bytes.chunks(PAGE_SIZE)) {
+            let mut data = vec![0; PAGE_SIZE];
+            data[..chunk.len()].copy_from_slice(chunk);
+            *page = Some(data.into_boxed_slice());
+        }
+        self.segments.push(rom);
         self.segments.sort_by_key(|s| s.start);
+        self.rebuild_page_table();
+    }
+
+    fn rebuild_page_table(&mut self) {
+        self.page_table.fill(UNMAPPED);
+        for (index, segment) in self.segments.iter().enumerate() {
+            if segment.end_exclusive == segment.start {
+                continue;
+            }
+            let first = (segment.start >> PAGE_SHIFT) as usize;
+            let last = ((segment.end_exclusive - 1) >> PAGE_SHIFT) as usize;
+            self.page_table[first..=last].fill(index as u8);
+        }
     }
 
     fn find_segment_index(&self, addr: u32) -> Option<usize> {
-        self.segments
-            .iter()
-            .position(|segment| segment.contains(addr))
+        let index = self.page_table[(addr >> PAGE_SHIFT) as usize];
+        let segment = self.segments.get(usize::from(index))?;
+        segment.contains(addr).then_some(usize::from(index))
+    }
+
+    /// The `N` bytes at `addr` when one segment page holds all of them.
+    fn bytes<const N: usize>(&self, addr: u32) -> Option<[u8; N]> {
+        let segment = &self.segments[self.find_segment_index(addr)?];
+        segment.read(segment.offset_of(addr))
+    }
+
+    /// Reads byte by byte, so an access straddling two pages or segments
+    /// still works and a fault names the first unmapped byte.
+    fn read_bytes_slow<const N: usize>(&self, addr: u32) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_u8_checked(addr.wrapping_add(i as u32))?;
+        }
+        Ok(bytes)
+    }
+
+    fn read_bytes<const N: usize>(&self, addr: u32) -> Result<[u8; N]> {
+        match self.bytes(addr) {
+            Some(bytes) => Ok(bytes),
+            None => self.read_bytes_slow(addr),
+        }
+    }
+
+    fn write_bytes<const N: usize>(&mut self, addr: u32, bytes: [u8; N]) -> Result<()> {
+        if let Some(index) = self.find_segment_index(addr) {
+            let segment = &mut self.segments[index];
+            if !segment.writable {
+                return Err(EmulatorError::ReadOnlyMemory { address: addr });
+            }
+            if segment.write(segment.offset_of(addr), bytes) {
+                return Ok(());
+            }
+        }
+        if N == 1 {
+            return Err(EmulatorError::MemoryOutOfBounds { address: addr });
+        }
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.write_u8_checked(addr.wrapping_add(i as u32), byte)?;
+        }
+        Ok(())
     }
 
     pub(crate) fn read_u8_checked(&self, addr: u32) -> Result<u8> {
-        let idx = self
-            .find_segment_index(addr)
-            .ok_or(EmulatorError::MemoryOutOfBounds { address: addr })?;
-        let segment = &self.segments[idx];
-        let offset = segment.offset_of(addr);
-        segment
-            .data
-            .get(offset)
-            .copied()
+        self.bytes::<1>(addr)
+            .map(|[byte]| byte)
             .ok_or(EmulatorError::MemoryOutOfBounds { address: addr })
     }
 
     pub(crate) fn write_u8_checked(&mut self, addr: u32, value: u8) -> Result<()> {
-        let idx = self
-            .find_segment_index(addr)
-            .ok_or(EmulatorError::MemoryOutOfBounds { address: addr })?;
-        let segment = &mut self.segments[idx];
-        if !segment.writable {
-            return Ok(());
-        }
-        let offset = segment.offset_of(addr);
-        let slot = segment
-            .data
-            .get_mut(offset)
-            .ok_or(EmulatorError::MemoryOutOfBounds { address: addr })?;
-        *slot = value;
-        Ok(())
+        self.write_bytes(addr, [value])
     }
 
     /// Read one byte via address-mapper translation.
@@ -142,28 +294,30 @@ impl Memory {
 
     /// Write one byte via address-mapper translation.
     ///
-    /// Writes to unmapped/read-only regions are ignored to preserve safety.
+    /// Writes to unmapped/read-only regions are ignored; use
+    /// [`Self::write_u8_checked`] to see them fail.
     pub fn write_u8(&mut self, addr: u32, value: u8) {
         let _ = self.write_u8_checked(addr, value);
     }
 
+    /// Read a little-endian 16-bit value through mapped segments.
+    pub fn read_u16_checked(&self, addr: u32) -> Result<u16> {
+        self.read_bytes(addr).map(u16::from_le_bytes)
+    }
+
+    /// Write a little-endian 16-bit value through mapped segments.
+    pub fn write_u16_checked(&mut self, addr: u32, value: u16) -> Result<()> {
+        self.write_bytes(addr, value.to_le_bytes())
+    }
+
     /// Read a little-endian 32-bit value through mapped segments.
     pub fn read_u32_checked(&self, addr: u32) -> Result<u32> {
-        let b0 = self.read_u8_checked(addr)?;
-        let b1 = self.read_u8_checked(addr.wrapping_add(1))?;
-        let b2 = self.read_u8_checked(addr.wrapping_add(2))?;
-        let b3 = self.read_u8_checked(addr.wrapping_add(3))?;
-        Ok(u32::from_le_bytes([b0, b1, b2, b3]))
+        self.read_bytes(addr).map(u32::from_le_bytes)
     }
 
     /// Write a little-endian 32-bit value through mapped segments.
     pub fn write_u32_checked(&mut self, addr: u32, value: u32) -> Result<()> {
-        let bytes = value.to_le_bytes();
-        self.write_u8_checked(addr, bytes[0])?;
-        self.write_u8_checked(addr.wrapping_add(1), bytes[1])?;
-        self.write_u8_checked(addr.wrapping_add(2), bytes[2])?;
-        self.write_u8_checked(addr.wrapping_add(3), bytes[3])?;
-        Ok(())
+        self.write_bytes(addr, value.to_le_bytes())
     }
 
     /// Read a little-endian 32-bit value through mapped segments.
@@ -178,15 +332,121 @@ impl Memory {
         let _ = self.write_u32_checked(addr, value);
     }
 
+    /// Zeroes RAM by releasing its pages.
     pub fn clear_writable(&mut self) {
         for segment in &mut self.segments {
             if segment.writable {
-                segment.data.fill(0);
+                segment.pages.fill(None);
             }
         }
     }
 
+    /// Bytes of address space backed by a segment, allocated or not.
     pub fn len_mapped_bytes(&self) -> usize {
-        self.segments.iter().map(|s| s.data.len()).sum()
+        self.segments.iter().map(Segment::len).sum()
+    }
+
+    /// Bytes of backing pages actually allocated.
+    pub fn resident_bytes(&self) -> usize {
+        self.segments.iter().map(Segment::resident_bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_table_routes_accesses_to_their_segments() {
+        let mut memory = Memory::new();
+        memory.map_rom(&[0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert_eq!(memory.read_u32_checked(ROM_START), Ok(0x4433_2211));
+        // ROM rejects writes; the tail past its last byte is unmapped.
+        assert_eq!(
+            memory.write_u16_checked(ROM_START, 0xFFFF),
+            Err(EmulatorError::ReadOnlyMemory { address: ROM_START })
+        );
+        assert_eq!(memory.read_u16_checked(ROM_START), Ok(0x2211));
+        assert_eq!(
+            memory.read_u32_checked(ROM_START + 2),
+            Err(EmulatorError::MemoryOutOfBounds {
+                address: ROM_START + 5
+            })
+        );
+
+        assert_eq!(
+            memory.write_u32_checked(VRAM_START + 0x1FFE, 0xAABB_CCDD),
+            Ok(())
+        );
+        assert_eq!(memory.read_u16_checked(VRAM_START + 0x2000), Ok(0xAABB));
+        assert_eq!(
+            memory.read_u8_checked(IO_START - 1),
+            Err(EmulatorError::MemoryOutOfBounds {
+                address: IO_START - 1
+            })
+        );
+    }
+
+    #[test]
+    fn console_model_selects_the_memory_map() {
+        let old = Memory::with_model(ConsoleModel::Old3ds);
+        let new = Memory::with_model(ConsoleModel::New3ds);
+        let fcram_end = FCRAM_START + FCRAM_SIZE as u32;
+        for addr in [
+            IO_START,
+            MPCORE_START,
+            DSP_RAM_START,
+            AXI_WRAM_START,
+            FCRAM_START,
+        ] {
+            assert_eq!(old.read_u32_checked(addr), Ok(0), "{addr:#010X}");
+        }
+        assert!(old.read_u8_checked(fcram_end).is_err());
+        assert!(old.read_u8_checked(N3DS_VRAM_START).is_err());
+        assert_eq!(new.read_u8_checked(fcram_end), Ok(0));
+        assert_eq!(new.read_u8_checked(N3DS_VRAM_START), Ok(0));
+        assert_eq!(
+            new.len_mapped_bytes() - old.len_mapped_bytes(),
+            N3DS_FCRAM_SIZE - FCRAM_SIZE + N3DS_VRAM_SIZE
+        );
+    }
+
+    #[test]
+    fn accesses_straddling_adjacent_segments_fall_back_to_bytes() {
+        // Flat RAM ends exactly where the ROM window starts.
+        let mut memory = Memory::flat();
+        memory.map_rom(&[0xAA; 4]);
+        memory.write_u8(ROM_START - 1, 0x12);
+        assert_eq!(memory.read_u32_checked(ROM_START - 2), Ok(0xAAAA_1200));
+    }
+
+    #[test]
+    fn pages_are_allocated_on_first_non_zero_write_and_released_on_clear() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.resident_bytes(), 0);
+        assert_eq!(
+            memory.len_mapped_bytes(),
+            BIOS_SIZE
+                + IO_SIZE
+                + MPCORE_SIZE
+                + VRAM_SIZE
+                + DSP_RAM_SIZE
+                + AXI_WRAM_SIZE
+                + FCRAM_SIZE
+        );
+
+        let page = FCRAM_START + 0x0100_0000;
+        memory.write_u32(page, 0);
+        memory.write_u8(BIOS_START, 0xFF);
+        assert_eq!(memory.resident_bytes(), 0);
+
+        memory.write_u32(page + 0xFFE, 0x1234_5678);
+        assert_eq!(memory.resident_bytes(), 2 * PAGE_SIZE);
+        assert_eq!(memory.read_u32(page + 0xFFE), 0x1234_5678);
+        assert_eq!(memory.read_u32(page), 0);
+
+        memory.clear_writable();
+        assert_eq!(memory.resident_bytes(), 0);
+        assert_eq!(memory.read_u32(page + 0xFFE), 0);
     }
 }
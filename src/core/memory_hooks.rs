@@ -0,0 +1,185 @@
+use super::bus::AccessWidth;
+use super::cpu::AddressSpace;
+use super::error::MemoryAccessKind;
+
+/// One completed load or store by a core. `value` is what crossed the bus,
+/// after any CPSR E-bit byte reversal of the register value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub core: usize,
+    /// Address of the instruction making the access.
+    pub pc: u32,
+    pub va: u32,
+    pub pa: u32,
+    pub width: AccessWidth,
+    pub value: u64,
+    pub kind: MemoryAccessKind,
+    pub cycle: u64,
+}
+
+/// Observes `len` bytes of `space` from `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookRange {
+    pub start: u32,
+    pub len: u32,
+    pub space: AddressSpace,
+}
+
+impl HookRange {
+    fn overlaps(&self, access: &MemoryAccess) -> bool {
+        let address = match self.space {
+            AddressSpace::Virtual => access.va,
+            AddressSpace::Physical => access.pa,
+        };
+        address.wrapping_sub(self.start) < self.len.max(1)
+            || self.start.wrapping_sub(address) < access.width.bytes()
+    }
+}
+
+/// Receives the accesses overlapping the ranges it was registered with.
+pub trait MemoryHook {
+    fn on_access(&mut self, access: &MemoryAccess);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryHookId(u32);
+
+struct RegisteredHook {
+    id: MemoryHookId,
+    /// Empty observes every access.
+    ranges: Vec<HookRange>,
+    hook: Box<dyn MemoryHook>,
+}
+
+fn matches(ranges: &[HookRange], access: &MemoryAccess) -> bool {
+    ranges.is_empty() || ranges.iter().any(|range| range.overlaps(access))
+}
+
+/// The hooks registered on a bus, plus the accesses kept for the emulator's
+/// `TraceCategory::MemoryAccess` ring buffer.
+///
+/// Cores only build a [`MemoryAccess`] while [`Self::is_active`], so an
+/// emulator without hooks pays one check per access.
+#[derive(Default)]
+pub struct MemoryHooks {
+    hooks: Vec<RegisteredHook>,
+    next_id: u32,
+    trace_ranges: Option<Vec<HookRange>>,
+    traced: Vec<MemoryAccess>,
+    cycle: u64,
+}
+
+impl MemoryHooks {
+    pub fn is_active(&self) -> bool {
+        !self.hooks.is_empty() || self.trace_ranges.is_some()
+    }
+
+    pub fn add(&mut self, ranges: Vec<HookRange>, hook: Box<dyn MemoryHook>) -> MemoryHookId {
+        let id = MemoryHookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(RegisteredHook { id, ranges, hook });
+        id
+    }
+
+    pub fn remove(&mut self, id: MemoryHookId) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|registered| registered.id != id);
+        self.hooks.len() != before
+    }
+
+    /// Starts keeping the accesses overlapping `ranges` for
+    /// [`Self::take_traced`], or stops with `None`.
+    pub fn set_trace(&mut self, ranges: Option<Vec<HookRange>>) {
+        self.trace_ranges = ranges;
+        if self.trace_ranges.is_none() {
+            self.traced.clear();
+        }
+    }
+
+    pub fn take_traced(&mut self) -> Vec<MemoryAccess> {
+        std::mem::take(&mut self.traced)
+    }
+
+    /// The emulator clock stamped on accesses from here on.
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    pub fn dispatch(&mut self, mut access: MemoryAccess) {
+        access.cycle = self.cycle;
+        for registered in &mut self.hooks {
+            if matches(&registered.ranges, &access) {
+                registered.hook.on_access(&access);
+            }
+        }
+        if let Some(ranges) = &self.trace_ranges
+            && matches(ranges, &access)
+        {
+            self.traced.push(access);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    struct Collect(Rc<RefCell<Vec<u32>>>);
+
+    impl MemoryHook for Collect {
+        fn on_access(&mut self, access: &MemoryAccess) {
+            self.0.borrow_mut().push(access.va);
+        }
+    }
+
+    fn access(va: u32, pa: u32, width: AccessWidth) -> MemoryAccess {
+        MemoryAccess {
+            core: 0,
+            pc: 0x0010_0000,
+            va,
+            pa,
+            width,
+            value: 0,
+            kind: MemoryAccessKind::Read,
+            cycle: 0,
+        }
+    }
+
+    #[test]
+    fn hooks_see_only_accesses_overlapping_their_ranges() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut hooks = MemoryHooks::default();
+        assert!(!hooks.is_active());
+        let id = hooks.add(
+            vec![HookRange {
+                start: 0x2000_0004,
+                len: 4,
+                space: AddressSpace::Physical,
+            }],
+            Box::new(Collect(Rc::clone(&seen))),
+        );
+        hooks.set_trace(Some(vec![HookRange {
+            start: 0x0010_0000,
+            len: 0x1000,
+            space: AddressSpace::Virtual,
+        }]));
+        hooks.set_cycle(42);
+
+        hooks.dispatch(access(0x0010_0000, 0x2000_0000, AccessWidth::Word));
+        hooks.dispatch(access(0x0010_0002, 0x2000_0002, AccessWidth::Word));
+        hooks.dispatch(access(0x0010_0007, 0x2000_0007, AccessWidth::Byte));
+        hooks.dispatch(access(0x0020_0008, 0x2000_0008, AccessWidth::Double));
+        assert_eq!(*seen.borrow(), [0x0010_0002, 0x0010_0007]);
+        let traced = hooks.take_traced();
+        assert_eq!(traced.len(), 3);
+        assert!(traced.iter().all(|access| access.cycle == 42));
+
+        assert!(hooks.remove(id));
+        assert!(!hooks.remove(id));
+        hooks.set_trace(None);
+        assert!(!hooks.is_active());
+    }
+}
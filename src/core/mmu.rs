@@ -1,38 +1,92 @@
-use std::collections::HashMap;
+//! ARMv6 virtual memory: the TTBR0/TTBR1 split by TTBCR.N, first-level
+//! sections, supersections and coarse tables, and second-level large, small
+//! and extended small pages.
+//!
+//! Second-level descriptors use the ARMv6 format when SCTLR.XP is set and
+//! the backwards-compatible one, with per-subpage AP, otherwise. First-level
+//! descriptors are always read in the ARMv6 format. TEX/C/B only decide
+//! shareability, which scopes exclusive accesses; there is no cache model for
+//! the other attributes to drive.
+
+mod tlb;
 
 use super::bus::Bus;
 use super::error::{EmulatorError, MemoryAccessKind, Result};
+use tlb::{MAIN_TLB_ENTRIES, MICRO_TLB_ENTRIES, Tlb, TlbEntry};
 
-const SECTION_DESCRIPTOR_MASK: u32 = 0b11;
-const SECTION_DESCRIPTOR_VALUE: u32 = 0b10;
-const SECTION_BASE_MASK: u32 = 0xFFF0_0000;
-const SECTION_VA_MASK: u32 = 0xFFF0_0000;
-const SECTION_OFFSET_MASK: u32 = 0x000F_FFFF;
+pub use tlb::TlbStats;
+
+const DESCRIPTOR_TYPE_MASK: u32 = 0b11;
+const L1_COARSE: u32 = 0b01;
+const L1_SECTION: u32 = 0b10;
+const L1_SUPERSECTION: u32 = 1 << 18;
+const L2_LARGE: u32 = 0b01;
+const L2_SMALL: u32 = 0b10;
+const SECTION_NG: u32 = 1 << 17;
+const PAGE_NG: u32 = 1 << 11;
 
+const SECTION_BASE_MASK: u32 = 0xFFF0_0000;
+const SUPERSECTION_BASE_MASK: u32 = 0xFF00_0000;
+const COARSE_BASE_MASK: u32 = 0xFFFF_FC00;
+const LARGE_PAGE_BASE_MASK: u32 = 0xFFFF_0000;
+const SMALL_PAGE_BASE_MASK: u32 = 0xFFFF_F000;
+const TTBR1_BASE_MASK: u32 = 0xFFFF_C000;
+
+const SECTION_SHIFT: u8 = 20;
+const SUPERSECTION_SHIFT: u8 = 24;
+const LARGE_PAGE_SHIFT: u8 = 16;
+const SMALL_PAGE_SHIFT: u8 = 12;
+const CONTROL_XP: u32 = 1 << 23;
+const TTBCR_N_MASK: u32 = 0x7;
+const TTBCR_PD0: u32 = 1 << 4;
+const TTBCR_PD1: u32 = 1 << 5;
+
+/// Where the last failed translation stopped, for the fault status
+/// registers.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct TlbEntry {
-    pa_section: u32,
-    domain: u8,
-    ap: u8,
-    apx: bool,
-    execute_never: bool,
+pub struct FaultLocation {
+    /// The fault came from a second-level (page) mapping.
+    pub page: bool,
+    pub domain: u8,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct AccessFlags {
-    ap: u8,
-    apx: bool,
-    execute_never: bool,
+/// TEX/C/B/S of a descriptor, without TEX remapping.
+#[derive(Debug, Clone, Copy)]
+struct MemoryAttributes {
+    tex: u32,
+    c: bool,
+    b: bool,
+    s: bool,
+}
+
+impl MemoryAttributes {
+    /// Strongly-ordered and shared Device memory are always shareable,
+    /// non-shared Device never, and Normal memory when S is set.
+    fn shareable(self) -> bool {
+        match (self.tex, self.c, self.b) {
+            (0, false, _) => true,
+            (2, false, false) => false,
+            _ => self.s,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Mmu {
     control: u32,
     ttbr0: u32,
+    ttbr1: u32,
+    ttbcr: u32,
     dacr: u32,
     icache_enabled: bool,
     dcache_enabled: bool,
-    tlb: HashMap<u32, TlbEntry>,
+    main_tlb: Tlb,
+    micro_itlb: Tlb,
+    micro_dtlb: Tlb,
+    /// ASID from CONTEXTIDR, tagging non-global TLB entries.
+    asid: u8,
+    tlb_stats: TlbStats,
+    last_fault: Option<FaultLocation>,
 }
 
 impl Default for Mmu {
@@ -46,54 +100,101 @@ impl Mmu {
         Self {
             control: 0,
             ttbr0: 0,
+            ttbr1: 0,
+            ttbcr: 0,
             dacr: 0,
             icache_enabled: false,
             dcache_enabled: false,
-            tlb: HashMap::new(),
+            main_tlb: Tlb::new(MAIN_TLB_ENTRIES),
+            micro_itlb: Tlb::new(MICRO_TLB_ENTRIES),
+            micro_dtlb: Tlb::new(MICRO_TLB_ENTRIES),
+            asid: 0,
+            tlb_stats: TlbStats::default(),
+            last_fault: None,
         }
     }
 
     pub fn reset(&mut self) {
-        self.control = 0;
-        self.ttbr0 = 0;
-        self.dacr = 0;
-        self.icache_enabled = false;
-        self.dcache_enabled = false;
-        self.tlb.clear();
+        *self = Self::new();
     }
 
     pub fn write_control(&mut self, value: u32) {
         let old_enabled = self.mmu_enabled();
         let old_icache = self.icache_enabled;
         let old_dcache = self.dcache_enabled;
+        let old_xp = self.control & CONTROL_XP;
         self.control = value;
         self.icache_enabled = (value & (1 << 12)) != 0;
         self.dcache_enabled = (value & (1 << 2)) != 0;
         if old_enabled != self.mmu_enabled()
             || old_icache != self.icache_enabled
             || old_dcache != self.dcache_enabled
+            || old_xp != value & CONTROL_XP
         {
             self.invalidate_tlb();
         }
     }
 
+    /// Like the hardware, none of the table base writes flush the TLB:
+    /// non-global entries are told apart by ASID and the guest invalidates
+    /// the rest itself.
     pub fn write_ttbr0(&mut self, value: u32) {
-        self.ttbr0 = value & 0xFFFF_C000;
-        self.invalidate_tlb();
+        self.ttbr0 = value;
     }
 
+    pub fn write_ttbr1(&mut self, value: u32) {
+        self.ttbr1 = value;
+    }
+
+    pub fn write_ttbcr(&mut self, value: u32) {
+        self.ttbcr = value;
+    }
+
+    /// Domains are checked on every lookup, so cached entries stay valid.
     pub fn write_dacr(&mut self, value: u32) {
         self.dacr = value;
-        self.invalidate_tlb();
+    }
+
+    /// Switches the ASID that new and matching TLB entries are tagged with;
+    /// like the hardware, this doesn't flush the TLB.
+    pub fn write_context_id(&mut self, value: u32) {
+        self.asid = value as u8;
     }
 
     pub fn invalidate_tlb(&mut self) {
-        self.tlb.clear();
+        self.main_tlb.clear();
+        self.micro_itlb.clear();
+        self.micro_dtlb.clear();
+    }
+
+    /// Drops every entry covering `va`, whatever its ASID.
+    pub fn invalidate_tlb_va(&mut self, va: u32) {
+        self.retain_tlb_entries(|entry| !entry.covers(va));
+    }
+
+    /// Drops the non-global entries tagged with `asid`.
+    pub fn invalidate_tlb_asid(&mut self, asid: u8) {
+        self.retain_tlb_entries(|entry| entry.global || entry.asid != asid);
+    }
+
+    /// Drops the entries covering `va` that `asid` would match.
+    pub fn invalidate_tlb_va_asid(&mut self, va: u32, asid: u8) {
+        self.retain_tlb_entries(|entry| !entry.matches(va, asid));
+    }
+
+    fn retain_tlb_entries(&mut self, keep: impl Fn(&TlbEntry) -> bool) {
+        self.main_tlb.retain(&keep);
+        self.micro_itlb.retain(&keep);
+        self.micro_dtlb.retain(&keep);
+    }
+
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.tlb_stats
     }
 
     #[cfg(test)]
     pub fn tlb_len(&self) -> usize {
-        self.tlb.len()
+        self.main_tlb.len()
     }
 
     pub fn mmu_enabled(&self) -> bool {
@@ -108,6 +209,22 @@ impl Mmu {
         self.dcache_enabled
     }
 
+    /// Level and domain of the most recent translation fault.
+    pub fn last_fault(&self) -> Option<FaultLocation> {
+        self.last_fault
+    }
+
+    /// Whether `va`, translated successfully just before, is shareable.
+    /// With the MMU off everything is Strongly-ordered, hence shareable.
+    pub fn shareable(&self, va: u32) -> bool {
+        !self.mmu_enabled()
+            || self
+                .micro_dtlb
+                .lookup(va, self.asid)
+                .or_else(|| self.main_tlb.lookup(va, self.asid))
+                .is_none_or(|entry| entry.shareable)
+    }
+
     pub fn translate_instruction(
         &mut self,
         memory: &mut dyn Bus,
@@ -146,45 +263,202 @@ impl Mmu {
             return Ok(va);
         }
 
-        let section_key = va & SECTION_VA_MASK;
-        let entry = if let Some(entry) = self.tlb.get(&section_key).copied() {
+        let asid = self.asid;
+        let micro_hit = match access {
+            MemoryAccessKind::Execute => self.micro_itlb.lookup(va, asid),
+            _ => self.micro_dtlb.lookup(va, asid),
+        };
+        let entry = if let Some(entry) = micro_hit {
+            self.tlb_stats.micro_hits += 1;
             entry
         } else {
-            let table_index = (va >> 20) * 4;
-            let desc_addr = self.ttbr0.wrapping_add(table_index);
-            let descriptor = memory.read_u32_checked(desc_addr)?;
-
-            if descriptor & SECTION_DESCRIPTOR_MASK != SECTION_DESCRIPTOR_VALUE {
-                return Err(EmulatorError::MmuTranslationFault {
-                    pc: 0,
-                    va,
-                    pa: None,
-                    access,
-                });
+            if access != MemoryAccessKind::Execute {
+                self.tlb_stats.data_micro_misses += 1;
             }
-
-            let entry = TlbEntry {
-                pa_section: descriptor & SECTION_BASE_MASK,
-                domain: ((descriptor >> 5) & 0xF) as u8,
-                ap: ((descriptor >> 10) & 0x3) as u8,
-                apx: ((descriptor >> 15) & 1) != 0,
-                execute_never: ((descriptor >> 4) & 1) != 0,
+            let entry = if let Some(entry) = self.main_tlb.lookup(va, asid) {
+                self.tlb_stats.main_hits += 1;
+                entry
+            } else {
+                self.tlb_stats.misses += 1;
+                let entry = self.walk(memory, va, access)?;
+                self.main_tlb.insert(entry);
+                entry
             };
-            self.tlb.insert(section_key, entry);
+            match access {
+                MemoryAccessKind::Execute => self.micro_itlb.insert(entry),
+                _ => self.micro_dtlb.insert(entry),
+            }
             entry
         };
 
         self.check_domain_and_permissions(va, entry, access, privileged)?;
-        Ok(entry.pa_section | (va & SECTION_OFFSET_MASK))
+        let offset_mask = (1_u32 << entry.size_shift) - 1;
+        Ok(entry.pa_base | (va & offset_mask))
+    }
+
+    /// The first-level table for `va` and whether walks through it are
+    /// disabled by TTBCR.PD0/PD1.
+    fn first_level_table(&self, va: u32) -> (u32, bool) {
+        let n = self.ttbcr & TTBCR_N_MASK;
+        if n > 0 && va >> (32 - n) != 0 {
+            (self.ttbr1 & TTBR1_BASE_MASK, self.ttbcr & TTBCR_PD1 != 0)
+        } else {
+            let base_mask = !((1_u32 << (14 - n)) - 1);
+            (self.ttbr0 & base_mask, self.ttbcr & TTBCR_PD0 != 0)
+        }
+    }
+
+    fn walk(
+        &mut self,
+        memory: &mut dyn Bus,
+        va: u32,
+        access: MemoryAccessKind,
+    ) -> Result<TlbEntry> {
+        let (table, walk_disabled) = self.first_level_table(va);
+        let section_fault = FaultLocation {
+            page: false,
+            domain: 0,
+        };
+        if walk_disabled {
+            return Err(self.translation_fault(va, access, section_fault));
+        }
+        let descriptor = memory.fetch_u32(table.wrapping_add((va >> 20) * 4))?;
+        let domain = ((descriptor >> 5) & 0xF) as u8;
+
+        match descriptor & DESCRIPTOR_TYPE_MASK {
+            L1_SECTION => {
+                let supersection = descriptor & L1_SUPERSECTION != 0;
+                let (pa_base, size_shift, domain) = if supersection {
+                    (descriptor & SUPERSECTION_BASE_MASK, SUPERSECTION_SHIFT, 0)
+                } else {
+                    (descriptor & SECTION_BASE_MASK, SECTION_SHIFT, domain)
+                };
+                let attributes = MemoryAttributes {
+                    tex: (descriptor >> 12) & 0x7,
+                    c: descriptor & (1 << 3) != 0,
+                    b: descriptor & (1 << 2) != 0,
+                    s: descriptor & (1 << 16) != 0,
+                };
+                Ok(TlbEntry {
+                    va_base: va & !((1_u32 << size_shift) - 1),
+                    pa_base,
+                    size_shift,
+                    domain,
+                    subpage_ap: uniform_ap(descriptor >> 10),
+                    subpage_shift: size_shift,
+                    apx: descriptor & (1 << 15) != 0,
+                    execute_never: descriptor & (1 << 4) != 0,
+                    shareable: attributes.shareable(),
+                    global: descriptor & SECTION_NG == 0,
+                    asid: self.asid,
+                })
+            }
+            L1_COARSE => {
+                let table = descriptor & COARSE_BASE_MASK;
+                let descriptor = memory.fetch_u32(table | (((va >> 12) & 0xFF) * 4))?;
+                self.second_level_entry(va, descriptor, domain)
+                    .ok_or_else(|| {
+                        self.translation_fault(va, access, FaultLocation { page: true, domain })
+                    })
+            }
+            _ => Err(self.translation_fault(va, access, section_fault)),
+        }
+    }
+
+    fn second_level_entry(&self, va: u32, descriptor: u32, domain: u8) -> Option<TlbEntry> {
+        let kind = descriptor & DESCRIPTOR_TYPE_MASK;
+        if kind == 0 {
+            return None;
+        }
+        let large = kind == L2_LARGE;
+        let size_shift = if large {
+            LARGE_PAGE_SHIFT
+        } else {
+            SMALL_PAGE_SHIFT
+        };
+        let pa_base = descriptor
+            & if large {
+                LARGE_PAGE_BASE_MASK
+            } else {
+                SMALL_PAGE_BASE_MASK
+            };
+        let c = descriptor & (1 << 3) != 0;
+        let b = descriptor & (1 << 2) != 0;
+        let mut entry = TlbEntry {
+            va_base: va & !((1_u32 << size_shift) - 1),
+            pa_base,
+            size_shift,
+            domain,
+            subpage_ap: uniform_ap(descriptor >> 4),
+            subpage_shift: size_shift,
+            apx: false,
+            execute_never: false,
+            shareable: false,
+            global: true,
+            asid: self.asid,
+        };
+        let tex = if large {
+            (descriptor >> 12) & 0x7
+        } else {
+            (descriptor >> 6) & 0x7
+        };
+
+        if self.control & CONTROL_XP != 0 {
+            entry.apx = descriptor & (1 << 9) != 0;
+            entry.execute_never = if large {
+                descriptor & (1 << 15) != 0
+            } else {
+                descriptor & 1 != 0
+            };
+            let s = descriptor & (1 << 10) != 0;
+            entry.global = descriptor & PAGE_NG == 0;
+            entry.shareable = MemoryAttributes { tex, c, b, s }.shareable();
+        } else {
+            let subpages = kind == L2_SMALL || large;
+            if subpages {
+                // AP0-AP3 cover 1 KiB subpages of a small page or 16 KiB
+                // subpages of a large one.
+                entry.subpage_ap = ((descriptor >> 4) & 0xFF) as u8;
+                entry.subpage_shift = if large { 14 } else { 10 };
+            }
+            let tex = if kind == L2_SMALL { 0 } else { tex };
+            entry.shareable = MemoryAttributes {
+                tex,
+                c,
+                b,
+                s: false,
+            }
+            .shareable();
+        }
+        Some(entry)
+    }
+
+    fn translation_fault(
+        &mut self,
+        va: u32,
+        access: MemoryAccessKind,
+        location: FaultLocation,
+    ) -> EmulatorError {
+        self.last_fault = Some(location);
+        EmulatorError::MmuTranslationFault {
+            pc: 0,
+            va,
+            pa: None,
+            access,
+        }
     }
 
     fn check_domain_and_permissions(
-        &self,
+        &mut self,
         va: u32,
         entry: TlbEntry,
         access: MemoryAccessKind,
         privileged: bool,
     ) -> Result<()> {
+        self.last_fault = Some(FaultLocation {
+            page: entry.is_page(),
+            domain: entry.domain,
+        });
         let domain_mode = (self.dacr >> (u32::from(entry.domain) * 2)) & 0b11;
         match domain_mode {
             0b00 | 0b10 => {
@@ -210,25 +484,14 @@ impl Mmu {
             });
         }
 
-        let flags = AccessFlags {
-            ap: entry.ap,
-            apx: entry.apx,
-            execute_never: entry.execute_never,
-        };
-        let allowed = match flags.ap {
-            0b00 => false,
-            0b01 => privileged,
-            0b10 => {
-                privileged || matches!(access, MemoryAccessKind::Read | MemoryAccessKind::Execute)
-            }
-            0b11 => {
-                if flags.apx {
-                    !matches!(access, MemoryAccessKind::Write)
-                } else {
-                    true
-                }
-            }
-            _ => false,
+        let write = matches!(access, MemoryAccessKind::Write);
+        let allowed = match (entry.apx, entry.ap(va)) {
+            (_, 0b00) => false,
+            (false, 0b01) => privileged,
+            (false, 0b10) => privileged || !write,
+            (false, _) => true,
+            (true, 0b01) => privileged && !write,
+            (true, _) => !write,
         };
 
         if !allowed {
@@ -244,18 +507,23 @@ impl Mmu {
     }
 }
 
+/// Replicates a 2-bit AP field across all four subpages.
+fn uniform_ap(ap: u32) -> u8 {
+    ((ap & 0b11) * 0b0101_0101) as u8
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::core::memory::Memory;
 
     fn section_desc(pa_base: u32, domain: u32, ap: u32) -> u32 {
-        (pa_base & SECTION_BASE_MASK) | (domain << 5) | (ap << 10) | SECTION_DESCRIPTOR_VALUE
+        (pa_base & SECTION_BASE_MASK) | (domain << 5) | (ap << 10) | L1_SECTION
     }
 
     #[test]
     fn translates_mapped_section_and_caches_tlb() {
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, section_desc(0x0800_0000, 0, 0b11))
             .unwrap_or_else(|e| panic!("write descriptor: {e}"));
@@ -272,9 +540,33 @@ mod tests {
         assert_eq!(mmu.tlb_len(), 1);
     }
 
+    #[test]
+    fn ttbr0_and_dacr_writes_keep_the_tlb() {
+        let mut memory = Memory::flat();
+        memory
+            .write_u32_checked(0x0000_4000, section_desc(0x0800_0000, 0, 0b11))
+            .unwrap_or_else(|e| panic!("write descriptor: {e}"));
+
+        let mut mmu = Mmu::new();
+        mmu.write_ttbr0(0x0000_4000);
+        mmu.write_dacr(0b01);
+        mmu.write_control(1);
+        mmu.translate(&mut memory, 0x0000_1234, MemoryAccessKind::Read, false)
+            .unwrap_or_else(|e| panic!("translation should succeed: {e}"));
+
+        mmu.write_ttbr0(0x0000_8000);
+        mmu.write_dacr(0);
+        assert_eq!(mmu.tlb_len(), 1);
+        let err = mmu
+            .translate(&mut memory, 0x0000_1234, MemoryAccessKind::Read, false)
+            .err()
+            .unwrap_or_else(|| panic!("expected domain fault from the cached entry"));
+        assert!(matches!(err, EmulatorError::MmuDomainFault { .. }));
+    }
+
     #[test]
     fn faults_on_unmapped_section() {
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         let mut mmu = Mmu::new();
         mmu.write_ttbr0(0x0000_4000);
         mmu.write_dacr(0b01);
@@ -297,7 +589,7 @@ mod tests {
 
     #[test]
     fn enforces_privileged_permission_for_ap01() {
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, section_desc(0x0010_0000, 0, 0b01))
             .unwrap_or_else(|e| panic!("write descriptor: {e}"));
@@ -329,7 +621,7 @@ mod tests {
 
     #[test]
     fn execute_never_section_faults_on_instruction_fetch() {
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, section_desc(0x0010_0000, 0, 0b11) | (1 << 4))
             .unwrap_or_else(|e| panic!("write descriptor: {e}"));
@@ -370,7 +662,7 @@ mod tests {
 
     #[test]
     fn apx_read_only_section_rejects_writes() {
-        let mut memory = Memory::new();
+        let mut memory = Memory::flat();
         memory
             .write_u32_checked(0x0000_4000, section_desc(0x0010_0000, 0, 0b11) | (1 << 15))
             .unwrap_or_else(|e| panic!("write descriptor: {e}"));
@@ -399,4 +691,239 @@ mod tests {
             }
         );
     }
+
+    fn mmu_with_table(memory: &mut Memory, entries: &[(u32, u32)], control: u32) -> Mmu {
+        for &(address, descriptor) in entries {
+            memory
+                .write_u32_checked(address, descriptor)
+                .unwrap_or_else(|e| panic!("write descriptor: {e}"));
+        }
+        let mut mmu = Mmu::new();
+        mmu.write_ttbr0(0x0000_4000);
+        mmu.write_dacr(0b01);
+        mmu.write_control(control);
+        mmu
+    }
+
+    #[test]
+    fn coarse_table_maps_small_and_large_pages() {
+        let mut memory = Memory::flat();
+        // VA 0x0000_0000-0x000F_FFFF -> coarse table at 0x8000, domain 1.
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[
+                (0x0000_4000, 0x0000_8000 | (1 << 5) | L1_COARSE),
+                // 0x1000: extended small page, AP=11.
+                (0x0000_8004, 0x0020_3000 | (0b11 << 4) | 0b10),
+                // 0x10000-0x1FFFF: large page, repeated across 16 entries.
+                (0x0000_8040, 0x0030_0000 | (0b11 << 4) | L2_LARGE),
+                (0x0000_807C, 0x0030_0000 | (0b11 << 4) | L2_LARGE),
+            ],
+            1 | CONTROL_XP,
+        );
+        mmu.write_dacr(0b01 << 2);
+
+        let pa = mmu
+            .translate_read(&mut memory, 0x0000_1234, false)
+            .unwrap_or_else(|e| panic!("small page should translate: {e}"));
+        assert_eq!(pa, 0x0020_3234);
+        let pa = mmu
+            .translate_read(&mut memory, 0x0001_0008, false)
+            .unwrap_or_else(|e| panic!("large page should translate: {e}"));
+        assert_eq!(pa, 0x0030_0008);
+        let pa = mmu
+            .translate_read(&mut memory, 0x0001_F00C, false)
+            .unwrap_or_else(|e| panic!("large page should translate: {e}"));
+        assert_eq!(pa, 0x0030_F00C);
+
+        assert!(mmu.translate_read(&mut memory, 0x0000_2000, false).is_err());
+        assert_eq!(
+            mmu.last_fault(),
+            Some(FaultLocation {
+                page: true,
+                domain: 1
+            })
+        );
+
+        assert_eq!(mmu.tlb_len(), 2);
+        mmu.invalidate_tlb_va(0x0001_4000);
+        assert_eq!(mmu.tlb_len(), 1);
+    }
+
+    #[test]
+    fn supersection_maps_sixteen_megabytes() {
+        let mut memory = Memory::flat();
+        let supersection = 0x2000_0000 | L1_SUPERSECTION | (0b11 << 10) | L1_SECTION;
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[(0x0000_4040, supersection), (0x0000_4068, supersection)],
+            1,
+        );
+
+        let pa = mmu
+            .translate_read(&mut memory, 0x0100_0010, false)
+            .unwrap_or_else(|e| panic!("supersection should translate: {e}"));
+        assert_eq!(pa, 0x2000_0010);
+        let pa = mmu
+            .translate_read(&mut memory, 0x01AB_CDEF, false)
+            .unwrap_or_else(|e| panic!("second table entry shares the mapping: {e}"));
+        assert_eq!(pa, 0x20AB_CDEF);
+    }
+
+    #[test]
+    fn legacy_small_page_applies_subpage_permissions() {
+        let mut memory = Memory::flat();
+        // AP0 = no access, AP1 = read-only for user, AP2/AP3 = full access.
+        let small = 0x0020_0000 | (0b1111_1000 << 4) | L2_SMALL;
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[(0x0000_4000, 0x0000_8000 | L1_COARSE), (0x0000_8000, small)],
+            1,
+        );
+
+        assert!(mmu.translate_read(&mut memory, 0x0000_0000, true).is_err());
+        assert!(mmu.translate_read(&mut memory, 0x0000_0400, false).is_ok());
+        assert!(
+            mmu.translate_write(&mut memory, 0x0000_0400, false)
+                .is_err()
+        );
+        let pa = mmu
+            .translate_write(&mut memory, 0x0000_0C10, false)
+            .unwrap_or_else(|e| panic!("AP3 subpage should be writable: {e}"));
+        assert_eq!(pa, 0x0020_0C10);
+    }
+
+    #[test]
+    fn ttbcr_n_routes_high_addresses_through_ttbr1() {
+        let mut memory = Memory::flat();
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[
+                (0x0000_4000, section_desc(0x0010_0000, 0, 0b11)),
+                (0x0000_E000, section_desc(0x0500_0000, 0, 0b11)),
+            ],
+            1,
+        );
+        mmu.write_ttbr1(0x0000_C000);
+        mmu.write_ttbcr(1);
+
+        let pa = mmu
+            .translate_read(&mut memory, 0x8000_0004, false)
+            .unwrap_or_else(|e| panic!("TTBR1 walk should succeed: {e}"));
+        assert_eq!(pa, 0x0500_0004);
+        let pa = mmu
+            .translate_read(&mut memory, 0x0000_0004, false)
+            .unwrap_or_else(|e| panic!("TTBR0 walk should succeed: {e}"));
+        assert_eq!(pa, 0x0010_0004);
+
+        // PD1 only stops walks; the cached entry goes with the guest's flush.
+        mmu.write_ttbcr(1 | TTBCR_PD1);
+        mmu.invalidate_tlb();
+        assert!(mmu.translate_read(&mut memory, 0x8000_0004, false).is_err());
+        assert_eq!(
+            mmu.last_fault(),
+            Some(FaultLocation {
+                page: false,
+                domain: 0
+            })
+        );
+        assert!(mmu.translate_read(&mut memory, 0x0000_0004, false).is_ok());
+    }
+
+    #[test]
+    fn shareability_follows_tex_c_b_and_s() {
+        let mut memory = Memory::flat();
+        let normal = section_desc(0x0010_0000, 0, 0b11) | (1 << 12) | (1 << 3);
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[
+                (0x0000_4000, section_desc(0x0010_0000, 0, 0b11)),
+                (0x0000_4004, normal),
+                (0x0000_4008, normal | (1 << 16)),
+            ],
+            1,
+        );
+        for va in [0x0000_0000, 0x0010_0000, 0x0020_0000] {
+            mmu.translate_read(&mut memory, va, false)
+                .unwrap_or_else(|e| panic!("translation should succeed: {e}"));
+        }
+        assert!(mmu.shareable(0x0000_0000));
+        assert!(!mmu.shareable(0x0010_0000));
+        assert!(mmu.shareable(0x0020_0000));
+    }
+
+    #[test]
+    fn non_global_mappings_are_tagged_with_the_context_asid() {
+        let mut memory = Memory::flat();
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[
+                (0x0000_4000, section_desc(0x0010_0000, 0, 0b11)),
+                (0x0000_4004, section_desc(0x0800_0000, 0, 0b11) | SECTION_NG),
+            ],
+            1,
+        );
+        mmu.write_context_id(0x105);
+        let read = |mmu: &mut Mmu, memory: &mut Memory, va| {
+            mmu.translate_read(memory, va, false)
+                .unwrap_or_else(|e| panic!("translation should succeed: {e}"))
+        };
+        read(&mut mmu, &mut memory, 0x0000_0010);
+        read(&mut mmu, &mut memory, 0x0010_0010);
+        read(&mut mmu, &mut memory, 0x0010_0020);
+        assert_eq!(
+            mmu.tlb_stats(),
+            TlbStats {
+                micro_hits: 1,
+                main_hits: 0,
+                misses: 2,
+                data_micro_misses: 2
+            }
+        );
+
+        // Another process's mapping of the same VA isn't reused, but the
+        // global one is.
+        memory.write_u32(0x0000_4004, section_desc(0x0900_0000, 0, 0b11) | SECTION_NG);
+        mmu.write_context_id(0x206);
+        assert_eq!(read(&mut mmu, &mut memory, 0x0010_0010), 0x0900_0010);
+        read(&mut mmu, &mut memory, 0x0000_0010);
+        assert_eq!(mmu.tlb_stats().misses, 3);
+        assert_eq!(mmu.tlb_len(), 3);
+
+        mmu.write_context_id(0x305);
+        assert_eq!(read(&mut mmu, &mut memory, 0x0010_0010), 0x0800_0010);
+
+        mmu.invalidate_tlb_asid(0x05);
+        assert_eq!(mmu.tlb_len(), 2);
+        mmu.invalidate_tlb_va_asid(0x0010_0000, 0x06);
+        mmu.invalidate_tlb_va_asid(0x0000_0000, 0x07);
+        assert_eq!(mmu.tlb_len(), 0);
+    }
+
+    #[test]
+    fn table_base_writes_keep_the_tlb() {
+        let mut memory = Memory::flat();
+        let mut mmu = mmu_with_table(
+            &mut memory,
+            &[(0x0000_4004, section_desc(0x0800_0000, 0, 0b11))],
+            1,
+        );
+        let read = |mmu: &mut Mmu, memory: &mut Memory| {
+            mmu.translate_read(memory, 0x0010_0010, false)
+                .unwrap_or_else(|e| panic!("translation should succeed: {e}"))
+        };
+        assert_eq!(read(&mut mmu, &mut memory), 0x0800_0010);
+        assert_eq!(mmu.tlb_len(), 1);
+
+        // Entries from the old tables stay until the guest invalidates them.
+        memory.write_u32(0x0000_8004, section_desc(0x0900_0000, 0, 0b11));
+        mmu.write_ttbr1(0x0000_8000);
+        mmu.write_ttbcr(1);
+        mmu.write_ttbr0(0x0000_8000);
+        assert_eq!(mmu.tlb_len(), 1);
+        assert_eq!(read(&mut mmu, &mut memory), 0x0800_0010);
+
+        mmu.invalidate_tlb();
+        assert_eq!(read(&mut mmu, &mut memory), 0x0900_0010);
+    }
 }
@@ -0,0 +1,165 @@
+//! Translation lookaside buffers: the ARM11 MPCore's 64-entry main TLB and
+//! its 8-entry instruction and data micro-TLBs, all fully associative with
+//! round-robin replacement.
+//!
+//! A non-global entry keeps the ASID it was walked under and only matches
+//! lookups made with that ASID; global entries match any ASID.
+
+use super::SECTION_SHIFT;
+
+pub(super) const MAIN_TLB_ENTRIES: usize = 64;
+pub(super) const MICRO_TLB_ENTRIES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TlbEntry {
+    pub(super) va_base: u32,
+    pub(super) pa_base: u32,
+    /// log2 of the mapping size.
+    pub(super) size_shift: u8,
+    pub(super) domain: u8,
+    /// One 2-bit AP field per subpage; the four are equal unless the page
+    /// uses backwards-compatible subpage permissions.
+    pub(super) subpage_ap: u8,
+    pub(super) subpage_shift: u8,
+    pub(super) apx: bool,
+    pub(super) execute_never: bool,
+    pub(super) shareable: bool,
+    pub(super) global: bool,
+    pub(super) asid: u8,
+}
+
+impl TlbEntry {
+    pub(super) fn covers(&self, va: u32) -> bool {
+        (va ^ self.va_base) >> self.size_shift == 0
+    }
+
+    pub(super) fn matches(&self, va: u32, asid: u8) -> bool {
+        self.covers(va) && (self.global || self.asid == asid)
+    }
+
+    pub(super) fn ap(&self, va: u32) -> u8 {
+        let subpage = (va >> self.subpage_shift) & 0b11;
+        (self.subpage_ap >> (subpage * 2)) & 0b11
+    }
+
+    pub(super) fn is_page(&self) -> bool {
+        self.size_shift < SECTION_SHIFT
+    }
+}
+
+/// Lookup counters, summed over the micro and main TLBs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    pub micro_hits: u64,
+    pub main_hits: u64,
+    /// Lookups that needed a table walk, including walks that faulted.
+    pub misses: u64,
+    /// Data accesses that missed the data micro-TLB.
+    pub data_micro_misses: u64,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Tlb {
+    entries: Vec<TlbEntry>,
+    capacity: usize,
+    next_victim: usize,
+}
+
+impl Tlb {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next_victim: 0,
+        }
+    }
+
+    pub(super) fn lookup(&self, va: u32, asid: u8) -> Option<TlbEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.matches(va, asid))
+            .copied()
+    }
+
+    /// Adds `entry`, replacing one for the same mapping or else the next
+    /// victim once the TLB is full.
+    pub(super) fn insert(&mut self, entry: TlbEntry) {
+        let same_mapping = self.entries.iter_mut().find(|old| {
+            old.va_base == entry.va_base
+                && old.size_shift == entry.size_shift
+                && (old.global || entry.global || old.asid == entry.asid)
+        });
+        if let Some(old) = same_mapping {
+            *old = entry;
+        } else if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next_victim] = entry;
+            self.next_victim = (self.next_victim + 1) % self.capacity;
+        }
+    }
+
+    pub(super) fn retain(&mut self, keep: impl Fn(&TlbEntry) -> bool) {
+        self.entries.retain(|entry| keep(entry));
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.entries.clear();
+        self.next_victim = 0;
+    }
+
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(va_base: u32, asid: u8) -> TlbEntry {
+        TlbEntry {
+            va_base,
+            pa_base: va_base,
+            size_shift: 12,
+            domain: 0,
+            subpage_ap: 0xFF,
+            subpage_shift: 12,
+            apx: false,
+            execute_never: false,
+            shareable: true,
+            global: false,
+            asid,
+        }
+    }
+
+    #[test]
+    fn full_tlb_replaces_entries_round_robin() {
+        let mut tlb = Tlb::new(2);
+        tlb.insert(page(0x1000, 1));
+        tlb.insert(page(0x2000, 1));
+        tlb.insert(page(0x3000, 1));
+        assert_eq!(tlb.len(), 2);
+        assert!(tlb.lookup(0x1000, 1).is_none());
+        tlb.insert(page(0x4000, 1));
+        assert!(tlb.lookup(0x2000, 1).is_none());
+        assert!(tlb.lookup(0x3000, 1).is_some());
+        assert!(tlb.lookup(0x4FFF, 1).is_some());
+    }
+
+    #[test]
+    fn non_global_entries_match_only_their_asid() {
+        let mut tlb = Tlb::new(4);
+        tlb.insert(page(0x1000, 1));
+        tlb.insert(page(0x1000, 2));
+        tlb.insert(TlbEntry {
+            global: true,
+            ..page(0x5000, 1)
+        });
+        assert_eq!(tlb.len(), 3);
+        assert!(tlb.lookup(0x1004, 3).is_none());
+        assert_eq!(tlb.lookup(0x1004, 2).map(|e| e.asid), Some(2));
+        assert!(tlb.lookup(0x5004, 3).is_some());
+    }
+}
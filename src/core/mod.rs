@@ -1,21 +1,30 @@
 pub mod bus;
+pub mod code_pages;
+pub mod conformance;
 pub mod cpu;
 pub mod diagnostics;
+pub mod disasm;
 pub mod dma;
 pub mod dsp;
 pub mod emulator;
 pub mod error;
+pub mod exclusive;
 pub mod fs;
+pub mod gdb;
+pub mod gic;
 pub mod ipc;
 pub mod irq;
 pub mod kernel;
 #[path = "loader/mod.rs"]
 pub mod loader;
 pub mod memory;
+pub mod memory_hooks;
 pub mod mmu;
 pub mod pica;
 pub mod rom;
 pub mod scheduler;
 pub mod services;
+#[cfg(test)]
+pub(crate) mod test_rom;
 pub mod timing;
 pub mod trace;
@@ -294,6 +294,10 @@ impl PicaGpu {
         self.enqueue_gsp_fifo_words(&[header, value]);
     }
 
+    pub fn has_pending_commands(&self) -> bool {
+        !self.fifo.is_empty()
+    }
+
     pub fn tick(&mut self, _cycle: u64) {
         if self.fifo.is_empty() {
             return;
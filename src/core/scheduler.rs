@@ -2,8 +2,17 @@
 pub enum ScheduledDeviceEvent {
     TimerExpiry,
     VBlank,
-    DmaCompletion { channel: u8 },
-    ServiceWake { pid: u32 },
+    DmaCompletion {
+        channel: u8,
+    },
+    ServiceWake {
+        pid: u32,
+    },
+    /// A callback a memory-mapped device scheduled for itself.
+    Device {
+        base: u32,
+        token: u32,
+    },
 }
 
 impl ScheduledDeviceEvent {
@@ -13,6 +22,7 @@ impl ScheduledDeviceEvent {
             ScheduledDeviceEvent::VBlank => 1,
             ScheduledDeviceEvent::DmaCompletion { .. } => 2,
             ScheduledDeviceEvent::ServiceWake { .. } => 3,
+            ScheduledDeviceEvent::Device { .. } => 4,
         }
     }
 }
@@ -80,6 +90,10 @@ impl Scheduler {
         due.into_iter().map(|entry| entry.event).collect()
     }
 
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.iter().map(|e| e.at_cycle).min()
+    }
+
     pub fn cycles(&self) -> u64 {
         self.cycles
     }
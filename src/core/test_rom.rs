@@ -0,0 +1,54 @@
+//! A minimal NCSD image for emulator-level tests: one NCCH whose `.code`
+//! lands at VA 0x00100000, which is ROM offset 0xA00.
+
+use super::emulator::Emulator3ds;
+
+pub(crate) fn valid_rom() -> Vec<u8> {
+    let mut rom = vec![0_u8; 0x5000];
+    rom[0x100..0x104].copy_from_slice(b"NCSD");
+    rom[0x120..0x124].copy_from_slice(&1u32.to_le_bytes());
+    rom[0x124..0x128].copy_from_slice(&0x20u32.to_le_bytes());
+
+    let ncch = 0x200;
+    rom[ncch + 0x100..ncch + 0x104].copy_from_slice(b"NCCH");
+    rom[ncch + 0x180..ncch + 0x184].copy_from_slice(&0x400u32.to_le_bytes());
+    rom[ncch + 0x1A8..ncch + 0x1AC].copy_from_slice(&3u32.to_le_bytes());
+    rom[ncch + 0x1AC..ncch + 0x1B0].copy_from_slice(&2u32.to_le_bytes());
+
+    let ex = ncch + 0x200;
+    rom[ex..ex + 4].copy_from_slice(&0x0010_0000u32.to_le_bytes());
+    rom[ex + 0x10..ex + 0x14].copy_from_slice(&0x0010_0000u32.to_le_bytes());
+    rom[ex + 0x18..ex + 0x1C].copy_from_slice(&0x20u32.to_le_bytes());
+    rom[ex + 0x20..ex + 0x24].copy_from_slice(&0x0010_1000u32.to_le_bytes());
+    rom[ex + 0x28..ex + 0x2C].copy_from_slice(&0x20u32.to_le_bytes());
+    rom[ex + 0x30..ex + 0x34].copy_from_slice(&0x0010_2000u32.to_le_bytes());
+    rom[ex + 0x38..ex + 0x3C].copy_from_slice(&0x20u32.to_le_bytes());
+    rom[ex + 0x3C..ex + 0x40].copy_from_slice(&0x10u32.to_le_bytes());
+    rom[ex + 0x1C..ex + 0x20].copy_from_slice(&0x2000u32.to_le_bytes());
+    rom[ex + 0x40..ex + 0x44].copy_from_slice(&0x8000u32.to_le_bytes());
+
+    let exefs = 0x800;
+    rom[exefs..exefs + 5].copy_from_slice(b".code");
+    rom[exefs + 8..exefs + 12].copy_from_slice(&0u32.to_le_bytes());
+    rom[exefs + 12..exefs + 16].copy_from_slice(&0x60u32.to_le_bytes());
+    rom[exefs + 0x200..exefs + 0x260].fill(0);
+
+    rom
+}
+
+/// Patches the instruction at ROM `offset`.
+pub(crate) fn write_insn(rom: &mut [u8], offset: usize, opcode: u32) {
+    rom[offset..offset + 4].copy_from_slice(&opcode.to_le_bytes());
+}
+
+/// Loads `program` at the title's entry point.
+pub(crate) fn boot(program: &[u32]) -> Emulator3ds {
+    let mut rom = valid_rom();
+    for (i, opcode) in program.iter().enumerate() {
+        write_insn(&mut rom, 0xA00 + i * 4, *opcode);
+    }
+    let mut emu = Emulator3ds::new();
+    emu.load_rom(&rom)
+        .unwrap_or_else(|e| panic!("load works: {e}"));
+    emu
+}
@@ -169,6 +169,26 @@ impl TimingModel {
         }
     }
 
+    /// Cycles until the next tick that would present a frame or start a
+    /// vblank on either screen, so callers can batch everything before it.
+    pub fn cycles_until_next_event(&self) -> u64 {
+        let frame = (CPU_HZ - self.video_phase).div_ceil(VIDEO_HZ);
+        let frame_cycles = CPU_HZ / VIDEO_HZ;
+        let line_cycles = (frame_cycles / u64::from(SCANLINES_TOTAL)).max(1);
+        let vblank_start = u64::from(SCANLINES_ACTIVE) * line_cycles;
+        let bottom_offset = u64::from(BOTTOM_SCANLINE_OFFSET) * line_cycles;
+        let until_vblank = |cycle_in_frame: u64| match (vblank_start + frame_cycles
+            - cycle_in_frame)
+            % frame_cycles
+        {
+            0 => frame_cycles,
+            cycles => cycles,
+        };
+        let top = until_vblank(self.cpu_cycles % frame_cycles);
+        let bottom = until_vblank((self.cpu_cycles + bottom_offset) % frame_cycles);
+        frame.min(top).min(bottom)
+    }
+
     pub fn snapshot(&self) -> TimingSnapshot {
         let nominal_samples_per_frame = (AUDIO_HZ / VIDEO_HZ) as i64;
         let expected_samples = self.video_frames_due as i64 * nominal_samples_per_frame;
@@ -255,6 +275,21 @@ mod tests {
         assert_eq!(first.audio_samples_due, second.audio_samples_due);
     }
 
+    #[test]
+    fn next_event_lands_on_the_first_edge() {
+        let mut timing = TimingModel::new();
+        for _ in 0..4 {
+            let until = timing.cycles_until_next_event();
+            let mut probe = timing.clone();
+            let quiet = probe.tick((until - 1) as u32);
+            assert_eq!(quiet.video_frames + quiet.top_vblank_edges, 0);
+            assert_eq!(quiet.bottom_vblank_edges, 0);
+            let edge = probe.tick(1);
+            assert!(edge.video_frames + edge.top_vblank_edges + edge.bottom_vblank_edges > 0);
+            timing = probe;
+        }
+    }
+
     #[test]
     fn drift_policy_scales_budget() {
         let mut timing = TimingModel::new();
@@ -3,6 +3,7 @@ pub use super::diagnostics::{
     StructuredError,
 };
 
+use super::bus::AccessWidth;
 use super::error::MemoryAccessKind;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -14,6 +15,7 @@ pub enum TraceCategory {
     GpuCommand,
     Irq,
     Timer,
+    MemoryAccess,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,6 +48,15 @@ pub enum TracePayload {
     TimerScheduled {
         period_cycles: u64,
     },
+    MemoryAccess {
+        core: usize,
+        pc: u32,
+        va: u32,
+        pa: u32,
+        width: AccessWidth,
+        value: u64,
+        access: MemoryAccessKind,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -2,10 +2,22 @@
 
 mod core;
 
-pub use crate::core::cpu::{CpuException, CpuRunState, ExceptionKind};
-pub use crate::core::emulator::{Emulator3ds, EmulatorConfig, EmulatorState};
-pub use crate::core::error::EmulatorError;
+pub use crate::core::bus::{AccessViolationPolicy, AccessWidth, BusDevice, DeviceContext};
+pub use crate::core::conformance::{
+    Divergence, FixtureError, Mismatch, TraceFixture, load_fixture_dir,
+};
+pub use crate::core::cpu::{
+    AddressSpace, BreakCondition, CpuException, CpuRunState, CycleModel, DebugEvent, ExceptionKind,
+    WatchKind, Watchpoint,
+};
+pub use crate::core::emulator::{Emulator3ds, EmulatorConfig, EmulatorState, StopReason};
+pub use crate::core::error::{EmulatorError, MemoryAccessKind};
+pub use crate::core::gdb::{GdbStub, GdbTransport};
+pub use crate::core::irq::IrqLine;
 pub use crate::core::kernel::{ServiceCall, ServiceEvent};
+pub use crate::core::memory::ConsoleModel;
+pub use crate::core::memory_hooks::{HookRange, MemoryAccess, MemoryHook, MemoryHookId};
+pub use crate::core::mmu::TlbStats;
 pub use crate::core::timing::{DriftCorrectionPolicy, TimingSnapshot};
 pub use crate::core::trace::{
     BootCheckpoint, BootCheckpointSnapshot, FaultSnapshot, StructuredError, TraceCategory,
@@ -95,6 +107,14 @@ impl Wasm3ds {
         self.inner.mapped_memory_bytes()
     }
 
+    pub fn reserved_memory_bytes(&self) -> usize {
+        self.inner.reserved_memory_bytes()
+    }
+
+    pub fn resident_memory_bytes(&self) -> usize {
+        self.inner.resident_memory_bytes()
+    }
+
     pub fn frame_rgba(&self) -> Vec<u8> {
         self.inner.frame_rgba()
     }